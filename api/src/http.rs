@@ -5,12 +5,14 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::collections::HashMap;
-use std::io::{self, Error, ErrorKind, Result};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Result, Write};
+use std::net::{TcpListener, TcpStream};
 use std::os::unix::io::AsRawFd;
 use std::path::PathBuf;
 use std::sync::mpsc::{Receiver, RecvError, SendError, Sender};
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use std::{fs, thread};
 
 use dbs_uhttp::{Body, HttpServer, MediaType, Request, Response, ServerError, StatusCode, Version};
@@ -21,15 +23,16 @@ use serde::Deserialize;
 use serde_json::{Error as SerdeError, Value};
 use url::Url;
 
-use nydus_utils::metrics::IoStatsError;
+use nydus_utils::metrics::{export_prometheus_metrics, IoStatsError};
 
 use crate::http_endpoint_common::{
-    EventsHandler, ExitHandler, MetricsBackendHandler, MetricsBlobcacheHandler, MountHandler,
-    SendFuseFdHandler, StartHandler, TakeoverFuseFdHandler,
+    EventsHandler, ExitHandler, MetricsBackendHandler, MetricsBlobcacheHandler,
+    MetricsBlobcacheScrubHandler, MetricsPrometheusHandler, MountHandler, SendFuseFdHandler,
+    StartHandler, TakeoverFuseFdHandler,
 };
 use crate::http_endpoint_v1::{
     FsBackendInfo, InfoHandler, MetricsFsAccessPatternHandler, MetricsFsFilesHandler,
-    MetricsFsGlobalHandler, MetricsFsInflightHandler, HTTP_ROOT_V1,
+    MetricsFsGlobalHandler, MetricsFsInflightHandler, MetricsFsSlowIoHandler, HTTP_ROOT_V1,
 };
 use crate::http_endpoint_v2::{BlobObjectListHandlerV2, InfoV2Handler, HTTP_ROOT_V2};
 
@@ -58,6 +61,16 @@ pub struct ApiUmountCmd {
     pub mountpoint: String,
 }
 
+/// Update storage backend configuration of a mounted filesystem.
+#[derive(Clone, Deserialize, Debug)]
+pub struct ApiBackendCmd {
+    /// Type of storage backend, which must be the same as the one in use.
+    #[serde(rename = "type")]
+    pub backend_type: String,
+    /// Configuration for storage backend, such as credentials and connection settings.
+    pub config: Value,
+}
+
 /// Set/update daemon configuration.
 #[derive(Clone, Deserialize, Debug)]
 pub struct DaemonConf {
@@ -156,6 +169,17 @@ fn default_work_dir() -> String {
     ".".to_string()
 }
 
+/// Configuration information for background scrubbing of cached data.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ScrubConfig {
+    /// Interval in seconds between two scrubbing passes over cached blobs, zero means disabled.
+    #[serde(default)]
+    pub interval: u64,
+    /// Amount of cached data read by the scrubber per second in unit of Bytes, zero means no limit.
+    #[serde(default)]
+    pub bandwidth_rate: u32,
+}
+
 /// Configuration information for file cache.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct FileCacheConfig {
@@ -165,6 +189,31 @@ pub struct FileCacheConfig {
     /// Deprecated: disable index mapping, keep it as false when possible.
     #[serde(default)]
     pub disable_indexed_map: bool,
+    /// Maximum disk space in bytes used by cached blob files, zero means no limit.
+    #[serde(default)]
+    pub capacity: u64,
+    /// Maximum percentage of the filesystem hosting `work_dir` used by cached blob files,
+    /// zero means no limit.
+    #[serde(default)]
+    pub capacity_percent: u32,
+    /// Reclaim disk space of cached chunks which haven't been accessed for the specified seconds,
+    /// zero means never.
+    #[serde(default)]
+    pub cold_chunk_secs: u64,
+    /// Maximum memory in bytes used by the LRU cache of decompressed chunks for each blob, only
+    /// used when chunk data is cached in compressed form and the hot chunk cache is disabled, zero
+    /// means disabled.
+    #[serde(default)]
+    pub chunk_lru_size: u64,
+    /// Store chunk data in a content-addressed store shared by all blobs in `work_dir`, so chunks
+    /// with the same digest are cached only once. Only Rafs v5 blobs are deduplicated, blobs of
+    /// Rafs v6 images are cached as usual. Not supported in compressed mode, and `work_dir` can't
+    /// be used by another nydusd process at the same time.
+    #[serde(default)]
+    pub dedup_chunks: bool,
+    /// Periodically verify cached chunks and drop corrupted ones.
+    #[serde(default)]
+    pub scrub: ScrubConfig,
 }
 
 impl FileCacheConfig {
@@ -199,6 +248,9 @@ pub struct FsCacheConfig {
     /// Working directory to store state and cached files.
     #[serde(default = "default_work_dir")]
     pub work_dir: String,
+    /// Periodically verify cached chunks and drop corrupted ones.
+    #[serde(default)]
+    pub scrub: ScrubConfig,
 }
 
 impl FsCacheConfig {
@@ -252,30 +304,124 @@ impl Default for ProxyConfig {
     }
 }
 
+/// Configuration information for the circuit breaker of a storage backend.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    /// Enable the circuit breaker.
+    pub enable: bool,
+    /// Open the circuit after so many read requests fail in a row, read requests fail fast then.
+    /// A request is counted as failed once when all its retries have failed.
+    pub failure_limit: u8,
+    /// Interval to let a probing request through when the circuit is open, in seconds.
+    pub recovery_interval: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            failure_limit: 5,
+            recovery_interval: 5,
+        }
+    }
+}
+
+/// Configuration information for a registry mirror.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MirrorConfig {
+    /// Mirror server URL with scheme, e.g. `http://mirror.example.com:5000`.
+    pub host: String,
+    /// Headers sent to the mirror server with each request.
+    pub headers: HashMap<String, String>,
+    /// Endpoint of mirror health checking, `<host>/v2/` by default.
+    pub ping_url: String,
+    /// Interval of mirror health checking when it's unavailable, in seconds.
+    pub health_check_interval: u64,
+    /// Mark the mirror as unavailable after failing so many times in a row.
+    pub failure_limit: u8,
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        Self {
+            host: String::new(),
+            headers: HashMap::new(),
+            ping_url: String::new(),
+            health_check_interval: 5,
+            failure_limit: 5,
+        }
+    }
+}
+
+impl fmt::Debug for MirrorConfig {
+    // Header values may carry credentials, such as `Authorization`, so don't log them.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let headers: HashMap<&String, &str> =
+            self.headers.keys().map(|k| (k, "<redacted>")).collect();
+        f.debug_struct("MirrorConfig")
+            .field("host", &self.host)
+            .field("headers", &headers)
+            .field("ping_url", &self.ping_url)
+            .field("health_check_interval", &self.health_check_interval)
+            .field("failure_limit", &self.failure_limit)
+            .finish()
+    }
+}
+
 /// Generic configuration for storage backends.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct RegistryOssConfig {
     /// Enable HTTP proxy for the read request.
     pub proxy: ProxyConfig,
+    /// Registry mirrors in order of preference, requests fail over to the next healthy mirror
+    /// and finally to the origin server.
+    pub mirrors: Vec<MirrorConfig>,
     /// Skip SSL certificate validation for HTTPS scheme.
     pub skip_verify: bool,
+    /// Path of PEM encoded CA certificates to verify servers, in addition to system CAs.
+    pub ca_file: String,
+    /// Path of PEM encoded client certificate for mutual TLS authentication.
+    pub client_cert_file: String,
+    /// Path of PEM encoded PKCS#8 private key of the client certificate.
+    pub client_key_file: String,
+    /// Minimum TLS version to connect servers, `1.0`, `1.1` or `1.2`.
+    pub min_tls_version: String,
     /// Drop the read request once http request timeout, in seconds.
     pub timeout: u64,
     /// Drop the read request once http connection timeout, in seconds.
     pub connect_timeout: u64,
     /// Retry count when read request failed.
     pub retry_limit: u8,
+    /// Delay before the first retry, in milliseconds, doubled on each following retry.
+    pub retry_backoff_ms: u64,
+    /// Maximum delay between retries, in milliseconds.
+    pub retry_backoff_max_ms: u64,
+    /// Stop retrying once the read request has taken so long, in seconds, 0 means no deadline.
+    pub retry_deadline: u64,
+    /// Fail read requests fast while the storage backend is known to be unavailable.
+    pub circuit_breaker: CircuitBreakerConfig,
 }
 
 impl Default for RegistryOssConfig {
     fn default() -> Self {
         Self {
             proxy: ProxyConfig::default(),
+            mirrors: Vec::new(),
             skip_verify: false,
+            ca_file: String::new(),
+            client_cert_file: String::new(),
+            client_key_file: String::new(),
+            min_tls_version: String::new(),
             timeout: 5,
             connect_timeout: 5,
             retry_limit: 0,
+            retry_backoff_ms: 100,
+            retry_backoff_max_ms: 5000,
+            retry_deadline: 0,
+            circuit_breaker: CircuitBreakerConfig::default(),
         }
     }
 }
@@ -309,6 +455,10 @@ pub enum ApiRequest {
     ExportBackendMetrics(Option<String>),
     /// Get blob cache metrics.
     ExportBlobcacheMetrics(Option<String>),
+    /// Get report of blob cache scrubbing.
+    ExportBlobcacheScrubReport(Option<String>),
+    /// Get all metrics in the Prometheus text exposition format.
+    ExportPrometheusMetrics,
 
     // Nydus API v1 requests
     /// Get filesystem global metrics.
@@ -317,10 +467,14 @@ pub enum ApiRequest {
     ExportFsAccessPatterns(Option<String>),
     /// Get filesystem backend information.
     ExportFsBackendInfo(String),
+    /// Update storage backend configuration of a filesystem.
+    UpdateFsBackend(String, ApiBackendCmd),
     /// Get filesystem file metrics.
     ExportFsFilesMetrics(Option<String>, bool),
     /// Get information about filesystem inflight requests.
     ExportFsInflightMetrics,
+    /// Get slowest read requests of a filesystem.
+    ExportFsSlowIo(Option<String>),
 
     // Nydus API v2
     /// Get daemon information excluding filesystem backends.
@@ -371,6 +525,8 @@ pub enum ApiError {
     Metrics(MetricsErrorKind),
     /// Failed to mount filesystem
     MountFilesystem(DaemonErrorKind),
+    /// Failed to update storage backend configuration of a filesystem
+    UpdateFsBackend(DaemonErrorKind),
     /// Failed to send request to the API service
     RequestSend(SendError<Option<ApiRequest>>),
     /// Unrecognized payload content
@@ -390,6 +546,10 @@ pub enum ApiResponsePayload {
     BackendMetrics(String),
     /// Blobcache metrics.
     BlobcacheMetrics(String),
+    /// Report of blobcache scrubbing.
+    BlobcacheScrubReport(String),
+    /// Metrics in the Prometheus text exposition format.
+    PrometheusMetrics(String),
     /// Daemon version, configuration and status information in json.
     DaemonInfo(String),
     /// No data is sent on the channel.
@@ -407,6 +567,8 @@ pub enum ApiResponsePayload {
     FsBackendInfo(String),
     // Filesystem Inflight Requests, v1.
     FsInflightMetrics(String),
+    /// Filesystem slowest read requests, v1.
+    FsSlowIo(String),
 
     /// List of blob objects, v2
     BlobObjectList(String),
@@ -447,10 +609,16 @@ pub enum HttpError {
     BackendMetrics(ApiError),
     /// Failed to get blobcache metrics.
     BlobcacheMetrics(ApiError),
+    /// Failed to get report of blobcache scrubbing.
+    BlobcacheScrubReport(ApiError),
+    /// Failed to get metrics in the Prometheus text exposition format.
+    PrometheusMetrics(ApiError),
 
     // Filesystem related errors (v1)
     /// Failed to get filesystem backend information
     FsBackendInfo(ApiError),
+    /// Failed to update filesystem storage backend configuration.
+    UpdateFsBackend(ApiError),
     /// Failed to get filesystem per-file metrics.
     FsFilesMetrics(ApiError),
     /// Failed to get global metrics.
@@ -459,6 +627,8 @@ pub enum HttpError {
     InflightMetrics(ApiError),
     /// Failed to get filesystem file access trace.
     Pattern(ApiError),
+    /// Failed to get filesystem slowest read requests.
+    SlowIo(ApiError),
 
     // Blob cache management related errors (v2)
     /// Failed to create blob object
@@ -516,7 +686,9 @@ pub(crate) fn parse_body<'a, F: Deserialize<'a>>(b: &'a Body) -> std::result::Re
 /// Translate ApiError message to HTTP status code.
 pub(crate) fn translate_status_code(e: &ApiError) -> StatusCode {
     match e {
-        ApiError::DaemonAbnormal(kind) | ApiError::MountFilesystem(kind) => match kind {
+        ApiError::DaemonAbnormal(kind)
+        | ApiError::MountFilesystem(kind)
+        | ApiError::UpdateFsBackend(kind) => match kind {
             DaemonErrorKind::NotReady => StatusCode::ServiceUnavailable,
             DaemonErrorKind::Unsupported => StatusCode::NotImplemented,
             DaemonErrorKind::UnexpectedEvent(_) => StatusCode::BadRequest,
@@ -563,6 +735,11 @@ pub trait EndpointHandler: Sync + Send {
         req: &Request,
         kicker: &dyn Fn(ApiRequest) -> ApiResponse,
     ) -> HttpResult;
+
+    /// Media type of the body of successful responses.
+    fn media_type(&self) -> MediaType {
+        MediaType::ApplicationJson
+    }
 }
 
 /// Struct to route HTTP requests to corresponding registered endpoint handlers.
@@ -599,6 +776,8 @@ lazy_static! {
         r.routes.insert(endpoint_v1!("/mount"), Box::new(MountHandler{}));
         r.routes.insert(endpoint_v1!("/metrics/backend"), Box::new(MetricsBackendHandler{}));
         r.routes.insert(endpoint_v1!("/metrics/blobcache"), Box::new(MetricsBlobcacheHandler{}));
+        r.routes.insert(endpoint_v1!("/metrics/blobcache/scrub"), Box::new(MetricsBlobcacheScrubHandler{}));
+        r.routes.insert(endpoint_v1!("/metrics/prometheus"), Box::new(MetricsPrometheusHandler{}));
 
         // Nydus API, v1
         r.routes.insert(endpoint_v1!("/daemon"), Box::new(InfoHandler{}));
@@ -607,6 +786,7 @@ lazy_static! {
         r.routes.insert(endpoint_v1!("/metrics/files"), Box::new(MetricsFsFilesHandler{}));
         r.routes.insert(endpoint_v1!("/metrics/inflight"), Box::new(MetricsFsInflightHandler{}));
         r.routes.insert(endpoint_v1!("/metrics/pattern"), Box::new(MetricsFsAccessPatternHandler{}));
+        r.routes.insert(endpoint_v1!("/metrics/slowio"), Box::new(MetricsFsSlowIoHandler{}));
 
         // Nydus API, v2
         r.routes.insert(endpoint_v2!("/daemon"), Box::new(InfoV2Handler{}));
@@ -671,12 +851,20 @@ fn handle_http_request(
 
     // Micro http should ensure that req path is legal.
     let uri_parsed = request.uri().get_abs_path().parse::<Uri>();
+    let mut media_type = MediaType::ApplicationJson;
     let mut response = match uri_parsed {
         Ok(uri) => match HTTP_ROUTES.routes.get(uri.path()) {
             Some(route) => route
                 .handle_request(request, &|r| {
                     kick_api_server(api_notifier.clone(), to_api, from_api, r)
                 })
+                .map(|r| {
+                    // Error messages are always in json.
+                    if r.status() == StatusCode::OK {
+                        media_type = route.media_type();
+                    }
+                    r
+                })
                 .unwrap_or_else(|err| error_response(err, StatusCode::BadRequest)),
             None => error_response(HttpError::NoRoute, StatusCode::NotFound),
         },
@@ -686,7 +874,7 @@ fn handle_http_request(
         }
     };
     response.set_server("Nydus API");
-    response.set_content_type(MediaType::ApplicationJson);
+    response.set_content_type(media_type);
 
     trace_api_end(&response, request.method(), begin_time);
 
@@ -787,6 +975,67 @@ pub fn start_http_thread(
     Ok((thread, waker))
 }
 
+/// Start a thread to serve metrics in the Prometheus text exposition format at TCP address `addr`.
+///
+/// Only `GET /metrics` is served, so metrics collectors get no access to the administration API.
+pub fn start_prometheus_thread(addr: &str) -> Result<thread::JoinHandle<()>> {
+    let listener = TcpListener::bind(addr)?;
+    info!(
+        "Prometheus metrics exporter running at {}",
+        listener.local_addr()?
+    );
+    spawn_prometheus_thread(listener)
+}
+
+fn spawn_prometheus_thread(listener: TcpListener) -> Result<thread::JoinHandle<()>> {
+    thread::Builder::new()
+        .name("nydus-prometheus".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => serve_prometheus_request(stream).unwrap_or_else(|e| {
+                        warn!("prometheus exporter: failed to serve request, {}", e)
+                    }),
+                    Err(e) => error!("prometheus exporter: failed to accept connection, {}", e),
+                }
+            }
+        })
+}
+
+fn serve_prometheus_request(mut stream: TcpStream) -> Result<()> {
+    // Don't let a stalled client block the exporter forever.
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Skip all request headers.
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if reader.read_line(&mut header)? <= 2 {
+            break;
+        }
+    }
+
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) if path == "/metrics" || path.starts_with("/metrics?") => {
+            ("200 OK", export_prometheus_metrics())
+        }
+        (Some("GET"), _) => ("404 Not Found", String::new()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -819,6 +1068,32 @@ mod tests {
         let config: FileCacheConfig = serde_json::from_str("{}").unwrap();
         assert_eq!(&config.work_dir, ".");
         assert!(!config.disable_indexed_map);
+        assert_eq!(config.capacity, 0);
+        assert_eq!(config.capacity_percent, 0);
+        assert_eq!(config.cold_chunk_secs, 0);
+        assert_eq!(config.chunk_lru_size, 0);
+        assert!(!config.dedup_chunks);
+        assert_eq!(config.scrub, ScrubConfig::default());
+
+        let config: FileCacheConfig =
+            serde_json::from_str("{\"capacity\":1048576,\"capacity_percent\":80}").unwrap();
+        assert_eq!(config.capacity, 0x10_0000);
+        assert_eq!(config.capacity_percent, 80);
+
+        let config: FileCacheConfig = serde_json::from_str("{\"cold_chunk_secs\":3600}").unwrap();
+        assert_eq!(config.cold_chunk_secs, 3600);
+
+        let config: FileCacheConfig = serde_json::from_str("{\"chunk_lru_size\":4194304}").unwrap();
+        assert_eq!(config.chunk_lru_size, 0x40_0000);
+
+        let config: FileCacheConfig = serde_json::from_str("{\"dedup_chunks\":true}").unwrap();
+        assert!(config.dedup_chunks);
+
+        let config: FileCacheConfig =
+            serde_json::from_str("{\"scrub\":{\"interval\":3600,\"bandwidth_rate\":1048576}}")
+                .unwrap();
+        assert_eq!(config.scrub.interval, 3600);
+        assert_eq!(config.scrub.bandwidth_rate, 0x10_0000);
 
         let config: FileCacheConfig =
             serde_json::from_str("{\"work_dir\":\"/tmp\",\"disable_indexed_map\":true}").unwrap();
@@ -836,6 +1111,8 @@ mod tests {
     fn test_fs_cache_config() {
         let config: FsCacheConfig = serde_json::from_str("{}").unwrap();
         assert_eq!(&config.work_dir, ".");
+        assert_eq!(config.scrub.interval, 0);
+        assert_eq!(config.scrub.bandwidth_rate, 0);
 
         let config: FileCacheConfig = serde_json::from_str("{\"work_dir\":\"/tmp\"}").unwrap();
         assert_eq!(&config.work_dir, "/tmp");
@@ -959,7 +1236,16 @@ mod tests {
             .routes
             .get("/api/v1/metrics/blobcache")
             .is_some());
+        assert!(HTTP_ROUTES
+            .routes
+            .get("/api/v1/metrics/blobcache/scrub")
+            .is_some());
+        assert!(HTTP_ROUTES
+            .routes
+            .get("/api/v1/metrics/prometheus")
+            .is_some());
         assert!(HTTP_ROUTES.routes.get("/api/v1/metrics/inflight").is_some());
+        assert!(HTTP_ROUTES.routes.get("/api/v1/metrics/slowio").is_some());
     }
 
     #[test]
@@ -968,6 +1254,37 @@ mod tests {
         assert!(HTTP_ROUTES.routes.get("/api/v2/blobs").is_some());
     }
 
+    #[test]
+    fn test_prometheus_exporter() {
+        use nydus_utils::metrics::BackendMetrics;
+        use std::io::Read;
+
+        let metrics = BackendMetrics::new("test-prometheus-exporter", "localfs");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        spawn_prometheus_thread(listener).unwrap();
+
+        let request = |req: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(req.as_bytes()).unwrap();
+            let mut resp = String::new();
+            stream.read_to_string(&mut resp).unwrap();
+            resp
+        };
+        let resp = request("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+        assert!(resp.contains(
+            r#"nydus_backend_read_requests_total{blob_id="test-prometheus-exporter",backend_type="localfs"} 0"#
+        ));
+        let resp = request("GET /api/v1/daemon HTTP/1.1\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let resp = request("PUT /metrics HTTP/1.1\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+
+        metrics.release().unwrap();
+    }
+
     #[test]
     fn test_kick_api_server() {
         let (to_api, from_route) = channel();
@@ -1034,4 +1351,23 @@ mod tests {
         assert_eq!(config.proxy.ping_url, "");
         assert_eq!(config.proxy.url, "");
     }
+
+    #[test]
+    fn test_mirror_config_debug() {
+        let mut config = RegistryOssConfig::default();
+        let mut mirror = MirrorConfig {
+            host: "http://mirror.example.com:5000".to_string(),
+            ..Default::default()
+        };
+        mirror.headers.insert(
+            "Authorization".to_string(),
+            "Basic dGVzdDpzZWNyZXQ=".to_string(),
+        );
+        config.mirrors.push(mirror);
+
+        let output = format!("{:?}", config);
+        assert!(output.contains("http://mirror.example.com:5000"));
+        assert!(output.contains("Authorization"));
+        assert!(!output.contains("dGVzdDpzZWNyZXQ="));
+    }
 }
@@ -7,7 +7,7 @@ use crate::http::{
     error_response, extract_query_part, parse_body, success_response, translate_status_code,
     ApiError, ApiRequest, ApiResponse, ApiResponsePayload, EndpointHandler, HttpError, HttpResult,
 };
-use dbs_uhttp::{Method, Request, Response};
+use dbs_uhttp::{MediaType, Method, Request, Response};
 
 // Convert an ApiResponse to a HTTP response.
 //
@@ -24,6 +24,8 @@ fn convert_to_response<O: FnOnce(ApiError) -> HttpError>(api_resp: ApiResponse,
                 Events(d) => success_response(Some(d)),
                 BackendMetrics(d) => success_response(Some(d)),
                 BlobcacheMetrics(d) => success_response(Some(d)),
+                BlobcacheScrubReport(d) => success_response(Some(d)),
+                PrometheusMetrics(d) => success_response(Some(d)),
                 _ => panic!("Unexpected response message from API service"),
             }
         }
@@ -127,6 +129,47 @@ impl EndpointHandler for MetricsBlobcacheHandler {
     }
 }
 
+/// Get report of blob cache scrubbing.
+pub struct MetricsBlobcacheScrubHandler {}
+impl EndpointHandler for MetricsBlobcacheScrubHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let id = extract_query_part(req, "id");
+                let r = kicker(ApiRequest::ExportBlobcacheScrubReport(id));
+                Ok(convert_to_response(r, HttpError::BlobcacheScrubReport))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+/// Get all metrics in the Prometheus text exposition format.
+pub struct MetricsPrometheusHandler {}
+impl EndpointHandler for MetricsPrometheusHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let r = kicker(ApiRequest::ExportPrometheusMetrics);
+                Ok(convert_to_response(r, HttpError::PrometheusMetrics))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+
+    fn media_type(&self) -> MediaType {
+        MediaType::PlainText
+    }
+}
+
 /// Mount a filesystem.
 pub struct MountHandler {}
 impl EndpointHandler for MountHandler {
@@ -33,6 +33,7 @@ fn convert_to_response<O: FnOnce(ApiError) -> HttpError>(api_resp: ApiResponse,
                 FsFilesPatterns(d) => success_response(Some(d)),
                 FsBackendInfo(d) => success_response(Some(d)),
                 FsInflightMetrics(d) => success_response(Some(d)),
+                FsSlowIo(d) => success_response(Some(d)),
                 _ => panic!("Unexpected response message from API service"),
             }
         }
@@ -66,7 +67,7 @@ impl EndpointHandler for InfoHandler {
     }
 }
 
-/// Get filesystem backend information.
+/// Get filesystem backend information and update storage backend configuration.
 pub struct FsBackendInfo {}
 impl EndpointHandler for FsBackendInfo {
     fn handle_request(
@@ -84,6 +85,16 @@ impl EndpointHandler for FsBackendInfo {
                 let r = kicker(ApiRequest::ExportFsBackendInfo(mountpoint));
                 Ok(convert_to_response(r, HttpError::FsBackendInfo))
             }
+            (Method::Put, Some(body)) => {
+                let mountpoint = extract_query_part(req, "mountpoint").ok_or_else(|| {
+                    HttpError::QueryString(
+                        "'mountpoint' should be specified in query string".to_string(),
+                    )
+                })?;
+                let cmd = parse_body(body)?;
+                let r = kicker(ApiRequest::UpdateFsBackend(mountpoint, cmd));
+                Ok(convert_to_response(r, HttpError::UpdateFsBackend))
+            }
             _ => Err(HttpError::BadRequest),
         }
     }
@@ -127,6 +138,25 @@ impl EndpointHandler for MetricsFsAccessPatternHandler {
     }
 }
 
+/// Get slowest read requests of a filesystem.
+pub struct MetricsFsSlowIoHandler {}
+impl EndpointHandler for MetricsFsSlowIoHandler {
+    fn handle_request(
+        &self,
+        req: &Request,
+        kicker: &dyn Fn(ApiRequest) -> ApiResponse,
+    ) -> HttpResult {
+        match (req.method(), req.body.as_ref()) {
+            (Method::Get, None) => {
+                let id = extract_query_part(req, "id");
+                let r = kicker(ApiRequest::ExportFsSlowIo(id));
+                Ok(convert_to_response(r, HttpError::SlowIo))
+            }
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
 /// Get filesystem file metrics.
 pub struct MetricsFsFilesHandler {}
 impl EndpointHandler for MetricsFsFilesHandler {
@@ -35,9 +35,12 @@ use nix::unistd::{getegid, geteuid};
 use serde::Deserialize;
 
 use nydus_api::http::BlobPrefetchConfig;
-use nydus_storage::device::{BlobDevice, BlobPrefetchRequest};
-use nydus_storage::factory::FactoryConfig;
+use nydus_storage::device::{
+    BlobChunkInfo, BlobDevice, BlobIoChunk, BlobIoDesc, BlobIoVec, BlobPrefetchRequest,
+};
+use nydus_storage::factory::{BackendConfig, FactoryConfig};
 use nydus_utils::metrics::{self, FopRecorder, StatsFop::*};
+use nydus_utils::slowio::{IoStage, StageTimer};
 
 use crate::metadata::layout::RAFS_ROOT_INODE;
 use crate::metadata::{
@@ -70,6 +73,14 @@ fn default_amplify_io() -> u32 {
     128 * 1024
 }
 
+fn default_slow_io_threshold_ms() -> u64 {
+    100
+}
+
+fn default_slow_io_capacity() -> usize {
+    32
+}
+
 /// Configuration information for filesystem data prefetch.
 #[derive(Clone, Default, Deserialize)]
 pub struct FsPrefetchControl {
@@ -101,6 +112,20 @@ pub struct FsPrefetchControl {
     pub prefetch_all: bool,
 }
 
+/// Configuration information for slow read request tracing.
+#[derive(Clone, Default, Deserialize)]
+pub struct SlowIoConfig {
+    /// Whether to record slow read requests with per-stage latency breakdown.
+    #[serde(default)]
+    pub enable: bool,
+    /// Only read requests taking longer than the threshold are recorded, in unit of milliseconds.
+    #[serde(default = "default_slow_io_threshold_ms")]
+    pub threshold_ms: u64,
+    /// Number of slowest read requests to keep.
+    #[serde(default = "default_slow_io_capacity")]
+    pub capacity: usize,
+}
+
 impl TryFrom<&RafsConfig> for BlobPrefetchConfig {
     type Error = RafsError;
 
@@ -125,13 +150,21 @@ impl TryFrom<&RafsConfig> for BlobPrefetchConfig {
 }
 
 /// Not everything can be safely exported from configuration.
-/// We trim the unneeded info from here.
+/// We trim the unneeded info from here, including from registry mirrors, whose headers may
+/// carry credentials.
 #[macro_export]
 macro_rules! trim_backend_config {
     ($config:expr, $($i:expr),*) => {
         let mut _n :&mut serde_json::Value = &mut $config["device"]["backend"]["config"];
         if let serde_json::Value::Object(ref mut m) = _n {
             $(if m.contains_key($i) { m[$i].take();} )*
+            if let Some(serde_json::Value::Array(mirrors)) = m.get_mut("mirrors") {
+                for mirror in mirrors.iter_mut() {
+                    if let serde_json::Value::Object(ref mut m) = mirror {
+                        $(if m.contains_key($i) { m[$i].take();} )*
+                    }
+                }
+            }
         }
     }
 }
@@ -164,6 +197,9 @@ pub struct RafsConfig {
     // ZERO value means, amplifying user io is not enabled.
     #[serde(default = "default_amplify_io")]
     pub amplify_io: u32,
+    /// Slow read request tracing configuration.
+    #[serde(default)]
+    pub slow_io: SlowIoConfig,
 }
 
 impl RafsConfig {
@@ -260,6 +296,12 @@ impl Rafs {
         rafs.ios.toggle_access_pattern(conf.access_pattern);
         rafs.ios
             .toggle_latest_read_files_recording(conf.latest_read_files);
+        if conf.slow_io.enable {
+            rafs.ios.slow_io().enable(
+                Duration::from_millis(conf.slow_io.threshold_ms),
+                conf.slow_io.capacity,
+            );
+        }
 
         Ok(rafs)
     }
@@ -289,6 +331,22 @@ impl Rafs {
             .update(&storage_conf, &blob_infos, self.fs_prefetch)
             .map_err(RafsError::SwapBackend)?;
         info!("update device is successful");
+        self.record_chunk_infos();
+
+        Ok(())
+    }
+
+    /// Update credentials and connection settings of the storage backend on the fly.
+    pub fn update_backend(&self, backend: BackendConfig) -> RafsResult<()> {
+        if !self.initialized {
+            warn!("Rafs is not yet initialized");
+            return Err(RafsError::Uninitialized);
+        }
+
+        self.device
+            .update_backend(backend)
+            .map_err(RafsError::SwapBackend)?;
+        info!("update storage backend is successful");
 
         Ok(())
     }
@@ -307,6 +365,7 @@ impl Rafs {
             self.device.start_prefetch();
             self.prefetch(r, prefetch_files);
         }
+        self.record_chunk_infos();
         self.initialized = true;
 
         Ok(())
@@ -473,6 +532,73 @@ impl Rafs {
         });
     }
 
+    // Provide chunk information objects with digests to the storage subsystem in background, so
+    // chunks not read yet may be verified by cache scrubbers too.
+    fn record_chunk_infos(&self) {
+        if !self.device.need_chunk_infos() {
+            return;
+        }
+        let sb = self.sb.clone();
+        let device = self.device.clone();
+
+        if sb.meta.is_v5() {
+            let _ = std::thread::spawn(move || {
+                for ino in RAFS_ROOT_INODE..=sb.get_max_ino() {
+                    let inode = match sb.get_inode(ino, false) {
+                        Ok(v) => v,
+                        Err(_) => continue,
+                    };
+                    if !inode.is_reg() || inode.is_empty_size() {
+                        continue;
+                    }
+                    match inode.alloc_bio_vecs(0, inode.size() as usize, false) {
+                        Ok(descs) => device.record_chunk_infos(&descs),
+                        Err(e) => debug!("failed to get chunks of inode {}, {}", ino, e),
+                    }
+                }
+            });
+        } else if sb.meta.is_v6() {
+            // Rafs v6 inodes address chunks by index, digests are only kept in the chunk table.
+            if sb.meta.chunk_table_size == 0 {
+                warn!("no chunk table in bootstrap, cached chunks can't be verified by digest");
+                return;
+            }
+            let _ = std::thread::spawn(move || Self::record_chunk_table(&sb, &device));
+        }
+    }
+
+    fn record_chunk_table(sb: &RafsSuper, device: &BlobDevice) {
+        let blobs = sb.superblock.get_blob_infos();
+        let mut io_vec = BlobIoVec::new();
+        let result = sb.walk_chunk_table(&mut |chunk| {
+            let blob = match blobs.get(chunk.blob_index() as usize) {
+                Some(v) if !chunk.is_hole() => v.clone(),
+                _ => return Ok(()),
+            };
+            if !io_vec.bi_vec.is_empty()
+                && (!io_vec.is_target_blob(blob.blob_index()) || io_vec.bi_vec.len() >= 1024)
+            {
+                device.record_chunk_infos(&[std::mem::take(&mut io_vec)]);
+            }
+            let size = chunk.uncompress_size() as usize;
+            io_vec.bi_vec.push(BlobIoDesc::new(
+                blob,
+                BlobIoChunk::from(chunk),
+                0,
+                size,
+                false,
+            ));
+            io_vec.bi_size += size;
+            Ok(())
+        });
+        if let Err(e) = result {
+            warn!("failed to load chunk digests from chunk table, {}", e);
+        }
+        if !io_vec.bi_vec.is_empty() {
+            device.record_chunk_infos(&[io_vec]);
+        }
+    }
+
     /// for blobfs
     pub fn fetch_range_synchronous(&self, prefetches: &[BlobPrefetchRequest]) -> Result<()> {
         self.device.fetch_range_synchronous(prefetches)
@@ -661,6 +787,8 @@ impl FileSystem for Rafs {
             return Err(einval!("offset + size wraps around."));
         }
 
+        let trace = self.ios.slow_io().begin();
+        let map_timer = StageTimer::new(IoStage::MapChunks);
         let inode = self.sb.get_inode(ino, false)?;
         let inode_size = inode.size();
         let mut recorder = FopRecorder::settle(Read, ino, &self.ios);
@@ -673,18 +801,30 @@ impl FileSystem for Rafs {
         let real_size = cmp::min(size as u64, inode_size - offset);
         let mut result = 0;
         let mut descs = inode.alloc_bio_vecs(offset, real_size as usize, true)?;
-        debug_assert!(!descs.is_empty() && !descs[0].bi_vec.is_empty());
+        debug_assert!(!descs.is_empty());
+        drop(map_timer);
 
         // Try to amplify user io for Rafs v5, to improve performance.
         if self.sb.meta.is_v5() && size < self.amplify_io {
             let all_chunks_ready = self.device.all_chunks_ready(&descs);
-            if !all_chunks_ready {
-                let chunk_size = self.metadata().chunk_size as u64;
-                let next_chunk_base = (offset + (size as u64) + chunk_size) & !chunk_size;
-                let window_base = std::cmp::min(next_chunk_base, inode_size);
-                let actual_size = window_base - (offset & !chunk_size);
+            let first_chunk = descs.first().and_then(|d| d.bi_vec.first());
+            let last_chunk = descs.last().and_then(|d| d.bi_vec.last());
+            if let (false, Some(first_chunk), Some(last_chunk)) =
+                (all_chunks_ready, first_chunk, last_chunk)
+            {
+                // Chunks may be of variable size if the image is built with content defined
+                // chunking, so the window starts right after the last chunk of user IO instead of
+                // the next chunk size aligned offset.
+                let first_chunk = first_chunk.chunkinfo.as_v5()?;
+                let last_chunk = last_chunk.chunkinfo.as_v5()?;
+                let window_base = std::cmp::min(
+                    last_chunk.file_offset() + last_chunk.uncompress_size() as u64,
+                    inode_size,
+                );
+                let actual_size = window_base - first_chunk.file_offset();
                 if actual_size < self.amplify_io as u64 {
                     let window_size = self.amplify_io as u64 - actual_size;
+                    let _timer = StageTimer::new(IoStage::MapChunks);
                     self.sb.amplify_io(
                         self.amplify_io,
                         &mut descs,
@@ -700,7 +840,6 @@ impl FileSystem for Rafs {
 
         for desc in descs.iter_mut() {
             debug_assert!(desc.validate());
-            debug_assert!(!desc.bi_vec.is_empty());
             debug_assert!(desc.bi_size != 0);
 
             // Avoid copying `desc`
@@ -712,6 +851,14 @@ impl FileSystem for Rafs {
             }
         }
         self.ios.latency_end(&start, Read);
+        if let Some(trace) = trace {
+            self.ios.slow_io().end(trace, ino, offset, size, || {
+                self.sb
+                    .path_from_ino(ino)
+                    .map(|p| p.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            });
+        }
 
         Ok(result)
     }
@@ -1080,4 +1227,15 @@ pub(crate) mod tests {
         config.fs_prefetch.prefetch_all = true;
         assert!(BlobPrefetchConfig::try_from(&config).is_ok());
     }
+
+    #[test]
+    fn test_slow_io_config() {
+        let config = RafsConfig::new();
+        assert!(!config.slow_io.enable);
+
+        let config: SlowIoConfig = serde_json::from_str(r#"{"enable":true}"#).unwrap();
+        assert!(config.enable);
+        assert_eq!(config.threshold_ms, 100);
+        assert_eq!(config.capacity, 32);
+    }
 }
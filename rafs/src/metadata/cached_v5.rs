@@ -361,7 +361,8 @@ impl RafsInode for CachedInodeV5 {
         }
         if self.is_reg() {
             let chunks = (self.i_size + chunk_size - 1) / chunk_size;
-            if !self.has_hole() && chunks != self.i_data.len() as u64 {
+            if !self.has_hole() && !self.has_variable_chunk() && chunks != self.i_data.len() as u64
+            {
                 return Err(einval!("invalid chunk count"));
             }
         } else if self.is_dir() {
@@ -637,6 +638,10 @@ impl RafsV5InodeOps for CachedInodeV5 {
         self.i_flags.contains(RafsV5InodeFlags::HAS_HOLE)
     }
 
+    fn has_variable_chunk(&self) -> bool {
+        self.i_flags.contains(RafsV5InodeFlags::VARIABLE_CHUNK)
+    }
+
     fn cast_ondisk(&self) -> Result<RafsV5Inode> {
         let i_symlink_size = if self.is_symlink() {
             self.get_symlink()?.byte_size() as u16
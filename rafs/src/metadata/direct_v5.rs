@@ -540,7 +540,10 @@ impl RafsInode for OndiskInodeWrapper {
                 return Err(std::io::Error::from_raw_os_error(libc::EOPNOTSUPP));
             }
             let chunks = (inode.i_size + chunk_size - 1) / chunk_size;
-            if !inode.has_hole() && chunks != inode.i_child_count as u64 {
+            if !inode.has_hole()
+                && !inode.has_variable_chunk()
+                && chunks != inode.i_child_count as u64
+            {
                 return Err(einval!(format!(
                     "invalid chunk count, ino {}, expected {}, actual {}",
                     inode.i_ino, chunks, inode.i_child_count,
@@ -873,6 +876,7 @@ impl RafsV5InodeOps for OndiskInodeWrapper {
     }
 
     impl_inode_wrapper!(has_hole, bool);
+    impl_inode_wrapper!(has_variable_chunk, bool);
 }
 
 pub struct DirectChunkInfoV5 {
@@ -43,7 +43,7 @@ use crate::metadata::{
             RafsV6InodeCompact, RafsV6InodeExtended, RafsV6OndiskInode, RafsV6XattrEntry,
             RafsV6XattrIbodyHeader, EROFS_BLOCK_SIZE, EROFS_INODE_CHUNK_BASED,
             EROFS_INODE_FLAT_INLINE, EROFS_INODE_FLAT_PLAIN, EROFS_INODE_SLOT_SIZE,
-            EROFS_I_DATALAYOUT_BITS, EROFS_I_VERSION_BIT, EROFS_I_VERSION_BITS,
+            EROFS_I_DATALAYOUT_BITS, EROFS_I_VERSION_BIT, EROFS_I_VERSION_BITS, EROFS_NULL_ADDR,
         },
         XattrName, XattrValue,
     },
@@ -314,12 +314,35 @@ impl DirectSuperBlockV6 {
             let mut v6_chunk = RafsV6InodeChunkAddr::new();
             v6_chunk.set_blob_index((chunk.blob_index() + 1) as u8);
             v6_chunk.set_blob_comp_index(chunk.id());
-            v6_chunk.set_block_addr((chunk.uncompress_offset() / EROFS_BLOCK_SIZE) as u32);
+            if chunk.is_hole() {
+                v6_chunk.set_block_addr(EROFS_NULL_ADDR);
+            } else {
+                v6_chunk.set_block_addr((chunk.uncompress_offset() / EROFS_BLOCK_SIZE) as u32);
+            }
             chunk_dict.insert(v6_chunk, chunk);
         }
 
         Ok(chunk_dict)
     }
+
+    fn _get_chunk_info(&self, idx: usize) -> Result<Arc<DirectChunkInfoV6>> {
+        let state = self.state.load();
+        let unit_size = size_of::<RafsV5ChunkInfo>();
+
+        let offset = state.meta.chunk_table_offset as usize + idx * unit_size;
+        if offset + unit_size
+            > (state.meta.chunk_table_offset + state.meta.chunk_table_size) as usize
+        {
+            return Err(einval!(format!(
+                "invalid chunk offset {} chunk table {} {}",
+                offset, state.meta.chunk_table_offset, state.meta.chunk_table_size
+            )));
+        }
+
+        let chunk = state.cast_to_ref::<RafsV5ChunkInfo>(state.base, offset)?;
+        let wrapper = DirectChunkInfoV6::new(chunk, self.clone(), offset);
+        Ok(Arc::new(wrapper))
+    }
 }
 
 impl RafsSuperInodes for DirectSuperBlockV6 {
@@ -369,22 +392,13 @@ impl RafsSuperBlock for DirectSuperBlockV6 {
     }
 
     fn get_chunk_info(&self, idx: usize) -> Result<Arc<dyn BlobChunkInfo>> {
-        let state = self.state.load();
-        let unit_size = size_of::<RafsV5ChunkInfo>();
-
-        let offset = state.meta.chunk_table_offset as usize + idx * unit_size;
-        if offset + unit_size
-            > (state.meta.chunk_table_offset + state.meta.chunk_table_size) as usize
-        {
-            return Err(einval!(format!(
-                "invalid chunk offset {} chunk table {} {}",
-                offset, state.meta.chunk_table_offset, state.meta.chunk_table_size
-            )));
-        }
+        self._get_chunk_info(idx)
+            .map(|v| v as Arc<dyn BlobChunkInfo>)
+    }
 
-        let chunk = state.cast_to_ref::<RafsV5ChunkInfo>(state.base, offset)?;
-        let wrapper = DirectChunkInfoV6::new(chunk, self.clone(), offset);
-        Ok(Arc::new(wrapper) as Arc<dyn BlobChunkInfo>)
+    fn get_chunk_info_v5(&self, idx: usize) -> Result<Arc<dyn BlobV5ChunkInfo>> {
+        self._get_chunk_info(idx)
+            .map(|v| v as Arc<dyn BlobV5ChunkInfo>)
     }
 }
 
@@ -1218,40 +1232,40 @@ impl RafsInode for OndiskInodeWrapper {
 
         let content_offset = (offset % chunk_size as u64) as u32;
         let mut left = std::cmp::min(self.size(), size as u64) as u32;
-        let mut content_len = std::cmp::min(chunk_size - content_offset, left);
-
-        // Safe to unwrap because chunks is not empty to reach here.
-        let first_chunk_addr = chunks.first().unwrap();
-        let desc = self.make_chunk_io(first_chunk_addr, content_offset, content_len, user_io);
 
         let mut descs = BlobIoVec::new();
-        descs.bi_vec.push(desc);
-        descs.bi_size += content_len as usize;
-        left -= content_len;
-
-        if left != 0 {
-            // Handle the rest of chunks since they shares the same content length = 0.
-            for c in chunks.iter().skip(1) {
-                content_len = std::cmp::min(chunk_size, left);
-                let desc = self.make_chunk_io(c, 0, content_len, user_io);
-
-                if c.blob_index() as u32 != descs.bi_vec[0].blob.blob_index() {
+        for (idx, c) in chunks.iter().enumerate() {
+            // Only the first chunk may start in the middle.
+            let content_offset = if idx == 0 { content_offset } else { 0 };
+            let content_len = std::cmp::min(chunk_size - content_offset, left);
+
+            if c.is_hole() {
+                // File holes are served with zeros, no blob IO is needed.
+                if !descs.bi_vec.is_empty() {
+                    vec.push(descs);
+                    descs = BlobIoVec::new();
+                }
+            } else {
+                let desc = self.make_chunk_io(c, content_offset, content_len, user_io);
+                if descs.is_hole()
+                    || (!descs.bi_vec.is_empty()
+                        && desc.blob.blob_index() != descs.bi_vec[0].blob.blob_index())
+                {
                     vec.push(descs);
                     descs = BlobIoVec::new();
                 }
-
-                // TODO: change type of bi_size to u32
                 descs.bi_vec.push(desc);
-                descs.bi_size += content_len as usize;
-                left -= content_len;
+            }
 
-                if left == 0 {
-                    break;
-                }
+            // TODO: change type of bi_size to u32
+            descs.bi_size += content_len as usize;
+            left -= content_len;
+            if left == 0 {
+                break;
             }
         }
 
-        if !descs.bi_vec.is_empty() {
+        if descs.bi_size != 0 {
             vec.push(descs)
         }
 
@@ -78,6 +78,9 @@ pub(crate) trait RafsV5InodeOps {
     /// Check whether the inode has hole chunk.
     fn has_hole(&self) -> bool;
 
+    /// Check whether the inode has chunks of variable sizes.
+    fn has_variable_chunk(&self) -> bool;
+
     /// Convert to the on disk data format.
     fn cast_ondisk(&self) -> Result<RafsV5Inode>;
 }
@@ -897,6 +900,8 @@ bitflags! {
         const XATTR = 0x0000_0004;
         /// Inode chunks has holes.
         const HAS_HOLE = 0x0000_0008;
+        /// Inode chunks have variable sizes, generated by content defined chunking.
+        const VARIABLE_CHUNK = 0x0000_0010;
    }
 }
 
@@ -1021,6 +1026,12 @@ impl RafsV5Inode {
         self.i_flags.contains(RafsV5InodeFlags::HAS_HOLE)
     }
 
+    /// Check whether the inode has chunks of variable sizes.
+    #[inline]
+    pub fn has_variable_chunk(&self) -> bool {
+        self.i_flags.contains(RafsV5InodeFlags::VARIABLE_CHUNK)
+    }
+
     /// Load an inode from a reader.
     pub fn load(&mut self, r: &mut RafsIoReader) -> Result<()> {
         r.read_exact(self.as_mut())
@@ -1222,13 +1233,18 @@ pub(crate) fn rafsv5_alloc_bio_vecs<I: RafsInode + RafsV5InodeChunkOps + RafsV5I
     let end = offset
         .checked_add(size as u64)
         .ok_or_else(|| einval!("invalid read size"))?;
-    let (index_start, index_end) = calculate_bio_chunk_index(
-        offset,
-        end,
-        inode.get_chunk_size() as u64,
-        inode.get_child_count(),
-        inode.has_hole(),
-    );
+    // Hole chunks may be omitted from the chunk array, then chunk index can't be calculated
+    // from the file offset.
+    let chunk_size = inode.get_chunk_size() as u64;
+    let sparse = inode.has_hole()
+        && inode.get_child_count() as u64 != (inode.size() + chunk_size - 1) / chunk_size;
+    let (index_start, index_end) = if inode.has_variable_chunk() {
+        // Chunk index can't be calculated from the file offset for variable sized chunks.
+        let index_start = search_chunk_index(inode, offset)?;
+        (index_start, inode.get_child_count())
+    } else {
+        calculate_bio_chunk_index(offset, end, chunk_size, inode.get_child_count(), sparse)
+    };
     trace!(
         "alloc bio desc offset {} size {} i_size {} index_start {} index_end {} i_child_count {}",
         offset,
@@ -1238,33 +1254,75 @@ pub(crate) fn rafsv5_alloc_bio_vecs<I: RafsInode + RafsV5InodeChunkOps + RafsV5I
         index_end,
         inode.get_child_count()
     );
-    if size == 0 || index_start >= inode.get_chunk_count() {
+    if size == 0 || (!inode.has_hole() && index_start >= inode.get_chunk_count()) {
         return Ok(vec![]);
     }
 
     let mut descs = Vec::with_capacity(4);
     let mut desc = BlobIoVec::new();
-    let chunk = inode.get_chunk_info_v5(index_start)?;
-    let blob = inode.get_blob_by_index(chunk.blob_index())?;
-    if !add_chunk_to_bio_desc(&mut desc, offset, end, chunk, blob, user_io) {
-        return Err(einval!("failed to create blob io vector"));
-    }
-    for idx in index_start + 1..index_end {
+    let mut pos = offset;
+    for idx in index_start..index_end {
         let chunk = inode.get_chunk_info_v5(idx)?;
+        let chunk_end = chunk.file_offset() + chunk.uncompress_size() as u64;
+        // The chunk is ahead of the start of the range.
+        if pos >= chunk_end {
+            continue;
+        }
+        // The chunk is passing the end of the range.
+        if end <= chunk.file_offset() {
+            break;
+        }
+
+        // Fill the gap between chunks with zeros.
+        if chunk.file_offset() > pos {
+            add_hole_to_bio_descs(&mut descs, &mut desc, chunk.file_offset() - pos);
+            pos = chunk.file_offset();
+        }
+        if chunk.is_hole() {
+            let hole_end = cmp::min(chunk_end, end);
+            add_hole_to_bio_descs(&mut descs, &mut desc, hole_end - pos);
+            pos = hole_end;
+            continue;
+        }
+
         let blob = inode.get_blob_by_index(chunk.blob_index())?;
-        if blob.blob_index() != desc.bi_vec[0].blob.blob_index() {
+        if desc.is_hole()
+            || (!desc.bi_vec.is_empty() && blob.blob_index() != desc.bi_vec[0].blob.blob_index())
+        {
             descs.push(desc);
             desc = BlobIoVec::new();
         }
         if !add_chunk_to_bio_desc(&mut desc, offset, end, chunk, blob, user_io) {
             return Err(einval!("failed to create blob io vector"));
         }
+        pos = cmp::min(chunk_end, end);
+    }
+    // Holes at the end of a sparse file may have no corresponding chunks.
+    if inode.has_hole() && pos < end {
+        let hole_end = cmp::min(end, inode.size());
+        add_hole_to_bio_descs(&mut descs, &mut desc, hole_end.saturating_sub(pos));
+    }
+    if desc.bi_size != 0 {
+        descs.push(desc);
+    }
+    if descs.is_empty() {
+        return Err(einval!("failed to create blob io vector"));
     }
-    descs.push(desc);
 
     Ok(descs)
 }
 
+// Append a file hole of `size` bytes, merging it with the current bio desc if it's also a hole.
+fn add_hole_to_bio_descs(descs: &mut Vec<BlobIoVec>, desc: &mut BlobIoVec, size: u64) {
+    if size == 0 {
+        return;
+    }
+    if !desc.bi_vec.is_empty() {
+        descs.push(std::mem::take(desc));
+    }
+    desc.bi_size += size as usize;
+}
+
 /// Add a new bio covering the IO range into the provided bio desc.
 ///
 /// Returns true if caller should continue checking more chunks.
@@ -1324,7 +1382,7 @@ fn add_chunk_to_bio_desc(
 /// - end: IO end to the file start, exclusive.
 /// - chunk_size: chunk size.
 /// - chunk_cnt: maximum number of chunks
-/// - has_hole: whether a file has holes in it.
+/// - has_hole: whether some chunks of the file have been omitted for holes.
 fn calculate_bio_chunk_index(
     offset: u64,
     end: u64,
@@ -1348,6 +1406,26 @@ fn calculate_bio_chunk_index(
     (index_start, index_end)
 }
 
+/// Search for index of the first chunk which ends after `offset` by binary search.
+///
+/// Chunks of a file are sorted by file offset, but may have different sizes.
+fn search_chunk_index<I: RafsInode + RafsV5InodeChunkOps>(inode: &I, offset: u64) -> Result<u32> {
+    let mut left = 0;
+    let mut right = inode.get_child_count();
+
+    while left < right {
+        let mid = left + (right - left) / 2;
+        let chunk = inode.get_chunk_info_v5(mid)?;
+        if chunk.file_offset() + chunk.uncompress_size() as u64 <= offset {
+            left = mid + 1;
+        } else {
+            right = mid;
+        }
+    }
+
+    Ok(left)
+}
+
 pub(crate) fn rafsv5_align(size: usize) -> usize {
     if size & (RAFSV5_ALIGNMENT - 1) == 0 {
         size
@@ -1726,6 +1804,77 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_rafsv5_alloc_bio_vecs_with_hole() {
+        use crate::mock::{MockChunkInfo, MockInode, CHUNK_SIZE};
+
+        let size = CHUNK_SIZE as u64;
+        // Chunk 0 holds data, chunk 1 is a hole chunk, chunk 2 is omitted and chunk 3 holds data,
+        // and the file ends with a hole without a chunk.
+        let chunks = vec![
+            Arc::new(MockChunkInfo::mock(0, 0, 100, 0, CHUNK_SIZE)),
+            Arc::new(MockChunkInfo::mock_hole(size, CHUNK_SIZE)),
+            Arc::new(MockChunkInfo::mock(size * 3, 100, 100, size, CHUNK_SIZE)),
+        ];
+        let inode = MockInode::mock_sparse(1, size * 5, chunks);
+
+        let descs = rafsv5_alloc_bio_vecs(&inode, 0, size as usize * 5, true).unwrap();
+        assert_eq!(descs.len(), 4);
+        assert_eq!(descs[0].bi_vec.len(), 1);
+        assert_eq!(descs[0].bi_size, CHUNK_SIZE as usize);
+        assert!(descs[1].is_hole());
+        assert_eq!(descs[1].bi_size, CHUNK_SIZE as usize * 2);
+        assert_eq!(descs[2].bi_vec.len(), 1);
+        assert_eq!(descs[2].bi_size, CHUNK_SIZE as usize);
+        assert!(descs[3].is_hole());
+        assert_eq!(descs[3].bi_size, CHUNK_SIZE as usize);
+
+        let descs = rafsv5_alloc_bio_vecs(&inode, size + 10, size as usize, true).unwrap();
+        assert_eq!(descs.len(), 1);
+        assert!(descs[0].is_hole());
+        assert_eq!(descs[0].bi_size, CHUNK_SIZE as usize);
+
+        let descs = rafsv5_alloc_bio_vecs(&inode, size * 3 - 10, 20, true).unwrap();
+        assert_eq!(descs.len(), 2);
+        assert!(descs[0].is_hole());
+        assert_eq!(descs[0].bi_size, 10);
+        assert_eq!(descs[1].bi_vec[0].offset, 0);
+        assert_eq!(descs[1].bi_vec[0].size, 10);
+    }
+
+    #[test]
+    fn test_rafsv5_alloc_bio_vecs_with_variable_chunk() {
+        use crate::mock::{MockChunkInfo, MockInode};
+
+        // Chunks of 0x1000, 0x3000 and 0x2000 bytes.
+        let chunks = vec![
+            Arc::new(MockChunkInfo::mock(0, 0, 0x100, 0, 0x1000)),
+            Arc::new(MockChunkInfo::mock(0x1000, 0x100, 0x100, 0x1000, 0x3000)),
+            Arc::new(MockChunkInfo::mock(0x4000, 0x200, 0x100, 0x4000, 0x2000)),
+        ];
+        let inode = MockInode::mock_variable_chunk(1, 0x6000, chunks);
+
+        assert_eq!(search_chunk_index(&inode, 0).unwrap(), 0);
+        assert_eq!(search_chunk_index(&inode, 0xfff).unwrap(), 0);
+        assert_eq!(search_chunk_index(&inode, 0x1000).unwrap(), 1);
+        assert_eq!(search_chunk_index(&inode, 0x3fff).unwrap(), 1);
+        assert_eq!(search_chunk_index(&inode, 0x4000).unwrap(), 2);
+        assert_eq!(search_chunk_index(&inode, 0x6000).unwrap(), 3);
+
+        let descs = rafsv5_alloc_bio_vecs(&inode, 0, 0x6000, true).unwrap();
+        assert_eq!(descs.len(), 1);
+        assert_eq!(descs[0].bi_vec.len(), 3);
+        assert_eq!(descs[0].bi_size, 0x6000);
+
+        let descs = rafsv5_alloc_bio_vecs(&inode, 0x2000, 0x2800, true).unwrap();
+        assert_eq!(descs.len(), 1);
+        assert_eq!(descs[0].bi_vec.len(), 2);
+        assert_eq!(descs[0].bi_vec[0].offset, 0x1000);
+        assert_eq!(descs[0].bi_vec[0].size, 0x2000);
+        assert_eq!(descs[0].bi_vec[1].offset, 0);
+        assert_eq!(descs[0].bi_vec[1].size, 0x800);
+    }
+
     #[test]
     fn test_rafsv5_align() {
         assert_eq!(rafsv5_align(0), 0);
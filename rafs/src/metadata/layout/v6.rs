@@ -32,6 +32,8 @@ pub const EROFS_INODE_FLAT_PLAIN: u16 = 0;
 pub const EROFS_INODE_FLAT_INLINE: u16 = 2;
 /// EROFS chunked inode.
 pub const EROFS_INODE_CHUNK_BASED: u16 = 4;
+/// EROFS null block address, which marks a hole chunk.
+pub const EROFS_NULL_ADDR: u32 = u32::MAX;
 /// EROFS device table offset.
 pub const EROFS_DEVTABLE_OFFSET: u16 =
     EROFS_SUPER_OFFSET + EROFS_SUPER_BLOCK_SIZE + EROFS_EXT_SUPER_BLOCK_SIZE;
@@ -1008,6 +1010,11 @@ impl RafsV6InodeChunkAddr {
         self.c_blk_addr = addr.to_le();
     }
 
+    /// Check whether the chunk is a hole chunk without backing blob data.
+    pub fn is_hole(&self) -> bool {
+        self.block_addr() == EROFS_NULL_ADDR
+    }
+
     /// Load a `RafsV6InodeChunkAddr` from a reader.
     pub fn load(&mut self, r: &mut RafsIoReader) -> Result<()> {
         r.read_exact(self.as_mut())
@@ -1212,7 +1219,7 @@ impl RafsV6Blob {
             u64::from_le(self.compressed_size),
             u32::from_le(self.chunk_size),
             u32::from_le(self.chunk_count),
-            BlobFeatures::empty(),
+            BlobFeatures::V6_NO_CHUNK_DIGEST,
         );
 
         let comp = compress::Algorithm::try_from(u32::from_le(self.compression_algo))
@@ -1917,6 +1924,17 @@ mod tests {
         assert_eq!(chunk2.blob_index(), 3);
         assert_eq!(chunk2.blob_comp_index(), 0x123456);
         assert_eq!(chunk2.block_addr(), 0xa5a53412);
+        assert!(!chunk2.is_hole());
+    }
+
+    #[test]
+    fn test_rafs_v6_hole_chunk_addr() {
+        let mut chunk = RafsV6InodeChunkAddr::new();
+        chunk.set_blob_index(1);
+        assert!(!chunk.is_hole());
+        chunk.set_block_addr(EROFS_NULL_ADDR);
+        assert!(chunk.is_hole());
+        assert_eq!(chunk.blob_index(), 1);
     }
 
     #[test]
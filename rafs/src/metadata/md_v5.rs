@@ -139,6 +139,10 @@ impl RafsSuper {
 
         // caller should ensure that `window_base` won't overlap last chunk of user IO.
         for d in more {
+            // Stop at file holes, which have no backing chunks.
+            if d.bi_vec.is_empty() {
+                break;
+            }
             let head_ck = &d.bi_vec[0].chunkinfo.as_base();
 
             if last_chunk.compress_offset() + last_chunk.compress_size() as u64
@@ -180,7 +184,7 @@ impl RafsSuper {
             let size = inode_size - window_base;
             let sz = std::cmp::min(size, window_size);
             let amplified_io_vec = inode.alloc_bio_vecs(window_base, sz as usize, false)?;
-            debug_assert!(!amplified_io_vec.is_empty() && !amplified_io_vec[0].bi_vec.is_empty());
+            debug_assert!(!amplified_io_vec.is_empty());
             // caller should ensure that `window_base` won't overlap last chunk of user IO.
             Self::merge_chunks_io(last_desc, &amplified_io_vec);
             window_size -= sz;
@@ -206,9 +210,7 @@ impl RafsSuper {
 
                     let sz = std::cmp::min(window_size, next_size);
                     let amplified_io_vec = ni.alloc_bio_vecs(0, sz as usize, false)?;
-                    debug_assert!(
-                        !amplified_io_vec.is_empty() && !amplified_io_vec[0].bi_vec.is_empty()
-                    );
+                    debug_assert!(!amplified_io_vec.is_empty());
                     if last_desc.has_same_blob(&amplified_io_vec[0]) {
                         // caller should ensure that `window_base` won't overlap last chunk
                         Self::merge_chunks_io(last_desc, &amplified_io_vec);
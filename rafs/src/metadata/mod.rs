@@ -11,6 +11,7 @@ use std::ffi::{OsStr, OsString};
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
 use std::fs::OpenOptions;
 use std::io::{Error, Result};
+use std::mem::size_of;
 use std::os::unix::ffi::OsStrExt;
 use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
@@ -25,8 +26,9 @@ use nydus_utils::compress;
 use nydus_utils::digest::{self, RafsDigest};
 use serde::Serialize;
 use serde_with::{serde_as, DisplayFromStr};
-use storage::device::{BlobChunkInfo, BlobInfo, BlobIoVec};
+use storage::device::{v5::BlobV5ChunkInfo, BlobChunkInfo, BlobInfo, BlobIoVec};
 
+use self::layout::v5::RafsV5ChunkInfo;
 use self::layout::{XattrName, XattrValue, RAFS_SUPER_VERSION_V5, RAFS_SUPER_VERSION_V6};
 use self::noop::NoopSuperBlock;
 use crate::fs::{RafsConfig, RAFS_DEFAULT_ATTR_TIMEOUT, RAFS_DEFAULT_ENTRY_TIMEOUT};
@@ -95,6 +97,11 @@ pub trait RafsSuperBlock: RafsSuperInodes + Send + Sync {
     fn get_chunk_info(&self, _idx: usize) -> Result<Arc<dyn BlobChunkInfo>> {
         unimplemented!()
     }
+
+    /// Get a chunk info with chunk digest, from the chunk table of Rafs v6 images.
+    fn get_chunk_info_v5(&self, _idx: usize) -> Result<Arc<dyn BlobV5ChunkInfo>> {
+        unimplemented!()
+    }
 }
 
 pub enum PostWalkAction {
@@ -689,6 +696,10 @@ impl RafsSuper {
 
         let descs = inode.alloc_bio_vecs(0, inode.size() as usize, false)?;
         for desc in descs {
+            // There's nothing to prefetch for file holes.
+            if desc.is_hole() {
+                continue;
+            }
             // Flush the pending prefetch if the next desc target a different blob.
             if !head_desc.has_same_blob(&desc) {
                 prefetcher(head_desc, true);
@@ -784,6 +795,26 @@ impl RafsSuper {
         }
         Ok(())
     }
+
+    /// Walkthrough the chunk table of Rafs v6 images, calling cb for each chunk information
+    /// object with chunk digest.
+    pub fn walk_chunk_table(
+        &self,
+        cb: &mut dyn FnMut(Arc<dyn BlobV5ChunkInfo>) -> Result<()>,
+    ) -> Result<()> {
+        let size = self.meta.chunk_table_size as usize;
+        let unit_size = size_of::<RafsV5ChunkInfo>();
+        if !self.meta.is_v6() || size % unit_size != 0 {
+            return Err(einval!(format!(
+                "invalid rafs v6 chunk table size {}",
+                size
+            )));
+        }
+        for idx in 0..size / unit_size {
+            cb(self.superblock.get_chunk_info_v5(idx)?)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
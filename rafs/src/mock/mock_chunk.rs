@@ -47,6 +47,15 @@ impl MockChunkInfo {
             ..Default::default()
         }
     }
+
+    pub fn mock_hole(file_offset: u64, size: u32) -> Self {
+        MockChunkInfo {
+            c_file_offset: file_offset,
+            c_decompress_size: size,
+            c_flags: BlobChunkFlags::HOLECHUNK,
+            ..Default::default()
+        }
+    }
 }
 
 impl BlobChunkInfo for MockChunkInfo {
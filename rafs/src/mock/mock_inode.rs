@@ -72,6 +72,18 @@ impl MockInode {
             ..Default::default()
         }
     }
+
+    pub fn mock_sparse(ino: Inode, size: u64, chunks: Vec<Arc<MockChunkInfo>>) -> Self {
+        let mut inode = Self::mock(ino, size, chunks);
+        inode.i_flags |= RafsV5InodeFlags::HAS_HOLE;
+        inode
+    }
+
+    pub fn mock_variable_chunk(ino: Inode, size: u64, chunks: Vec<Arc<MockChunkInfo>>) -> Self {
+        let mut inode = Self::mock(ino, size, chunks);
+        inode.i_flags |= RafsV5InodeFlags::VARIABLE_CHUNK;
+        inode
+    }
 }
 
 impl RafsInode for MockInode {
@@ -268,7 +280,11 @@ impl RafsV5InodeOps for MockInode {
     }
 
     fn has_hole(&self) -> bool {
-        false
+        self.i_flags.contains(RafsV5InodeFlags::HAS_HOLE)
+    }
+
+    fn has_variable_chunk(&self) -> bool {
+        self.i_flags.contains(RafsV5InodeFlags::VARIABLE_CHUNK)
     }
 
     fn cast_ondisk(&self) -> Result<RafsV5Inode> {
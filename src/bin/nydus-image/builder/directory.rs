@@ -18,8 +18,8 @@ use crate::core::context::{
 use crate::core::node::{Node, Overlay};
 use crate::core::tree::Tree;
 
-const TAR_BLOB_NAME: &str = "image.blob";
-const TAR_BOOTSTRAP_NAME: &str = "image.boot";
+pub(crate) const TAR_BLOB_NAME: &str = "image.blob";
+pub(crate) const TAR_BOOTSTRAP_NAME: &str = "image.boot";
 
 struct FilesystemTreeBuilder {}
 
@@ -198,3 +198,179 @@ impl Builder for DirectoryBuilder {
         BuildOutput::new(blob_mgr, bootstrap_mgr)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{Read, Seek, SeekFrom};
+    use std::path::Path;
+
+    use nydus_utils::{compress, digest};
+    use rafs::metadata::{RafsInode, RafsMode, RafsSuper};
+    use storage::device::BlobChunkInfo;
+    use storage::factory::BackendConfig;
+    use tar::Archive;
+    use vmm_sys_util::tempdir::TempDir;
+
+    use super::*;
+    use crate::core::chunker::FastCdc;
+    use crate::core::context::{ArtifactStorage, SourceType};
+    use crate::core::node::WhiteoutSpec;
+    use crate::trace::{EventTracerClass, TimingTracerClass, TraceClass};
+    use crate::unpack::OCIUnpacker;
+
+    fn create_file(path: &Path, data: &[u8]) {
+        File::create(path).unwrap().write_all(data).unwrap();
+    }
+
+    fn create_source(path: &Path) {
+        create_file(&path.join("empty"), b"");
+        create_file(&path.join("small"), b"small file");
+        let data = (0..0x340000u32)
+            .map(|v| (v.wrapping_mul(2654435761) >> 13) as u8)
+            .collect::<Vec<u8>>();
+        create_file(&path.join("large"), &data);
+        fs::create_dir(path.join("sub")).unwrap();
+        // Duplicated chunks are deduplicated in the same order by both paths.
+        create_file(&path.join("sub/large-copy"), &data[0x100000..]);
+        // Zero chunks are holes.
+        let mut file = File::create(path.join("sub/sparse")).unwrap();
+        file.write_all(&data[..0x1000]).unwrap();
+        file.set_len(0x500000).unwrap();
+        create_file(&path.join("sub/compressible"), &[0x5au8; 0x180000]);
+    }
+
+    fn build(
+        source: &Path,
+        output: &Path,
+        version: RafsVersion,
+        chunker: Option<FastCdc>,
+        workers: usize,
+    ) -> (Vec<u8>, Vec<u8>) {
+        let blob_path = output.join(format!("blob-{}", workers));
+        let bootstrap_path = output.join(format!("bootstrap-{}", workers));
+        let mut ctx = BuildContext::new(
+            String::new(),
+            false,
+            0,
+            compress::Algorithm::Lz4Block,
+            digest::Algorithm::Blake3,
+            false,
+            WhiteoutSpec::Oci,
+            SourceType::Directory,
+            source.to_path_buf(),
+            Default::default(),
+            Some(ArtifactStorage::SingleFile(blob_path.clone())),
+            false,
+        );
+        ctx.set_fs_version(version);
+        ctx.set_chunker(chunker);
+        ctx.set_compress_workers(workers);
+        let mut bootstrap_mgr = BootstrapManager::new(
+            Some(ArtifactStorage::SingleFile(bootstrap_path.clone())),
+            None,
+        );
+        let mut blob_mgr = BlobManager::new();
+        DirectoryBuilder::new()
+            .build(&mut ctx, &mut bootstrap_mgr, &mut blob_mgr)
+            .unwrap();
+
+        (
+            fs::read(blob_path).unwrap(),
+            fs::read(bootstrap_path).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_parallel_build_identical() {
+        register_tracer!(TraceClass::Timing, TimingTracerClass);
+        register_tracer!(TraceClass::Event, EventTracerClass);
+
+        let source = TempDir::new().unwrap();
+        create_source(source.as_path());
+
+        let cases = vec![
+            (RafsVersion::V5, None),
+            (
+                RafsVersion::V5,
+                Some(FastCdc::new(0x2000, 0x10000, 0x40000).unwrap()),
+            ),
+            (RafsVersion::V6, None),
+        ];
+        for (version, chunker) in cases {
+            let output = TempDir::new().unwrap();
+            let (blob, bootstrap) = build(source.as_path(), output.as_path(), version, chunker, 1);
+            assert!(!blob.is_empty());
+            for workers in [2, 8] {
+                let (parallel_blob, parallel_bootstrap) = build(
+                    source.as_path(),
+                    output.as_path(),
+                    version,
+                    chunker,
+                    workers,
+                );
+                assert!(
+                    parallel_blob == blob,
+                    "blob differs with {} workers",
+                    workers
+                );
+                assert!(
+                    parallel_bootstrap == bootstrap,
+                    "bootstrap differs with {} workers",
+                    workers
+                );
+            }
+        }
+    }
+    #[test]
+    fn test_build_v6_sparse_file() {
+        register_tracer!(TraceClass::Timing, TimingTracerClass);
+        register_tracer!(TraceClass::Event, EventTracerClass);
+
+        let source = TempDir::new().unwrap();
+        let data = [0xa5u8; 0x1000];
+        let mut file = File::create(source.as_path().join("sparse")).unwrap();
+        file.seek(SeekFrom::Start(0x100000)).unwrap();
+        file.write_all(&data).unwrap();
+        file.set_len(0x380000).unwrap();
+        drop(file);
+        let mut expected = vec![0u8; 0x380000];
+        expected[0x100000..0x101000].copy_from_slice(&data);
+
+        let output = TempDir::new().unwrap();
+        build(source.as_path(), output.as_path(), RafsVersion::V6, None, 1);
+        let blob_path = output.as_path().join("blob-1");
+        let bootstrap_path = output.as_path().join("bootstrap-1");
+
+        // Hole chunks are recorded with `EROFS_NULL_ADDR` as block address in the inode.
+        let rs = RafsSuper::load_from_metadata(&bootstrap_path, RafsMode::Direct, false).unwrap();
+        let ino = rs.ino_from_path(Path::new("/sparse")).unwrap();
+        let inode = rs.get_inode(ino, false).unwrap();
+        assert_eq!(inode.get_chunk_count(), 4);
+        let holes = (0..4)
+            .map(|idx| inode.get_chunk_info(idx).unwrap().is_hole())
+            .collect::<Vec<_>>();
+        assert_eq!(holes, vec![true, false, true, true]);
+
+        // Hole chunks are served with zeros when reading file data back.
+        let unpacked = output.as_path().join("unpacked.tar");
+        let backend = BackendConfig {
+            backend_type: "localfs".to_string(),
+            backend_config: json!({ "blob_file": blob_path }),
+        };
+        OCIUnpacker::new(&bootstrap_path, Some(backend), &unpacked)
+            .unwrap()
+            .unpack()
+            .unwrap();
+        let mut archive = Archive::new(File::open(&unpacked).unwrap());
+        let mut entry = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap())
+            .find(|e| &*e.path().unwrap() == Path::new("sparse"))
+            .unwrap();
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content).unwrap();
+        assert!(content == expected, "data of sparse file differs");
+    }
+}
@@ -9,10 +9,12 @@ use crate::core::context::{BlobManager, BootstrapManager, BuildContext, BuildOut
 pub(crate) use diff::DiffBuilder;
 pub(crate) use directory::DirectoryBuilder;
 pub(crate) use stargz::StargzBuilder;
+pub(crate) use tarball::{TarballBuilder, TAR_STDIN};
 
 mod diff;
 mod directory;
 mod stargz;
+mod tarball;
 
 pub(crate) trait Builder {
     fn build(
@@ -0,0 +1,1107 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Generate RAFS filesystem from OCI tar layer streams.
+//!
+//! The tar stream may be read from a file or from stdin, optionally compressed by gzip or zstd.
+//! File data is dumped into the data blob while walking the tar stream, so the tar stream is
+//! only read once and may be a pipe. Chunks are digested and compressed by worker threads if
+//! `compress_workers` is bigger than 1, the generated blob is identical to a single-threaded build.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::read::GzDecoder;
+use nix::sys::stat::makedev;
+use tar::{Archive, Entry, EntryType, Header};
+
+use nydus_utils::digest::RafsDigest;
+use nydus_utils::{div_round_up, ByteSize};
+use rafs::metadata::layout::v5::{RafsV5Inode, RafsV5InodeFlags};
+use rafs::metadata::layout::RafsXAttrs;
+use rafs::metadata::Inode;
+
+use super::directory::{TAR_BLOB_NAME, TAR_BOOTSTRAP_NAME};
+use crate::builder::Builder;
+use crate::core::blob::{Blob, ChunkWriter};
+use crate::core::bootstrap::Bootstrap;
+use crate::core::chunk_dict::ChunkDict;
+use crate::core::context::{
+    BlobContext, BlobManager, BootstrapManager, BuildContext, BuildOutput, RafsVersion,
+};
+use crate::core::node::{InodeWrapper, Node, Overlay};
+use crate::core::pipeline::{ChunkJob, ChunkPipeline};
+use crate::core::tree::Tree;
+
+/// Path to read the tar stream from stdin.
+pub(crate) const TAR_STDIN: &str = "-";
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const PAX_XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+/// Open the tar stream and transparently decompress it according to the magic number.
+fn open_tar_stream(path: &Path) -> Result<Box<dyn Read>> {
+    let reader: Box<dyn Read> = if path == Path::new(TAR_STDIN) {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(path).with_context(|| format!("failed to open tar {:?}", path))?)
+    };
+    let mut reader = BufReader::new(reader);
+    let magic = reader
+        .fill_buf()
+        .with_context(|| format!("failed to read tar {:?}", path))?;
+
+    if magic.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(GzDecoder::new(reader)))
+    } else if magic.starts_with(&ZSTD_MAGIC) {
+        let decoder = zstd::stream::read::Decoder::with_buffer(reader)
+            .context("failed to create zstd decoder")?;
+        Ok(Box::new(decoder))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Convert path of a tar entry to an absolute path in the RAFS filesystem.
+fn normalize_path(path: &Path) -> Result<PathBuf> {
+    let mut result = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => result.push(name),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => {
+                bail!("invalid path {:?} in tar stream", path)
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+struct TarballTreeBuilder<'a, T: ChunkDict> {
+    ctx: &'a BuildContext,
+    writer: ChunkWriter<'a, T>,
+    /// Worker threads to digest and compress chunks, chunks are processed by the builder thread
+    /// if not set.
+    pipeline: Option<ChunkPipeline>,
+    layered: bool,
+    layer_idx: u16,
+    next_ino: Inode,
+    /// Parsed nodes, the first one is always the root node.
+    nodes: Vec<Node>,
+    /// Map file path to index of the node, used to handle hardlinks and lost parent directories.
+    path_map: HashMap<PathBuf, usize>,
+}
+
+impl<'a, T: ChunkDict> TarballTreeBuilder<'a, T> {
+    fn new(
+        ctx: &'a BuildContext,
+        blob_ctx: &'a mut BlobContext,
+        blob_index: u32,
+        chunk_dict: &'a mut T,
+        layered: bool,
+        layer_idx: u16,
+    ) -> Self {
+        Self {
+            ctx,
+            writer: ChunkWriter::new(ctx, blob_ctx, blob_index, chunk_dict, None, 0),
+            pipeline: None,
+            layered,
+            layer_idx,
+            next_ino: 1,
+            nodes: Vec::new(),
+            path_map: HashMap::new(),
+        }
+    }
+
+    fn build(mut self) -> Result<Tree> {
+        if self.ctx.compress_workers > 1 {
+            self.pipeline = Some(ChunkPipeline::new(
+                self.ctx.compress_workers,
+                self.ctx.digester,
+                self.ctx.compressor,
+            )?);
+        }
+        let reader = open_tar_stream(&self.ctx.source_path)?;
+        let mut archive = Archive::new(reader);
+        let entries = archive
+            .entries()
+            .context("failed to read entries from tar")?;
+
+        // Tar streams usually don't contain the root directory, so create a default one which
+        // will be replaced if the root directory appears in the stream.
+        let root = self.make_dir_node(PathBuf::from("/"))?;
+        self.add_node(root)?;
+
+        for entry in entries {
+            let mut entry = entry.context("failed to read entry from tar")?;
+            let path = normalize_path(&entry.path()?)?;
+            event_tracer!("load_from_tar", +1);
+            // Hardlinks share data chunks with the link target.
+            let is_hard_link = entry.header().entry_type().is_hard_link();
+            if let Some(node) = self
+                .parse_entry(&mut entry, path.clone())
+                .with_context(|| format!("failed to parse tar entry {:?}", path))?
+            {
+                if let Some(idx) = self.add_node(node)? {
+                    if !is_hard_link {
+                        self.dump_node(idx, &mut entry)
+                            .with_context(|| format!("failed to parse tar entry {:?}", path))?;
+                    }
+                }
+            }
+        }
+        self.flush_chunks()?;
+
+        let mut nodes = self.nodes.into_iter();
+        let mut tree = Tree::new(nodes.next().unwrap());
+        for node in nodes {
+            tree.apply(&node, false, self.ctx.whiteout_spec)?;
+        }
+
+        Ok(tree)
+    }
+
+    /// Add the node into the tree, return index of the node or `None` if it's dropped.
+    fn add_node(&mut self, node: Node) -> Result<Option<usize>> {
+        if !self.layered
+            && node.whiteout_type(self.ctx.whiteout_spec).is_some()
+            && !node.is_overlayfs_opaque(self.ctx.whiteout_spec)
+        {
+            return Ok(None);
+        }
+
+        if let Some(parent) = node.target().parent() {
+            if !self.path_map.contains_key(parent) {
+                let dir = self.make_dir_node(parent.to_path_buf())?;
+                self.add_node(dir)?;
+            }
+        }
+
+        if node.target() == Path::new("/") && !self.nodes.is_empty() {
+            self.nodes[0] = node;
+            return Ok(Some(0));
+        }
+        let idx = self.nodes.len();
+        self.path_map.insert(node.target().to_path_buf(), idx);
+        self.nodes.push(node);
+
+        Ok(Some(idx))
+    }
+
+    /// Dump file data of the node from the tar entry into the blob.
+    fn dump_node<R: Read>(&mut self, idx: usize, reader: &mut R) -> Result<()> {
+        let size = self.nodes[idx].inode.size();
+        let pipeline = match self.pipeline.as_mut() {
+            Some(pipeline) if self.nodes[idx].is_reg() && size > 0 => pipeline,
+            _ => {
+                self.nodes[idx].dump_blob_with_reader(
+                    self.ctx,
+                    self.writer.blob_ctx,
+                    self.writer.blob_index,
+                    self.writer.chunk_dict,
+                    reader,
+                )?;
+                return Ok(());
+            }
+        };
+
+        let writer = &mut self.writer;
+        let nodes = &mut self.nodes;
+        let path = nodes[idx].path.clone();
+        // Take the buffer out of `blob_ctx`, which is updated when writing chunks.
+        let mut chunk_data_buf = mem::take(&mut writer.blob_ctx.chunk_data_buf);
+        let ret = Node::split_chunks(
+            self.ctx,
+            size,
+            &mut chunk_data_buf,
+            &mut |_offset, buf| Node::read_chunk_from_reader(&path, reader, buf),
+            &mut |file_offset, data, is_hole| {
+                pipeline.submit(ChunkJob {
+                    node: idx,
+                    file_offset,
+                    data: data.to_vec(),
+                    is_hole,
+                    processed: None,
+                })?;
+                // Write processed chunks, and wait if there are too many chunks in flight.
+                while let Some(job) = pipeline.next(pipeline.is_full())? {
+                    writer.write(nodes, job)?;
+                }
+                Ok(())
+            },
+        );
+        writer.blob_ctx.chunk_data_buf = chunk_data_buf;
+
+        ret
+    }
+
+    /// Write all chunks processed by worker threads into the blob.
+    fn flush_chunks(&mut self) -> Result<()> {
+        match self.pipeline.as_mut() {
+            Some(pipeline) => self.writer.flush(pipeline, &mut self.nodes),
+            None => Ok(()),
+        }
+    }
+
+    /// Create directory node for the root and for lost parent directories.
+    fn make_dir_node(&mut self, path: PathBuf) -> Result<Node> {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Directory);
+        header.set_mode(0o755);
+        let ino = self.alloc_ino();
+
+        self.make_node(&header, path, ino, None, RafsXAttrs::new(), 0)
+    }
+
+    fn alloc_ino(&mut self) -> Inode {
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        ino
+    }
+
+    fn parse_entry<R: Read>(
+        &mut self,
+        entry: &mut Entry<R>,
+        path: PathBuf,
+    ) -> Result<Option<Node>> {
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_hard_link() {
+            return self.parse_hardlink(entry, path).map(Some);
+        }
+        match entry_type {
+            EntryType::Regular
+            | EntryType::Continuous
+            | EntryType::GNUSparse
+            | EntryType::Directory
+            | EntryType::Symlink
+            | EntryType::Char
+            | EntryType::Block
+            | EntryType::Fifo => {}
+            _ => {
+                // PAX and GNU long name headers have been consumed by the tar iterator.
+                warn!(
+                    "ignore unsupported tar entry {:?} of type {:?}",
+                    path, entry_type
+                );
+                return Ok(None);
+            }
+        }
+
+        let mut header = entry.header().clone();
+        let mut xattrs = RafsXAttrs::new();
+        if let Some(extensions) = entry.pax_extensions()? {
+            for extension in extensions {
+                let extension = extension?;
+                let key = extension
+                    .key()
+                    .map_err(|_| anyhow!("invalid pax extension key"))?;
+                if let Some(name) = key.strip_prefix(PAX_XATTR_PREFIX) {
+                    xattrs.add(OsString::from(name), extension.value_bytes().to_vec());
+                } else if let Ok(value) = extension.value() {
+                    // The numeric fields in the ustar header may overflow, prefer pax values.
+                    let value = value.split('.').next().unwrap_or_default();
+                    match key {
+                        "uid" => header.set_uid(value.parse()?),
+                        "gid" => header.set_gid(value.parse()?),
+                        "mtime" => header.set_mtime(value.parse()?),
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        let symlink = if entry_type.is_symlink() {
+            let link = entry
+                .link_name()?
+                .ok_or_else(|| anyhow!("symlink without target"))?;
+            Some(link.as_os_str().to_owned())
+        } else {
+            None
+        };
+        let size = if entry_type.is_dir() || symlink.is_some() {
+            0
+        } else {
+            entry.size()
+        };
+
+        let ino = self.alloc_ino();
+        let node = self.make_node(&header, path, ino, symlink, xattrs, size)?;
+
+        Ok(Some(node))
+    }
+
+    /// Hardlinks share inode and data chunks with the link target, which must appear earlier
+    /// in the tar stream.
+    fn parse_hardlink<R: Read>(&mut self, entry: &mut Entry<R>, path: PathBuf) -> Result<Node> {
+        let link = entry
+            .link_name()?
+            .ok_or_else(|| anyhow!("hardlink without target"))?;
+        let link = normalize_path(&link)?;
+        // Chunks of the link target may still be processed by worker threads.
+        self.flush_chunks()?;
+        let target = match self.path_map.get(&link) {
+            Some(idx) => &self.nodes[*idx],
+            None => bail!("hardlink target {:?} doesn't exist", link),
+        };
+
+        let mut node = target.clone();
+        let name_size = path.file_name().unwrap_or_default().byte_size() as u16;
+        match &mut node.inode {
+            InodeWrapper::V5(i) | InodeWrapper::V6(i) => {
+                i.i_name_size = name_size;
+                i.i_flags |= RafsV5InodeFlags::HARDLINK;
+            }
+        }
+        node.target = Node::generate_target(&path, &node.source);
+        node.target_vec = Node::generate_target_vec(&node.target);
+        node.path = path;
+
+        Ok(node)
+    }
+
+    fn make_node(
+        &self,
+        header: &Header,
+        path: PathBuf,
+        ino: Inode,
+        symlink: Option<OsString>,
+        xattrs: RafsXAttrs,
+        size: u64,
+    ) -> Result<Node> {
+        let entry_type = header.entry_type();
+        let file_type = match entry_type {
+            EntryType::Directory => libc::S_IFDIR,
+            EntryType::Symlink => libc::S_IFLNK,
+            EntryType::Char => libc::S_IFCHR,
+            EntryType::Block => libc::S_IFBLK,
+            EntryType::Fifo => libc::S_IFIFO,
+            _ => libc::S_IFREG,
+        };
+        let mode = (header.mode()? & 0o7777) | file_type;
+        let rdev = match entry_type {
+            EntryType::Char | EntryType::Block => makedev(
+                header.device_major()?.unwrap_or_default() as u64,
+                header.device_minor()?.unwrap_or_default() as u64,
+            ),
+            _ => 0,
+        };
+
+        let mut flags = RafsV5InodeFlags::default();
+        let mut symlink_size = 0;
+        let mut size = size;
+        if let Some(symlink) = symlink.as_ref() {
+            flags |= RafsV5InodeFlags::SYMLINK;
+            symlink_size = symlink.byte_size() as u16;
+            size = symlink_size as u64;
+        }
+        if !xattrs.is_empty() {
+            flags |= RafsV5InodeFlags::XATTR;
+        }
+
+        let is_root = path == Path::new("/");
+        let name_size = if is_root {
+            1
+        } else {
+            path.file_name().unwrap_or_default().byte_size() as u16
+        };
+        let (uid, gid) = if self.ctx.explicit_uidgid {
+            (header.uid()? as u32, header.gid()? as u32)
+        } else {
+            (0, 0)
+        };
+        // Ignore mtime of the root directory, as the directory builder does.
+        let mtime = if is_root { 0 } else { header.mtime()? };
+        let child_count = if file_type == libc::S_IFREG {
+            div_round_up(size, self.ctx.chunk_size as u64) as u32
+        } else {
+            0
+        };
+
+        let inode = RafsV5Inode {
+            i_digest: RafsDigest::default(),
+            i_parent: 0,
+            i_ino: ino,
+            i_projid: 0,
+            i_uid: uid,
+            i_gid: gid,
+            i_mode: mode,
+            i_size: size,
+            i_nlink: 1,
+            i_blocks: div_round_up(size + xattrs.aligned_size_v5() as u64, 512),
+            i_flags: flags,
+            i_child_index: 0,
+            i_child_count: child_count,
+            i_name_size: name_size,
+            i_symlink_size: symlink_size,
+            i_rdev: rdev as u32,
+            i_mtime: mtime,
+            i_mtime_nsec: 0,
+            i_reserved: [0; 8],
+        };
+        let inode = match self.ctx.fs_version {
+            RafsVersion::V5 => InodeWrapper::V5(inode),
+            RafsVersion::V6 => InodeWrapper::V6(inode),
+        };
+
+        let source = PathBuf::from("/");
+        let target = Node::generate_target(&path, &source);
+        let target_vec = Node::generate_target_vec(&target);
+        let mut node = Node {
+            index: 0,
+            src_ino: ino,
+            src_dev: u64::MAX,
+            rdev,
+            overlay: Overlay::UpperAddition,
+            explicit_uidgid: self.ctx.explicit_uidgid,
+            source,
+            target,
+            path,
+            target_vec,
+            inode,
+            chunks: Vec::new(),
+            symlink,
+            xattrs,
+            layer_idx: self.layer_idx,
+            ctime: 0,
+            offset: 0,
+            dirents: Vec::new(),
+            v6_datalayout: 0,
+            v6_compact_inode: false,
+            v6_force_extended_inode: true,
+            dirents_offset: 0,
+        };
+        node.set_v6_inode_compact();
+
+        Ok(node)
+    }
+}
+
+pub(crate) struct TarballBuilder {}
+
+impl TarballBuilder {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Builder for TarballBuilder {
+    fn build(
+        &mut self,
+        ctx: &mut BuildContext,
+        bootstrap_mgr: &mut BootstrapManager,
+        blob_mgr: &mut BlobManager,
+    ) -> Result<BuildOutput> {
+        let mut bootstrap_ctx = bootstrap_mgr.create_ctx(ctx.inline_bootstrap)?;
+        let layered = bootstrap_ctx.layered;
+        let layer_idx = if layered { 1u16 } else { 0u16 };
+        let mut bootstrap = Bootstrap::new()?;
+
+        // File data is dumped while parsing the tar stream, so the parent bootstrap must be
+        // loaded first to get the blob table and chunks for deduplication.
+        let parent = if layered {
+            ctx.prefetch.disable();
+            Some(bootstrap.load_parent_bootstrap(ctx, bootstrap_mgr, blob_mgr)?)
+        } else {
+            None
+        };
+
+        let mut blob_ctx = BlobContext::new(
+            ctx.blob_id.clone(),
+            ctx.blob_storage.clone(),
+            ctx.blob_offset,
+            ctx.inline_bootstrap,
+        )?;
+        blob_ctx.set_chunk_dict(blob_mgr.get_chunk_dict());
+        blob_ctx.set_chunk_size(ctx.chunk_size);
+        blob_ctx.set_meta_info_enabled(ctx.fs_version == RafsVersion::V6);
+        blob_mgr.extend_blob_table_from_chunk_dict()?;
+        let blob_index = blob_mgr.alloc_index()?;
+
+        let mut tree = timing_tracer!(
+            {
+                TarballTreeBuilder::new(
+                    ctx,
+                    &mut blob_ctx,
+                    blob_index,
+                    &mut blob_mgr.chunk_dict_cache,
+                    layered,
+                    layer_idx,
+                )
+                .build()
+            },
+            "load_from_tar"
+        )?;
+
+        let origin_bootstarp_offset = bootstrap_ctx.offset;
+        if let Some(parent) = parent {
+            bootstrap.build(ctx, &mut bootstrap_ctx, &mut tree)?;
+            tree = bootstrap.apply(
+                ctx,
+                &mut bootstrap_ctx,
+                bootstrap_mgr,
+                blob_mgr,
+                Some(parent),
+            )?;
+        }
+        bootstrap_ctx.offset = origin_bootstarp_offset;
+        bootstrap_ctx.layered = false;
+        timing_tracer!(
+            { bootstrap.build(ctx, &mut bootstrap_ctx, &mut tree) },
+            "build_bootstrap"
+        )?;
+
+        let mut blob = Blob::new();
+        let blob_exists = timing_tracer!(
+            {
+                blob.dump(
+                    ctx,
+                    &mut blob_ctx,
+                    blob_index,
+                    &mut bootstrap_ctx.nodes,
+                    &mut blob_mgr.chunk_dict_cache,
+                )
+            },
+            "dump_blob"
+        )?;
+
+        let mut blob_writer = blob_ctx.writer.take().unwrap();
+        let blob_id = blob_ctx.blob_id();
+        if blob_exists {
+            if ctx.inline_bootstrap {
+                if let Some(blob_writer) = &mut blob_ctx.writer {
+                    blob_writer.write_tar_header(TAR_BLOB_NAME, blob_writer.pos()?)?;
+                }
+            } else {
+                blob_writer.finalize(blob_id.clone())?;
+            }
+            blob_mgr.add(blob_ctx);
+        }
+
+        let blob_table = blob_mgr.to_blob_table(ctx)?;
+        bootstrap.dump(ctx, &mut bootstrap_ctx, &blob_table)?;
+
+        if ctx.inline_bootstrap {
+            let bootstrap_data = bootstrap_ctx.writer.data();
+            blob_writer.write_all(bootstrap_data)?;
+            blob_writer.write_tar_header(TAR_BOOTSTRAP_NAME, bootstrap_data.len() as u64)?;
+            blob_writer.finalize(blob_id)?;
+        }
+
+        bootstrap_mgr.add(bootstrap_ctx);
+        BuildOutput::new(blob_mgr, bootstrap_mgr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::os::unix::io::AsRawFd;
+    use std::sync::Arc;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use nix::unistd::{close, dup, dup2};
+    use nydus_utils::{compress, digest};
+    use rafs::metadata::{RafsInode, RafsMode, RafsSuper};
+    use rafs::RafsIoReader;
+    use storage::device::BlobChunkInfo;
+    use storage::factory::BackendConfig;
+    use vmm_sys_util::tempdir::TempDir;
+
+    use super::*;
+    use crate::core::context::{ArtifactStorage, SourceType};
+    use crate::core::node::WhiteoutSpec;
+    use crate::trace::{EventTracerClass, TimingTracerClass, TraceClass};
+    use crate::unpack::{pax_record, OCIUnpacker};
+
+    fn new_header(entry_type: EntryType, mode: u32, size: u64) -> Header {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_mode(mode);
+        header.set_size(size);
+        header.set_uid(1000);
+        header.set_gid(1000);
+        header.set_mtime(1_600_000_000);
+        header
+    }
+
+    fn append(builder: &mut tar::Builder<Vec<u8>>, path: &str, entry_type: EntryType, data: &[u8]) {
+        let mode = if entry_type == EntryType::Directory {
+            0o755
+        } else {
+            0o644
+        };
+        let mut header = new_header(entry_type, mode, data.len() as u64);
+        builder.append_data(&mut header, path, data).unwrap();
+    }
+
+    fn append_link(
+        builder: &mut tar::Builder<Vec<u8>>,
+        path: &str,
+        entry_type: EntryType,
+        target: &str,
+    ) {
+        let mut header = new_header(entry_type, 0o777, 0);
+        builder.append_link(&mut header, path, target).unwrap();
+    }
+
+    fn append_device(
+        builder: &mut tar::Builder<Vec<u8>>,
+        path: &str,
+        entry_type: EntryType,
+        major: u32,
+        minor: u32,
+    ) {
+        let mut header = new_header(entry_type, 0o600, 0);
+        header.set_device_major(major).unwrap();
+        header.set_device_minor(minor).unwrap();
+        builder.append_data(&mut header, path, io::empty()).unwrap();
+    }
+
+    // Append a PAX extended header with extended attributes for the next entry.
+    fn append_xattrs(builder: &mut tar::Builder<Vec<u8>>, xattrs: &[(&str, &[u8])]) {
+        let mut data = Vec::new();
+        for (name, value) in xattrs {
+            let key = format!("{}{}", PAX_XATTR_PREFIX, name);
+            data.extend_from_slice(&pax_record(key.as_bytes(), value));
+        }
+        let mut header = Header::new_ustar();
+        header.set_entry_type(EntryType::XHeader);
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        builder
+            .append_data(&mut header, "PaxHeader", data.as_slice())
+            .unwrap();
+    }
+
+    fn file_data() -> Vec<u8> {
+        (0..0x1800u32).map(|v| (v % 251) as u8).collect()
+    }
+
+    // Entries which are unpacked back to the same tar entries.
+    fn append_common(builder: &mut tar::Builder<Vec<u8>>) {
+        append(builder, "dir", EntryType::Directory, &[]);
+        append_xattrs(
+            builder,
+            &[
+                ("user.key", b"value"),
+                ("security.capability", &[1, 0, 2, 0]),
+            ],
+        );
+        append(builder, "dir/file", EntryType::Regular, &file_data());
+        append_link(builder, "dir/link", EntryType::Link, "dir/file");
+        append_link(builder, "dir/symlink", EntryType::Symlink, "file");
+        append(builder, "dev", EntryType::Directory, &[]);
+        append_device(builder, "dev/char", EntryType::Char, 1, 3);
+        append_device(builder, "dev/block", EntryType::Block, 7, 1);
+        append(builder, "fifo", EntryType::Fifo, &[]);
+    }
+
+    fn base_layer() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        append_common(&mut builder);
+        // The parent directory "old" is missing from the tar stream.
+        append(&mut builder, "old/stale", EntryType::Regular, b"stale");
+        append(&mut builder, "removed", EntryType::Regular, b"removed");
+        append(&mut builder, ".wh.ignored", EntryType::Regular, &[]);
+        builder.into_inner().unwrap()
+    }
+
+    fn upper_layer() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        append(&mut builder, ".wh.removed", EntryType::Regular, &[]);
+        append(&mut builder, "old/.wh..wh..opq", EntryType::Regular, &[]);
+        append(&mut builder, "old/new", EntryType::Regular, b"new");
+        builder.into_inner().unwrap()
+    }
+
+    fn build(
+        source: &Path,
+        output: &Path,
+        name: &str,
+        parent: Option<&Path>,
+        whiteout_spec: WhiteoutSpec,
+    ) -> Result<(PathBuf, PathBuf)> {
+        build_with(source, output, name, parent, whiteout_spec, |_| {})
+    }
+
+    // Build the image with build context options adjusted by `setup`.
+    fn build_with<F: FnOnce(&mut BuildContext)>(
+        source: &Path,
+        output: &Path,
+        name: &str,
+        parent: Option<&Path>,
+        whiteout_spec: WhiteoutSpec,
+        setup: F,
+    ) -> Result<(PathBuf, PathBuf)> {
+        register_tracer!(TraceClass::Timing, TimingTracerClass);
+        register_tracer!(TraceClass::Event, EventTracerClass);
+
+        let blob_path = output.join(format!("{}.blob", name));
+        let bootstrap_path = output.join(format!("{}.bootstrap", name));
+        let mut ctx = BuildContext::new(
+            String::new(),
+            false,
+            0,
+            compress::Algorithm::None,
+            digest::Algorithm::Blake3,
+            true,
+            whiteout_spec,
+            SourceType::Tar,
+            source.to_path_buf(),
+            Default::default(),
+            Some(ArtifactStorage::SingleFile(blob_path.clone())),
+            false,
+        );
+        setup(&mut ctx);
+        let parent = match parent {
+            Some(p) => Some(Box::new(File::open(p)?) as RafsIoReader),
+            None => None,
+        };
+        let mut bootstrap_mgr = BootstrapManager::new(
+            Some(ArtifactStorage::SingleFile(bootstrap_path.clone())),
+            parent,
+        );
+        let mut blob_mgr = BlobManager::new();
+        TarballBuilder::new().build(&mut ctx, &mut bootstrap_mgr, &mut blob_mgr)?;
+
+        Ok((blob_path, bootstrap_path))
+    }
+
+    fn lookup(rs: &RafsSuper, path: &str) -> Option<Arc<dyn RafsInode>> {
+        let ino = rs.ino_from_path(Path::new(path)).ok()?;
+        Some(rs.get_inode(ino, false).unwrap())
+    }
+
+    // Chunks are not compressed, so file data can be read from the blob directly.
+    fn read_file(blob: &[u8], inode: &dyn RafsInode) -> Vec<u8> {
+        let mut data = Vec::new();
+        for idx in 0..inode.get_chunk_count() {
+            let chunk = inode.get_chunk_info(idx).unwrap();
+            let offset = chunk.compress_offset() as usize;
+            data.extend_from_slice(&blob[offset..offset + chunk.compress_size() as usize]);
+        }
+        data
+    }
+
+    #[test]
+    fn test_build_from_tar() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = tmp_dir.as_path();
+        let tar_path = dir.join("base.tar");
+        fs::write(&tar_path, base_layer()).unwrap();
+        let (blob_path, bootstrap_path) =
+            build(&tar_path, dir, "base", None, WhiteoutSpec::Oci).unwrap();
+
+        let blob = fs::read(&blob_path).unwrap();
+        let rs = RafsSuper::load_from_metadata(&bootstrap_path, RafsMode::Direct, false).unwrap();
+        let file = lookup(&rs, "/dir/file").unwrap();
+        let attr = file.get_attr();
+        assert_eq!(attr.mode, libc::S_IFREG | 0o644);
+        assert_eq!(
+            (attr.uid, attr.gid, attr.mtime),
+            (1000, 1000, 1_600_000_000)
+        );
+        assert_eq!(attr.nlink, 2);
+        assert_eq!(read_file(&blob, file.as_ref()), file_data());
+        // Extended attributes from PAX records.
+        assert_eq!(
+            file.get_xattr(OsStr::new("user.key")).unwrap(),
+            Some(b"value".to_vec())
+        );
+        assert_eq!(
+            file.get_xattr(OsStr::new("security.capability")).unwrap(),
+            Some(vec![1, 0, 2, 0])
+        );
+
+        // Hardlinks share the inode with the link target.
+        let link = lookup(&rs, "/dir/link").unwrap();
+        assert!(link.is_hardlink());
+        assert_eq!(link.ino(), file.ino());
+        let symlink = lookup(&rs, "/dir/symlink").unwrap();
+        assert!(symlink.is_symlink());
+        assert_eq!(symlink.get_symlink().unwrap(), OsString::from("file"));
+
+        // Device files and the lost parent directory.
+        assert!(lookup(&rs, "/old").unwrap().is_dir());
+        let char_dev = lookup(&rs, "/dev/char").unwrap();
+        assert_eq!(char_dev.get_attr().mode, libc::S_IFCHR | 0o600);
+        assert_eq!(char_dev.rdev(), makedev(1, 3) as u32);
+        let block_dev = lookup(&rs, "/dev/block").unwrap();
+        assert_eq!(block_dev.get_attr().mode, libc::S_IFBLK | 0o600);
+        assert_eq!(block_dev.rdev(), makedev(7, 1) as u32);
+        let fifo = lookup(&rs, "/fifo").unwrap();
+        assert_eq!(fifo.get_attr().mode, libc::S_IFIFO | 0o644);
+
+        // Whiteouts in the base layer have nothing to remove.
+        assert!(lookup(&rs, "/.wh.ignored").is_none());
+        assert!(lookup(&rs, "/ignored").is_none());
+    }
+
+    #[test]
+    fn test_build_from_tar_with_whiteouts() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = tmp_dir.as_path();
+        let base_path = dir.join("base.tar");
+        fs::write(&base_path, base_layer()).unwrap();
+        let upper_path = dir.join("upper.tar");
+        fs::write(&upper_path, upper_layer()).unwrap();
+        let (_, base_bootstrap) = build(&base_path, dir, "base", None, WhiteoutSpec::Oci).unwrap();
+
+        // Whiteouts remove files and directory entries of lower layers.
+        let (_, bootstrap) = build(
+            &upper_path,
+            dir,
+            "upper",
+            Some(&base_bootstrap),
+            WhiteoutSpec::Oci,
+        )
+        .unwrap();
+        let rs = RafsSuper::load_from_metadata(&bootstrap, RafsMode::Direct, false).unwrap();
+        assert!(lookup(&rs, "/removed").is_none());
+        assert!(lookup(&rs, "/.wh.removed").is_none());
+        assert!(lookup(&rs, "/old/stale").is_none());
+        assert!(lookup(&rs, "/old/.wh..wh..opq").is_none());
+        assert!(lookup(&rs, "/old/new").is_some());
+        assert!(lookup(&rs, "/dir/file").is_some());
+
+        // Whiteouts are kept as is without whiteout spec.
+        let (_, bootstrap) = build(&upper_path, dir, "raw", None, WhiteoutSpec::None).unwrap();
+        let rs = RafsSuper::load_from_metadata(&bootstrap, RafsMode::Direct, false).unwrap();
+        assert!(lookup(&rs, "/.wh.removed").unwrap().is_reg());
+        assert!(lookup(&rs, "/old/.wh..wh..opq").unwrap().is_reg());
+    }
+
+    #[test]
+    fn test_build_from_tar_with_invalid_hardlink() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = tmp_dir.as_path();
+        // The hardlink target must appear earlier in the tar stream.
+        let mut builder = tar::Builder::new(Vec::new());
+        append_link(&mut builder, "link", EntryType::Link, "file");
+        append(&mut builder, "file", EntryType::Regular, b"file");
+        let tar_path = dir.join("invalid.tar");
+        fs::write(&tar_path, builder.into_inner().unwrap()).unwrap();
+        assert!(build(&tar_path, dir, "invalid", None, WhiteoutSpec::Oci).is_err());
+    }
+
+    #[test]
+    fn test_build_from_compressed_tar() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = tmp_dir.as_path();
+        let tar = base_layer();
+        let tar_path = dir.join("layer.tar");
+        fs::write(&tar_path, &tar).unwrap();
+        let (blob, bootstrap) = build(&tar_path, dir, "tar", None, WhiteoutSpec::Oci).unwrap();
+        let expected = (fs::read(blob).unwrap(), fs::read(bootstrap).unwrap());
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&tar).unwrap();
+        let gzip_path = dir.join("layer.tar.gz");
+        fs::write(&gzip_path, encoder.finish().unwrap()).unwrap();
+        let zstd_path = dir.join("layer.tar.zst");
+        fs::write(
+            &zstd_path,
+            zstd::stream::encode_all(tar.as_slice(), 0).unwrap(),
+        )
+        .unwrap();
+
+        for (name, path) in [("gzip", &gzip_path), ("zstd", &zstd_path)] {
+            let (blob, bootstrap) = build(path, dir, name, None, WhiteoutSpec::Oci).unwrap();
+            assert!(
+                fs::read(blob).unwrap() == expected.0,
+                "{} blob differs",
+                name
+            );
+            assert!(
+                fs::read(bootstrap).unwrap() == expected.1,
+                "{} bootstrap differs",
+                name
+            );
+        }
+
+        // Read the gzip compressed tar stream from stdin.
+        let stdin = dup(0).unwrap();
+        let file = File::open(&gzip_path).unwrap();
+        dup2(file.as_raw_fd(), 0).unwrap();
+        let result = build(Path::new(TAR_STDIN), dir, "stdin", None, WhiteoutSpec::Oci);
+        dup2(stdin, 0).unwrap();
+        close(stdin).unwrap();
+        let (blob, bootstrap) = result.unwrap();
+        assert!(fs::read(blob).unwrap() == expected.0);
+        assert!(fs::read(bootstrap).unwrap() == expected.1);
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct TarEntry {
+        entry_type: EntryType,
+        mode: u32,
+        uid: u64,
+        gid: u64,
+        mtime: u64,
+        data: Vec<u8>,
+        link: Option<PathBuf>,
+        device: Option<(u32, u32)>,
+        xattrs: Vec<(String, Vec<u8>)>,
+    }
+
+    fn read_tar(path: &Path) -> BTreeMap<PathBuf, TarEntry> {
+        let mut archive = Archive::new(File::open(path).unwrap());
+        let mut entries = BTreeMap::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = normalize_path(&entry.path().unwrap()).unwrap();
+            let header = entry.header().clone();
+            let entry_type = header.entry_type();
+            let mut xattrs = Vec::new();
+            if let Some(extensions) = entry.pax_extensions().unwrap() {
+                for extension in extensions {
+                    let extension = extension.unwrap();
+                    if let Some(name) = extension.key().unwrap().strip_prefix(PAX_XATTR_PREFIX) {
+                        xattrs.push((name.to_string(), extension.value_bytes().to_vec()));
+                    }
+                }
+            }
+            xattrs.sort();
+            let device = match entry_type {
+                EntryType::Char | EntryType::Block => Some((
+                    header.device_major().unwrap().unwrap(),
+                    header.device_minor().unwrap().unwrap(),
+                )),
+                _ => None,
+            };
+            let link = entry.link_name().unwrap().map(|v| v.into_owned());
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            let tar_entry = TarEntry {
+                entry_type,
+                // Hardlinks take the mode of the link target.
+                mode: if entry_type.is_hard_link() {
+                    0
+                } else {
+                    header.mode().unwrap()
+                },
+                uid: header.uid().unwrap(),
+                gid: header.gid().unwrap(),
+                mtime: header.mtime().unwrap(),
+                data,
+                link,
+                device,
+                xattrs,
+            };
+            entries.insert(path, tar_entry);
+        }
+        entries
+    }
+
+    #[test]
+    fn test_unpack_round_trip() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = tmp_dir.as_path();
+        let mut builder = tar::Builder::new(Vec::new());
+        append_common(&mut builder);
+        append(&mut builder, ".wh.removed", EntryType::Regular, &[]);
+        append(&mut builder, "dir/.wh..wh..opq", EntryType::Regular, &[]);
+        let tar_path = dir.join("layer.tar");
+        fs::write(&tar_path, builder.into_inner().unwrap()).unwrap();
+
+        // Keep whiteouts as regular files, as they are in an OCI tar layer.
+        let (blob_path, bootstrap_path) =
+            build(&tar_path, dir, "layer", None, WhiteoutSpec::None).unwrap();
+        let output = dir.join("unpacked.tar");
+        let backend = BackendConfig {
+            backend_type: "localfs".to_string(),
+            backend_config: json!({ "blob_file": blob_path }),
+        };
+        OCIUnpacker::new(&bootstrap_path, Some(backend), &output)
+            .unwrap()
+            .unpack()
+            .unwrap();
+
+        let expected = read_tar(&tar_path);
+        let unpacked = read_tar(&output);
+        assert_eq!(
+            unpacked.keys().collect::<Vec<_>>(),
+            expected.keys().collect::<Vec<_>>()
+        );
+        for (path, entry) in expected.iter() {
+            assert_eq!(&unpacked[path], entry, "entry {:?} differs", path);
+        }
+        let link = &unpacked[Path::new("/dir/link")];
+        assert_eq!(link.entry_type, EntryType::Link);
+        assert_eq!(link.link, Some(PathBuf::from("dir/file")));
+    }
+
+    #[test]
+    fn test_build_from_tar_with_compress_workers() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dir = tmp_dir.as_path();
+        let mut builder = tar::Builder::new(Vec::new());
+        append_common(&mut builder);
+        for idx in 0..16u32 {
+            // Files of multiple chunks, with hole chunks and duplicated chunks.
+            let mut data: Vec<u8> = (0..0x5800u32).map(|v| ((v * idx) % 241) as u8).collect();
+            data[0x1000..0x2000].fill(0);
+            let name = format!("dir/file{}", idx);
+            append(&mut builder, &name, EntryType::Regular, &data);
+            if idx % 4 == 0 {
+                // The link target may be still processed by worker threads.
+                append_link(
+                    &mut builder,
+                    &format!("{}.link", name),
+                    EntryType::Link,
+                    &name,
+                );
+            }
+        }
+        let tar_path = dir.join("layer.tar");
+        fs::write(&tar_path, builder.into_inner().unwrap()).unwrap();
+
+        let outputs: Vec<(Vec<u8>, Vec<u8>)> = [1, 4]
+            .iter()
+            .map(|workers| {
+                let name = format!("workers{}", workers);
+                let (blob_path, bootstrap_path) =
+                    build_with(&tar_path, dir, &name, None, WhiteoutSpec::Oci, |ctx| {
+                        ctx.compressor = compress::Algorithm::Lz4Block;
+                        ctx.set_chunk_size(0x1000);
+                        ctx.set_compress_workers(*workers);
+                    })
+                    .unwrap();
+                (
+                    fs::read(blob_path).unwrap(),
+                    fs::read(bootstrap_path).unwrap(),
+                )
+            })
+            .collect();
+        assert!(!outputs[0].0.is_empty());
+        assert!(outputs[0].0 == outputs[1].0, "blobs differ");
+        assert!(outputs[0].1 == outputs[1].1, "bootstraps differ");
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path(Path::new(".")).unwrap(), PathBuf::from("/"));
+        assert_eq!(normalize_path(Path::new("./")).unwrap(), PathBuf::from("/"));
+        assert_eq!(
+            normalize_path(Path::new("./a/b")).unwrap(),
+            PathBuf::from("/a/b")
+        );
+        assert_eq!(
+            normalize_path(Path::new("/a/./b/")).unwrap(),
+            PathBuf::from("/a/b")
+        );
+        assert!(normalize_path(Path::new("a/../../b")).is_err());
+    }
+}
@@ -13,7 +13,8 @@ use storage::meta::{BlobChunkInfoOndisk, BlobMetaHeaderOndisk};
 
 use super::chunk_dict::ChunkDict;
 use super::context::{BlobContext, BuildContext, SourceType};
-use super::node::Node;
+use super::node::{ChunkDumpState, Node};
+use super::pipeline::{ChunkJob, ChunkPipeline};
 
 pub struct Blob {}
 
@@ -36,18 +37,34 @@ impl Blob {
                 let (inodes, prefetch_entries) = blob_ctx
                     .blob_layout
                     .layout_blob_simple(&ctx.prefetch, nodes)?;
-                for (idx, inode) in inodes.iter().enumerate() {
-                    let node = &mut nodes[*inode];
-                    let size = node
-                        .dump_blob(ctx, blob_ctx, blob_index, chunk_dict)
-                        .context("failed to dump blob chunks")?;
-                    if idx < prefetch_entries {
-                        blob_ctx.blob_readahead_size += size;
+                if ctx.compress_workers > 1 {
+                    self.dump_nodes_parallel(
+                        ctx,
+                        blob_ctx,
+                        blob_index,
+                        nodes,
+                        chunk_dict,
+                        &inodes,
+                        prefetch_entries,
+                    )?;
+                } else {
+                    for (idx, inode) in inodes.iter().enumerate() {
+                        let node = &mut nodes[*inode];
+                        let size = node
+                            .dump_blob(ctx, blob_ctx, blob_index, chunk_dict)
+                            .context("failed to dump blob chunks")?;
+                        if idx < prefetch_entries {
+                            blob_ctx.blob_readahead_size += size;
+                        }
                     }
                 }
 
                 self.dump_meta_data(blob_ctx)?;
             }
+            SourceType::Tar => {
+                // Chunk data has been dumped while parsing the tar stream.
+                self.dump_meta_data(blob_ctx)?;
+            }
             SourceType::StargzIndex => {
                 for node in nodes {
                     if node.overlay.is_lower_layer() {
@@ -82,6 +99,71 @@ impl Blob {
         Ok(blob_exists)
     }
 
+    /// Dump file data of nodes in the layout order, with chunks digested and compressed by
+    /// worker threads.
+    ///
+    /// Processed chunks are written into the blob in the same order as the single-threaded
+    /// path, so the generated blob is identical.
+    #[allow(clippy::too_many_arguments)]
+    fn dump_nodes_parallel<T: ChunkDict>(
+        &mut self,
+        ctx: &BuildContext,
+        blob_ctx: &mut BlobContext,
+        blob_index: u32,
+        nodes: &mut [Node],
+        chunk_dict: &mut T,
+        inodes: &[usize],
+        prefetch_entries: usize,
+    ) -> Result<()> {
+        let mut pipeline = ChunkPipeline::new(ctx.compress_workers, ctx.digester, ctx.compressor)?;
+        let mut writer = ChunkWriter::new(
+            ctx,
+            blob_ctx,
+            blob_index,
+            chunk_dict,
+            Some(inodes),
+            prefetch_entries,
+        );
+        let mut chunk_data_buf = vec![0u8; ctx.chunk_size as usize];
+
+        for (idx, inode) in inodes.iter().enumerate() {
+            let node = &mut nodes[*inode];
+            if !node.is_reg() || node.inode.size() == 0 {
+                // No chunk to dump, just set the inode digest.
+                node.dump_blob(ctx, writer.blob_ctx, blob_index, writer.chunk_dict)
+                    .context("failed to dump blob chunks")?;
+                continue;
+            }
+
+            let (mut file, sparse) = node.open_data_file()?;
+            let path = node.path.clone();
+            let size = node.inode.size();
+            Node::split_chunks(
+                ctx,
+                size,
+                &mut chunk_data_buf,
+                &mut |offset, buf| Node::read_chunk_data(&path, &mut file, sparse, offset, buf),
+                &mut |file_offset, data, is_hole| {
+                    pipeline.submit(ChunkJob {
+                        node: idx,
+                        file_offset,
+                        data: data.to_vec(),
+                        is_hole,
+                        processed: None,
+                    })?;
+                    // Write processed chunks, and wait if there are too many chunks in flight.
+                    while let Some(job) = pipeline.next(pipeline.is_full())? {
+                        writer.write(nodes, job)?;
+                    }
+                    Ok(())
+                },
+            )
+            .context("failed to dump blob chunks")?;
+        }
+
+        writer.flush(&mut pipeline, nodes)
+    }
+
     pub(crate) fn dump_meta_data(&mut self, blob_ctx: &mut BlobContext) -> Result<()> {
         // Dump is only required if there is chunk in the blob or blob meta info enabled
         if !blob_ctx.blob_meta_info_enabled || blob_ctx.compressed_blob_size == 0 {
@@ -122,3 +204,88 @@ impl Blob {
         Ok(())
     }
 }
+
+/// Write chunks processed by worker threads into the blob in order.
+pub(crate) struct ChunkWriter<'a, T: ChunkDict> {
+    ctx: &'a BuildContext,
+    pub blob_ctx: &'a mut BlobContext,
+    pub blob_index: u32,
+    pub chunk_dict: &'a mut T,
+    /// Map node index of chunk jobs to index of `nodes`, in layout order. Chunk jobs refer to
+    /// `nodes` directly if not set.
+    inodes: Option<&'a [usize]>,
+    prefetch_entries: usize,
+    /// Node index of the node being dumped and its dump state.
+    current: Option<(usize, ChunkDumpState)>,
+}
+
+impl<'a, T: ChunkDict> ChunkWriter<'a, T> {
+    pub fn new(
+        ctx: &'a BuildContext,
+        blob_ctx: &'a mut BlobContext,
+        blob_index: u32,
+        chunk_dict: &'a mut T,
+        inodes: Option<&'a [usize]>,
+        prefetch_entries: usize,
+    ) -> Self {
+        ChunkWriter {
+            ctx,
+            blob_ctx,
+            blob_index,
+            chunk_dict,
+            inodes,
+            prefetch_entries,
+            current: None,
+        }
+    }
+
+    fn node_index(&self, idx: usize) -> usize {
+        self.inodes.map(|v| v[idx]).unwrap_or(idx)
+    }
+
+    /// Write a processed chunk into the blob.
+    pub fn write(&mut self, nodes: &mut [Node], job: ChunkJob) -> Result<()> {
+        // Chunks of a node are consecutive, so the previous node has been fully dumped.
+        if matches!(self.current, Some((idx, _)) if idx != job.node) {
+            self.finish(nodes);
+        }
+        let ctx = self.ctx;
+        let node_index = self.node_index(job.node);
+        let (_, state) = self
+            .current
+            .get_or_insert_with(|| (job.node, ChunkDumpState::new(ctx)));
+
+        nodes[node_index]
+            .dump_chunk(
+                ctx,
+                self.blob_ctx,
+                self.blob_index,
+                self.chunk_dict,
+                state,
+                job.file_offset,
+                &job.data,
+                job.is_hole,
+                job.processed,
+            )
+            .context("failed to dump blob chunks")
+    }
+
+    /// Write all chunks in flight and finish the last node.
+    pub fn flush(&mut self, pipeline: &mut ChunkPipeline, nodes: &mut [Node]) -> Result<()> {
+        while let Some(job) = pipeline.next(true)? {
+            self.write(nodes, job)?;
+        }
+        self.finish(nodes);
+
+        Ok(())
+    }
+
+    fn finish(&mut self, nodes: &mut [Node]) {
+        if let Some((idx, state)) = self.current.take() {
+            let size = nodes[self.node_index(idx)].finish_chunks(self.ctx, state);
+            if idx < self.prefetch_entries {
+                self.blob_ctx.blob_readahead_size += size;
+            }
+        }
+    }
+}
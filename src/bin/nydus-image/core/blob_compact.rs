@@ -297,6 +297,10 @@ impl BlobCompactor {
             for chunk_idx in 0..node.chunks.len() {
                 let chunk = &mut node.chunks[chunk_idx];
                 let chunk_key = ChunkKey::from(&chunk.inner);
+                // Hole chunks take no space in blobs, so there's nothing to dedup or move.
+                if chunk.inner.is_hole() {
+                    continue;
+                }
                 if !matches!(
                     self.states[chunk.inner.blob_index() as usize],
                     Some(State::ChunkDict)
@@ -297,7 +297,7 @@ impl Bootstrap {
         }
     }
 
-    fn load_parent_bootstrap(
+    pub(crate) fn load_parent_bootstrap(
         &mut self,
         ctx: &mut BuildContext,
         bootstrap_mgr: &mut BootstrapManager,
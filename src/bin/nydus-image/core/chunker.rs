@@ -0,0 +1,210 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Content defined chunking based on the FastCDC algorithm.
+//!
+//! Chunk boundaries are decided by a rolling gear hash over file content, so inserting or
+//! removing data only affects chunks around the modification, which helps to deduplicate data
+//! among different versions of a file. Normalized chunking is used to keep chunk sizes close to
+//! the average size: a stricter mask is used before the average size and a looser mask after it.
+
+use std::fmt;
+
+use anyhow::Result;
+
+/// Generate the gear table from a fixed seed by splitmix64, so that chunk boundaries are stable
+/// across builds.
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut seed = 0x6e79_6475_7363_6463u64;
+    let mut i = 0;
+    while i < 256 {
+        seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+const GEAR: [u64; 256] = gear_table();
+
+/// Minimal chunk size supported by content defined chunking.
+pub const CDC_MIN_CHUNK_SIZE: u32 = 0x400;
+
+/// Content defined chunker using the FastCDC algorithm.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FastCdc {
+    min_size: u32,
+    avg_size: u32,
+    max_size: u32,
+    mask_small: u64,
+    mask_large: u64,
+}
+
+impl FastCdc {
+    /// Create a chunker with minimal, average and maximal chunk sizes.
+    ///
+    /// The average size must be a power of two and `min_size < avg_size < max_size`.
+    pub fn new(min_size: u32, avg_size: u32, max_size: u32) -> Result<Self> {
+        if min_size < CDC_MIN_CHUNK_SIZE
+            || min_size >= avg_size
+            || avg_size >= max_size
+            || !avg_size.is_power_of_two()
+        {
+            bail!(
+                "invalid content defined chunking sizes: min {:#x}, avg {:#x}, max {:#x}",
+                min_size,
+                avg_size,
+                max_size
+            );
+        }
+
+        let bits = avg_size.trailing_zeros();
+        // Use the high bits of the gear hash, which are affected by more bytes than the low bits.
+        Ok(FastCdc {
+            min_size,
+            avg_size,
+            max_size,
+            mask_small: !0u64 << (64 - (bits + 1)),
+            mask_large: !0u64 << (64 - (bits - 1)),
+        })
+    }
+
+    pub fn max_size(&self) -> u32 {
+        self.max_size
+    }
+
+    /// Find the length of the first chunk in `data`.
+    ///
+    /// The caller should pass in `max_size` bytes of data unless it reaches the end of file.
+    pub fn cut(&self, data: &[u8]) -> usize {
+        let min_size = self.min_size as usize;
+        if data.len() <= min_size {
+            return data.len();
+        }
+
+        let end = std::cmp::min(data.len(), self.max_size as usize);
+        let normal = std::cmp::min(end, self.avg_size as usize);
+        let mut hash = 0u64;
+        let mut pos = min_size;
+
+        while pos < normal {
+            hash = (hash << 1).wrapping_add(GEAR[data[pos] as usize]);
+            if hash & self.mask_small == 0 {
+                return pos + 1;
+            }
+            pos += 1;
+        }
+        while pos < end {
+            hash = (hash << 1).wrapping_add(GEAR[data[pos] as usize]);
+            if hash & self.mask_large == 0 {
+                return pos + 1;
+            }
+            pos += 1;
+        }
+
+        end
+    }
+}
+
+impl fmt::Display for FastCdc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "fastcdc min {:#x} avg {:#x} max {:#x}",
+            self.min_size, self.avg_size, self.max_size
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_data(size: usize) -> Vec<u8> {
+        let mut seed = 0x1234_5678u64;
+        (0..size)
+            .map(|_| {
+                seed = seed
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (seed >> 56) as u8
+            })
+            .collect()
+    }
+
+    fn split(cdc: &FastCdc, mut data: &[u8]) -> Vec<usize> {
+        let mut chunks = Vec::new();
+        while !data.is_empty() {
+            let len = cdc.cut(&data[..std::cmp::min(data.len(), cdc.max_size() as usize)]);
+            chunks.push(len);
+            data = &data[len..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn test_fastcdc_new() {
+        assert!(FastCdc::new(0x1000, 0x4000, 0x10000).is_ok());
+        assert!(FastCdc::new(0x100, 0x4000, 0x10000).is_err());
+        assert!(FastCdc::new(0x4000, 0x4000, 0x10000).is_err());
+        assert!(FastCdc::new(0x1000, 0x5000, 0x10000).is_err());
+        assert!(FastCdc::new(0x1000, 0x10000, 0x10000).is_err());
+
+        let cdc = FastCdc::new(0x10000, 0x40000, 0x100000).unwrap();
+        assert_eq!(cdc.mask_small.count_ones(), 19);
+        assert_eq!(cdc.mask_large.count_ones(), 17);
+        assert_eq!(cdc.max_size(), 0x100000);
+    }
+
+    #[test]
+    fn test_fastcdc_cut() {
+        let cdc = FastCdc::new(0x1000, 0x4000, 0x10000).unwrap();
+        let data = random_data(0x100000);
+        let chunks = split(&cdc, &data);
+
+        assert_eq!(chunks.iter().sum::<usize>(), data.len());
+        for len in &chunks[..chunks.len() - 1] {
+            assert!(*len > 0x1000 && *len <= 0x10000);
+        }
+        let avg = data.len() / chunks.len();
+        assert!(
+            avg > 0x2000 && avg < 0x8000,
+            "average chunk size {:#x}",
+            avg
+        );
+
+        // Short data and zero-filled data.
+        assert_eq!(cdc.cut(&data[..0x800]), 0x800);
+        assert_eq!(cdc.cut(&[0u8; 0x20000]), 0x10000);
+    }
+
+    #[test]
+    fn test_fastcdc_shift_resistant() {
+        let cdc = FastCdc::new(0x1000, 0x4000, 0x10000).unwrap();
+        let data = random_data(0x100000);
+        let mut shifted = random_data(0x100);
+        shifted.extend_from_slice(&data);
+
+        // Chunk boundaries resynchronize after the inserted data.
+        let mut ends = std::collections::HashSet::new();
+        let mut pos = 0;
+        for len in split(&cdc, &data) {
+            pos += len;
+            ends.insert(pos);
+        }
+        let mut pos = 0;
+        let mut matched = 0;
+        for len in split(&cdc, &shifted) {
+            pos += len;
+            if pos >= 0x100 && ends.contains(&(pos - 0x100)) {
+                matched += 1;
+            }
+        }
+        assert!(matched > ends.len() / 2);
+    }
+}
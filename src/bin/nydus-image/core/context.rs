@@ -31,6 +31,7 @@ use storage::device::{BlobFeatures, BlobInfo};
 use storage::meta::{BlobChunkInfoOndisk, BlobMetaHeaderOndisk};
 
 use super::chunk_dict::{ChunkDict, HashChunkDict};
+use super::chunker::FastCdc;
 use super::layout::BlobLayout;
 use super::node::{ChunkSource, ChunkWrapper, Node, WhiteoutSpec};
 use super::prefetch::{Prefetch, PrefetchPolicy};
@@ -79,6 +80,7 @@ pub enum SourceType {
     Directory,
     StargzIndex,
     Diff,
+    Tar,
 }
 
 impl Default for SourceType {
@@ -94,6 +96,7 @@ impl FromStr for SourceType {
             "directory" => Ok(Self::Directory),
             "stargz_index" => Ok(Self::StargzIndex),
             "diff" => Ok(Self::Diff),
+            "tar" => Ok(Self::Tar),
             _ => Err(anyhow!("invalid source type")),
         }
     }
@@ -828,6 +831,11 @@ pub struct BuildContext {
     pub whiteout_spec: WhiteoutSpec,
     /// Chunk slice size.
     pub chunk_size: u32,
+    /// Content defined chunker, file data is split at fixed `chunk_size` if not set.
+    pub chunker: Option<FastCdc>,
+    /// Number of worker threads to digest and compress chunk data, chunks are processed by the
+    /// builder thread if it's not bigger than 1.
+    pub compress_workers: usize,
     /// Version number of output metadata and data blob.
     pub fs_version: RafsVersion,
 
@@ -874,6 +882,8 @@ impl BuildContext {
             whiteout_spec,
 
             chunk_size: RAFS_DEFAULT_CHUNK_SIZE as u32,
+            chunker: None,
+            compress_workers: 1,
             fs_version: RafsVersion::default(),
 
             source_type,
@@ -893,6 +903,14 @@ impl BuildContext {
     pub fn set_chunk_size(&mut self, chunk_size: u32) {
         self.chunk_size = chunk_size;
     }
+
+    pub fn set_chunker(&mut self, chunker: Option<FastCdc>) {
+        self.chunker = chunker;
+    }
+
+    pub fn set_compress_workers(&mut self, workers: usize) {
+        self.compress_workers = workers;
+    }
 }
 
 impl Default for BuildContext {
@@ -907,6 +925,8 @@ impl Default for BuildContext {
             whiteout_spec: WhiteoutSpec::default(),
 
             chunk_size: RAFS_DEFAULT_CHUNK_SIZE as u32,
+            chunker: None,
+            compress_workers: 1,
             fs_version: RafsVersion::default(),
 
             source_type: SourceType::default(),
@@ -6,8 +6,10 @@ pub(crate) mod blob;
 pub(crate) mod blob_compact;
 pub(crate) mod bootstrap;
 pub(crate) mod chunk_dict;
+pub(crate) mod chunker;
 pub(crate) mod context;
 pub(crate) mod layout;
 pub(crate) mod node;
+pub(crate) mod pipeline;
 pub(crate) mod prefetch;
 pub(crate) mod tree;
@@ -5,23 +5,28 @@
 
 //! An in-memory RAFS inode for image building and inspection.
 
+use std::borrow::Cow;
+use std::cmp;
 use std::ffi::{OsStr, OsString};
 use std::fmt::{self, Display, Formatter, Result as FmtResult};
 use std::fs::{self, File};
-use std::io::{Read, SeekFrom, Write};
-use std::mem::size_of;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::mem::{self, size_of};
 use std::os::linux::fs::MetadataExt;
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
 use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
 
 use anyhow::{Context, Error, Result};
+use nix::errno::Errno;
 use nix::sys::stat;
+use nix::unistd::{lseek, Whence};
 use sha2::digest::Digest;
 
 use nydus_utils::{
     compress,
-    digest::{DigestHasher, RafsDigest},
+    digest::{self, DigestHasher, RafsDigest, RafsDigestHasher},
     div_round_up, round_down_4k, round_up, try_round_up_4k, ByteSize,
 };
 use rafs::metadata::cached_v5::{CachedChunkInfoV5, CachedInodeV5};
@@ -33,7 +38,7 @@ use rafs::metadata::layout::v5::{
 use rafs::metadata::layout::v6::{
     align_offset, calculate_nid, RafsV6Dirent, RafsV6InodeChunkAddr, RafsV6InodeChunkHeader,
     RafsV6InodeCompact, RafsV6InodeExtended, RafsV6OndiskInode, EROFS_BLOCK_SIZE,
-    EROFS_INODE_CHUNK_BASED, EROFS_INODE_FLAT_INLINE, EROFS_INODE_FLAT_PLAIN,
+    EROFS_INODE_CHUNK_BASED, EROFS_INODE_FLAT_INLINE, EROFS_INODE_FLAT_PLAIN, EROFS_NULL_ADDR,
 };
 use rafs::metadata::layout::RafsXAttrs;
 use rafs::metadata::{Inode, RafsInode, RafsStore};
@@ -185,6 +190,49 @@ impl Display for ChunkSource {
     }
 }
 
+/// State shared by all chunks of a file when dumping file data into the blob.
+pub(crate) struct ChunkDumpState {
+    inode_hasher: RafsDigestHasher,
+    blob_size: u64,
+    /// Size and digest of the last hole chunk.
+    hole_id: Option<(u32, RafsDigest)>,
+}
+
+impl ChunkDumpState {
+    pub fn new(ctx: &BuildContext) -> Self {
+        ChunkDumpState {
+            inode_hasher: RafsDigest::hasher(ctx.digester),
+            blob_size: 0,
+            hole_id: None,
+        }
+    }
+}
+
+/// Digest and compressed data of a chunk, which may be computed by worker threads.
+pub(crate) struct ProcessedChunk {
+    pub id: RafsDigest,
+    pub compressed: Vec<u8>,
+    pub is_compressed: bool,
+}
+
+impl ProcessedChunk {
+    pub fn new(
+        data: &[u8],
+        digester: digest::Algorithm,
+        compressor: compress::Algorithm,
+    ) -> Result<Self> {
+        let id = RafsDigest::from_buf(data, digester);
+        let (compressed, is_compressed) =
+            compress::compress(data, compressor).context("failed to compress chunk data")?;
+
+        Ok(ProcessedChunk {
+            id,
+            compressed: compressed.into_owned(),
+            is_compressed,
+        })
+    }
+}
+
 /// Rafs inode information to support image building and parsing.
 #[derive(Clone)]
 pub struct Node {
@@ -320,153 +368,393 @@ impl Node {
         blob_index: u32,
         chunk_dict: &mut T,
     ) -> Result<u64> {
-        if self.is_dir() {
+        if !self.is_reg() {
+            self.set_non_reg_digest(ctx)?;
             return Ok(0);
-        } else if self.is_symlink() {
+        }
+
+        let (mut file, sparse) = self.open_data_file()?;
+        let path = self.path.clone();
+
+        self.dump_blob_chunks(ctx, blob_ctx, blob_index, chunk_dict, |offset, buf| {
+            Self::read_chunk_data(&path, &mut file, sparse, offset, buf)
+        })
+    }
+
+    /// Open the source file to read file data, and check whether it's a sparse file.
+    pub(crate) fn open_data_file(&self) -> Result<(File, bool)> {
+        let file = File::open(&self.path)
+            .with_context(|| format!("failed to open node file {:?}", self.path))?;
+        // Only sparse files, which have less blocks allocated than file size, need to probe
+        // file holes by SEEK_DATA. All-zero chunks are always recorded as hole chunks.
+        let sparse = file
+            .metadata()
+            .map(|m| m.st_blocks() * 512 < m.st_size())
+            .with_context(|| format!("failed to get metadata of node file {:?}", self.path))?;
+
+        Ok((file, sparse))
+    }
+
+    /// Dump file data from `reader` instead of the source file, such as an entry of tar stream.
+    pub fn dump_blob_with_reader<T: ChunkDict, R: Read>(
+        self: &mut Node,
+        ctx: &BuildContext,
+        blob_ctx: &mut BlobContext,
+        blob_index: u32,
+        chunk_dict: &mut T,
+        reader: &mut R,
+    ) -> Result<u64> {
+        if !self.is_reg() {
+            self.set_non_reg_digest(ctx)?;
+            return Ok(0);
+        }
+
+        let path = self.path.clone();
+        self.dump_blob_chunks(ctx, blob_ctx, blob_index, chunk_dict, |_offset, buf| {
+            Self::read_chunk_from_reader(&path, reader, buf)
+        })
+    }
+
+    /// Read chunk data sequentially from `reader`, return whether the chunk is a hole.
+    pub(crate) fn read_chunk_from_reader<R: Read>(
+        path: &Path,
+        reader: &mut R,
+        buf: &mut [u8],
+    ) -> Result<bool> {
+        reader
+            .read_exact(buf)
+            .with_context(|| format!("failed to read data of {:?}", path))?;
+        Ok(buf.iter().all(|v| *v == 0))
+    }
+
+    fn set_non_reg_digest(&mut self, ctx: &BuildContext) -> Result<()> {
+        if self.is_symlink() {
             if let Some(symlink) = self.symlink.as_ref() {
                 self.inode
                     .set_digest(RafsDigest::from_buf(symlink.as_bytes(), ctx.digester));
-                return Ok(0);
             } else {
                 return Err(Error::msg("inode's symblink is invalid."));
             }
         } else if self.is_special() {
             self.inode
                 .set_digest(RafsDigest::hasher(ctx.digester).digest_finalize());
-            return Ok(0);
         }
 
-        let mut file = File::open(&self.path)
-            .with_context(|| format!("failed to open node file {:?}", self.path))?;
-        let mut inode_hasher = RafsDigest::hasher(ctx.digester);
-        let mut blob_size = 0u64;
-
-        // `child_count` of regular file is reused as `chunk_count`.
-        for i in 0..self.inode.child_count() {
-            let chunk_size = blob_ctx.chunk_size;
-            let file_offset = i as u64 * chunk_size as u64;
-            let chunk_size = if i == self.inode.child_count() - 1 {
-                (self.inode.size() as u64)
-                    .checked_sub((chunk_size * i) as u64)
-                    .ok_or_else(|| {
-                        anyhow!("the rest chunk size of inode is bigger than chunk_size")
-                    })? as u32
-            } else {
-                chunk_size
-            };
+        Ok(())
+    }
 
-            let chunk_data = &mut blob_ctx.chunk_data_buf[0..chunk_size as usize];
-            file.read_exact(chunk_data)
-                .with_context(|| format!("failed to read node file {:?}", self.path))?;
+    /// Split file data into chunks and dump them into the blob.
+    ///
+    /// `read_chunk` fills the buffer with data at the file offset and returns whether the chunk
+    /// is a hole. File data is read sequentially, so `read_chunk` may ignore the file offset.
+    fn dump_blob_chunks<T, F>(
+        &mut self,
+        ctx: &BuildContext,
+        blob_ctx: &mut BlobContext,
+        blob_index: u32,
+        chunk_dict: &mut T,
+        mut read_chunk: F,
+    ) -> Result<u64>
+    where
+        T: ChunkDict,
+        F: FnMut(u64, &mut [u8]) -> Result<bool>,
+    {
+        let mut state = ChunkDumpState::new(ctx);
+        let size = self.inode.size();
+        // Take the buffer out of `blob_ctx`, which is updated when dumping chunks.
+        let mut chunk_data_buf = mem::take(&mut blob_ctx.chunk_data_buf);
+
+        let ret = Self::split_chunks(
+            ctx,
+            size,
+            &mut chunk_data_buf,
+            &mut read_chunk,
+            &mut |file_offset, chunk_data, is_hole| {
+                self.dump_chunk(
+                    ctx,
+                    blob_ctx,
+                    blob_index,
+                    chunk_dict,
+                    &mut state,
+                    file_offset,
+                    chunk_data,
+                    is_hole,
+                    None,
+                )
+            },
+        );
+        blob_ctx.chunk_data_buf = chunk_data_buf;
+        ret?;
 
-            // TODO: check for hole chunks. One possible way is to always save
-            // a global hole chunk and check for digest duplication
-            let chunk_id = RafsDigest::from_buf(chunk_data, ctx.digester);
-            inode_hasher.digest_update(chunk_id.as_ref());
+        Ok(self.finish_chunks(ctx, state))
+    }
 
-            let mut chunk = self.inode.create_chunk();
-            chunk.set_id(chunk_id);
+    /// Split file data into chunks in file order.
+    ///
+    /// File data is split at fixed `chunk_size`, or by content defined chunking if enabled.
+    /// `dump_chunk` is called with the file offset, data and hole flag of each chunk.
+    pub(crate) fn split_chunks<R, D>(
+        ctx: &BuildContext,
+        size: u64,
+        chunk_data_buf: &mut [u8],
+        read_chunk: &mut R,
+        dump_chunk: &mut D,
+    ) -> Result<()>
+    where
+        R: FnMut(u64, &mut [u8]) -> Result<bool>,
+        D: FnMut(u64, &[u8], bool) -> Result<()>,
+    {
+        let chunker = match ctx.chunker.as_ref() {
+            Some(chunker) => chunker,
+            None => {
+                let chunk_size = ctx.chunk_size as u64;
+                let mut file_offset = 0;
+                while file_offset < size {
+                    let chunk_size = cmp::min(chunk_size, size - file_offset) as usize;
+                    let chunk_data = &mut chunk_data_buf[0..chunk_size];
+                    let is_hole = read_chunk(file_offset, chunk_data)?;
+                    dump_chunk(file_offset, chunk_data, is_hole)?;
+                    file_offset += chunk_size as u64;
+                }
+                return Ok(());
+            }
+        };
 
-            // Check whether we already have the same chunk data by matching chunk digest.
-            let exist_chunk = match blob_ctx.chunk_dict.get_chunk(&chunk_id) {
-                Some(v) => Some((v, true)),
-                None => chunk_dict.get_chunk(&chunk_id).map(|v| (v, false)),
-            };
-            if let Some((cached_chunk, from_dict)) = exist_chunk {
-                // TODO: we should also compare the actual data to avoid chunk digest conflicts.
-                // hole cached_chunk may have zero uncompressed size
-                if cached_chunk.uncompressed_size() == 0
-                    || cached_chunk.uncompressed_size() == chunk_size
-                {
-                    // The chunks of hardlink should be always deduplicated.
-                    if !self.is_hardlink() {
-                        event_tracer!("dedup_decompressed_size", +chunk_size);
-                        event_tracer!("dedup_chunks", +1);
-                    }
+        // Data after the cut point is kept in the buffer and becomes head of the next chunk.
+        let mut file_offset = 0u64;
+        let mut buffered = 0usize;
+        while file_offset < size {
+            let window = cmp::min(chunker.max_size() as u64, size - file_offset) as usize;
+            if buffered < window {
+                read_chunk(
+                    file_offset + buffered as u64,
+                    &mut chunk_data_buf[buffered..window],
+                )?;
+                buffered = window;
+            }
 
-                    chunk.copy_from(cached_chunk);
-                    chunk.set_file_offset(file_offset);
-                    if from_dict {
-                        let idx = blob_ctx.chunk_dict.get_real_blob_idx(chunk.blob_index());
-                        chunk.set_blob_index(idx);
-                    }
-                    trace!(
-                        "\t\tbuilding duplicated chunk: {} compressor {}",
-                        chunk,
-                        ctx.compressor
-                    );
+            let chunk_size = chunker.cut(&chunk_data_buf[..window]);
+            let chunk_data = &chunk_data_buf[..chunk_size];
+            let is_hole = chunk_data.iter().all(|v| *v == 0);
+            dump_chunk(file_offset, chunk_data, is_hole)?;
 
-                    let source = if from_dict {
-                        ChunkSource::Dict
-                    } else {
-                        ChunkSource::Build
-                    };
-                    self.chunks.push(NodeChunk {
-                        source,
-                        inner: chunk,
-                    });
-
-                    continue;
-                }
-            }
+            chunk_data_buf.copy_within(chunk_size..buffered, 0);
+            buffered -= chunk_size;
+            file_offset += chunk_size as u64;
+        }
 
-            // Compress chunk data
-            let (compressed, is_compressed) = compress::compress(chunk_data, ctx.compressor)
-                .with_context(|| format!("failed to compress node file {:?}", self.path))?;
-            let compressed_size = compressed.len();
+        Ok(())
+    }
 
-            // Move cursor to offset of next chunk
-            let aligned_chunk_size = if ctx.aligned_chunk {
-                // Safe to unwrap because `chunk_size` is much less than u32::MAX.
-                try_round_up_4k(chunk_size).unwrap()
-            } else {
-                chunk_size
-            };
+    /// Finish dumping chunks of the node, return compressed size of chunks dumped into the blob.
+    pub(crate) fn finish_chunks(&mut self, ctx: &BuildContext, state: ChunkDumpState) -> u64 {
+        if ctx.chunker.is_some() {
+            // `child_count` of regular file is reused as `chunk_count`.
+            self.inode.set_child_count(self.chunks.len() as u32);
+            self.inode.set_has_variable_chunk(true);
+        }
+        // Finish inode digest calculation
+        self.inode.set_digest(state.inode_hasher.digest_finalize());
 
-            let pre_decompress_offset = blob_ctx.decompress_offset;
-            let pre_compress_offset = blob_ctx.compress_offset;
-
-            blob_ctx.compress_offset += compressed_size as u64;
-            blob_ctx.decompressed_blob_size =
-                blob_ctx.decompress_offset + aligned_chunk_size as u64;
-            blob_ctx.compressed_blob_size += compressed_size as u64;
-            blob_ctx.decompress_offset += aligned_chunk_size as u64;
-            blob_ctx.blob_hash.update(&compressed);
-
-            // Dump compressed chunk data to blob
-            event_tracer!("blob_decompressed_size", +chunk_size);
-            event_tracer!("blob_compressed_size", +compressed_size);
-            if let Some(writer) = &mut blob_ctx.writer {
-                writer
-                    .write_all(&compressed)
-                    .context("failed to write blob")?;
-            }
+        state.blob_size
+    }
 
-            let chunk_index = blob_ctx.alloc_index()?;
+    /// Dump a chunk into the blob, or reference an existing chunk with the same content.
+    ///
+    /// Digest and compressed data of the chunk are computed here unless `processed` is given.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn dump_chunk<T: ChunkDict>(
+        &mut self,
+        ctx: &BuildContext,
+        blob_ctx: &mut BlobContext,
+        blob_index: u32,
+        chunk_dict: &mut T,
+        state: &mut ChunkDumpState,
+        file_offset: u64,
+        chunk_data: &[u8],
+        is_hole: bool,
+        processed: Option<ProcessedChunk>,
+    ) -> Result<()> {
+        let chunk_size = chunk_data.len() as u32;
+
+        if is_hole {
+            let chunk_id = match state.hole_id {
+                Some((size, id)) if size == chunk_size => id,
+                _ => {
+                    let id = RafsDigest::from_buf(chunk_data, ctx.digester);
+                    state.hole_id = Some((chunk_size, id));
+                    id
+                }
+            };
+            state.inode_hasher.digest_update(chunk_id.as_ref());
+
+            // Hole chunks take no space in the blob, they are served with zeros.
+            let mut chunk = self.inode.create_chunk();
+            chunk.set_id(chunk_id);
             chunk.set_chunk_info(
                 blob_index,
-                chunk_index,
+                0,
                 file_offset,
-                pre_decompress_offset,
-                pre_compress_offset,
-                compressed_size,
+                blob_ctx.decompress_offset,
+                blob_ctx.compress_offset,
+                0,
                 chunk_size,
-                is_compressed,
+                false,
             )?;
+            chunk.set_hole(true);
+            trace!("\t\tbuilding hole chunk: {}", chunk);
+            event_tracer!("hole_chunks", +1);
 
-            blob_ctx.add_chunk_meta_info(&chunk)?;
-            chunk_dict.add_chunk(chunk.clone());
+            self.inode.set_has_hole(true);
             self.chunks.push(NodeChunk {
                 source: ChunkSource::Build,
                 inner: chunk,
             });
-            blob_size += compressed_size as u64;
+            return Ok(());
         }
 
-        // Finish inode digest calculation
-        self.inode.set_digest(inode_hasher.digest_finalize());
+        let (chunk_id, compressed) = match processed {
+            Some(p) => (p.id, Some((p.compressed, p.is_compressed))),
+            None => (RafsDigest::from_buf(chunk_data, ctx.digester), None),
+        };
+        state.inode_hasher.digest_update(chunk_id.as_ref());
+
+        let mut chunk = self.inode.create_chunk();
+        chunk.set_id(chunk_id);
 
-        Ok(blob_size)
+        // Check whether we already have the same chunk data by matching chunk digest.
+        let exist_chunk = match blob_ctx.chunk_dict.get_chunk(&chunk_id) {
+            Some(v) => Some((v, true)),
+            None => chunk_dict.get_chunk(&chunk_id).map(|v| (v, false)),
+        };
+        if let Some((cached_chunk, from_dict)) = exist_chunk {
+            // TODO: we should also compare the actual data to avoid chunk digest conflicts.
+            // hole cached_chunk may have zero uncompressed size
+            if cached_chunk.uncompressed_size() == 0
+                || cached_chunk.uncompressed_size() == chunk_size
+            {
+                // The chunks of hardlink should be always deduplicated.
+                if !self.is_hardlink() {
+                    event_tracer!("dedup_decompressed_size", +chunk_size);
+                    event_tracer!("dedup_chunks", +1);
+                }
+
+                chunk.copy_from(cached_chunk);
+                chunk.set_file_offset(file_offset);
+                if from_dict {
+                    let idx = blob_ctx.chunk_dict.get_real_blob_idx(chunk.blob_index());
+                    chunk.set_blob_index(idx);
+                }
+                trace!(
+                    "\t\tbuilding duplicated chunk: {} compressor {}",
+                    chunk,
+                    ctx.compressor
+                );
+
+                let source = if from_dict {
+                    ChunkSource::Dict
+                } else {
+                    ChunkSource::Build
+                };
+                self.chunks.push(NodeChunk {
+                    source,
+                    inner: chunk,
+                });
+
+                return Ok(());
+            }
+        }
+
+        // Compress chunk data
+        let (compressed, is_compressed) = match compressed {
+            Some((compressed, is_compressed)) => (Cow::Owned(compressed), is_compressed),
+            None => compress::compress(chunk_data, ctx.compressor)
+                .with_context(|| format!("failed to compress node file {:?}", self.path))?,
+        };
+        let compressed_size = compressed.len();
+
+        // Move cursor to offset of next chunk
+        let aligned_chunk_size = if ctx.aligned_chunk {
+            // Safe to unwrap because `chunk_size` is much less than u32::MAX.
+            try_round_up_4k(chunk_size).unwrap()
+        } else {
+            chunk_size
+        };
+
+        let pre_decompress_offset = blob_ctx.decompress_offset;
+        let pre_compress_offset = blob_ctx.compress_offset;
+
+        blob_ctx.compress_offset += compressed_size as u64;
+        blob_ctx.decompressed_blob_size = blob_ctx.decompress_offset + aligned_chunk_size as u64;
+        blob_ctx.compressed_blob_size += compressed_size as u64;
+        blob_ctx.decompress_offset += aligned_chunk_size as u64;
+        blob_ctx.blob_hash.update(&compressed);
+
+        // Dump compressed chunk data to blob
+        event_tracer!("blob_decompressed_size", +chunk_size);
+        event_tracer!("blob_compressed_size", +compressed_size);
+        if let Some(writer) = &mut blob_ctx.writer {
+            writer
+                .write_all(&compressed)
+                .context("failed to write blob")?;
+        }
+
+        let chunk_index = blob_ctx.alloc_index()?;
+        chunk.set_chunk_info(
+            blob_index,
+            chunk_index,
+            file_offset,
+            pre_decompress_offset,
+            pre_compress_offset,
+            compressed_size,
+            chunk_size,
+            is_compressed,
+        )?;
+
+        blob_ctx.add_chunk_meta_info(&chunk)?;
+        chunk_dict.add_chunk(chunk.clone());
+        self.chunks.push(NodeChunk {
+            source: ChunkSource::Build,
+            inner: chunk,
+        });
+        state.blob_size += compressed_size as u64;
+
+        Ok(())
+    }
+
+    /// Read data of the chunk at `offset` into `buf`, return true if it's a hole chunk.
+    ///
+    /// For sparse files, file holes are detected by `SEEK_DATA` without reading them.
+    pub(crate) fn read_chunk_data(
+        path: &Path,
+        file: &mut File,
+        sparse: bool,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<bool> {
+        if sparse {
+            let end = offset + buf.len() as u64;
+            let has_data = match lseek(file.as_raw_fd(), offset as i64, Whence::SeekData) {
+                Ok(pos) => (pos as u64) < end,
+                // There's no more data after `offset`.
+                Err(Errno::ENXIO) => false,
+                // The filesystem may not support SEEK_DATA, fall back to reading data.
+                Err(_) => true,
+            };
+            if !has_data {
+                for v in buf.iter_mut() {
+                    *v = 0;
+                }
+                return Ok(true);
+            }
+            file.seek(SeekFrom::Start(offset))
+                .with_context(|| format!("failed to seek node file {:?}", path))?;
+        }
+
+        file.read_exact(buf)
+            .with_context(|| format!("failed to read node file {:?}", path))?;
+
+        Ok(buf.iter().all(|v| *v == 0))
     }
 
     pub fn dump_bootstrap_v5(
@@ -519,7 +807,7 @@ impl Node {
         }
     }
 
-    fn set_v6_inode_compact(&mut self) {
+    pub(crate) fn set_v6_inode_compact(&mut self) {
         if self.v6_force_extended_inode
             || self.inode.uid() > std::u16::MAX as u32
             || self.inode.gid() > std::u16::MAX as u32
@@ -711,8 +999,13 @@ impl Node {
                 // for erofs, bump id by 1 since device id 0 is bootstrap.
                 v6_chunk.set_blob_index((chunk.inner.blob_index() + 1) as u8);
                 v6_chunk.set_blob_comp_index(chunk.inner.index());
-                v6_chunk
-                    .set_block_addr((chunk.inner.uncompressed_offset() / EROFS_BLOCK_SIZE) as u32);
+                if chunk.inner.is_hole() {
+                    v6_chunk.set_block_addr(EROFS_NULL_ADDR);
+                } else {
+                    v6_chunk.set_block_addr(
+                        (chunk.inner.uncompressed_offset() / EROFS_BLOCK_SIZE) as u32,
+                    );
+                }
                 trace!("name {:?} chunk {}", self.name(), chunk);
 
                 chunks.extend(v6_chunk.as_ref());
@@ -1365,6 +1658,43 @@ impl InodeWrapper {
         }
     }
 
+    pub fn set_has_hole(&mut self, enable: bool) {
+        match self {
+            InodeWrapper::V5(i) => {
+                if enable {
+                    i.i_flags |= RafsV5InodeFlags::HAS_HOLE;
+                } else {
+                    i.i_flags &= !RafsV5InodeFlags::HAS_HOLE;
+                }
+            }
+            InodeWrapper::V6(i) => {
+                if enable {
+                    i.i_flags |= RafsV5InodeFlags::HAS_HOLE;
+                } else {
+                    i.i_flags &= !RafsV5InodeFlags::HAS_HOLE;
+                }
+            }
+        }
+    }
+    pub fn set_has_variable_chunk(&mut self, enable: bool) {
+        match self {
+            InodeWrapper::V5(i) => {
+                if enable {
+                    i.i_flags |= RafsV5InodeFlags::VARIABLE_CHUNK;
+                } else {
+                    i.i_flags &= !RafsV5InodeFlags::VARIABLE_CHUNK;
+                }
+            }
+            InodeWrapper::V6(i) => {
+                if enable {
+                    i.i_flags |= RafsV5InodeFlags::VARIABLE_CHUNK;
+                } else {
+                    i.i_flags &= !RafsV5InodeFlags::VARIABLE_CHUNK;
+                }
+            }
+        }
+    }
+
     pub fn ino(&self) -> Inode {
         match self {
             InodeWrapper::V5(i) => i.i_ino,
@@ -1725,6 +2055,20 @@ impl ChunkWrapper {
         }
     }
 
+    pub fn is_hole(&self) -> bool {
+        match self {
+            ChunkWrapper::V5(c) => c.flags.contains(BlobChunkFlags::HOLECHUNK),
+            ChunkWrapper::V6(c) => c.flags.contains(BlobChunkFlags::HOLECHUNK),
+        }
+    }
+
+    pub fn set_hole(&mut self, hole: bool) {
+        match self {
+            ChunkWrapper::V5(c) => c.flags.set(BlobChunkFlags::HOLECHUNK, hole),
+            ChunkWrapper::V6(c) => c.flags.set(BlobChunkFlags::HOLECHUNK, hole),
+        }
+    }
+
     #[allow(clippy::too_many_arguments)]
     #[inline]
     fn set_chunk_info(
@@ -2016,4 +2360,31 @@ mod tests {
 
         std::fs::remove_file(&pa_pyc).unwrap();
     }
+
+    #[test]
+    fn test_read_chunk_data_with_hole() {
+        let pa = TempDir::new().unwrap();
+        let pa_aa = TempFile::new_in(pa.as_path()).unwrap();
+        let mut file = pa_aa.as_file().try_clone().unwrap();
+        file.seek(SeekFrom::Start(0x10000)).unwrap();
+        file.write_all(&[0xa5u8; 0x1000]).unwrap();
+        file.set_len(0x40000).unwrap();
+
+        let path = pa_aa.as_path();
+        let mut buf = vec![0x1u8; 0x10000];
+        assert!(Node::read_chunk_data(path, &mut file, true, 0, &mut buf).unwrap());
+        assert!(buf.iter().all(|v| *v == 0));
+        assert!(!Node::read_chunk_data(path, &mut file, true, 0x10000, &mut buf).unwrap());
+        assert_eq!(buf[0], 0xa5);
+        assert!(Node::read_chunk_data(path, &mut file, true, 0x30000, &mut buf).unwrap());
+
+        // All-zero chunks are holes even without SEEK_DATA.
+        file.seek(SeekFrom::Start(0x20000)).unwrap();
+        assert!(Node::read_chunk_data(path, &mut file, false, 0x20000, &mut buf).unwrap());
+
+        let mut chunk = ChunkWrapper::new(RafsVersion::V6);
+        assert!(!chunk.is_hole());
+        chunk.set_hole(true);
+        assert!(chunk.is_hole());
+    }
 }
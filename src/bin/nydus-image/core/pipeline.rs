@@ -0,0 +1,214 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Pipeline to digest and compress chunk data by a pool of worker threads.
+//!
+//! Chunks are submitted in file order, processed concurrently and handed back in the submitting
+//! order, so the generated blob is byte-identical to the one generated by a single thread.
+
+use std::collections::BTreeMap;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{Context, Result};
+use nydus_utils::{compress, digest};
+
+use super::node::ProcessedChunk;
+
+/// Max number of chunks in flight for each worker thread.
+const CHUNKS_PER_WORKER: u64 = 4;
+
+/// A chunk to be digested and compressed.
+pub(crate) struct ChunkJob {
+    /// Index of the node owning the chunk.
+    pub node: usize,
+    pub file_offset: u64,
+    pub data: Vec<u8>,
+    pub is_hole: bool,
+    /// Digest and compressed data of the chunk, hole chunks are not processed.
+    pub processed: Option<ProcessedChunk>,
+}
+
+pub(crate) struct ChunkPipeline {
+    job_tx: Option<Sender<(u64, ChunkJob)>>,
+    result_rx: Receiver<(u64, Result<ChunkJob>)>,
+    workers: Vec<JoinHandle<()>>,
+    /// Processed chunks waiting for chunks submitted before them.
+    pending: BTreeMap<u64, Result<ChunkJob>>,
+    next_submit: u64,
+    next_output: u64,
+    max_in_flight: u64,
+}
+
+impl ChunkPipeline {
+    pub fn new(
+        workers: usize,
+        digester: digest::Algorithm,
+        compressor: compress::Algorithm,
+    ) -> Result<Self> {
+        let (job_tx, job_rx) = channel::<(u64, ChunkJob)>();
+        let (result_tx, result_rx) = channel();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+
+        let mut handles = Vec::with_capacity(workers);
+        for idx in 0..workers {
+            let job_rx = job_rx.clone();
+            let result_tx = result_tx.clone();
+            let handle = thread::Builder::new()
+                .name(format!("chunk_worker_{}", idx))
+                .spawn(move || Self::work(job_rx, result_tx, digester, compressor))
+                .context("failed to create chunk worker thread")?;
+            handles.push(handle);
+        }
+
+        Ok(ChunkPipeline {
+            job_tx: Some(job_tx),
+            result_rx,
+            workers: handles,
+            pending: BTreeMap::new(),
+            next_submit: 0,
+            next_output: 0,
+            max_in_flight: workers as u64 * CHUNKS_PER_WORKER,
+        })
+    }
+
+    fn work(
+        job_rx: Arc<Mutex<Receiver<(u64, ChunkJob)>>>,
+        result_tx: Sender<(u64, Result<ChunkJob>)>,
+        digester: digest::Algorithm,
+        compressor: compress::Algorithm,
+    ) {
+        loop {
+            // Release the lock before processing the chunk.
+            let msg = job_rx.lock().unwrap().recv();
+            let (seq, mut job) = match msg {
+                Ok(v) => v,
+                // The job channel has been closed.
+                Err(_) => break,
+            };
+            let ret = if job.is_hole {
+                Ok(job)
+            } else {
+                ProcessedChunk::new(&job.data, digester, compressor).map(|processed| {
+                    job.processed = Some(processed);
+                    job
+                })
+            };
+            if result_tx.send((seq, ret)).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Check whether there are too many chunks in flight, the caller should wait for processed
+    /// chunks before submitting more.
+    pub fn is_full(&self) -> bool {
+        self.next_submit - self.next_output >= self.max_in_flight
+    }
+
+    /// Submit a chunk to worker threads.
+    pub fn submit(&mut self, job: ChunkJob) -> Result<()> {
+        // Safe to unwrap because the job channel is only closed on drop.
+        self.job_tx
+            .as_ref()
+            .unwrap()
+            .send((self.next_submit, job))
+            .map_err(|_| anyhow!("chunk worker threads exited unexpectedly"))?;
+        self.next_submit += 1;
+
+        Ok(())
+    }
+
+    /// Get the next processed chunk in submitting order.
+    ///
+    /// Block until the chunk is ready if `wait` is true, otherwise return `None` if it's not
+    /// ready yet. Return `None` if all submitted chunks have been handed back.
+    pub fn next(&mut self, wait: bool) -> Result<Option<ChunkJob>> {
+        if self.next_output == self.next_submit {
+            return Ok(None);
+        }
+
+        loop {
+            if let Some(ret) = self.pending.remove(&self.next_output) {
+                self.next_output += 1;
+                return ret.map(Some);
+            }
+
+            let (seq, ret) = if wait {
+                self.result_rx
+                    .recv()
+                    .map_err(|_| anyhow!("chunk worker threads exited unexpectedly"))?
+            } else {
+                match self.result_rx.try_recv() {
+                    Ok(v) => v,
+                    Err(TryRecvError::Empty) => return Ok(None),
+                    Err(TryRecvError::Disconnected) => {
+                        bail!("chunk worker threads exited unexpectedly")
+                    }
+                }
+            };
+            self.pending.insert(seq, ret);
+        }
+    }
+}
+
+impl Drop for ChunkPipeline {
+    fn drop(&mut self) {
+        // Close the job channel to stop worker threads.
+        self.job_tx.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_pipeline_order() {
+        let mut pipeline =
+            ChunkPipeline::new(4, digest::Algorithm::Blake3, compress::Algorithm::Lz4Block)
+                .unwrap();
+        let mut chunks = Vec::new();
+        for idx in 0..64usize {
+            // Chunks of different sizes take different time to process.
+            let data = vec![(idx % 8) as u8; (64 - idx) * 0x1000];
+            chunks.push(data.clone());
+            pipeline
+                .submit(ChunkJob {
+                    node: idx,
+                    file_offset: 0,
+                    data,
+                    is_hole: idx % 8 == 0,
+                    processed: None,
+                })
+                .unwrap();
+        }
+
+        for (idx, data) in chunks.iter().enumerate() {
+            let job = pipeline.next(true).unwrap().unwrap();
+            assert_eq!(job.node, idx);
+            assert_eq!(&job.data, data);
+            if idx % 8 == 0 {
+                assert!(job.processed.is_none());
+            } else {
+                let expected = ProcessedChunk::new(
+                    data,
+                    digest::Algorithm::Blake3,
+                    compress::Algorithm::Lz4Block,
+                )
+                .unwrap();
+                let processed = job.processed.unwrap();
+                assert_eq!(processed.id, expected.id);
+                assert_eq!(processed.compressed, expected.compressed);
+                assert_eq!(processed.is_compressed, expected.is_compressed);
+            }
+        }
+        assert!(pipeline.next(true).unwrap().is_none());
+        assert!(pipeline.next(false).unwrap().is_none());
+    }
+}
@@ -31,9 +31,12 @@ use nydus_storage::factory::{BackendConfig, BlobFactory};
 use nydus_storage::RAFS_DEFAULT_CHUNK_SIZE;
 use nydus_utils::{compress, digest};
 
-use crate::builder::{Builder, DiffBuilder, DirectoryBuilder, StargzBuilder};
+use crate::builder::{
+    Builder, DiffBuilder, DirectoryBuilder, StargzBuilder, TarballBuilder, TAR_STDIN,
+};
 use crate::core::blob_compact::BlobCompactor;
 use crate::core::chunk_dict::{import_chunk_dict, parse_chunk_dict_arg};
+use crate::core::chunker::FastCdc;
 use crate::core::context::{
     ArtifactStorage, BlobManager, BootstrapManager, BuildContext, BuildOutput, BuildOutputArtifact,
     RafsVersion, SourceType,
@@ -43,6 +46,7 @@ use crate::core::prefetch::Prefetch;
 use crate::core::tree;
 use crate::merge::Merger;
 use crate::trace::{EventTracerClass, TimingTracerClass, TraceClass};
+use crate::unpack::OCIUnpacker;
 use crate::validator::Validator;
 
 #[macro_use]
@@ -52,6 +56,7 @@ mod core;
 mod inspect;
 mod merge;
 mod stat;
+mod unpack;
 mod validator;
 
 const BLOB_ID_MAXIMUM_LENGTH: usize = 255;
@@ -172,7 +177,7 @@ fn prepare_cmd_args(bti_string: String) -> ArgMatches<'static> {
                         .help("type of the source:")
                         .takes_value(true)
                         .default_value("directory")
-                        .possible_values(&["directory", "stargz_index", "diff"])
+                        .possible_values(&["directory", "stargz_index", "diff", "tar"])
                 )
                 .arg(
                     Arg::with_name("diff-overlay-hint")
@@ -233,6 +238,36 @@ fn prepare_cmd_args(bti_string: String) -> ArgMatches<'static> {
                         .required(false)
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::with_name("chunking-algorithm")
+                        .long("chunking-algorithm")
+                        .help("algorithm to split file data into chunks, fastcdc is only supported by RAFS v5:")
+                        .takes_value(true)
+                        .required(false)
+                        .default_value("fixed")
+                        .possible_values(&["fixed", "fastcdc"]),
+                )
+                .arg(
+                    Arg::with_name("cdc-min-size")
+                        .long("cdc-min-size")
+                        .help("minimal chunk size of content defined chunking, default to cdc-avg-size / 4")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("cdc-avg-size")
+                        .long("cdc-avg-size")
+                        .help("average chunk size of content defined chunking, must be power of two, default to cdc-max-size / 4")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("cdc-max-size")
+                        .long("cdc-max-size")
+                        .help("maximal chunk size of content defined chunking, must not be bigger than chunk-size, default to chunk-size")
+                        .required(false)
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::with_name("compressor")
                         .long("compressor")
@@ -243,6 +278,14 @@ fn prepare_cmd_args(bti_string: String) -> ArgMatches<'static> {
                         .default_value("lz4_block")
                         .possible_values(&["none", "lz4_block", "gzip", "zstd"]),
                 )
+                .arg(
+                    Arg::with_name("compress-workers")
+                        .long("compress-workers")
+                        .help("number of threads to digest and compress chunk data, generated blob is identical to the one generated by a single thread:")
+                        .takes_value(true)
+                        .required(false)
+                        .default_value("1"),
+                )
                 .arg(
                     Arg::with_name("digester")
                         .long("digester")
@@ -503,6 +546,55 @@ fn prepare_cmd_args(bti_string: String) -> ArgMatches<'static> {
                         .help("path to JSON output file")
                         .takes_value(true))
         )
+        .subcommand(
+            SubCommand::with_name("unpack")
+                .about("Unpack nydus image into an OCI tar layer")
+                .arg(
+                    Arg::with_name("bootstrap")
+                        .long("bootstrap")
+                        .short("B")
+                        .help("path to nydus image's metadata blob (required)")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("blob")
+                        .long("blob")
+                        .short("b")
+                        .help("path to nydus image's data blob")
+                        .takes_value(true)
+                        .conflicts_with_all(&["blob-dir", "backend-type"]),
+                )
+                .arg(
+                    Arg::with_name("blob-dir")
+                        .long("blob-dir")
+                        .short("D")
+                        .help("directory holding nydus image's data blobs")
+                        .takes_value(true)
+                        .conflicts_with("backend-type"),
+                )
+                .arg(
+                    Arg::with_name("backend-type")
+                        .long("backend-type")
+                        .help("type of backend to fetch data blobs")
+                        .takes_value(true)
+                        .requires("backend-config-file"),
+                )
+                .arg(
+                    Arg::with_name("backend-config-file")
+                        .long("backend-config-file")
+                        .help("config file of backend")
+                        .takes_value(true)
+                        .requires("backend-type"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .help("path to the output tar file")
+                        .required(true)
+                        .takes_value(true),
+                )
+        )
         .arg(
             Arg::with_name("log-file")
                 .long("log-file")
@@ -561,6 +653,8 @@ fn main() -> Result<()> {
         Command::stat(matches)
     } else if let Some(matches) = cmd.subcommand_matches("compact") {
         Command::compact(matches, &build_info)
+    } else if let Some(matches) = cmd.subcommand_matches("unpack") {
+        Command::unpack(matches)
     } else {
         println!("{}", cmd.usage());
         Ok(())
@@ -616,6 +710,29 @@ impl Command {
                 }
                 digester = digest::Algorithm::Sha256;
             }
+            SourceType::Tar => {
+                // Prefetch patterns of the `fs` policy are read from stdin too.
+                if source_path == Path::new(TAR_STDIN) {
+                    if matches.value_of("prefetch-policy") == Some("fs") {
+                        bail!("prefetch-policy fs can't be used when reading tar from stdin");
+                    }
+                } else {
+                    Self::ensure_file(&source_path)?;
+                }
+            }
+        }
+
+        let chunker = Self::get_chunker(matches, chunk_size)?;
+        if let Some(chunker) = chunker.as_ref() {
+            // RAFS v6 inodes address file data by fixed size chunk slots of `1 << chunkbits`
+            // bytes, as required by EROFS, so variable size chunks can't be represented.
+            if !version.is_v5() {
+                bail!("content defined chunking is only supported by RAFS v5, RAFS v6 inodes need fixed size chunks");
+            }
+            if source_type == SourceType::StargzIndex {
+                bail!("content defined chunking can't be used with stargz index");
+            }
+            info!("split file data into chunks by {}", chunker);
         }
 
         let prefetch = Self::get_prefetch(matches)?;
@@ -637,6 +754,8 @@ impl Command {
         );
         build_ctx.set_fs_version(version);
         build_ctx.set_chunk_size(chunk_size);
+        build_ctx.set_chunker(chunker);
+        build_ctx.set_compress_workers(Self::get_compress_workers(matches)?);
 
         let mut blob_mgr = BlobManager::new();
         if let Some(chunk_dict_arg) = matches.value_of("chunk-dict") {
@@ -670,6 +789,7 @@ impl Command {
         let mut builder: Box<dyn Builder> = match source_type {
             SourceType::Directory => Box::new(DirectoryBuilder::new()),
             SourceType::StargzIndex => Box::new(StargzBuilder::new()),
+            SourceType::Tar => Box::new(TarballBuilder::new()),
             SourceType::Diff => Box::new(DiffBuilder::new(
                 extra_paths,
                 diff_overlay_hint,
@@ -838,6 +958,34 @@ impl Command {
         Ok(())
     }
 
+    fn unpack(matches: &clap::ArgMatches) -> Result<()> {
+        let bootstrap = Self::get_bootstrap(matches)?;
+        let output = Path::new(matches.value_of("output").unwrap());
+        let backend = if let Some(blob) = matches.value_of("blob") {
+            Some(BackendConfig {
+                backend_type: "localfs".to_string(),
+                backend_config: json!({ "blob_file": blob }),
+            })
+        } else if let Some(dir) = matches.value_of("blob-dir") {
+            Self::ensure_directory(dir)?;
+            Some(BackendConfig {
+                backend_type: "localfs".to_string(),
+                backend_config: json!({ "dir": dir }),
+            })
+        } else if let Some(backend_type) = matches.value_of("backend-type") {
+            // Safe to unwrap because `backend-type` requires `backend-config-file`.
+            let backend_file = matches.value_of("backend-config-file").unwrap();
+            Some(BackendConfig::from_file(backend_type, backend_file)?)
+        } else {
+            None
+        };
+
+        let unpacker = OCIUnpacker::new(bootstrap, backend, output)?;
+        unpacker
+            .unpack()
+            .with_context(|| format!("failed to unpack bootstrap {:?}", bootstrap))
+    }
+
     fn get_bootstrap<'a>(matches: &'a clap::ArgMatches) -> Result<&'a Path> {
         match matches.value_of("bootstrap") {
             None => bail!("missing parameter `bootstrap`"),
@@ -855,7 +1003,10 @@ impl Command {
         // Must specify a path to blob file.
         // For cli/binary interface compatibility sake, keep option `backend-config`, but
         // it only receives "localfs" backend type and it will be REMOVED in the future
-        let blob_stor = if source_type == SourceType::Directory || source_type == SourceType::Diff {
+        let blob_stor = if source_type == SourceType::Directory
+            || source_type == SourceType::Diff
+            || source_type == SourceType::Tar
+        {
             if let Some(p) = matches
                 .value_of("blob")
                 .map(|b| ArtifactStorage::SingleFile(b.into()))
@@ -963,6 +1114,51 @@ impl Command {
         }
     }
 
+    fn get_chunker(matches: &clap::ArgMatches, chunk_size: u32) -> Result<Option<FastCdc>> {
+        match matches.value_of("chunking-algorithm") {
+            None | Some("fixed") => Ok(None),
+            Some("fastcdc") => {
+                let parse_size = |name: &str, default: u32| -> Result<u32> {
+                    match matches.value_of(name) {
+                        None => Ok(default),
+                        Some(v) => {
+                            let param = v.trim_start_matches("0x").trim_start_matches("0X");
+                            u32::from_str_radix(param, 16)
+                                .context(format!("invalid {} {}", name, v))
+                        }
+                    }
+                };
+                let max_size = parse_size("cdc-max-size", chunk_size)?;
+                if max_size > chunk_size {
+                    bail!(
+                        "cdc-max-size {:#x} is bigger than chunk size {:#x}",
+                        max_size,
+                        chunk_size
+                    );
+                }
+                let avg_size = parse_size("cdc-avg-size", max_size / 4)?;
+                let min_size = parse_size("cdc-min-size", avg_size / 4)?;
+                FastCdc::new(min_size, avg_size, max_size).map(Some)
+            }
+            Some(v) => bail!("invalid chunking algorithm {}", v),
+        }
+    }
+
+    fn get_compress_workers(matches: &clap::ArgMatches) -> Result<usize> {
+        match matches.value_of("compress-workers") {
+            None => Ok(1),
+            Some(v) => {
+                let workers: usize = v
+                    .parse()
+                    .context(format!("invalid compress-workers {}", v))?;
+                if workers == 0 {
+                    bail!("compress-workers should be at least 1");
+                }
+                Ok(workers)
+            }
+        }
+    }
+
     fn get_prefetch(matches: &clap::ArgMatches) -> Result<Prefetch> {
         let prefetch_policy = matches
             .value_of("prefetch-policy")
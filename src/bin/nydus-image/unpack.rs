@@ -0,0 +1,341 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Unpack a RAFS filesystem into an OCI tar layer.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use nix::sys::stat::{major, minor};
+use tar::{EntryType, Header};
+
+use rafs::metadata::{Inode, RafsInode, RafsMode, RafsSuper};
+use storage::device::BlobDevice;
+use storage::factory::{BackendConfig, CacheConfig, FactoryConfig};
+
+const OCISPEC_WHITEOUT_PREFIX: &str = ".wh.";
+const OCISPEC_WHITEOUT_OPAQUE: &str = ".wh..wh..opq";
+const OVERLAYFS_WHITEOUT_OPAQUE: &str = "trusted.overlay.opaque";
+const PAX_XATTR_PREFIX: &str = "SCHILY.xattr.";
+const PAX_HEADER_NAME: &str = "PaxHeader";
+
+/// Unpack a RAFS filesystem into an OCI tar layer.
+///
+/// File data is read from data blobs through a `BlobDevice` created from the storage backend
+/// configuration, so data blobs may be stored in any supported storage backend.
+pub struct OCIUnpacker {
+    bootstrap: PathBuf,
+    backend: Option<BackendConfig>,
+    output: PathBuf,
+}
+
+impl OCIUnpacker {
+    pub fn new(bootstrap: &Path, backend: Option<BackendConfig>, output: &Path) -> Result<Self> {
+        Ok(Self {
+            bootstrap: bootstrap.to_path_buf(),
+            backend,
+            output: output.to_path_buf(),
+        })
+    }
+
+    pub fn unpack(&self) -> Result<()> {
+        let rs = RafsSuper::load_from_metadata(&self.bootstrap, RafsMode::Direct, true)
+            .with_context(|| format!("failed to load bootstrap {:?}", self.bootstrap))?;
+        let device = match self.backend.as_ref() {
+            Some(backend) => {
+                let config = Arc::new(FactoryConfig {
+                    id: "unpacker".to_string(),
+                    backend: backend.clone(),
+                    cache: CacheConfig::default(),
+                });
+                let blob_infos = rs.superblock.get_blob_infos();
+                Some(
+                    BlobDevice::new(&config, &blob_infos)
+                        .context("failed to create blob device")?,
+                )
+            }
+            None => None,
+        };
+
+        let file = File::create(&self.output)
+            .with_context(|| format!("failed to create output file {:?}", self.output))?;
+        let mut writer = TarWriter {
+            builder: tar::Builder::new(BufWriter::new(file)),
+            device,
+            chunk_size: rs.meta.chunk_size as u64,
+            hardlinks: HashMap::new(),
+        };
+
+        let root = rs.get_inode(rs.superblock.root_ino(), true)?;
+        timing_tracer!(
+            { writer.walk(root.as_ref(), Path::new("")) },
+            "unpack_to_tar"
+        )?;
+        writer
+            .builder
+            .into_inner()
+            .context("failed to finish tar stream")?
+            .flush()
+            .context("failed to flush tar stream")?;
+
+        Ok(())
+    }
+}
+
+struct TarWriter<W: Write> {
+    builder: tar::Builder<W>,
+    device: Option<BlobDevice>,
+    chunk_size: u64,
+    /// Map inode number to the path of the first unpacked link of hardlinks.
+    hardlinks: HashMap<Inode, PathBuf>,
+}
+
+impl<W: Write> TarWriter<W> {
+    fn walk(&mut self, dir: &dyn RafsInode, dir_path: &Path) -> Result<()> {
+        for idx in 0..dir.get_child_count() {
+            let child = dir.get_child_by_index(idx)?;
+            let path = dir_path.join(child.name());
+            event_tracer!("unpack_files", +1);
+            self.append(child.clone(), &path)
+                .with_context(|| format!("failed to unpack {:?}", path))?;
+            if child.is_dir() {
+                self.walk(child.as_ref(), &path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn append(&mut self, inode: Arc<dyn RafsInode>, path: &Path) -> Result<()> {
+        let attr = inode.get_attr();
+        let mut header = Header::new_gnu();
+        header.set_mode(attr.mode & 0o7777);
+        header.set_uid(attr.uid as u64);
+        header.set_gid(attr.gid as u64);
+        header.set_mtime(attr.mtime);
+        header.set_size(0);
+
+        // Hardlinks share extended attributes with the link target.
+        if inode.is_hardlink() && !inode.is_dir() {
+            if let Some(target) = self.hardlinks.get(&inode.ino()) {
+                header.set_entry_type(EntryType::Link);
+                self.builder.append_link(&mut header, path, target)?;
+                return Ok(());
+            }
+            self.hardlinks.insert(inode.ino(), path.to_path_buf());
+        }
+
+        let mut xattrs = Vec::new();
+        let mut opaque = false;
+        if inode.has_xattr() {
+            for name in inode.get_xattrs()? {
+                let name = OsStr::from_bytes(&name);
+                if let Some(value) = inode.get_xattr(name)? {
+                    // Convert overlayfs opaque directories to OCI opaque whiteouts.
+                    if inode.is_dir() && name == OVERLAYFS_WHITEOUT_OPAQUE {
+                        opaque = value == b"y";
+                        if opaque {
+                            continue;
+                        }
+                    }
+                    xattrs.push((name.as_bytes().to_vec(), value));
+                }
+            }
+        }
+        if !xattrs.is_empty() {
+            self.append_pax_xattrs(&xattrs)?;
+        }
+
+        let file_type = attr.mode & libc::S_IFMT;
+        if inode.is_dir() {
+            header.set_entry_type(EntryType::Directory);
+            self.builder.append_data(&mut header, path, io::empty())?;
+            if opaque {
+                self.append_whiteout(&path.join(OCISPEC_WHITEOUT_OPAQUE))?;
+            }
+        } else if inode.is_symlink() {
+            header.set_entry_type(EntryType::Symlink);
+            self.builder
+                .append_link(&mut header, path, inode.get_symlink()?)?;
+        } else if inode.is_reg() {
+            header.set_entry_type(EntryType::Regular);
+            header.set_size(inode.size());
+            let reader = FileDataReader::new(self.device.as_ref(), inode, self.chunk_size);
+            self.builder.append_data(&mut header, path, reader)?;
+        } else if file_type == libc::S_IFCHR && inode.rdev() == 0 {
+            // Convert overlayfs whiteouts to OCI whiteouts.
+            let mut name = OsStr::new(OCISPEC_WHITEOUT_PREFIX).to_os_string();
+            name.push(inode.name());
+            self.append_whiteout(&path.with_file_name(name))?;
+        } else {
+            let entry_type = match file_type {
+                libc::S_IFCHR => EntryType::Char,
+                libc::S_IFBLK => EntryType::Block,
+                libc::S_IFIFO => EntryType::Fifo,
+                _ => bail!("unsupported file type {:o}", file_type),
+            };
+            header.set_entry_type(entry_type);
+            if entry_type != EntryType::Fifo {
+                let rdev = inode.rdev() as u64;
+                header.set_device_major(major(rdev) as u32)?;
+                header.set_device_minor(minor(rdev) as u32)?;
+            }
+            self.builder.append_data(&mut header, path, io::empty())?;
+        }
+
+        Ok(())
+    }
+
+    fn append_whiteout(&mut self, path: &Path) -> Result<()> {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o644);
+        header.set_size(0);
+        self.builder.append_data(&mut header, path, io::empty())?;
+
+        Ok(())
+    }
+
+    /// Append a PAX extended header carrying extended attributes for the next entry.
+    fn append_pax_xattrs(&mut self, xattrs: &[(Vec<u8>, Vec<u8>)]) -> Result<()> {
+        let mut data = Vec::new();
+        for (name, value) in xattrs {
+            let mut key = PAX_XATTR_PREFIX.as_bytes().to_vec();
+            key.extend_from_slice(name);
+            data.extend_from_slice(&pax_record(&key, value));
+        }
+
+        let mut header = Header::new_ustar();
+        header.set_entry_type(EntryType::XHeader);
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        self.builder
+            .append_data(&mut header, PAX_HEADER_NAME, data.as_slice())?;
+
+        Ok(())
+    }
+}
+
+/// Encode a PAX extended header record: "<length> <key>=<value>\n".
+///
+/// The length field counts the whole record, including the length field itself.
+pub(crate) fn pax_record(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while rest + len.to_string().len() != len {
+        len = rest + len.to_string().len();
+    }
+
+    let mut record = format!("{} ", len).into_bytes();
+    record.extend_from_slice(key);
+    record.push(b'=');
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+/// Reader to fetch file data from data blobs, one chunk at a time.
+struct FileDataReader<'a> {
+    device: Option<&'a BlobDevice>,
+    inode: Arc<dyn RafsInode>,
+    chunk_size: u64,
+    offset: u64,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<'a> FileDataReader<'a> {
+    fn new(device: Option<&'a BlobDevice>, inode: Arc<dyn RafsInode>, chunk_size: u64) -> Self {
+        Self {
+            device,
+            inode,
+            chunk_size,
+            offset: 0,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    fn fill_buf(&mut self) -> io::Result<()> {
+        let size = cmp::min(self.chunk_size, self.inode.size() - self.offset) as usize;
+        self.buf.resize(size, 0);
+        self.pos = 0;
+
+        let mut count = 0;
+        for mut desc in self.inode.alloc_bio_vecs(self.offset, size, true)? {
+            if desc.is_hole() {
+                self.buf[count..count + desc.bi_size].fill(0);
+                count += desc.bi_size;
+                continue;
+            }
+            let device = self.device.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "storage backend is required to unpack file data",
+                )
+            })?;
+            let n = device.read_to_buf(&mut desc, &mut self.buf[count..])?;
+            if n != desc.bi_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("short read from blob, expect {} got {}", desc.bi_size, n),
+                ));
+            }
+            count += n;
+        }
+        if count != size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "failed to read data at offset {}, expect {} got {}",
+                    self.offset, size, count
+                ),
+            ));
+        }
+        self.offset += size as u64;
+
+        Ok(())
+    }
+}
+
+impl Read for FileDataReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.buf.len() {
+            if self.offset >= self.inode.size() {
+                return Ok(0);
+            }
+            self.fill_buf()?;
+        }
+
+        let n = cmp::min(buf.len(), self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pax_record() {
+        assert_eq!(pax_record(b"a", b"b"), b"6 a=b\n".to_vec());
+        assert_eq!(
+            pax_record(b"SCHILY.xattr.user.k", b"v"),
+            b"25 SCHILY.xattr.user.k=v\n".to_vec()
+        );
+        // The length field grows from one digit to two digits.
+        assert_eq!(pax_record(b"abcd", b"e"), b"9 abcd=e\n".to_vec());
+        assert_eq!(pax_record(b"abcde", b"f"), b"11 abcde=f\n".to_vec());
+    }
+}
@@ -50,9 +50,14 @@ impl NydusdClient {
         Ok(b)
     }
 
-    pub async fn put(&self, path: &str, data: Option<String>) -> Result<()> {
+    pub async fn put(
+        &self,
+        path: &str,
+        data: Option<String>,
+        query: Option<Vec<(&str, &str)>>,
+    ) -> Result<()> {
         let client = Client::unix();
-        let uri = self.build_uri(path, None);
+        let uri = self.build_uri(path, query);
         let (body, _) = if let Some(d) = data {
             let l = d.len();
             (d.into(), l)
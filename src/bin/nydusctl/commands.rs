@@ -74,6 +74,8 @@ Prefetch Requests:      {requests}
 Prefetch Average Size:  {avg_prefetch_size} Bytes
 Prefetch Unmerged:      {unmerged_blocks}
 Persister Buffer:       {buffered}
+Coalesced Requests:     {coalesced_requests}
+Coalesced Amount:       {coalesced_size} Bytes
 "#,
                 partial_hits = m["partial_hits"],
                 whole_hits = m["whole_hits"],
@@ -91,6 +93,8 @@ Persister Buffer:       {buffered}
                 workers = m["prefetch_workers"],
                 unmerged_blocks = m["prefetch_unmerged_chunks"],
                 buffered = m["buffered_backend_size"],
+                coalesced_requests = m["coalesced_requests"],
+                coalesced_size = m["coalesced_size"],
             );
         }
 
@@ -201,12 +205,18 @@ Backend Type:       {backend_type}
 Read Amount:        {read_amount} Bytes ({read_count_mb} MB)
 Read Count:         {read_count}
 Read Errors:        {read_errors}
+Circuit State:      {circuit_state}
+Circuit Opened:     {circuit_open_count}
+Circuit Rejected:   {circuit_rejected}
 "#,
                 backend_type = m["backend_type"],
                 read_amount = m["read_amount_total"],
                 read_count = m["read_count"],
                 read_count_mb = m["read_amount_total"].as_f64().unwrap() / 1024.0 / 1024.0,
                 read_errors = m["read_errors"],
+                circuit_state = m["circuit_state"],
+                circuit_open_count = m["circuit_open_count"],
+                circuit_rejected = m["circuit_rejected"],
             );
 
             println!(
@@ -294,6 +304,96 @@ Opendir({}) Lookup({}) Readdir({}) Readdirplus({}) Access({}) Forget({}) BatchFo
     }
 }
 
+pub(crate) struct CommandSlowIo {}
+
+impl CommandSlowIo {
+    pub async fn execute(
+        &self,
+        raw: bool,
+        client: &NydusdClient,
+        _params: Option<CommandParams>,
+    ) -> Result<()> {
+        let records = client.get("metrics/slowio").await?;
+        if raw {
+            println!("{}", records);
+        } else {
+            let records = records.as_array().unwrap();
+            if records.is_empty() {
+                println!("No slow read request recorded");
+            }
+            for r in records {
+                print!(
+                    r#"
+Path:                   {path}
+Inode:                  {ino}
+Offset:                 {offset}
+Size:                   {size}
+Timestamp:              {timestamp}
+Latency:                {latency}us
+"#,
+                    path = r["path"],
+                    ino = r["ino"],
+                    offset = r["offset"],
+                    size = r["size"],
+                    timestamp = r["timestamp_secs"],
+                    latency = r["latency_micros"],
+                );
+                for (stage, latency) in r["stages"].as_object().unwrap() {
+                    println!("  {:<22}{}us", stage, latency);
+                }
+                for c in r["chunks"].as_array().unwrap() {
+                    println!("  Blob {} Chunks {}", c["blob_id"], c["chunks"]);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) struct CommandScrub {}
+
+impl CommandScrub {
+    pub async fn execute(
+        &self,
+        raw: bool,
+        client: &NydusdClient,
+        _params: Option<CommandParams>,
+    ) -> Result<()> {
+        let report = client.get("metrics/blobcache/scrub").await?;
+        if raw {
+            println!("{}", report);
+        } else {
+            print!(
+                r#"
+Passes:                 {passes}
+Last Pass:              {last_pass}
+Scanned Chunks:         {scanned_chunks}
+Scanned Amount:         {scanned_size} Bytes
+Skipped Chunks:         {skipped_chunks}
+Corrupted Chunks:       {corrupted_chunks}
+Repaired Chunks:        {repaired_chunks}
+"#,
+                passes = report["passes"],
+                last_pass = report["last_pass_secs"],
+                scanned_chunks = report["scanned_chunks"],
+                scanned_size = report["scanned_size"],
+                skipped_chunks = report["skipped_chunks"],
+                corrupted_chunks = report["corrupted_chunks"],
+                repaired_chunks = report["repaired_chunks"],
+            );
+            for c in report["recent_corruptions"].as_array().unwrap() {
+                println!(
+                    "  Blob {} Chunk {} Repaired {} Timestamp {}",
+                    c["blob_id"], c["chunk_index"], c["repaired"], c["timestamp_secs"]
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
 pub(crate) struct CommandDaemon {}
 
 impl CommandDaemon {
@@ -318,7 +418,7 @@ impl CommandDaemon {
             }
 
             let data = serde_json::to_string(&real)?;
-            client.put("daemon", Some(data)).await?;
+            client.put("daemon", Some(data), None).await?;
         } else {
             let info = client.get("daemon").await?;
             let i = info.as_object().unwrap();
@@ -395,6 +495,29 @@ impl CommandMount {
     }
 }
 
+pub(crate) struct CommandUpdateBackend {}
+
+impl CommandUpdateBackend {
+    pub async fn execute(
+        &self,
+        _raw: bool,
+        client: &NydusdClient,
+        params: Option<CommandParams>,
+    ) -> Result<()> {
+        let p = params.unwrap();
+        let mountpoint = &p["mountpoint"];
+        let config = std::fs::read_to_string(&p["config"])?;
+
+        client
+            .put(
+                "daemon/backend",
+                Some(config),
+                Some(vec![("mountpoint", mountpoint)]),
+            )
+            .await
+    }
+}
+
 pub(crate) struct CommandUmount {}
 
 impl CommandUmount {
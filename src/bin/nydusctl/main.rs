@@ -22,7 +22,8 @@ mod client;
 mod commands;
 
 use commands::{
-    CommandBackend, CommandBlobcache, CommandDaemon, CommandFsStats, CommandMount, CommandUmount,
+    CommandBackend, CommandBlobcache, CommandDaemon, CommandFsStats, CommandMount, CommandScrub,
+    CommandSlowIo, CommandUmount, CommandUpdateBackend,
 };
 
 #[tokio::main]
@@ -94,6 +95,14 @@ async fn main() -> Result<()> {
         );
 
     let app = app
+        .subcommand(
+            SubCommand::with_name("slowio")
+                .about("Show slowest read requests of the file system with per-stage latency breakdown"),
+        )
+        .subcommand(
+            SubCommand::with_name("scrub")
+                .about("Show report of the background scrubber verifying cached chunks"),
+        )
         .subcommand(
             SubCommand::with_name("mount")
                 .about("Attach a file system backend")
@@ -134,6 +143,23 @@ async fn main() -> Result<()> {
                         .takes_value(true)
                         .index(1),
                 ),
+        )
+        .subcommand(
+            SubCommand::with_name("backend")
+                .about("Update storage backend credentials and connection settings of a file system without remounting it")
+                .arg(
+                    Arg::with_name("config")
+                        .help("Storage backend configuration file, in the same format as `device.backend` of the file system configuration")
+                        .long("config")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("mountpoint")
+                        .long("mountpoint")
+                        .required(true)
+                        .takes_value(true),
+                ),
         );
 
     let cmd = app.get_matches();
@@ -185,6 +211,16 @@ async fn main() -> Result<()> {
         }
     }
 
+    if cmd.subcommand_matches("slowio").is_some() {
+        let cmd = CommandSlowIo {};
+        cmd.execute(raw, &client, None).await?
+    }
+
+    if cmd.subcommand_matches("scrub").is_some() {
+        let cmd = CommandScrub {};
+        cmd.execute(raw, &client, None).await?
+    }
+
     if let Some(matches) = cmd.subcommand_matches("mount") {
         // Safe to unwrap as it is required by clap
         let mut context = HashMap::new();
@@ -223,5 +259,22 @@ async fn main() -> Result<()> {
         cmd.execute(raw, &client, Some(context)).await?
     }
 
+    if let Some(matches) = cmd.subcommand_matches("backend") {
+        // Safe to unwrap as it is required by clap
+        let mut context = HashMap::new();
+
+        context.insert(
+            "mountpoint".to_string(),
+            matches.value_of("mountpoint").unwrap().to_string(),
+        );
+        context.insert(
+            "config".to_string(),
+            matches.value_of("config").unwrap().to_string(),
+        );
+
+        let cmd = CommandUpdateBackend {};
+        cmd.execute(raw, &client, Some(context)).await?
+    }
+
     Ok(())
 }
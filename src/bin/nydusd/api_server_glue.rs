@@ -16,10 +16,12 @@ use nix::unistd::Pid;
 
 use nydus::{FsBackendType, NydusError};
 use nydus_api::http::{
-    start_http_thread, ApiError, ApiMountCmd, ApiRequest, ApiResponse, ApiResponsePayload,
-    ApiResult, BlobCacheEntry, BlobCacheObjectId, DaemonConf, DaemonErrorKind, MetricsErrorKind,
+    start_http_thread, ApiBackendCmd, ApiError, ApiMountCmd, ApiRequest, ApiResponse,
+    ApiResponsePayload, ApiResult, BlobCacheEntry, BlobCacheObjectId, DaemonConf, DaemonErrorKind,
+    MetricsErrorKind,
 };
 use nydus_utils::metrics;
+use storage::factory::BackendConfig;
 
 use crate::daemon::{DaemonError, NydusDaemon};
 use crate::fs_service::{FsBackendMountCmd, FsBackendUmountCmd, FsService};
@@ -72,6 +74,8 @@ impl ApiServer {
             ApiRequest::Umount(mountpoint) => self.do_umount(mountpoint),
             ApiRequest::ExportBackendMetrics(id) => Self::export_backend_metrics(id),
             ApiRequest::ExportBlobcacheMetrics(id) => Self::export_blobcache_metrics(id),
+            ApiRequest::ExportBlobcacheScrubReport(id) => Self::export_blobcache_scrub_report(id),
+            ApiRequest::ExportPrometheusMetrics => Self::export_prometheus_metrics(),
 
             // Nydus API v1
             ApiRequest::ExportFsGlobalMetrics(id) => Self::export_global_metrics(id),
@@ -80,7 +84,9 @@ impl ApiServer {
             }
             ApiRequest::ExportFsAccessPatterns(id) => Self::export_access_patterns(id),
             ApiRequest::ExportFsBackendInfo(mountpoint) => self.backend_info(&mountpoint),
+            ApiRequest::UpdateFsBackend(mountpoint, cmd) => self.update_backend(&mountpoint, cmd),
             ApiRequest::ExportFsInflightMetrics => self.export_inflight_metrics(),
+            ApiRequest::ExportFsSlowIo(id) => Self::export_slow_io(id),
 
             // Nydus API v2
             ApiRequest::GetDaemonInfoV2 => self.daemon_info(false),
@@ -178,6 +184,12 @@ impl ApiServer {
             .map_err(|e| ApiError::Metrics(MetricsErrorKind::Stats(e)))
     }
 
+    fn export_slow_io(id: Option<String>) -> ApiResponse {
+        metrics::export_slow_io(&id)
+            .map(ApiResponsePayload::FsSlowIo)
+            .map_err(|e| ApiError::Metrics(MetricsErrorKind::Stats(e)))
+    }
+
     fn export_backend_metrics(id: Option<String>) -> ApiResponse {
         metrics::export_backend_metrics(&id)
             .map(ApiResponsePayload::BackendMetrics)
@@ -190,6 +202,18 @@ impl ApiServer {
             .map_err(|e| ApiError::Metrics(MetricsErrorKind::Stats(e)))
     }
 
+    fn export_blobcache_scrub_report(id: Option<String>) -> ApiResponse {
+        metrics::export_blobcache_scrub_report(&id)
+            .map(ApiResponsePayload::BlobcacheScrubReport)
+            .map_err(|e| ApiError::Metrics(MetricsErrorKind::Stats(e)))
+    }
+
+    fn export_prometheus_metrics() -> ApiResponse {
+        Ok(ApiResponsePayload::PrometheusMetrics(
+            metrics::export_prometheus_metrics(),
+        ))
+    }
+
     #[inline]
     fn get_daemon_object(&self) -> std::result::Result<Arc<dyn NydusDaemon>, ApiError> {
         Ok(DAEMON_CONTROLLER.get_daemon())
@@ -203,6 +227,17 @@ impl ApiServer {
         Ok(ApiResponsePayload::FsBackendInfo(info))
     }
 
+    fn update_backend(&self, mountpoint: &str, cmd: ApiBackendCmd) -> ApiResponse {
+        let config = BackendConfig {
+            backend_type: cmd.backend_type,
+            backend_config: cmd.config,
+        };
+        self.get_default_fs_service()?
+            .update_backend(mountpoint, config)
+            .map(|_| ApiResponsePayload::Empty)
+            .map_err(|e| ApiError::UpdateFsBackend(e.into()))
+    }
+
     /// Detect if there is fop being hang.
     /// `ApiResponsePayload::Empty` will be converted to http status code 204, which means
     /// there is no requests being processed right now.
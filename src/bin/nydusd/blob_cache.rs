@@ -50,6 +50,8 @@ impl BlobCacheConfigBootstrap {
 /// Configuration information for cached data blob objects.
 pub struct BlobCacheConfigDataBlob {
     blob_info: Arc<BlobInfo>,
+    // Path of the bootstrap blob which first references the data blob.
+    bootstrap_path: PathBuf,
     scoped_blob_id: String,
     factory_config: Arc<FactoryConfig>,
     ref_count: AtomicU32,
@@ -61,6 +63,11 @@ impl BlobCacheConfigDataBlob {
         &self.blob_info
     }
 
+    /// Get file path of the bootstrap blob which the [`BlobInfo`] of the data blob comes from.
+    pub fn bootstrap_path(&self) -> &Path {
+        &self.bootstrap_path
+    }
+
     /// Get ['FactoryConfig'] of the data blob.
     pub fn factory_config(&self) -> &Arc<FactoryConfig> {
         &self.factory_config
@@ -80,12 +87,14 @@ impl BlobCacheObjectConfig {
     fn new_data_blob(
         domain_id: String,
         blob_info: Arc<BlobInfo>,
+        bootstrap_path: PathBuf,
         factory_config: Arc<FactoryConfig>,
     ) -> Self {
         let scoped_blob_id = generate_blob_key(&domain_id, blob_info.blob_id());
 
         BlobCacheObjectConfig::DataBlob(Arc::new(BlobCacheConfigDataBlob {
             blob_info,
+            bootstrap_path,
             scoped_blob_id,
             factory_config,
             ref_count: AtomicU32::new(1),
@@ -354,7 +363,7 @@ impl BlobCacheMgr {
         let bootstrap = BlobCacheObjectConfig::new_bootstrap_blob(
             domain_id.to_string(),
             id.to_string(),
-            path,
+            path.clone(),
             factory_config.clone(),
         );
 
@@ -373,6 +382,7 @@ impl BlobCacheMgr {
             let data_blob = BlobCacheObjectConfig::new_data_blob(
                 domain_id.to_string(),
                 bi,
+                path.clone(),
                 factory_config.clone(),
             );
             let data_blob_config = match &data_blob {
@@ -16,11 +16,13 @@ use std::ptr::read_unaligned;
 use std::string::String;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Barrier, Mutex, MutexGuard};
+use std::thread;
 
 use mio::unix::SourceFd;
 use mio::{Events, Interest, Poll, Token, Waker};
+use rafs::metadata::{RafsMode, RafsSuper};
 use storage::cache::BlobCache;
-use storage::device::BlobPrefetchRequest;
+use storage::device::{BlobChunkInfo, BlobIoChunk, BlobIoDesc, BlobPrefetchRequest};
 use storage::factory::BLOB_FACTORY;
 
 use crate::blob_cache::{
@@ -419,6 +421,7 @@ impl FsCacheHandler {
                 Ok((blob, blob_size)) => {
                     e.insert((FsCacheObject::DataBlob(blob.clone()), msg.fd));
                     state.id_to_config_map.insert(hdr.object_id, config.clone());
+                    Self::record_chunk_infos(&config, blob.clone());
                     let _ = self.do_prefetch(&config, blob);
                     format!("copen {},{}", hdr.msg_id, blob_size)
                 }
@@ -472,6 +475,48 @@ impl FsCacheHandler {
         }
     }
 
+    // Provide chunk digests from the chunk table of the bootstrap to the blob cache object in
+    // background, so chunks cached by fscache may be verified by the cache scrubber.
+    fn record_chunk_infos(config: &BlobCacheConfigDataBlob, blob: Arc<dyn BlobCache>) {
+        if !blob.need_chunk_infos() {
+            return;
+        }
+        let path = config.bootstrap_path().to_path_buf();
+        let blob_info = config.blob_info().clone();
+
+        let _ = thread::spawn(move || {
+            let mut bios = Vec::new();
+            let result =
+                RafsSuper::load_from_metadata(&path, RafsMode::Direct, false).and_then(|rs| {
+                    if rs.meta.chunk_table_size == 0 {
+                        return Err(enoent!("no chunk table in bootstrap"));
+                    }
+                    rs.walk_chunk_table(&mut |chunk| {
+                        if chunk.blob_index() != blob_info.blob_index() || chunk.is_hole() {
+                            return Ok(());
+                        }
+                        let size = chunk.uncompress_size() as usize;
+                        let chunk = BlobIoChunk::from(chunk);
+                        bios.push(BlobIoDesc::new(blob_info.clone(), chunk, 0, size, false));
+                        if bios.len() >= 1024 {
+                            blob.record_chunk_infos(&bios);
+                            bios.clear();
+                        }
+                        Ok(())
+                    })
+                });
+            blob.record_chunk_infos(&bios);
+            if let Err(e) = result {
+                warn!(
+                    "fscache: failed to load chunk digests of blob {} from bootstrap {}, {}",
+                    blob_info.blob_id(),
+                    path.display(),
+                    e
+                );
+            }
+        });
+    }
+
     /// The `fscache` factory essentially creates a namespace for blob objects cached by the
     /// fscache subsystem. The data blob files will be managed the in kernel fscache driver,
     /// the chunk map file will be managed by the userspace daemon. We need to figure out the
@@ -17,6 +17,7 @@ use nydus::{FsBackendDesc, FsBackendType};
 use rafs::fs::{Rafs, RafsConfig};
 use rafs::{trim_backend_config, RafsError, RafsIoRead};
 use serde::{self, Deserialize, Serialize};
+use storage::factory::BackendConfig;
 
 use crate::daemon::DaemonResult;
 use crate::upgrade::{self, UpgradeManager};
@@ -26,7 +27,7 @@ use crate::DaemonError;
 type BackFileSystem = Box<dyn BackendFileSystem<Inode = u64, Handle = u64> + Send + Sync>;
 
 /// Command to mount a filesystem.
-#[derive(Clone)]
+#[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct FsBackendMountCmd {
     pub fs_type: FsBackendType,
     pub source: String,
@@ -52,13 +53,7 @@ impl FsBackendCollection {
             FsBackendType::Rafs => {
                 let mut config: serde_json::Value =
                     serde_json::from_str(&cmd.config).map_err(DaemonError::Serde)?;
-                trim_backend_config!(
-                    config,
-                    "access_key_id",
-                    "access_key_secret",
-                    "auth",
-                    "token"
-                );
+                trim_credentials(&mut config);
                 Some(config)
             }
             FsBackendType::PassthroughFs => {
@@ -82,6 +77,42 @@ impl FsBackendCollection {
     pub fn del(&mut self, id: &str) {
         self.0.remove(id);
     }
+
+    pub fn update_backend(&mut self, id: &str, backend: &BackendConfig) -> DaemonResult<()> {
+        let desc = self.0.get_mut(id).ok_or(DaemonError::NotFound)?;
+        if let Some(config) = desc.config.as_mut() {
+            config["device"]["backend"] =
+                serde_json::to_value(backend).map_err(DaemonError::Serde)?;
+            trim_credentials(config);
+        }
+
+        Ok(())
+    }
+}
+
+fn trim_credentials(config: &mut serde_json::Value) {
+    trim_backend_config!(
+        config,
+        "access_key_id",
+        "access_key_secret",
+        "auth",
+        "token",
+        "registry_token",
+        "session_token",
+        "headers"
+    );
+
+    // Tiers of the composite storage backend carry their own credentials.
+    if let Some(tiers) = config
+        .pointer_mut("/device/backend/config/backends")
+        .and_then(|v| v.as_array_mut())
+    {
+        for tier in tiers.iter_mut() {
+            let mut tier_config = serde_json::json!({ "device": { "backend": tier.take() } });
+            trim_credentials(&mut tier_config);
+            *tier = tier_config["device"]["backend"].take();
+        }
+    }
 }
 
 /// Define services provided by a filesystem provider.
@@ -152,6 +183,56 @@ pub trait FsService: Send + Sync {
         Ok(())
     }
 
+    /// Recreate a filesystem backend from states saved by a previous nydusd instance and
+    /// mount it at the same vfs index, so inode numbers seen by the kernel stay valid.
+    fn restore_mount(&self, cmd: &FsBackendMountCmd, vfs_index: u8) -> DaemonResult<()> {
+        let backend = fs_backend_factory(cmd)?;
+        self.get_vfs()
+            .restore_mount(backend, vfs_index, &cmd.mountpoint)
+            .map_err(|e| {
+                DaemonError::Common(format!(
+                    "failed to restore mount {}, {:?}",
+                    cmd.mountpoint, e
+                ))
+            })?;
+        info!(
+            "{} restored at {} with vfs index {}",
+            &cmd.fs_type, &cmd.mountpoint, vfs_index
+        );
+        self.backend_collection().add(&cmd.mountpoint, cmd)?;
+
+        if let Some(mut mgr_guard) = self.upgrade_mgr() {
+            upgrade::add_mounts_state(&mut mgr_guard, cmd.clone(), vfs_index)?;
+        }
+
+        Ok(())
+    }
+
+    /// Update credentials and connection settings of the storage backend of a mounted Rafs
+    /// instance, without remounting it or dropping its blob caches.
+    fn update_backend(&self, mountpoint: &str, backend: BackendConfig) -> DaemonResult<()> {
+        let fs = self
+            .backend_from_mountpoint(mountpoint)?
+            .ok_or(DaemonError::NotFound)?;
+        let any_fs = fs.deref().as_any();
+        let rafs = any_fs
+            .downcast_ref::<Rafs>()
+            .ok_or_else(|| DaemonError::FsTypeMismatch("to rafs".to_string()))?;
+
+        rafs.update_backend(backend.clone())
+            .map_err(DaemonError::Rafs)?;
+
+        self.backend_collection()
+            .update_backend(mountpoint, &backend)?;
+        // Keep the saved mount command in sync, so the new daemon instance uses the updated
+        // configuration after upgrade or failover.
+        if let Some(mut mgr_guard) = self.upgrade_mgr() {
+            upgrade::update_mounts_backend(&mut mgr_guard, mountpoint, &backend)?;
+        }
+
+        Ok(())
+    }
+
     fn backend_from_mountpoint(&self, mp: &str) -> DaemonResult<Option<Arc<BackFileSystem>>> {
         self.get_vfs().get_rootfs(mp).map_err(|e| e.into())
     }
@@ -259,6 +340,80 @@ mod tests {
         assert_eq!(col.0.len(), 0);
     }
 
+    #[test]
+    fn it_should_trim_credentials() {
+        let mut config = serde_json::json!({
+            "device": {
+                "backend": {
+                    "type": "composite",
+                    "config": {
+                        "backends": [
+                            {"type": "localfs", "config": {"dir": "/blobs"}},
+                            {"type": "registry", "config": {"host": "h", "auth": "secret"}}
+                        ]
+                    }
+                }
+            }
+        });
+        trim_credentials(&mut config);
+
+        let tiers = &config["device"]["backend"]["config"]["backends"];
+        assert_eq!(tiers[0]["config"]["dir"], "/blobs");
+        assert_eq!(tiers[1]["type"], "registry");
+        assert_eq!(tiers[1]["config"]["host"], "h");
+        assert!(tiers[1]["config"]["auth"].is_null());
+    }
+
+    #[test]
+    fn it_should_trim_mirror_headers() {
+        let mut config = serde_json::json!({
+            "device": {
+                "backend": {
+                    "type": "composite",
+                    "config": {
+                        "backends": [
+                            {
+                                "type": "registry",
+                                "config": {
+                                    "host": "h",
+                                    "mirrors": [
+                                        {"host": "http://m1", "headers": {"Authorization": "secret"}}
+                                    ]
+                                }
+                            }
+                        ]
+                    }
+                }
+            }
+        });
+        trim_credentials(&mut config);
+        let mirrors = &config["device"]["backend"]["config"]["backends"][0]["config"]["mirrors"];
+        assert_eq!(mirrors[0]["host"], "http://m1");
+        assert!(mirrors[0]["headers"].is_null());
+
+        let mut config = serde_json::json!({
+            "device": {
+                "backend": {
+                    "type": "registry",
+                    "config": {
+                        "host": "h",
+                        "mirrors": [
+                            {"host": "http://m1", "headers": {"Authorization": "secret"}},
+                            {"host": "http://m2"}
+                        ]
+                    }
+                }
+            }
+        });
+        trim_credentials(&mut config);
+        let mirrors = &config["device"]["backend"]["config"]["mirrors"];
+        assert_eq!(mirrors.as_array().unwrap().len(), 2);
+        assert_eq!(mirrors[0]["host"], "http://m1");
+        assert!(mirrors[0]["headers"].is_null());
+        assert_eq!(mirrors[1]["host"], "http://m2");
+        assert!(!config.to_string().contains("secret"));
+    }
+
     #[test]
     fn it_should_verify_prefetch_files() {
         let files = validate_prefetch_file_list(&Some(vec!["/etc/passwd".to_string()]));
@@ -32,6 +32,7 @@ use mio::Waker;
 #[cfg(target_os = "linux")]
 use nix::sys::stat::{major, minor};
 use nydus_app::BuildTimeInfo;
+use nydus_utils::slowio;
 use serde::Serialize;
 
 use crate::daemon::{
@@ -39,7 +40,7 @@ use crate::daemon::{
     DaemonStateMachineSubscriber, NydusDaemon,
 };
 use crate::fs_service::{FsBackendCollection, FsBackendMountCmd, FsService};
-use crate::upgrade::{self, FailoverPolicy, UpgradeManager};
+use crate::upgrade::{self, FailoverPolicy, RestoreReason, UpgradeManager};
 use crate::DAEMON_CONTROLLER;
 
 #[derive(Serialize)]
@@ -119,6 +120,7 @@ impl FuseServer {
                 warn!("get fuse request failed: {:?}", e);
                 Error::from_raw_os_error(libc::EINVAL)
             })? {
+                slowio::mark_request_received();
                 if let Err(e) = self
                     .server
                     .handle_message(reader, writer, None, Some(metrics_hook))
@@ -243,6 +245,8 @@ pub struct FusedevDaemon {
     request_sender: Arc<Mutex<Sender<DaemonStateMachineInput>>>,
     result_receiver: Mutex<Receiver<DaemonResult<()>>>,
     service: Arc<FusedevFsService>,
+    // Why states are restored when taking over the FUSE session from a previous instance.
+    restore_reason: RestoreReason,
     state: AtomicI32,
     supervisor: Option<String>,
     threads_cnt: u32,
@@ -251,6 +255,11 @@ pub struct FusedevDaemon {
 }
 
 impl FusedevDaemon {
+    /// Get the FUSE filesystem service associated with the daemon.
+    pub fn service(&self) -> &Arc<FusedevFsService> {
+        &self.service
+    }
+
     fn kick_one_server(&self, waker: Arc<Waker>) -> Result<()> {
         let mut s = self.service.create_fuse_server()?;
         let inflight_op = self.service.create_inflight_op();
@@ -399,7 +408,7 @@ impl NydusDaemon for FusedevDaemon {
     }
 
     fn restore(&self) -> DaemonResult<()> {
-        upgrade::fusedev_upgrade::restore(self)
+        upgrade::fusedev_upgrade::restore(self, self.restore_reason)
     }
 
     fn get_default_fs_service(&self) -> Option<Arc<dyn FsService>> {
@@ -528,6 +537,10 @@ pub fn create_fuse_daemon(
     let (trigger, events_rx) = channel::<DaemonStateMachineInput>();
     let (result_sender, result_receiver) = channel::<DaemonResult<()>>();
     let service = FusedevFsService::new(vfs, &mnt, supervisor.as_ref(), fp, readonly)?;
+    let crashed = match api_sock.as_ref() {
+        Some(sock) if !upgrade => is_crashed(&mnt, sock)?,
+        _ => false,
+    };
     let daemon = Arc::new(FusedevDaemon {
         bti,
         id,
@@ -538,6 +551,11 @@ pub fn create_fuse_daemon(
         result_receiver: Mutex::new(result_receiver),
         request_sender: Arc::new(Mutex::new(trigger)),
         service: Arc::new(service),
+        restore_reason: if crashed {
+            RestoreReason::Failover
+        } else {
+            RestoreReason::Upgrade
+        },
         state_machine_thread: Mutex::new(None),
         fuse_service_threads: Mutex::new(Vec::new()),
     });
@@ -547,9 +565,7 @@ pub fn create_fuse_daemon(
 
     // Without api socket, nydusd can't do neither live-upgrade nor failover, so the helper
     // finding a victim is not necessary.
-    if (api_sock.as_ref().is_some() && !upgrade && !is_crashed(&mnt, api_sock.as_ref().unwrap())?)
-        || api_sock.is_none()
-    {
+    if api_sock.is_none() || (!upgrade && !crashed) {
         if let Some(cmd) = mount_cmd {
             daemon.service.mount(cmd)?;
         }
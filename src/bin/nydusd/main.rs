@@ -32,7 +32,9 @@ use nix::sys::signal;
 use rlimit::Resource;
 
 use nydus::FsBackendType;
+use nydus_api::http::start_prometheus_thread;
 use nydus_app::{dump_program_info, setup_logging, BuildTimeInfo};
+use storage::cache::HOT_CHUNK_CACHE;
 
 use crate::api_server_glue::ApiServerController;
 use crate::blob_cache::BlobCacheMgr;
@@ -197,6 +199,10 @@ extern "C" fn sig_exit(_sig: std::os::raw::c_int) {
     DAEMON_CONTROLLER.shutdown();
 }
 
+extern "C" fn sig_reload(_sig: std::os::raw::c_int) {
+    storage::backend::connection::reload_tls_config();
+}
+
 #[cfg(feature = "virtiofs")]
 const SHARED_DIR_HELP_MESSAGE: &str = "Directory shared by host and guest for \
 passthroughfs, which also enables passthroughfs mode";
@@ -363,6 +369,23 @@ fn prepare_commandline_options() -> App<'static, 'static> {
                 .required(false)
                 .global(true),
         )
+        .arg(
+            Arg::with_name("prometheus-listen")
+                .long("prometheus-listen")
+                .help("TCP address to serve metrics in the Prometheus text exposition format, e.g. 127.0.0.1:9110")
+                .takes_value(true)
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("hot-chunk-cache-size")
+                .long("hot-chunk-cache-size")
+                .default_value("0")
+                .help("Maximum memory in bytes to cache hot chunks, shared by all filesystems (0 disables the cache)")
+                .takes_value(true)
+                .required(false)
+                .global(true),
+        )
         .arg(
             Arg::with_name("config")
                 .long("config")
@@ -701,6 +724,18 @@ fn main() -> Result<()> {
     setup_logging(logging_file, level)?;
     dump_program_info(crate_version!());
     handle_rlimit_nofile_option(&args, "rlimit-nofile")?;
+    // `hot-chunk-cache-size` has a default value, so safe to unwrap().
+    let hot_chunk_cache_size: u64 = args
+        .value_of("hot-chunk-cache-size")
+        .unwrap()
+        .parse()
+        .map_err(|_e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "invalid value for option `hot-chunk-cache-size`",
+            )
+        })?;
+    HOT_CHUNK_CACHE.set_capacity(hot_chunk_cache_size);
 
     match args.subcommand_name() {
         Some("daemon") => {
@@ -738,10 +773,16 @@ fn main() -> Result<()> {
     // Start the HTTP Administration API server
     let mut api_controller = ApiServerController::new(apisock);
     api_controller.start()?;
+    // Start the Prometheus metrics exporter
+    if let Some(addr) = args.value_of("prometheus-listen") {
+        start_prometheus_thread(addr)?;
+    }
 
     // Initialize and run the daemon controller event loop.
     nydus_app::signal::register_signal_handler(signal::SIGINT, sig_exit);
     nydus_app::signal::register_signal_handler(signal::SIGTERM, sig_exit);
+    // Reload CA and client certificates of storage backends.
+    nydus_app::signal::register_signal_handler(signal::SIGHUP, sig_reload);
 
     // Run the main event loop
     if DAEMON_CONTROLLER.is_active() {
@@ -1,28 +1,245 @@
+// Copyright (C) 2022 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: (Apache-2.0 AND BSD-3-Clause)
+
+//! Live-upgrade and failover support for nydusd.
+//!
+//! The daemon keeps track of all mounted filesystem backends in the `UpgradeManager`. When asked
+//! by the supervisor, it serializes those states together with the FUSE session file descriptor
+//! and sends them to the supervisor socket. A new nydusd instance, either started for upgrading
+//! or to take over a crashed one, fetches the states and the file descriptor back from the
+//! supervisor, restores all mounts and resumes serving the same FUSE connection.
+
+use std::collections::HashMap;
 use std::convert::TryFrom;
-#[cfg(feature = "fusedev")]
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
 use std::path::PathBuf;
 
-use crate::daemon::DaemonResult;
+use sendfd::{RecvWithFd, SendWithFd};
+use serde::{Deserialize, Serialize};
+use serde_json::Error as SerdeError;
+use storage::factory::BackendConfig;
+
+use crate::daemon::{DaemonError, DaemonResult};
 use crate::fs_service::FsBackendUmountCmd;
 use crate::FsBackendMountCmd;
 
+/// Version of the states format exchanged with the supervisor.
+const UPGRADE_STATES_VERSION: u32 = 1;
+/// Maximum size of states exchanged with the supervisor in one message.
+const MAX_STATES_SIZE: usize = 0x10_0000;
+
+/// Error codes related to live-upgrade and failover.
 #[derive(Debug)]
-pub enum UpgradeMgrError {}
-pub struct UpgradeManager {}
+pub enum UpgradeMgrError {
+    /// Failed to connect to the supervisor.
+    Connect(io::Error),
+    /// Failed to send states to the supervisor.
+    SendStates(io::Error),
+    /// Failed to receive states from the supervisor.
+    RecvStates(io::Error),
+    /// Failed to serialize/deserialize states.
+    Serde(SerdeError),
+    /// States are invalid or incompatible.
+    InvalidStates(String),
+    /// The FUSE session file descriptor is not available.
+    MissingFuseFd,
+}
+
+impl Display for UpgradeMgrError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connect(e) => write!(f, "failed to connect to supervisor, {}", e),
+            Self::SendStates(e) => write!(f, "failed to send states to supervisor, {}", e),
+            Self::RecvStates(e) => write!(f, "failed to receive states from supervisor, {}", e),
+            Self::Serde(e) => write!(f, "failed to serialize/deserialize states, {}", e),
+            Self::InvalidStates(s) => write!(f, "invalid states, {}", s),
+            Self::MissingFuseFd => write!(f, "fuse session file descriptor is not available"),
+        }
+    }
+}
+
+impl From<UpgradeMgrError> for DaemonError {
+    fn from(e: UpgradeMgrError) -> Self {
+        DaemonError::UpgradeManager(e)
+    }
+}
+
+/// States of a mounted filesystem backend.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MountState {
+    /// Command to recreate the filesystem backend.
+    pub cmd: FsBackendMountCmd,
+    /// Index of the backend inside the vfs, which is encoded into inode numbers.
+    pub vfs_index: u8,
+}
+
+/// Manager to maintain and exchange states needed by live-upgrade and failover.
+pub struct UpgradeManager {
+    supervisor: PathBuf,
+    mounts: HashMap<String, MountState>,
+}
 
-#[cfg(feature = "fusedev")]
 impl UpgradeManager {
-    pub fn new(_: PathBuf) -> Self {
-        UpgradeManager {}
+    /// Create a new instance of `UpgradeManager` to communicate with the `supervisor` socket.
+    pub fn new(supervisor: PathBuf) -> Self {
+        UpgradeManager {
+            supervisor,
+            mounts: HashMap::new(),
+        }
+    }
+
+    /// Get states of all mounted filesystem backends, sorted by vfs index.
+    pub fn mounts(&self) -> Vec<MountState> {
+        let mut mounts: Vec<MountState> = self.mounts.values().cloned().collect();
+        mounts.sort_by_key(|m| m.vfs_index);
+        mounts
+    }
+
+    /// Send `states` and the file descriptor `fd` to the supervisor.
+    pub fn save_states<T: Serialize>(&self, states: &T, fd: RawFd) -> DaemonResult<()> {
+        let stream = self.connect()?;
+        send_states(&stream, states, fd)?;
+        info!("states have been saved to supervisor {:?}", self.supervisor);
+        Ok(())
     }
+
+    /// Fetch states and the associated file descriptor back from the supervisor.
+    pub fn restore_states<T: for<'de> Deserialize<'de>>(&self) -> DaemonResult<(T, File)> {
+        let stream = self.connect()?;
+        let (states, file) = recv_states(&stream)?;
+        info!(
+            "states have been restored from supervisor {:?}",
+            self.supervisor
+        );
+        Ok((states, file))
+    }
+
+    fn connect(&self) -> DaemonResult<UnixStream> {
+        UnixStream::connect(&self.supervisor).map_err(|e| UpgradeMgrError::Connect(e).into())
+    }
+}
+
+/// Wrapper to carry versioned states.
+#[derive(Deserialize, Serialize)]
+struct StatesWrapper<T> {
+    version: u32,
+    states: T,
 }
 
-#[derive(PartialEq)]
+// Size of the length prefix of the states message.
+const STATES_LEN_SIZE: usize = std::mem::size_of::<u32>();
+
+// The states message consists of a little endian `u32` length prefix followed by the serialized
+// states, and the file descriptor is sent together with the first byte of the message.
+fn send_states<T: Serialize>(stream: &UnixStream, states: &T, fd: RawFd) -> DaemonResult<()> {
+    let wrapper = StatesWrapper {
+        version: UPGRADE_STATES_VERSION,
+        states,
+    };
+    let data = serde_json::to_vec(&wrapper).map_err(UpgradeMgrError::Serde)?;
+    if data.len() >= MAX_STATES_SIZE {
+        return Err(UpgradeMgrError::InvalidStates(format!(
+            "states size 0x{:x} exceeds limit 0x{:x}",
+            data.len(),
+            MAX_STATES_SIZE
+        ))
+        .into());
+    }
+
+    let mut msg = Vec::with_capacity(STATES_LEN_SIZE + data.len());
+    msg.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    msg.extend_from_slice(&data);
+    let sent = stream
+        .send_with_fd(&msg, &[fd])
+        .map_err(UpgradeMgrError::SendStates)?;
+    if sent == 0 {
+        return Err(UpgradeMgrError::SendStates(eio!("supervisor closed the connection")).into());
+    }
+    let mut writer = stream;
+    writer
+        .write_all(&msg[sent..])
+        .map_err(UpgradeMgrError::SendStates)?;
+
+    Ok(())
+}
+
+fn recv_states<T: for<'de> Deserialize<'de>>(stream: &UnixStream) -> DaemonResult<(T, File)> {
+    let mut msg = vec![0u8; STATES_LEN_SIZE + MAX_STATES_SIZE];
+    let mut fds = [-1 as RawFd; 1];
+    let (mut size, fd_cnt) = stream
+        .recv_with_fd(&mut msg, &mut fds)
+        .map_err(UpgradeMgrError::RecvStates)?;
+    if fd_cnt != 1 || fds[0] < 0 {
+        return Err(UpgradeMgrError::MissingFuseFd.into());
+    }
+    // Safe because we have received the file descriptor and it's owned by us now.
+    let file = unsafe { File::from_raw_fd(fds[0]) };
+
+    // The message may arrive in pieces, so keep reading until the whole states have arrived.
+    let mut reader = stream;
+    if size < STATES_LEN_SIZE {
+        reader
+            .read_exact(&mut msg[size..STATES_LEN_SIZE])
+            .map_err(UpgradeMgrError::RecvStates)?;
+        size = STATES_LEN_SIZE;
+    }
+    let mut len = [0u8; STATES_LEN_SIZE];
+    len.copy_from_slice(&msg[..STATES_LEN_SIZE]);
+    let len = u32::from_le_bytes(len) as usize;
+    if len == 0 || len >= MAX_STATES_SIZE {
+        return Err(
+            UpgradeMgrError::InvalidStates(format!("invalid states size 0x{:x}", len)).into(),
+        );
+    }
+    if size < STATES_LEN_SIZE + len {
+        reader
+            .read_exact(&mut msg[size..STATES_LEN_SIZE + len])
+            .map_err(UpgradeMgrError::RecvStates)?;
+    }
+
+    let data = &msg[STATES_LEN_SIZE..STATES_LEN_SIZE + len];
+    let wrapper: StatesWrapper<T> = serde_json::from_slice(data).map_err(UpgradeMgrError::Serde)?;
+    if wrapper.version != UPGRADE_STATES_VERSION {
+        return Err(UpgradeMgrError::InvalidStates(format!(
+            "unsupported states version {}",
+            wrapper.version
+        ))
+        .into());
+    }
+
+    Ok((wrapper.states, file))
+}
+
+/// Reason for a new nydusd instance to restore states from the supervisor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RestoreReason {
+    /// The previous instance has handed over its FUSE session for live-upgrade.
+    Upgrade,
+    /// The previous instance crashed, leaving requests pending on the FUSE connection.
+    Failover,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum FailoverPolicy {
     Flush,
     Resend,
 }
 
+impl FailoverPolicy {
+    /// Name of the control file under `/sys/fs/fuse/connections/<conn>/` for the policy.
+    pub fn control_name(&self) -> &'static str {
+        match self {
+            FailoverPolicy::Flush => "flush",
+            FailoverPolicy::Resend => "resend",
+        }
+    }
+}
+
 impl TryFrom<&str> for FailoverPolicy {
     type Error = std::io::Error;
 
@@ -36,33 +253,245 @@ impl TryFrom<&str> for FailoverPolicy {
 }
 
 pub fn add_mounts_state(
-    _mgr: &mut UpgradeManager,
-    _cmd: FsBackendMountCmd,
-    _vfs_index: u8,
+    mgr: &mut UpgradeManager,
+    cmd: FsBackendMountCmd,
+    vfs_index: u8,
 ) -> DaemonResult<()> {
+    if mgr.mounts.contains_key(&cmd.mountpoint) {
+        return Err(DaemonError::AlreadyExists);
+    }
+    mgr.mounts
+        .insert(cmd.mountpoint.clone(), MountState { cmd, vfs_index });
     Ok(())
 }
 
-pub fn update_mounts_state(_mgr: &mut UpgradeManager, _cmd: FsBackendMountCmd) -> DaemonResult<()> {
+pub fn update_mounts_state(mgr: &mut UpgradeManager, cmd: FsBackendMountCmd) -> DaemonResult<()> {
+    let state = mgr
+        .mounts
+        .get_mut(&cmd.mountpoint)
+        .ok_or(DaemonError::NotFound)?;
+    state.cmd = cmd;
     Ok(())
 }
 
-pub fn remove_mounts_state(
-    _mgr: &mut UpgradeManager,
-    _cmd: FsBackendUmountCmd,
+pub fn update_mounts_backend(
+    mgr: &mut UpgradeManager,
+    mountpoint: &str,
+    backend: &BackendConfig,
 ) -> DaemonResult<()> {
+    let state = mgr
+        .mounts
+        .get_mut(mountpoint)
+        .ok_or(DaemonError::NotFound)?;
+    let mut config: serde_json::Value =
+        serde_json::from_str(&state.cmd.config).map_err(DaemonError::Serde)?;
+    config["device"]["backend"] = serde_json::to_value(backend).map_err(DaemonError::Serde)?;
+    state.cmd.config = config.to_string();
     Ok(())
 }
 
+pub fn remove_mounts_state(mgr: &mut UpgradeManager, cmd: FsBackendUmountCmd) -> DaemonResult<()> {
+    mgr.mounts
+        .remove(&cmd.mountpoint)
+        .map(|_| ())
+        .ok_or(DaemonError::NotFound)
+}
+
 #[cfg(feature = "fusedev")]
 pub mod fusedev_upgrade {
-    use crate::daemon::DaemonResult;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+    use std::sync::atomic::Ordering;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::{MountState, RestoreReason, UpgradeMgrError};
+    use crate::daemon::{DaemonError, DaemonResult};
+    use crate::fs_service::FsService;
     use crate::fusedev::FusedevDaemon;
-    pub fn save(_daemon: &FusedevDaemon) -> DaemonResult<()> {
-        Ok(())
+
+    /// States of a FUSE daemon to be saved to and restored from the supervisor.
+    ///
+    /// FUSE requests being processed by the previous instance aren't saved, they are left to the
+    /// kernel to flush or resend according to the failover policy.
+    #[derive(Deserialize, Serialize)]
+    struct FusedevStates {
+        /// Fuse connection ID.
+        conn: u64,
+        /// Mounted filesystem backends.
+        mounts: Vec<MountState>,
+    }
+
+    pub fn save(daemon: &FusedevDaemon) -> DaemonResult<()> {
+        let svc = daemon.service();
+        let mgr = svc.upgrade_mgr().ok_or(DaemonError::Unsupported)?;
+        let session = svc.session.lock().unwrap();
+        let file = session
+            .get_fuse_file()
+            .ok_or(UpgradeMgrError::MissingFuseFd)?;
+        let states = FusedevStates {
+            conn: svc.conn.load(Ordering::Acquire),
+            mounts: mgr.mounts(),
+        };
+
+        mgr.save_states(&states, file.as_raw_fd())
     }
 
-    pub fn restore(_daemon: &FusedevDaemon) -> DaemonResult<()> {
+    pub fn restore(daemon: &FusedevDaemon, reason: RestoreReason) -> DaemonResult<()> {
+        let svc = daemon.service();
+        // Release the lock before restoring mounts, which will update the manager again.
+        let (states, file) = svc
+            .upgrade_mgr()
+            .ok_or(DaemonError::Unsupported)?
+            .restore_states::<FusedevStates>()?;
+
+        for m in states.mounts.iter() {
+            svc.restore_mount(&m.cmd, m.vfs_index)?;
+        }
+        svc.session.lock().unwrap().set_fuse_file(file);
+        svc.conn.store(states.conn, Ordering::Release);
+
+        // The previous instance has drained all requests before handing over for upgrading.
+        if reason == RestoreReason::Failover {
+            handle_failover(states.conn, svc.failover_policy.control_name());
+        }
+
         Ok(())
     }
+
+    /// Ask the kernel to flush or resend pending requests on the fuse connection, if supported.
+    fn handle_failover(conn: u64, control: &str) {
+        let path = format!("/sys/fs/fuse/connections/{}/{}", conn, control);
+        if !Path::new(&path).exists() {
+            info!("fuse control file {} is not available, skip it", path);
+            return;
+        }
+
+        match OpenOptions::new().write(true).open(&path) {
+            Ok(mut f) => {
+                if let Err(e) = f.write_all(b"1") {
+                    warn!("failed to write fuse control file {}, {}", path, e);
+                }
+            }
+            Err(e) => warn!("failed to open fuse control file {}, {}", path, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nydus::FsBackendType;
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::os::unix::io::AsRawFd;
+    use vmm_sys_util::tempfile::TempFile;
+
+    fn mount_cmd(mountpoint: &str) -> FsBackendMountCmd {
+        FsBackendMountCmd {
+            fs_type: FsBackendType::Rafs,
+            source: "bootstrap".to_string(),
+            config: "{}".to_string(),
+            mountpoint: mountpoint.to_string(),
+            prefetch_files: None,
+        }
+    }
+
+    #[test]
+    fn test_mounts_state() {
+        let mut mgr = UpgradeManager::new(PathBuf::from("/tmp/supervisor.sock"));
+
+        add_mounts_state(&mut mgr, mount_cmd("/b"), 1).unwrap();
+        add_mounts_state(&mut mgr, mount_cmd("/a"), 0).unwrap();
+        assert!(add_mounts_state(&mut mgr, mount_cmd("/a"), 2).is_err());
+        let mounts = mgr.mounts();
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[0].cmd.mountpoint, "/a");
+        assert_eq!(mounts[1].vfs_index, 1);
+
+        let mut cmd = mount_cmd("/b");
+        cmd.source = "bootstrap2".to_string();
+        update_mounts_state(&mut mgr, cmd).unwrap();
+        assert_eq!(mgr.mounts()[1].cmd.source, "bootstrap2");
+        assert!(update_mounts_state(&mut mgr, mount_cmd("/c")).is_err());
+
+        remove_mounts_state(
+            &mut mgr,
+            FsBackendUmountCmd {
+                mountpoint: "/a".to_string(),
+            },
+        )
+        .unwrap();
+        assert_eq!(mgr.mounts().len(), 1);
+        assert!(remove_mounts_state(
+            &mut mgr,
+            FsBackendUmountCmd {
+                mountpoint: "/a".to_string(),
+            },
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_send_recv_states() {
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        let mut tmp = TempFile::new().unwrap().into_file();
+        tmp.write_all(b"nydus").unwrap();
+
+        let states = vec![MountState {
+            cmd: mount_cmd("/"),
+            vfs_index: 3,
+        }];
+        send_states(&sender, &states, tmp.as_raw_fd()).unwrap();
+        let (states2, mut file) = recv_states::<Vec<MountState>>(&receiver).unwrap();
+        assert_eq!(states2.len(), 1);
+        assert_eq!(states2[0].vfs_index, 3);
+        assert_eq!(states2[0].cmd.mountpoint, "/");
+
+        let mut buf = String::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "nydus");
+    }
+
+    #[test]
+    fn test_recv_split_states() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let tmp = TempFile::new().unwrap().into_file();
+        let states = vec![MountState {
+            cmd: mount_cmd("/mnt"),
+            vfs_index: 1,
+        }];
+        let data = serde_json::to_vec(&StatesWrapper {
+            version: UPGRADE_STATES_VERSION,
+            states: &states,
+        })
+        .unwrap();
+        let mut msg = (data.len() as u32).to_le_bytes().to_vec();
+        msg.extend_from_slice(&data);
+
+        // Split the message inside the length prefix and inside the states.
+        sender.send_with_fd(&msg[..2], &[tmp.as_raw_fd()]).unwrap();
+        let handle = std::thread::spawn(move || {
+            sender.write_all(&msg[2..10]).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            sender.write_all(&msg[10..]).unwrap();
+        });
+        let (states2, _file) = recv_states::<Vec<MountState>>(&receiver).unwrap();
+        handle.join().unwrap();
+        assert_eq!(states2.len(), 1);
+        assert_eq!(states2[0].cmd.mountpoint, "/mnt");
+        assert_eq!(states2[0].vfs_index, 1);
+    }
+
+    #[test]
+    fn test_failover_policy() {
+        assert_eq!(
+            FailoverPolicy::try_from("flush").unwrap(),
+            FailoverPolicy::Flush
+        );
+        assert_eq!(FailoverPolicy::Resend.control_name(), "resend");
+        assert!(FailoverPolicy::try_from("invalid").is_err());
+    }
 }
@@ -0,0 +1,426 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Storage backend driver to access blobs from a list of tiered storage backends.
+//!
+//! The composite backend wraps an ordered list of storage backends, such as `localfs` holding
+//! pre-seeded blobs then `registry`. Each blob is served by the first tier having it, and read
+//! requests fail over to the following tiers on failure. Blobs served by lower tiers may be
+//! written back into a local directory, so following requests are served by the local tier.
+use std::cmp;
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+
+use nydus_utils::metrics::BackendMetrics;
+
+use crate::backend::{BackendResult, BlobBackend, BlobReader};
+use crate::factory::{BackendConfig, BlobFactory};
+use crate::utils::alloc_buf;
+
+/// Size of backend requests to fetch blobs to write back.
+const WRITE_BACK_REQUEST_SIZE: u64 = 0x100000;
+/// Suffix of blob files being written back.
+const WRITE_BACK_SUFFIX: &str = ".writeback";
+
+/// Composite configuration information to access blobs from tiered storage backends.
+///
+/// This structure is externally visible through configuration file and HTTP API, please keep them
+/// stable.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct CompositeConfig {
+    /// Storage backends in order of preference, such as `localfs` then `registry`.
+    pub backends: Vec<BackendConfig>,
+    /// Directory to write blobs served by lower tiers back, empty to disable write back.
+    ///
+    /// It's normally the `dir` of the first `localfs` tier, so written back blobs are served by
+    /// the local tier.
+    #[serde(default)]
+    pub write_back_dir: String,
+}
+
+struct Tier {
+    // Name of the tier in metrics, `<index>:<backend type>`.
+    name: String,
+    config: Mutex<BackendConfig>,
+    backend: Arc<dyn BlobBackend + Send + Sync>,
+}
+
+struct CompositeReader {
+    blob_id: String,
+    tiers: Arc<Vec<Tier>>,
+    // Readers of each tier, created on demand.
+    readers: Vec<RwLock<Option<Arc<dyn BlobReader>>>>,
+    // Index of the preferred tier to serve the blob.
+    current: AtomicUsize,
+    metrics: Arc<BackendMetrics>,
+}
+
+impl CompositeReader {
+    fn reader(&self, idx: usize) -> BackendResult<Arc<dyn BlobReader>> {
+        if let Some(reader) = self.readers[idx].read().unwrap().as_ref() {
+            return Ok(reader.clone());
+        }
+        let reader = self.tiers[idx].backend.get_reader(&self.blob_id)?;
+        *self.readers[idx].write().unwrap() = Some(reader.clone());
+
+        Ok(reader)
+    }
+
+    // Serve the request by the preferred tier, and fail over to following tiers on failure.
+    // Return the result and index of the tier serving the request.
+    fn serve<T, F>(&self, mut op: F) -> BackendResult<(T, usize)>
+    where
+        F: FnMut(&dyn BlobReader) -> BackendResult<T>,
+    {
+        let mut idx = self.current.load(Ordering::Acquire);
+        loop {
+            match self.reader(idx).and_then(|reader| op(reader.as_ref())) {
+                Ok(v) => return Ok((v, idx)),
+                Err(e) if idx + 1 < self.tiers.len() => {
+                    warn!(
+                        "tier {} failed to serve blob {}, fail over to next tier, {:?}",
+                        self.tiers[idx].name, self.blob_id, e
+                    );
+                    idx += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Write the blob served by a lower tier back into `dir`, and switch to the first tier having
+    // the blob afterwards.
+    fn write_back(&self, dir: &Path) -> Result<()> {
+        let src = self.current.load(Ordering::Acquire);
+        let reader = self.reader(src).map_err(|e| eio!(e))?;
+        let size = reader.blob_size().map_err(|e| eio!(e))?;
+        let path = dir.join(&self.blob_id);
+        let tmp_path = dir.join(format!("{}{}", self.blob_id, WRITE_BACK_SUFFIX));
+
+        let copy = || -> Result<()> {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(&tmp_path)?;
+            let mut buf = alloc_buf(cmp::min(size, WRITE_BACK_REQUEST_SIZE) as usize);
+            let mut offset = 0;
+            while offset < size {
+                let count = cmp::min(size - offset, WRITE_BACK_REQUEST_SIZE) as usize;
+                let sz = reader
+                    .read(&mut buf[..count], offset)
+                    .map_err(|e| eio!(e))?;
+                if sz == 0 {
+                    return Err(eio!("unexpected end of blob"));
+                }
+                file.write_all(&buf[..sz])?;
+                offset += sz as u64;
+            }
+            file.sync_all()?;
+            fs::rename(&tmp_path, &path)
+        };
+        if let Err(e) = copy() {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+
+        for idx in 0..src {
+            if let Ok(reader) = self.tiers[idx].backend.get_reader(&self.blob_id) {
+                *self.readers[idx].write().unwrap() = Some(reader);
+                self.current.store(idx, Ordering::Release);
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl BlobReader for CompositeReader {
+    fn blob_size(&self) -> BackendResult<u64> {
+        self.serve(|reader| reader.blob_size()).map(|v| v.0)
+    }
+
+    fn try_read(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
+        self.serve(|reader| reader.try_read(buf, offset))
+            .map(|v| v.0)
+    }
+
+    fn read(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
+        let begin_time = self.metrics.begin();
+        // Readers of the tiers are responsible for retrying failed requests.
+        let result = self.serve(|reader| reader.read(buf, offset));
+
+        match result {
+            Ok((size, idx)) => {
+                self.metrics.tier_read(&self.tiers[idx].name);
+                self.metrics.end(&begin_time, buf.len(), false);
+                Ok(size)
+            }
+            Err(e) => {
+                self.metrics.end(&begin_time, buf.len(), true);
+                Err(e)
+            }
+        }
+    }
+
+    fn prefetch_blob_data_range(&self, ra_offset: u64, ra_size: u64) -> BackendResult<()> {
+        self.reader(self.current.load(Ordering::Acquire))?
+            .prefetch_blob_data_range(ra_offset, ra_size)
+    }
+
+    fn stop_data_prefetch(&self) -> BackendResult<()> {
+        self.reader(self.current.load(Ordering::Acquire))?
+            .stop_data_prefetch()
+    }
+
+    fn metrics(&self) -> &BackendMetrics {
+        &self.metrics
+    }
+}
+
+/// Storage backend to access blobs from a list of tiered storage backends.
+pub struct Composite {
+    tiers: Arc<Vec<Tier>>,
+    write_back_dir: Option<PathBuf>,
+    // Blobs being written back.
+    write_backs: Arc<Mutex<HashSet<String>>>,
+    metrics: Arc<BackendMetrics>,
+}
+
+impl Composite {
+    /// Create a new composite storage backend.
+    pub fn new(config: serde_json::value::Value, id: Option<&str>) -> Result<Composite> {
+        let config: CompositeConfig = serde_json::from_value(config).map_err(|e| einval!(e))?;
+        let id = id.ok_or_else(|| einval!("composite backend requires blob_id"))?;
+        if config.backends.is_empty() {
+            return Err(einval!(
+                "composite backend requires at least one storage backend"
+            ));
+        }
+
+        let mut tiers = Vec::with_capacity(config.backends.len());
+        for (idx, backend) in config.backends.into_iter().enumerate() {
+            let name = format!("{}:{}", idx, backend.backend_type);
+            let tier_id = format!("{}:{}", id, idx);
+            let tier_backend = BlobFactory::new_backend(backend.clone(), &tier_id)?;
+            tiers.push(Tier {
+                name,
+                config: Mutex::new(backend),
+                backend: tier_backend,
+            });
+        }
+        let write_back_dir = if config.write_back_dir.is_empty() {
+            None
+        } else {
+            fs::create_dir_all(&config.write_back_dir)?;
+            Some(PathBuf::from(config.write_back_dir))
+        };
+
+        Ok(Composite {
+            tiers: Arc::new(tiers),
+            write_back_dir,
+            write_backs: Arc::new(Mutex::new(HashSet::new())),
+            metrics: BackendMetrics::new(id, "composite"),
+        })
+    }
+
+    // Write the blob back in background, if it's not being written back yet.
+    fn write_back(&self, reader: Arc<CompositeReader>, dir: &Path) {
+        if !self
+            .write_backs
+            .lock()
+            .unwrap()
+            .insert(reader.blob_id.clone())
+        {
+            return;
+        }
+
+        let blob_id = reader.blob_id.clone();
+        let write_backs = self.write_backs.clone();
+        let dir = dir.to_path_buf();
+        let result = thread::Builder::new()
+            .name("nydus-composite-write-back".to_string())
+            .spawn(move || {
+                match reader.write_back(&dir) {
+                    Ok(_) => info!("blob {} written back to {}", reader.blob_id, dir.display()),
+                    Err(e) => warn!("failed to write back blob {}, {}", reader.blob_id, e),
+                }
+                write_backs.lock().unwrap().remove(&reader.blob_id);
+            });
+        if let Err(e) = result {
+            warn!(
+                "failed to spawn thread to write back blob {}, {}",
+                blob_id, e
+            );
+            self.write_backs.lock().unwrap().remove(&blob_id);
+        }
+    }
+}
+
+impl BlobBackend for Composite {
+    fn shutdown(&self) {
+        for tier in self.tiers.iter() {
+            tier.backend.shutdown();
+        }
+    }
+
+    fn metrics(&self) -> &BackendMetrics {
+        &self.metrics
+    }
+
+    fn get_reader(&self, blob_id: &str) -> BackendResult<Arc<dyn BlobReader>> {
+        let mut readers = Vec::with_capacity(self.tiers.len());
+        let mut current = None;
+        let mut last_err = None;
+
+        // Pick the first tier having the blob.
+        for (idx, tier) in self.tiers.iter().enumerate() {
+            let mut reader = None;
+            if current.is_none() {
+                match tier.backend.get_reader(blob_id) {
+                    Ok(v) => {
+                        reader = Some(v);
+                        current = Some(idx);
+                    }
+                    Err(e) => {
+                        debug!("tier {} doesn't have blob {}, {:?}", tier.name, blob_id, e);
+                        last_err = Some(e);
+                    }
+                }
+            }
+            readers.push(RwLock::new(reader));
+        }
+        let current = match current {
+            Some(idx) => idx,
+            // Safe to unwrap because there's at least one tier.
+            None => return Err(last_err.unwrap()),
+        };
+
+        let reader = Arc::new(CompositeReader {
+            blob_id: blob_id.to_string(),
+            tiers: self.tiers.clone(),
+            readers,
+            current: AtomicUsize::new(current),
+            metrics: self.metrics.clone(),
+        });
+        if current > 0 {
+            if let Some(dir) = self.write_back_dir.as_ref() {
+                self.write_back(reader.clone(), dir);
+            }
+        }
+
+        Ok(reader)
+    }
+
+    fn update_config(&self, config: serde_json::value::Value) -> Result<()> {
+        let config: CompositeConfig = serde_json::from_value(config).map_err(|e| einval!(e))?;
+        if config.backends.len() != self.tiers.len()
+            || config
+                .backends
+                .iter()
+                .zip(self.tiers.iter())
+                .any(|(b, t)| b.backend_type != t.config.lock().unwrap().backend_type)
+        {
+            return Err(einval!("can't change tiers of composite backend"));
+        }
+
+        // Only update tiers with changed configuration, some storage backends don't support it.
+        for (backend, tier) in config.backends.into_iter().zip(self.tiers.iter()) {
+            let mut current = tier.config.lock().unwrap();
+            if *current != backend {
+                tier.backend.update_config(backend.backend_config.clone())?;
+                *current = backend;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Composite {
+    fn drop(&mut self) {
+        self.metrics.release().unwrap_or_else(|e| error!("{:?}", e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[cfg(feature = "backend-localfs")]
+    #[test]
+    fn test_composite_backend() {
+        let local = TempDir::new().unwrap();
+        let remote = TempDir::new().unwrap();
+        let local_dir = local.as_path().to_str().unwrap();
+        let remote_dir = remote.as_path().to_str().unwrap();
+        let data: Vec<u8> = (0..0x3000u32).map(|v| v as u8).collect();
+        fs::write(local.as_path().join("blob1"), &data[..0x1000]).unwrap();
+        fs::write(remote.as_path().join("blob2"), &data).unwrap();
+
+        let config = json!({
+            "backends": [
+                {"type": "localfs", "config": {"dir": local_dir}},
+                {"type": "localfs", "config": {"dir": remote_dir}}
+            ]
+        });
+        assert!(Composite::new(json!({"backends": []}), Some("test-composite")).is_err());
+        let composite = Composite::new(config.clone(), Some("test-composite")).unwrap();
+        let mut buf = vec![0u8; 0x100];
+
+        // Served by the local tier.
+        let reader = composite.get_reader("blob1").unwrap();
+        assert_eq!(reader.blob_size().unwrap(), 0x1000);
+        assert_eq!(reader.read(&mut buf, 0x100).unwrap(), 0x100);
+        assert_eq!(buf, data[0x100..0x200]);
+        assert_eq!(composite.metrics.tier_read_count("0:localfs"), 1);
+
+        // Served by the remote tier.
+        let reader = composite.get_reader("blob2").unwrap();
+        assert_eq!(reader.read(&mut buf, 0x1000).unwrap(), 0x100);
+        assert_eq!(buf, data[0x1000..0x1100]);
+        assert_eq!(composite.metrics.tier_read_count("1:localfs"), 1);
+        assert!(!local.as_path().join("blob2").exists());
+        assert!(composite.get_reader("missing").is_err());
+
+        let tier = json!({"backends": [{"type": "localfs", "config": {"dir": local_dir}}]});
+        assert!(composite.update_config(tier).is_err());
+        composite.update_config(config).unwrap();
+        composite.shutdown();
+
+        // Written back into the local tier, and served by the local tier afterwards.
+        let config = json!({
+            "backends": [
+                {"type": "localfs", "config": {"dir": local_dir}},
+                {"type": "localfs", "config": {"dir": remote_dir}}
+            ],
+            "write_back_dir": local_dir
+        });
+        let composite = Composite::new(config, Some("test-composite-write-back")).unwrap();
+        let reader = composite.get_reader("blob2").unwrap();
+        for _ in 0..100 {
+            if composite.write_backs.lock().unwrap().is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(composite.write_backs.lock().unwrap().is_empty());
+        assert_eq!(fs::read(local.as_path().join("blob2")).unwrap(), data);
+        assert_eq!(reader.read(&mut buf, 0x2000).unwrap(), 0x100);
+        assert_eq!(buf, data[0x2000..0x2100]);
+        assert_eq!(composite.metrics.tier_read_count("0:localfs"), 1);
+        assert_eq!(composite.metrics.tier_read_count("1:localfs"), 0);
+
+        composite.shutdown();
+    }
+}
@@ -4,25 +4,44 @@
 
 //! Help library to manage network connections.
 use std::collections::HashMap;
+use std::fs;
 use std::io::Read;
 use std::io::Result;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::Duration;
 
-use reqwest::header::HeaderMap;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use reqwest::{
     self,
     blocking::{Body, Client, Response},
     redirect::Policy,
-    Method, StatusCode, Url,
+    tls, Certificate, Identity, Method, StatusCode, Url,
 };
 
-use nydus_api::http::RegistryOssConfig;
+use nydus_api::http::{MirrorConfig, RegistryOssConfig};
+use nydus_utils::metrics::BackendMetrics;
+
+use crate::backend::retry::{CircuitBreaker, RetryPolicy};
 
 const HEADER_AUTHORIZATION: &str = "Authorization";
+/// Path prefix of registry APIs, which are the only requests served by registry mirrors.
+const REGISTRY_API_PREFIX: &str = "/v2/";
+const PEM_CERT_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_CERT_END: &str = "-----END CERTIFICATE-----";
+
+/// Generation of TLS configuration files, increased to reload them.
+static TLS_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Reload CA certificates and client certificates of all network connections.
+///
+/// Connections rebuild their HTTP clients before sending the next request. It's safe to call
+/// the function in signal handlers.
+pub fn reload_tls_config() {
+    TLS_GENERATION.fetch_add(1, Ordering::AcqRel);
+}
 
 /// Error codes related to network communication.
 #[derive(Debug)]
@@ -102,16 +121,115 @@ impl ProxyHealth {
 
 #[derive(Debug)]
 struct Proxy {
-    client: Client,
+    client: RwLock<Client>,
     health: ProxyHealth,
     fallback: bool,
 }
 
+/// Registry mirror to fail over requests to.
+#[derive(Debug)]
+struct Mirror {
+    /// Mirror server URL with scheme, without trailing slash.
+    host: String,
+    headers: HeaderMap,
+    health: ProxyHealth,
+    failed_times: AtomicU32,
+    failure_limit: u32,
+}
+
+impl Mirror {
+    fn new(config: &MirrorConfig) -> Result<Self> {
+        let host = config.host.trim_end_matches('/').to_string();
+        Url::from_str(&host).map_err(|e| einval!(format!("invalid mirror {}, {}", host, e)))?;
+        let ping_url = if config.ping_url.is_empty() {
+            format!("{}{}", host, REGISTRY_API_PREFIX)
+        } else {
+            config.ping_url.clone()
+        };
+        let ping_url = Url::from_str(&ping_url).map_err(|e| einval!(e))?;
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in config.headers.iter() {
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| einval!(e))?;
+            let value = HeaderValue::from_str(value).map_err(|e| einval!(e))?;
+            headers.insert(name, value);
+        }
+
+        Ok(Mirror {
+            host,
+            headers,
+            health: ProxyHealth::new(config.health_check_interval, Some(ping_url)),
+            failed_times: AtomicU32::new(0),
+            failure_limit: std::cmp::max(config.failure_limit as u32, 1),
+        })
+    }
+
+    /// Get the url to send the request to the mirror.
+    ///
+    /// Return `None` for requests not to registry APIs, such as requests to the registry token
+    /// server or redirected blob storage, which are always sent to the original servers.
+    fn mirror_url(&self, url: &str) -> Option<String> {
+        let url = Url::from_str(url).ok()?;
+        if !url.path().starts_with(REGISTRY_API_PREFIX) {
+            return None;
+        }
+
+        let mut mirror_url = format!("{}{}", self.host, url.path());
+        if let Some(query) = url.query() {
+            mirror_url.push('?');
+            mirror_url.push_str(query);
+        }
+
+        Some(mirror_url)
+    }
+
+    fn succeed(&self) {
+        self.failed_times.store(0, Ordering::Relaxed);
+    }
+
+    fn fail(&self) {
+        let failed_times = self.failed_times.fetch_add(1, Ordering::Relaxed) + 1;
+        if failed_times >= self.failure_limit && self.health.ok() {
+            warn!(
+                "Mirror {} failed {} times, mark it as unavailable",
+                self.host, failed_times
+            );
+            self.health.set(false);
+        }
+    }
+
+    fn recover(&self) {
+        info!("Mirror {} recovered", self.host);
+        self.failed_times.store(0, Ordering::Relaxed);
+        self.health.set(true);
+    }
+}
+
 /// Check whether the HTTP status code is a success result.
 pub(crate) fn is_success_status(status: StatusCode) -> bool {
     status >= StatusCode::OK && status < StatusCode::BAD_REQUEST
 }
 
+/// Split PEM encoded certificates in a CA bundle.
+fn split_pem_certs(pem: &str) -> Result<Vec<&str>> {
+    let mut certs = Vec::new();
+    let mut data = pem;
+
+    while let Some(start) = data.find(PEM_CERT_BEGIN) {
+        let end = data[start..]
+            .find(PEM_CERT_END)
+            .ok_or_else(|| einval!("unterminated PEM certificate"))?;
+        let end = start + end + PEM_CERT_END.len();
+        certs.push(&data[start..end]);
+        data = &data[end..];
+    }
+    if certs.is_empty() {
+        return Err(einval!("no PEM certificate found"));
+    }
+
+    Ok(certs)
+}
+
 /// Convert a HTTP `Response` into an `Result<Response>`.
 pub(crate) fn respond(resp: Response, catch_status: bool) -> ConnectionResult<Response> {
     if !catch_status || is_success_status(resp.status()) {
@@ -125,14 +243,39 @@ pub(crate) fn respond(resp: Response, catch_status: bool) -> ConnectionResult<Re
 /// A network connection to communicate with remote server.
 #[derive(Debug)]
 pub(crate) struct Connection {
-    client: Client,
+    client: RwLock<Client>,
     proxy: Option<Proxy>,
+    mirrors: Vec<Mirror>,
+    metrics: Option<Arc<BackendMetrics>>,
+    config: RegistryOssConfig,
+    retry_policy: RetryPolicy,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    tls_generation: AtomicU64,
     shutdown: AtomicBool,
 }
 
 impl Connection {
     /// Create a new connection according to the configuration.
-    pub fn new(config: &RegistryOssConfig) -> Result<Arc<Connection>> {
+    pub fn new(
+        config: &RegistryOssConfig,
+        metrics: Option<Arc<BackendMetrics>>,
+    ) -> Result<Arc<Connection>> {
+        Self::create(config, metrics, None)
+    }
+
+    /// Create a new connection according to the configuration to replace this one.
+    ///
+    /// State of the circuit breaker, the proxy and mirrors is carried over if they are not
+    /// reconfigured, so updating credentials doesn't reset it.
+    pub fn renew(&self, config: &RegistryOssConfig) -> Result<Arc<Connection>> {
+        Self::create(config, self.metrics.clone(), Some(self))
+    }
+
+    fn create(
+        config: &RegistryOssConfig,
+        metrics: Option<Arc<BackendMetrics>>,
+        previous: Option<&Connection>,
+    ) -> Result<Arc<Connection>> {
         info!("backend config: {:?}", config);
         let client = Self::build_connection("", config)?;
         let proxy = if !config.proxy.url.is_empty() {
@@ -142,18 +285,38 @@ impl Connection {
                 None
             };
             Some(Proxy {
-                client: Self::build_connection(&config.proxy.url, config)?,
+                client: RwLock::new(Self::build_connection(&config.proxy.url, config)?),
                 health: ProxyHealth::new(config.proxy.check_interval, ping_url),
                 fallback: config.proxy.fallback,
             })
         } else {
             None
         };
+        let mirrors = config
+            .mirrors
+            .iter()
+            .map(Mirror::new)
+            .collect::<Result<Vec<_>>>()?;
+        let circuit_breaker = match previous {
+            Some(p) if p.config.circuit_breaker == config.circuit_breaker => {
+                p.circuit_breaker.clone()
+            }
+            _ => CircuitBreaker::from_config(&config.circuit_breaker, metrics.clone()),
+        };
         let connection = Arc::new(Connection {
-            client,
+            client: RwLock::new(client),
             proxy,
+            mirrors,
+            metrics,
+            config: config.clone(),
+            retry_policy: RetryPolicy::from_config(config),
+            circuit_breaker,
+            tls_generation: AtomicU64::new(TLS_GENERATION.load(Ordering::Acquire)),
             shutdown: AtomicBool::new(false),
         });
+        if let Some(previous) = previous {
+            connection.inherit_health(previous);
+        }
 
         if let Some(proxy) = &connection.proxy {
             if proxy.health.ping_url.is_some() {
@@ -188,9 +351,68 @@ impl Connection {
             }
         }
 
+        for idx in 0..connection.mirrors.len() {
+            let conn = connection.clone();
+            let connect_timeout = config.connect_timeout;
+
+            // Spawn thread to recover the mirror once it's available again
+            thread::spawn(move || {
+                let mirror = &conn.mirrors[idx];
+                let ping_url = mirror.health.ping_url.as_ref().unwrap();
+
+                loop {
+                    thread::sleep(mirror.health.check_interval);
+                    if conn.shutdown.load(Ordering::Acquire) {
+                        break;
+                    }
+                    if mirror.health.ok() {
+                        continue;
+                    }
+
+                    let client = conn.client.read().unwrap().clone();
+                    let available = client
+                        .get(ping_url.clone())
+                        .headers(mirror.headers.clone())
+                        .timeout(Duration::from_secs(connect_timeout))
+                        .send()
+                        .map(|resp| resp.status() < StatusCode::INTERNAL_SERVER_ERROR)
+                        .unwrap_or(false);
+                    if available {
+                        mirror.recover();
+                    }
+                }
+            });
+        }
+
         Ok(connection)
     }
 
+    // Carry over health of the proxy and mirrors from the connection to be replaced.
+    fn inherit_health(&self, previous: &Connection) {
+        if let (Some(proxy), Some(old)) = (self.proxy.as_ref(), previous.proxy.as_ref()) {
+            if self.config.proxy.url == previous.config.proxy.url {
+                proxy.health.set(old.health.ok());
+            }
+        }
+        for mirror in self.mirrors.iter() {
+            if let Some(old) = previous.mirrors.iter().find(|m| m.host == mirror.host) {
+                let failed_times = old.failed_times.load(Ordering::Relaxed);
+                mirror.failed_times.store(failed_times, Ordering::Relaxed);
+                mirror.health.set(old.health.ok());
+            }
+        }
+    }
+
+    /// Get the policy to retry failed requests.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
+    /// Get the circuit breaker of the storage backend.
+    pub fn circuit_breaker(&self) -> Option<Arc<CircuitBreaker>> {
+        self.circuit_breaker.clone()
+    }
+
     /// Shutdown the connection.
     pub fn shutdown(&self) {
         self.shutdown.store(true, Ordering::Release);
@@ -209,16 +431,19 @@ impl Connection {
         if self.shutdown.load(Ordering::Acquire) {
             return Err(ConnectionError::Disconnected);
         }
+        self.reload_tls();
+        let client = self.client.read().unwrap().clone();
 
         if let Some(proxy) = &self.proxy {
             if proxy.health.ok() {
+                let proxy_client = proxy.client.read().unwrap().clone();
                 let data_cloned: Option<ReqBody<R>> = match data.as_ref() {
                     Some(ReqBody::Form(form)) => Some(ReqBody::Form(form.clone())),
                     Some(ReqBody::Buf(buf)) => Some(ReqBody::Buf(buf.clone())),
                     _ => None,
                 };
                 let result = self.call_inner(
-                    &proxy.client,
+                    &proxy_client,
                     method.clone(),
                     url,
                     &query,
@@ -248,8 +473,69 @@ impl Connection {
             }
         }
 
+        // Registry mirrors are only used to download data.
+        if data.is_none() {
+            for mirror in self.mirrors.iter() {
+                if !mirror.health.ok() {
+                    continue;
+                }
+                let mirror_url = match mirror.mirror_url(url) {
+                    Some(v) => v,
+                    None => break,
+                };
+                let mut mirror_headers = headers.clone();
+                for (name, value) in mirror.headers.iter() {
+                    mirror_headers.insert(name.clone(), value.clone());
+                }
+
+                let result = self.call_inner::<R>(
+                    &client,
+                    method.clone(),
+                    &mirror_url,
+                    &query,
+                    None,
+                    mirror_headers,
+                    false,
+                    false,
+                );
+                match result {
+                    // The mirror works but doesn't serve the content, try the next server.
+                    Ok(resp)
+                        if resp.status() == StatusCode::NOT_FOUND
+                            || resp.status() == StatusCode::FORBIDDEN
+                            || resp.status() == StatusCode::TOO_MANY_REQUESTS =>
+                    {
+                        mirror.succeed();
+                        debug!(
+                            "Request mirror {} got status {}, fallback to next server",
+                            mirror.host,
+                            resp.status()
+                        );
+                        continue;
+                    }
+                    Ok(resp) if resp.status() < StatusCode::INTERNAL_SERVER_ERROR => {
+                        mirror.succeed();
+                        return respond(resp, catch_status);
+                    }
+                    Ok(resp) => warn!(
+                        "Request mirror {} failed with status {}, fallback to next server",
+                        mirror.host,
+                        resp.status()
+                    ),
+                    Err(err) => warn!(
+                        "Request mirror {} failed: {:?}, fallback to next server",
+                        mirror.host, err
+                    ),
+                }
+                mirror.fail();
+                if let Some(metrics) = self.metrics.as_ref() {
+                    metrics.mirror_error(&mirror.host);
+                }
+            }
+        }
+
         self.call_inner(
-            &self.client,
+            &client,
             method,
             url,
             &query,
@@ -260,6 +546,35 @@ impl Connection {
         )
     }
 
+    /// Rebuild HTTP clients if TLS configuration files have been reloaded.
+    fn reload_tls(&self) {
+        let generation = TLS_GENERATION.load(Ordering::Acquire);
+        if self.tls_generation.swap(generation, Ordering::AcqRel) == generation
+            || (self.config.ca_file.is_empty() && self.config.client_cert_file.is_empty())
+        {
+            return;
+        }
+
+        let client = match Self::build_connection("", &self.config) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("failed to reload TLS configuration, {}", e);
+                return;
+            }
+        };
+        if let Some(proxy) = &self.proxy {
+            match Self::build_connection(&self.config.proxy.url, &self.config) {
+                Ok(v) => *proxy.client.write().unwrap() = v,
+                Err(e) => {
+                    error!("failed to reload TLS configuration for proxy, {}", e);
+                    return;
+                }
+            }
+        }
+        *self.client.write().unwrap() = client;
+        info!("TLS configuration reloaded");
+    }
+
     fn build_connection(proxy: &str, config: &RegistryOssConfig) -> Result<Client> {
         let connect_timeout = if config.connect_timeout != 0 {
             Some(Duration::from_secs(config.connect_timeout))
@@ -281,6 +596,56 @@ impl Connection {
             cb = cb.danger_accept_invalid_certs(true);
         }
 
+        if !config.ca_file.is_empty() {
+            let pem = fs::read_to_string(&config.ca_file).map_err(|e| {
+                einval!(format!("failed to read CA file {}, {}", config.ca_file, e))
+            })?;
+            for cert in split_pem_certs(&pem)? {
+                let cert = Certificate::from_pem(cert.as_bytes())
+                    .map_err(|e| einval!(format!("invalid CA file {}, {}", config.ca_file, e)))?;
+                cb = cb.add_root_certificate(cert);
+            }
+        }
+
+        match (
+            config.client_cert_file.is_empty(),
+            config.client_key_file.is_empty(),
+        ) {
+            (true, true) => {}
+            (false, false) => {
+                let cert = fs::read(&config.client_cert_file).map_err(|e| {
+                    einval!(format!(
+                        "failed to read client certificate {}, {}",
+                        config.client_cert_file, e
+                    ))
+                })?;
+                let key = fs::read(&config.client_key_file).map_err(|e| {
+                    einval!(format!(
+                        "failed to read client key {}, {}",
+                        config.client_key_file, e
+                    ))
+                })?;
+                let identity = Identity::from_pkcs8_pem(&cert, &key)
+                    .map_err(|e| einval!(format!("invalid client certificate or key, {}", e)))?;
+                cb = cb.identity(identity);
+            }
+            _ => {
+                return Err(einval!(
+                    "both client_cert_file and client_key_file are required for mutual TLS"
+                ))
+            }
+        }
+
+        if !config.min_tls_version.is_empty() {
+            let version = match config.min_tls_version.as_str() {
+                "1.0" => tls::Version::TLS_1_0,
+                "1.1" => tls::Version::TLS_1_1,
+                "1.2" => tls::Version::TLS_1_2,
+                v => return Err(einval!(format!("unsupported minimum TLS version {}", v))),
+            };
+            cb = cb.min_tls_version(version);
+        }
+
         if !proxy.is_empty() {
             cb = cb.proxy(reqwest::Proxy::all(proxy).map_err(|e| einval!(e))?)
         }
@@ -343,10 +708,130 @@ impl Connection {
     }
 }
 
+/// Backend specific state and the network connection to access a storage backend.
+///
+/// Storage backends share their context with blob readers through an `ArcSwap`, so it may be
+/// replaced on the fly to rotate credentials and connection settings. Requests hold a reference
+/// to the context in use, thus requests in flight finish with the old settings.
+#[derive(Debug)]
+pub(crate) struct ConnectionContext<S> {
+    pub state: S,
+    pub connection: Arc<Connection>,
+}
+
+impl<S> Drop for ConnectionContext<S> {
+    fn drop(&mut self) {
+        // Stop the health checking threads, which hold references to the connection.
+        self.connection.shutdown();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Cursor;
+    use crate::test::mock_http::{start_mock_http_server, MockHttpResponse};
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::ssl::{SslAcceptor, SslMethod, SslVerifyMode};
+    use openssl::x509::extension::{BasicConstraints, KeyUsage, SubjectAlternativeName};
+    use openssl::x509::{X509NameBuilder, X509};
+    use std::io::{Cursor, Write};
+    use std::net::TcpListener;
+    use vmm_sys_util::tempdir::TempDir;
+
+    /// Generate a self-signed CA certificate, or a certificate for `localhost` signed by `issuer`.
+    fn generate_cert(
+        cn: &str,
+        serial: u32,
+        issuer: Option<(&X509, &PKey<Private>)>,
+    ) -> (X509, PKey<Private>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", cn).unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = BigNum::from_u32(serial).unwrap().to_asn1_integer().unwrap();
+        builder.set_serial_number(&serial).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        match issuer {
+            Some((ca, ca_key)) => {
+                builder.set_issuer_name(ca.subject_name()).unwrap();
+                let san = SubjectAlternativeName::new()
+                    .dns("localhost")
+                    .build(&builder.x509v3_context(Some(ca), None))
+                    .unwrap();
+                builder.append_extension(san).unwrap();
+                builder.sign(ca_key, MessageDigest::sha256()).unwrap();
+            }
+            None => {
+                builder.set_issuer_name(&name).unwrap();
+                let constraints = BasicConstraints::new().critical().ca().build().unwrap();
+                builder.append_extension(constraints).unwrap();
+                let usage = KeyUsage::new()
+                    .critical()
+                    .key_cert_sign()
+                    .crl_sign()
+                    .build()
+                    .unwrap();
+                builder.append_extension(usage).unwrap();
+                builder.sign(&key, MessageDigest::sha256()).unwrap();
+            }
+        }
+
+        (builder.build(), key)
+    }
+
+    /// Start a TLS server requiring client certificates signed by `ca`, and return its port.
+    fn start_tls_server(ca: &X509, cert: &X509, key: &PKey<Private>) -> u16 {
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_certificate(cert).unwrap();
+        acceptor.set_private_key(key).unwrap();
+        acceptor.cert_store_mut().add_cert(ca.clone()).unwrap();
+        acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        let acceptor = Arc::new(acceptor.build());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let acceptor = acceptor.clone();
+                thread::spawn(move || {
+                    let mut stream = match acceptor.accept(stream) {
+                        Ok(v) => v,
+                        Err(_) => return,
+                    };
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while !request.windows(4).any(|v| v == b"\r\n\r\n") {
+                        match stream.read(&mut buf) {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let _ = stream.write_all(
+                        b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+                    );
+                    let _ = stream.shutdown();
+                });
+            }
+        });
+
+        port
+    }
 
     #[test]
     fn test_progress() {
@@ -380,6 +865,306 @@ mod tests {
         assert!(checker.ok());
     }
 
+    #[test]
+    fn test_mirror() {
+        let mut config = MirrorConfig {
+            host: "http://mirror:5000/".to_string(),
+            failure_limit: 2,
+            ..Default::default()
+        };
+        config
+            .headers
+            .insert("X-Mirror".to_string(), "mirror".to_string());
+        let mirror = Mirror::new(&config).unwrap();
+        assert_eq!(mirror.host, "http://mirror:5000");
+        assert_eq!(
+            mirror.health.ping_url.as_ref().unwrap().as_str(),
+            "http://mirror:5000/v2/"
+        );
+        assert_eq!(mirror.headers.get("x-mirror").unwrap(), "mirror");
+
+        assert_eq!(
+            mirror
+                .mirror_url("https://registry/v2/repo/blobs/sha256:abc?ns=docker.io")
+                .unwrap(),
+            "http://mirror:5000/v2/repo/blobs/sha256:abc?ns=docker.io"
+        );
+        assert!(mirror.mirror_url("https://auth/token?scope=repo").is_none());
+
+        mirror.fail();
+        assert!(mirror.health.ok());
+        mirror.succeed();
+        mirror.fail();
+        assert!(mirror.health.ok());
+        mirror.fail();
+        assert!(!mirror.health.ok());
+        mirror.recover();
+        assert!(mirror.health.ok());
+
+        config.host = "mirror".to_string();
+        assert!(Mirror::new(&config).is_err());
+    }
+
+    #[test]
+    fn test_mirror_failover() {
+        let origin =
+            start_mock_http_server(|_| MockHttpResponse::new(200).body(b"origin".to_vec()));
+        let mirror1_down = Arc::new(AtomicBool::new(true));
+        let down = mirror1_down.clone();
+        let mirror1 = start_mock_http_server(move |_| {
+            if down.load(Ordering::Relaxed) {
+                MockHttpResponse::new(503)
+            } else {
+                MockHttpResponse::new(200).body(b"mirror1".to_vec())
+            }
+        });
+        let mirror2 = start_mock_http_server(|req| {
+            let header = req.headers.get("x-mirror").cloned().unwrap_or_default();
+            MockHttpResponse::new(200).body(format!("mirror2 {}", header).into_bytes())
+        });
+
+        let mut headers = HashMap::new();
+        headers.insert("X-Mirror".to_string(), "header".to_string());
+        let config = RegistryOssConfig {
+            mirrors: vec![
+                MirrorConfig {
+                    host: format!("http://{}", mirror1),
+                    health_check_interval: 1,
+                    failure_limit: 1,
+                    ..Default::default()
+                },
+                MirrorConfig {
+                    host: format!("http://{}", mirror2),
+                    headers,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let metrics = BackendMetrics::new("test-mirror-failover", "registry");
+        let connection = Connection::new(&config, Some(metrics.clone())).unwrap();
+        let get = |path: &str| {
+            connection
+                .call::<&[u8]>(
+                    Method::GET,
+                    &format!("http://{}{}", origin, path),
+                    None,
+                    None,
+                    HeaderMap::new(),
+                    true,
+                )
+                .unwrap()
+                .text()
+                .unwrap()
+        };
+
+        // The preferred mirror is down, fail over to the next mirror.
+        assert_eq!(get("/v2/repo/blobs/sha256:abc"), "mirror2 header");
+        assert_eq!(
+            metrics.mirror_error_count(&format!("http://{}", mirror1)),
+            1
+        );
+        assert_eq!(get("/v2/repo/blobs/sha256:abc"), "mirror2 header");
+        assert_eq!(
+            metrics.mirror_error_count(&format!("http://{}", mirror1)),
+            1
+        );
+        // Requests not to registry APIs are sent to the origin server.
+        assert_eq!(get("/token"), "origin");
+
+        // Return to the preferred mirror once it recovers.
+        mirror1_down.store(false, Ordering::Relaxed);
+        let mut recovered = false;
+        for _ in 0..50 {
+            if connection.mirrors[0].health.ok() {
+                recovered = true;
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert!(recovered);
+        assert_eq!(get("/v2/repo/blobs/sha256:abc"), "mirror1");
+
+        connection.shutdown();
+        metrics.release().unwrap();
+    }
+
+    #[test]
+    fn test_mirror_missing_content() {
+        let origin =
+            start_mock_http_server(|_| MockHttpResponse::new(200).body(b"origin".to_vec()));
+        let mirror1 = start_mock_http_server(|req| {
+            if req.path.contains("sha256:missing") {
+                MockHttpResponse::new(404)
+            } else {
+                MockHttpResponse::new(200).body(b"mirror1".to_vec())
+            }
+        });
+        let mirror2 = start_mock_http_server(|_| MockHttpResponse::new(403));
+
+        let config = RegistryOssConfig {
+            mirrors: vec![
+                MirrorConfig {
+                    host: format!("http://{}", mirror1),
+                    failure_limit: 1,
+                    ..Default::default()
+                },
+                MirrorConfig {
+                    host: format!("http://{}", mirror2),
+                    failure_limit: 1,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let metrics = BackendMetrics::new("test-mirror-missing-content", "registry");
+        let connection = Connection::new(&config, Some(metrics.clone())).unwrap();
+        let get = |path: &str| {
+            connection
+                .call::<&[u8]>(
+                    Method::GET,
+                    &format!("http://{}{}", origin, path),
+                    None,
+                    None,
+                    HeaderMap::new(),
+                    true,
+                )
+                .unwrap()
+                .text()
+                .unwrap()
+        };
+
+        // Mirrors without the blob are skipped, and the blob is fetched from the origin server.
+        assert_eq!(get("/v2/repo/blobs/sha256:missing"), "origin");
+        // Missing content doesn't make the mirrors unhealthy.
+        assert!(connection.mirrors[0].health.ok());
+        assert!(connection.mirrors[1].health.ok());
+        assert_eq!(
+            metrics.mirror_error_count(&format!("http://{}", mirror1)),
+            0
+        );
+        assert_eq!(get("/v2/repo/blobs/sha256:abc"), "mirror1");
+
+        connection.shutdown();
+        metrics.release().unwrap();
+    }
+
+    #[test]
+    fn test_renew_connection() {
+        let mirror = |host: &str| MirrorConfig {
+            host: host.to_string(),
+            health_check_interval: 3600,
+            failure_limit: 1,
+            ..Default::default()
+        };
+        let mut config = RegistryOssConfig {
+            mirrors: vec![mirror("http://127.0.0.1:1"), mirror("http://127.0.0.1:2")],
+            ..Default::default()
+        };
+        config.circuit_breaker.enable = true;
+        config.circuit_breaker.failure_limit = 1;
+        let connection = Connection::new(&config, None).unwrap();
+        connection.mirrors[0].fail();
+        connection.mirrors[1].fail();
+        let breaker = connection.circuit_breaker().unwrap();
+        breaker.fail();
+        assert!(!breaker.allow());
+
+        // Health of kept mirrors and the circuit state survive updating the configuration.
+        config.mirrors = vec![mirror("http://127.0.0.1:2"), mirror("http://127.0.0.1:3")];
+        let renewed = connection.renew(&config).unwrap();
+        connection.shutdown();
+        assert!(!renewed.mirrors[0].health.ok());
+        assert!(renewed.mirrors[1].health.ok());
+        assert!(Arc::ptr_eq(&breaker, &renewed.circuit_breaker().unwrap()));
+
+        // The circuit breaker is reset if it's reconfigured.
+        config.circuit_breaker.failure_limit = 2;
+        let reconfigured = renewed.renew(&config).unwrap();
+        renewed.shutdown();
+        assert!(reconfigured.circuit_breaker().unwrap().allow());
+        reconfigured.shutdown();
+    }
+
+    #[test]
+    fn test_split_pem_certs() {
+        let pem = format!(
+            "# CA 1\n{}\nMIIB\n{}\n# CA 2\n{}\nMIIC\n{}\n",
+            PEM_CERT_BEGIN, PEM_CERT_END, PEM_CERT_BEGIN, PEM_CERT_END
+        );
+        let certs = split_pem_certs(&pem).unwrap();
+        assert_eq!(certs.len(), 2);
+        assert_eq!(
+            certs[1],
+            format!("{}\nMIIC\n{}", PEM_CERT_BEGIN, PEM_CERT_END)
+        );
+
+        assert!(split_pem_certs("").is_err());
+        assert!(split_pem_certs(&format!("{}\nMIIB\n", PEM_CERT_BEGIN)).is_err());
+    }
+
+    #[test]
+    fn test_mutual_tls() {
+        let (ca, ca_key) = generate_cert("nydus test CA", 1, None);
+        let (server_cert, server_key) = generate_cert("localhost", 2, Some((&ca, &ca_key)));
+        let (client_cert, client_key) = generate_cert("nydus client", 3, Some((&ca, &ca_key)));
+        let (other_ca, _) = generate_cert("nydus other CA", 4, None);
+        let port = start_tls_server(&ca, &server_cert, &server_key);
+        let url = format!("https://localhost:{}/v2/", port);
+
+        let dir = TempDir::new().unwrap();
+        let ca_file = dir.as_path().join("ca.pem");
+        let cert_file = dir.as_path().join("client.pem");
+        let key_file = dir.as_path().join("client.key");
+        fs::write(&ca_file, other_ca.to_pem().unwrap()).unwrap();
+        fs::write(&cert_file, client_cert.to_pem().unwrap()).unwrap();
+        fs::write(&key_file, client_key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+        let get = |connection: &Connection| {
+            connection
+                .call::<&[u8]>(Method::GET, &url, None, None, HeaderMap::new(), true)
+                .and_then(|resp| resp.text().map_err(ConnectionError::Format))
+        };
+
+        // The server certificate is signed by an unknown CA.
+        let mut config = RegistryOssConfig {
+            ca_file: ca_file.to_str().unwrap().to_string(),
+            client_cert_file: cert_file.to_str().unwrap().to_string(),
+            client_key_file: key_file.to_str().unwrap().to_string(),
+            min_tls_version: "1.2".to_string(),
+            ..Default::default()
+        };
+        let connection = Connection::new(&config, None).unwrap();
+        assert!(get(&connection).is_err());
+
+        // Reload the updated CA file.
+        fs::write(&ca_file, ca.to_pem().unwrap()).unwrap();
+        reload_tls_config();
+        assert_eq!(get(&connection).unwrap(), "ok");
+        connection.shutdown();
+
+        // The server requires client certificates.
+        config.client_cert_file.clear();
+        config.client_key_file.clear();
+        let connection = Connection::new(&config, None).unwrap();
+        assert!(get(&connection).is_err());
+        connection.shutdown();
+
+        config.client_cert_file = cert_file.to_str().unwrap().to_string();
+        assert!(Connection::new(&config, None).is_err());
+        config.client_key_file = key_file.to_str().unwrap().to_string();
+        config.min_tls_version = "1.4".to_string();
+        assert!(Connection::new(&config, None).is_err());
+        config.min_tls_version.clear();
+        config.ca_file = dir
+            .as_path()
+            .join("missing.pem")
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(Connection::new(&config, None).is_err());
+    }
+
     #[test]
     fn test_is_success_status() {
         assert!(!is_success_status(StatusCode::CONTINUE));
@@ -0,0 +1,381 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Storage backend driver to access blobs on plain HTTP(S) file servers and CDNs.
+use std::collections::HashMap;
+use std::io::{Read, Result};
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, RANGE};
+use reqwest::{Method, StatusCode, Url};
+
+use nydus_api::http::RegistryOssConfig;
+use nydus_utils::metrics::BackendMetrics;
+
+use crate::backend::connection::{Connection, ConnectionContext, ConnectionError};
+use crate::backend::retry::{CircuitBreaker, RetryPolicy};
+use crate::backend::{BackendError, BackendResult, BlobBackend, BlobReader};
+
+/// Placeholder in the url template to be replaced by blob id.
+const BLOB_ID_PLACEHOLDER: &str = "{blob_id}";
+
+/// Error codes related to HTTP storage backend.
+#[derive(Debug)]
+pub enum HttpError {
+    Request(ConnectionError),
+    ConstructHeader(String),
+    Transport(std::io::Error),
+    Response(String),
+}
+
+impl From<HttpError> for BackendError {
+    fn from(error: HttpError) -> Self {
+        BackendError::Http(error)
+    }
+}
+
+/// HTTP configuration information to access blobs.
+///
+/// This structure is externally visible through configuration file and HTTP API, please keep them
+/// stable.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct HttpConfig {
+    /// Url template to access blobs, `{blob_id}` will be replaced by the blob id, for example
+    /// `https://cdn.example.com/blobs/{blob_id}`.
+    pub url: String,
+    /// Custom headers sent with each request, such as authentication tokens.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+#[derive(Debug)]
+struct HttpState {
+    url: String,
+    headers: HeaderMap,
+    retry_limit: u8,
+}
+
+impl HttpState {
+    fn new(config: HttpConfig, retry_limit: u8) -> Result<Self> {
+        if !config.url.contains(BLOB_ID_PLACEHOLDER) {
+            return Err(einval!(format!(
+                "url template '{}' of http backend doesn't contain '{}'",
+                config.url, BLOB_ID_PLACEHOLDER
+            )));
+        }
+        Url::parse(&config.url.replace(BLOB_ID_PLACEHOLDER, "blob")).map_err(|e| {
+            einval!(format!(
+                "invalid url template '{}' of http backend, {}",
+                config.url, e
+            ))
+        })?;
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in config.headers.iter() {
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| einval!(e))?;
+            let value = HeaderValue::from_str(value).map_err(|e| einval!(e))?;
+            headers.insert(name, value);
+        }
+
+        Ok(HttpState {
+            url: config.url,
+            headers,
+            retry_limit,
+        })
+    }
+
+    fn url(&self, blob_id: &str) -> String {
+        self.url.replace(BLOB_ID_PLACEHOLDER, blob_id)
+    }
+}
+
+type HttpContext = ConnectionContext<HttpState>;
+
+struct HttpReader {
+    blob_id: String,
+    context: Arc<ArcSwap<HttpContext>>,
+    metrics: Arc<BackendMetrics>,
+}
+
+impl BlobReader for HttpReader {
+    fn blob_size(&self) -> BackendResult<u64> {
+        let context = self.context.load_full();
+        let url = context.state.url(&self.blob_id);
+        let resp = context
+            .connection
+            .call::<&[u8]>(
+                Method::HEAD,
+                url.as_str(),
+                None,
+                None,
+                context.state.headers.clone(),
+                true,
+            )
+            .map_err(HttpError::Request)?;
+        let content_length = resp
+            .headers()
+            .get(CONTENT_LENGTH)
+            .ok_or_else(|| HttpError::Response("invalid content length".to_string()))?;
+
+        Ok(content_length
+            .to_str()
+            .map_err(|err| HttpError::Response(format!("invalid content length: {:?}", err)))?
+            .parse::<u64>()
+            .map_err(|err| HttpError::Response(format!("invalid content length: {:?}", err)))?)
+    }
+
+    fn try_read(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
+        let context = self.context.load_full();
+        let url = context.state.url(&self.blob_id);
+        let mut headers = context.state.headers.clone();
+        let end_at = offset + buf.len() as u64 - 1;
+        let range = format!("bytes={}-{}", offset, end_at);
+
+        headers.insert(
+            RANGE,
+            range
+                .as_str()
+                .parse()
+                .map_err(|e| HttpError::ConstructHeader(format!("{}", e)))?,
+        );
+
+        // Safe because the the call() is a synchronous operation.
+        let mut resp = context
+            .connection
+            .call::<&[u8]>(Method::GET, url.as_str(), None, None, headers, true)
+            .map_err(HttpError::Request)?;
+        // The whole blob is returned if the server ignores the `Range` header.
+        if resp.status() != StatusCode::PARTIAL_CONTENT && offset != 0 {
+            return Err(HttpError::Response(format!(
+                "server doesn't support range request, status {}",
+                resp.status()
+            ))
+            .into());
+        }
+
+        let mut size = 0;
+        while size < buf.len() {
+            let count = resp.read(&mut buf[size..]).map_err(HttpError::Transport)?;
+            if count == 0 {
+                break;
+            }
+            size += count;
+        }
+
+        Ok(size)
+    }
+
+    fn prefetch_blob_data_range(&self, _ra_offset: u64, _ra_size: u64) -> BackendResult<()> {
+        Err(BackendError::Unsupported(
+            "Http backend does not support prefetch as per on-disk blob entries".to_string(),
+        ))
+    }
+
+    fn stop_data_prefetch(&self) -> BackendResult<()> {
+        Err(BackendError::Unsupported(
+            "Http backend does not support prefetch as per on-disk blob entries".to_string(),
+        ))
+    }
+
+    fn metrics(&self) -> &BackendMetrics {
+        &self.metrics
+    }
+
+    fn retry_limit(&self) -> u8 {
+        self.context.load().state.retry_limit
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.context.load().connection.retry_policy()
+    }
+
+    fn circuit_breaker(&self) -> Option<Arc<CircuitBreaker>> {
+        self.context.load().connection.circuit_breaker()
+    }
+}
+
+/// Storage backend to access blobs on plain HTTP(S) servers by range requests.
+#[derive(Debug)]
+pub struct Http {
+    context: Arc<ArcSwap<HttpContext>>,
+    metrics: Option<Arc<BackendMetrics>>,
+    #[allow(unused)]
+    id: Option<String>,
+}
+
+impl Http {
+    /// Create a new HTTP storage backend.
+    pub fn new(config: serde_json::value::Value, id: Option<&str>) -> Result<Http> {
+        let metrics = id.map(|i| BackendMetrics::new(i, "http"));
+        let context = Self::new_context(config, metrics.clone(), None).map_err(|e| {
+            if let Some(metrics) = metrics.as_ref() {
+                metrics.release().unwrap_or_else(|e| error!("{:?}", e));
+            }
+            e
+        })?;
+
+        Ok(Http {
+            context: Arc::new(ArcSwap::new(Arc::new(context))),
+            metrics,
+            id: id.map(|i| i.to_string()),
+        })
+    }
+
+    fn new_context(
+        config: serde_json::value::Value,
+        metrics: Option<Arc<BackendMetrics>>,
+        previous: Option<&Connection>,
+    ) -> Result<HttpContext> {
+        let common_config: RegistryOssConfig =
+            serde_json::from_value(config.clone()).map_err(|e| einval!(e))?;
+        let retry_limit = common_config.retry_limit;
+        let http_config: HttpConfig = serde_json::from_value(config).map_err(|e| einval!(e))?;
+        let state = HttpState::new(http_config, retry_limit)?;
+        let connection = match previous {
+            Some(connection) => connection.renew(&common_config)?,
+            None => Connection::new(&common_config, metrics)?,
+        };
+
+        Ok(HttpContext { state, connection })
+    }
+}
+
+impl BlobBackend for Http {
+    fn shutdown(&self) {
+        self.context.load().connection.shutdown();
+    }
+
+    fn metrics(&self) -> &BackendMetrics {
+        // `metrics()` is only used for nydusd, which will always provide valid `blob_id`, thus
+        // `self.metrics` has valid value.
+        self.metrics.as_ref().unwrap()
+    }
+
+    fn get_reader(&self, blob_id: &str) -> BackendResult<Arc<dyn BlobReader>> {
+        if let Some(metrics) = self.metrics.as_ref() {
+            Ok(Arc::new(HttpReader {
+                blob_id: blob_id.to_string(),
+                context: self.context.clone(),
+                metrics: metrics.clone(),
+            }))
+        } else {
+            Err(BackendError::Unsupported(
+                "no metrics object available for HttpReader".to_string(),
+            ))
+        }
+    }
+
+    fn update_config(&self, config: serde_json::value::Value) -> Result<()> {
+        let previous = self.context.load();
+        let context = Self::new_context(config, self.metrics.clone(), Some(&previous.connection))?;
+        self.context.store(Arc::new(context));
+        Ok(())
+    }
+}
+
+impl Drop for Http {
+    fn drop(&mut self) {
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.release().unwrap_or_else(|e| error!("{:?}", e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::mock_http::{start_mock_http_server, MockHttpResponse};
+    use serde_json::Value;
+
+    #[test]
+    fn test_http_state() {
+        let mut config = HttpConfig {
+            url: "https://cdn.example.com/blobs".to_string(),
+            headers: HashMap::new(),
+        };
+        assert!(HttpState::new(config.clone(), 0).is_err());
+        config.url = "cdn.example.com/{blob_id}".to_string();
+        assert!(HttpState::new(config.clone(), 0).is_err());
+
+        config.url = "https://cdn.example.com/blobs/sha256:{blob_id}?v=1".to_string();
+        config
+            .headers
+            .insert("X-Token".to_string(), "token".to_string());
+        let state = HttpState::new(config.clone(), 3).unwrap();
+        assert_eq!(
+            state.url("abc"),
+            "https://cdn.example.com/blobs/sha256:abc?v=1"
+        );
+        assert_eq!(state.headers.get("x-token").unwrap(), "token");
+        assert_eq!(state.retry_limit, 3);
+
+        config
+            .headers
+            .insert("X-Invalid".to_string(), "a\nb".to_string());
+        assert!(HttpState::new(config, 0).is_err());
+    }
+
+    #[test]
+    fn test_http_read() {
+        let data: Vec<u8> = (0..0x1000u32).map(|v| v as u8).collect();
+        let blob = data.clone();
+        let addr = start_mock_http_server(move |req| {
+            if req.headers.get("x-token").map(|v| v.as_str()) != Some("token") {
+                MockHttpResponse::new(401)
+            } else if req.path == "/blobs/range" {
+                MockHttpResponse::blob(req, &blob)
+            } else if req.path == "/blobs/norange" {
+                // Ignore the `Range` header and return the whole blob.
+                MockHttpResponse::new(200).body(blob.clone())
+            } else {
+                MockHttpResponse::new(404)
+            }
+        });
+        let config = format!(
+            r#"{{"url":"http://{}/blobs/{{blob_id}}","headers":{{"X-Token":"token"}},
+            "retry_limit":2}}"#,
+            addr
+        );
+        let json: Value = serde_json::from_str(&config).unwrap();
+        let http = Http::new(json, Some("test-http")).unwrap();
+        http.metrics();
+
+        let reader = http.get_reader("range").unwrap();
+        assert_eq!(reader.retry_limit(), 2);
+        assert_eq!(reader.blob_size().unwrap(), 0x1000);
+        let mut buf = vec![0u8; 0x100];
+        assert_eq!(reader.read(&mut buf, 0x80).unwrap(), 0x100);
+        assert_eq!(buf, data[0x80..0x180]);
+        // Read beyond the end of blob.
+        assert_eq!(reader.read(&mut buf, 0xf80).unwrap(), 0x80);
+        assert_eq!(buf[..0x80], data[0xf80..]);
+
+        let reader = http.get_reader("norange").unwrap();
+        assert_eq!(reader.read(&mut buf, 0).unwrap(), 0x100);
+        assert_eq!(buf, data[..0x100]);
+        assert!(reader.try_read(&mut buf, 0x80).is_err());
+
+        let reader = http.get_reader("missing").unwrap();
+        assert!(reader.blob_size().is_err());
+        assert!(reader.try_read(&mut buf, 0).is_err());
+
+        // Rotate the token, existing readers send requests with the new one.
+        let reader = http.get_reader("range").unwrap();
+        let config = format!(
+            r#"{{"url":"http://{}/blobs/{{blob_id}}","headers":{{"X-Token":"expired"}}}}"#,
+            addr
+        );
+        http.update_config(serde_json::from_str(&config).unwrap())
+            .unwrap();
+        assert!(reader.blob_size().is_err());
+        assert!(http.update_config(Value::Null).is_err());
+        let config = config.replace("expired", "token");
+        http.update_config(serde_json::from_str(&config).unwrap())
+            .unwrap();
+        assert_eq!(reader.blob_size().unwrap(), 0x1000);
+
+        http.shutdown();
+    }
+}
@@ -9,24 +9,53 @@
 //! - [Registry](registry/struct.Registry.html): backend driver to access blobs on container image
 //!   registry.
 //! - [Oss](oss/struct.Oss.html): backend driver to access blobs on Oss(Object Storage System).
+//! - [S3](s3/struct.S3.html): backend driver to access blobs on S3 compatible object storage
+//!   services.
+//! - [Http](http/struct.Http.html): backend driver to access blobs on plain HTTP(S) servers and
+//!   CDNs by range requests.
+//! - [Composite](composite/struct.Composite.html): backend driver to access blobs from a list of
+//!   tiered storage backends, such as local directory then registry.
+//! - [Oci](oci/struct.Oci.html): backend driver to access blobs in OCI image-layout directories
+//!   or uncompressed `oci-archive` tarballs.
 //! - [LocalFs](localfs/struct.LocalFs.html): backend driver to access blobs on local file system.
 //!   The [LocalFs](localfs/struct.LocalFs.html) storage backend supports backend level data
 //!   prefetching, which is to load data into page cache.
 
 use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
 
 use fuse_backend_rs::transport::FileVolatileSlice;
 use nydus_utils::metrics::{BackendMetrics, ERROR_HOLDER};
+use nydus_utils::slowio::{IoStage, StageTimer};
 
+use self::retry::{CircuitBreaker, RetryPolicy};
 use crate::utils::{alloc_buf, copyv};
 use crate::StorageError;
 
-#[cfg(any(feature = "backend-oss", feature = "backend-registry"))]
+#[cfg(feature = "backend-composite")]
+pub mod composite;
+#[cfg(feature = "backend-composite")]
+pub use self::composite::CompositeConfig;
+#[cfg(any(
+    feature = "backend-http",
+    feature = "backend-oss",
+    feature = "backend-registry",
+    feature = "backend-s3"
+))]
 pub mod connection;
+#[cfg(feature = "backend-http")]
+pub mod http;
+#[cfg(feature = "backend-http")]
+pub use self::http::HttpConfig;
 #[cfg(feature = "backend-localfs")]
 pub mod localfs;
 #[cfg(feature = "backend-localfs")]
 pub use localfs::LocalFsConfig;
+#[cfg(feature = "backend-oci")]
+pub mod oci;
+#[cfg(feature = "backend-oci")]
+pub use oci::OciConfig;
 #[cfg(feature = "backend-oss")]
 pub mod oss;
 #[cfg(feature = "backend-oss")]
@@ -35,12 +64,19 @@ pub use oss::OssConfig;
 pub mod registry;
 #[cfg(feature = "backend-registry")]
 pub use registry::RegistryConfig;
+pub mod retry;
+#[cfg(feature = "backend-s3")]
+pub mod s3;
+#[cfg(feature = "backend-s3")]
+pub use s3::S3Config;
 
 /// Error codes related to storage backend operations.
 #[derive(Debug)]
 pub enum BackendError {
     /// Unsupported operation.
     Unsupported(String),
+    /// Storage backend is known to be unavailable, the circuit breaker is open.
+    CircuitOpen,
     /// Failed to copy data from/into blob.
     CopyData(StorageError),
     #[cfg(feature = "backend-registry")]
@@ -49,9 +85,18 @@ pub enum BackendError {
     #[cfg(feature = "backend-localfs")]
     /// Error from LocalFs storage backend.
     LocalFs(self::localfs::LocalFsError),
+    #[cfg(feature = "backend-oci")]
+    /// Error from OCI image-layout storage backend.
+    Oci(self::oci::OciError),
     #[cfg(feature = "backend-oss")]
     /// Error from OSS storage backend.
     Oss(self::oss::OssError),
+    #[cfg(feature = "backend-s3")]
+    /// Error from S3 storage backend.
+    S3(self::s3::S3Error),
+    #[cfg(feature = "backend-http")]
+    /// Error from HTTP storage backend.
+    Http(self::http::HttpError),
 }
 
 /// Specialized `Result` for storage backends.
@@ -75,34 +120,61 @@ pub trait BlobReader: Send + Sync {
     /// - bytes of data read, which may be smaller than buf.len()
     /// - error code if error happens
     ///
-    /// It will try `BlobBackend::retry_limit()` times at most and return the first successfully
-    /// read data.
+    /// It will retry as per `BlobReader::retry_policy()` and return the first successfully read
+    /// data, or fail fast if the circuit breaker of the storage backend is open. A request failed
+    /// after all retries counts as one failure of the circuit breaker.
     fn read(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
-        let mut retry_count = self.retry_limit();
+        let policy = self.retry_policy();
+        let breaker = self.circuit_breaker();
+        let allow = || breaker.as_ref().map(|b| b.allow()).unwrap_or(true);
+        let _timer = StageTimer::new(IoStage::Backend);
+        let begin = Instant::now();
         let begin_time = self.metrics().begin();
+        let mut retry_count = 0;
+
+        if !allow() {
+            self.metrics().end(&begin_time, buf.len(), true);
+            return Err(BackendError::CircuitOpen);
+        }
 
         loop {
             match self.try_read(buf, offset) {
                 Ok(size) => {
+                    if let Some(breaker) = breaker.as_ref() {
+                        breaker.succeed();
+                    }
                     self.metrics().end(&begin_time, buf.len(), false);
                     return Ok(size);
                 }
                 Err(err) => {
-                    if retry_count > 0 {
-                        warn!(
-                            "Read from backend failed: {:?}, retry count {}",
-                            err, retry_count
-                        );
-                        retry_count -= 1;
-                    } else {
-                        self.metrics().end(&begin_time, buf.len(), true);
-                        ERROR_HOLDER
-                            .lock()
-                            .unwrap()
-                            .push(&format!("{:?}", err))
-                            .unwrap_or_else(|_| error!("Failed when try to hold error"));
-                        return Err(err);
+                    if retry_count < policy.limit {
+                        let delay = policy.backoff(retry_count);
+                        if policy.before_deadline(begin, delay) {
+                            warn!(
+                                "Read from backend failed: {:?}, retry count {}, retry in {:?}",
+                                err,
+                                policy.limit - retry_count,
+                                delay
+                            );
+                            thread::sleep(delay);
+                            if allow() {
+                                retry_count += 1;
+                                continue;
+                            }
+                        }
+                    }
+
+                    // Count one failure per request once it's given up, not per attempt.
+                    if let Some(breaker) = breaker.as_ref() {
+                        breaker.fail();
                     }
+                    self.metrics().end(&begin_time, buf.len(), true);
+                    ERROR_HOLDER
+                        .lock()
+                        .unwrap()
+                        .push(&format!("{:?}", err))
+                        .unwrap_or_else(|_| error!("Failed when try to hold error"));
+                    return Err(err);
                 }
             }
         }
@@ -160,6 +232,16 @@ pub trait BlobReader: Send + Sync {
     fn retry_limit(&self) -> u8 {
         0
     }
+
+    /// Get the policy to retry failed requests.
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::new(self.retry_limit())
+    }
+
+    /// Get the circuit breaker shared by all readers of the storage backend.
+    fn circuit_breaker(&self) -> Option<Arc<CircuitBreaker>> {
+        None
+    }
 }
 
 /// Trait to access blob files on backend storages, such as OSS, registry, local fs etc.
@@ -172,9 +254,24 @@ pub trait BlobBackend: Send + Sync {
 
     /// Get a blob reader object to access blod `blob_id`.
     fn get_reader(&self, blob_id: &str) -> BackendResult<Arc<dyn BlobReader>>;
+
+    /// Update credentials and connection settings of the storage backend on the fly.
+    ///
+    /// Blob readers created by the backend switch to the new configuration for following
+    /// requests, and requests already in flight finish with the old configuration.
+    fn update_config(&self, _config: serde_json::Value) -> std::io::Result<()> {
+        Err(enosys!(
+            "storage backend doesn't support updating configuration"
+        ))
+    }
 }
 
-#[cfg(any(feature = "backend-oss", feature = "backend-registry"))]
+#[cfg(any(
+    feature = "backend-http",
+    feature = "backend-oss",
+    feature = "backend-registry",
+    feature = "backend-s3"
+))]
 /// Get default http scheme for network connection.
 fn default_http_scheme() -> String {
     "https".to_string()
@@ -182,7 +279,12 @@ fn default_http_scheme() -> String {
 
 #[cfg(test)]
 mod tests {
-    #[cfg(any(feature = "backend-oss", feature = "backend-registry"))]
+    #[cfg(any(
+        feature = "backend-http",
+        feature = "backend-oss",
+        feature = "backend-registry",
+        feature = "backend-s3"
+    ))]
     #[test]
     fn test_default_http_scheme() {
         assert_eq!(super::default_http_scheme(), "https");
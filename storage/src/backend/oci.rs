@@ -0,0 +1,334 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Storage backend driver to access blobs in OCI image layouts.
+//!
+//! Blobs are resolved through `blobs/sha256/<digest>` of an OCI image-layout directory, or of an
+//! uncompressed `oci-archive` tarball holding the image layout. Offsets of blobs in the tarball are
+//! indexed once when creating the backend, so blobs are read by `pread()` without extracting.
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Error, Result};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use nix::sys::uio;
+use nydus_utils::metrics::BackendMetrics;
+
+use crate::backend::{BackendError, BackendResult, BlobBackend, BlobReader};
+
+/// Directory of blobs addressed by sha256 digest in the image layout.
+const OCI_BLOBS_DIR: &str = "blobs/sha256";
+/// File marking the root of an image layout.
+const OCI_LAYOUT_FILE: &str = "oci-layout";
+
+type OciResult<T> = std::result::Result<T, OciError>;
+
+/// Error codes related to OCI image-layout storage backend.
+#[derive(Debug)]
+pub enum OciError {
+    BlobFile(Error),
+    ReadBlob(nix::Error),
+}
+
+impl From<OciError> for BackendError {
+    fn from(error: OciError) -> Self {
+        BackendError::Oci(error)
+    }
+}
+
+/// OCI image-layout configuration information to access blobs.
+///
+/// This structure is externally visible through configuration file and HTTP API, please keep them
+/// stable.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct OciConfig {
+    /// Path of the OCI image-layout directory.
+    #[serde(default)]
+    pub dir: String,
+    /// Path of the uncompressed `oci-archive` tarball holding the OCI image layout.
+    #[serde(default)]
+    pub archive: String,
+}
+
+struct OciBlob {
+    file: Arc<File>,
+    // Offset of the blob data in `file`.
+    offset: u64,
+    size: u64,
+    metrics: Arc<BackendMetrics>,
+}
+
+impl BlobReader for OciBlob {
+    fn blob_size(&self) -> BackendResult<u64> {
+        Ok(self.size)
+    }
+
+    fn try_read(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
+        if offset >= self.size {
+            return Ok(0);
+        }
+        // Don't read beyond the end of blob, which may be followed by other members of archive.
+        let size = cmp::min(buf.len() as u64, self.size - offset) as usize;
+
+        uio::pread(
+            self.file.as_raw_fd(),
+            &mut buf[..size],
+            (self.offset + offset) as i64,
+        )
+        .map_err(|e| OciError::ReadBlob(e).into())
+    }
+
+    fn prefetch_blob_data_range(&self, _ra_offset: u64, _ra_size: u64) -> BackendResult<()> {
+        Ok(())
+    }
+
+    fn stop_data_prefetch(&self) -> BackendResult<()> {
+        Ok(())
+    }
+
+    fn metrics(&self) -> &BackendMetrics {
+        &self.metrics
+    }
+}
+
+enum OciSource {
+    Dir(PathBuf),
+    // The archive file, and offset and size of blobs in it indexed by digest.
+    Archive(Arc<File>, HashMap<String, (u64, u64)>),
+}
+
+/// Storage backend to access blobs in OCI image-layout directories or archives.
+pub struct Oci {
+    source: OciSource,
+    metrics: Arc<BackendMetrics>,
+    // Hashmap to map blob id to blob reader.
+    entries: RwLock<HashMap<String, Arc<OciBlob>>>,
+}
+
+impl Oci {
+    /// Create a new OCI image-layout storage backend.
+    pub fn new(config: serde_json::value::Value, id: Option<&str>) -> Result<Oci> {
+        let config: OciConfig = serde_json::from_value(config).map_err(|e| einval!(e))?;
+        let id = id.ok_or_else(|| einval!("OCI backend requires blob_id"))?;
+
+        let source = match (config.dir.is_empty(), config.archive.is_empty()) {
+            (false, true) => {
+                let dir = PathBuf::from(&config.dir);
+                if !dir.join(OCI_LAYOUT_FILE).is_file() {
+                    return Err(einval!(format!(
+                        "{} is not an OCI image layout directory",
+                        config.dir
+                    )));
+                }
+                OciSource::Dir(dir)
+            }
+            (true, false) => {
+                let file = File::open(&config.archive).map_err(|e| {
+                    einval!(format!("failed to open archive {}, {}", config.archive, e))
+                })?;
+                let blobs = index_archive(&file)?;
+                info!(
+                    "indexed {} blobs in OCI archive {}",
+                    blobs.len(),
+                    config.archive
+                );
+                OciSource::Archive(Arc::new(file), blobs)
+            }
+            _ => {
+                return Err(einval!(
+                    "exactly one of dir and archive of OCI image layout is required"
+                ))
+            }
+        };
+
+        Ok(Oci {
+            source,
+            metrics: BackendMetrics::new(id, "oci"),
+            entries: RwLock::new(HashMap::new()),
+        })
+    }
+
+    fn get_blob(&self, blob_id: &str) -> OciResult<Arc<OciBlob>> {
+        if let Some(entry) = self.entries.read().unwrap().get(blob_id) {
+            return Ok(entry.clone());
+        }
+
+        let digest = blob_id.strip_prefix("sha256:").unwrap_or(blob_id);
+        if digest.is_empty() || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(OciError::BlobFile(einval!(format!(
+                "invalid blob id {}",
+                blob_id
+            ))));
+        }
+        let blob = match &self.source {
+            OciSource::Dir(dir) => {
+                let file =
+                    File::open(dir.join(OCI_BLOBS_DIR).join(digest)).map_err(OciError::BlobFile)?;
+                let size = file.metadata().map_err(OciError::BlobFile)?.len();
+                OciBlob {
+                    file: Arc::new(file),
+                    offset: 0,
+                    size,
+                    metrics: self.metrics.clone(),
+                }
+            }
+            OciSource::Archive(file, blobs) => {
+                let (offset, size) = blobs.get(digest).ok_or_else(|| {
+                    OciError::BlobFile(enoent!(format!("blob {} not found in archive", digest)))
+                })?;
+                OciBlob {
+                    file: file.clone(),
+                    offset: *offset,
+                    size: *size,
+                    metrics: self.metrics.clone(),
+                }
+            }
+        };
+
+        let mut entries = self.entries.write().unwrap();
+        let entry = entries
+            .entry(blob_id.to_string())
+            .or_insert_with(|| Arc::new(blob));
+
+        Ok(entry.clone())
+    }
+}
+
+impl BlobBackend for Oci {
+    fn shutdown(&self) {}
+
+    fn metrics(&self) -> &BackendMetrics {
+        &self.metrics
+    }
+
+    fn get_reader(&self, blob_id: &str) -> BackendResult<Arc<dyn BlobReader>> {
+        self.get_blob(blob_id)
+            .map(|v| v as Arc<dyn BlobReader>)
+            .map_err(|e| e.into())
+    }
+}
+
+impl Drop for Oci {
+    fn drop(&mut self) {
+        self.metrics.release().unwrap_or_else(|e| error!("{:?}", e));
+    }
+}
+
+/// Index offsets and sizes of blobs in an OCI archive, by walking through tar headers once.
+fn index_archive(file: &File) -> Result<HashMap<String, (u64, u64)>> {
+    let mut archive = tar::Archive::new(file);
+    let mut blobs = HashMap::new();
+    let mut is_layout = false;
+
+    for entry in archive.entries_with_seek()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        let path = path.strip_prefix("./").unwrap_or(path.as_path());
+        if path == Path::new(OCI_LAYOUT_FILE) {
+            is_layout = true;
+        } else if let Ok(name) = path.strip_prefix(OCI_BLOBS_DIR) {
+            if let Some(digest) = name.to_str() {
+                blobs.insert(
+                    digest.to_string(),
+                    (entry.raw_file_position(), entry.size()),
+                );
+            }
+        }
+    }
+    if !is_layout {
+        return Err(einval!("archive doesn't hold an OCI image layout"));
+    }
+
+    Ok(blobs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs;
+    use vmm_sys_util::tempdir::TempDir;
+
+    const DIGEST: &str = "7a0b2c1e3f4d5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b";
+
+    fn check_backend(oci: &Oci, data: &[u8]) {
+        let reader = oci.get_reader(DIGEST).unwrap();
+        assert_eq!(reader.blob_size().unwrap(), data.len() as u64);
+        let mut buf = vec![0u8; 0x100];
+        assert_eq!(reader.read(&mut buf, 0x1000).unwrap(), 0x100);
+        assert_eq!(buf, data[0x1000..0x1100]);
+        // Reads are limited to the blob.
+        assert_eq!(reader.read(&mut buf, 0x2f80).unwrap(), 0x80);
+        assert_eq!(buf[..0x80], data[0x2f80..]);
+        assert_eq!(reader.read(&mut buf, 0x3000).unwrap(), 0);
+
+        let reader = oci.get_reader(&format!("sha256:{}", DIGEST)).unwrap();
+        assert_eq!(reader.blob_size().unwrap(), data.len() as u64);
+        assert!(oci.get_reader(&DIGEST[1..]).is_err());
+        assert!(oci.get_reader("../oci-layout").is_err());
+    }
+
+    #[test]
+    fn test_oci_layout_dir() {
+        let tmpdir = TempDir::new().unwrap();
+        let dir = tmpdir.as_path();
+        let data: Vec<u8> = (0..0x3000u32).map(|v| v as u8).collect();
+        let config = json!({ "dir": dir.to_str().unwrap() });
+        assert!(Oci::new(config.clone(), Some("test-oci-dir")).is_err());
+
+        fs::write(
+            dir.join(OCI_LAYOUT_FILE),
+            r#"{"imageLayoutVersion":"1.0.0"}"#,
+        )
+        .unwrap();
+        fs::create_dir_all(dir.join(OCI_BLOBS_DIR)).unwrap();
+        fs::write(dir.join(OCI_BLOBS_DIR).join(DIGEST), &data).unwrap();
+        assert!(Oci::new(json!({}), Some("test-oci-dir")).is_err());
+        let oci = Oci::new(config, Some("test-oci-dir")).unwrap();
+        check_backend(&oci, &data);
+    }
+
+    #[test]
+    fn test_oci_archive() {
+        let tmpdir = TempDir::new().unwrap();
+        let path = tmpdir.as_path().join("image.tar");
+        let data: Vec<u8> = (0..0x3000u32).map(|v| v as u8).collect();
+        let append = |builder: &mut tar::Builder<File>, path: &str, data: &[u8]| {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, data).unwrap();
+        };
+
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+        append(&mut builder, "./index.json", b"{}");
+        append(&mut builder, "./blobs/sha256/0123", &data[..0x100]);
+        append(&mut builder, &format!("./blobs/sha256/{}", DIGEST), &data);
+        append(&mut builder, "./blobs/sha256/4567", &data[..0x100]);
+        builder.finish().unwrap();
+        let config = json!({ "archive": path.to_str().unwrap() });
+        assert!(Oci::new(config.clone(), Some("test-oci-archive")).is_err());
+
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+        append(
+            &mut builder,
+            "oci-layout",
+            br#"{"imageLayoutVersion":"1.0.0"}"#,
+        );
+        append(&mut builder, "./blobs/sha256/0123", &data[..0x100]);
+        append(&mut builder, &format!("./blobs/sha256/{}", DIGEST), &data);
+        append(&mut builder, "./blobs/sha256/4567", &data[..0x100]);
+        builder.finish().unwrap();
+        let oci = Oci::new(config, Some("test-oci-archive")).unwrap();
+        check_backend(&oci, &data);
+        assert_eq!(oci.get_reader("0123").unwrap().blob_size().unwrap(), 0x100);
+    }
+}
@@ -8,6 +8,7 @@ use std::io::{Error, Result};
 use std::sync::Arc;
 use std::time::SystemTime;
 
+use arc_swap::ArcSwap;
 use hmac::{Hmac, Mac};
 use reqwest::header::{HeaderMap, CONTENT_LENGTH};
 use reqwest::Method;
@@ -16,7 +17,8 @@ use sha1::Sha1;
 use nydus_api::http::RegistryOssConfig;
 use nydus_utils::metrics::BackendMetrics;
 
-use crate::backend::connection::{Connection, ConnectionError};
+use crate::backend::connection::{Connection, ConnectionContext, ConnectionError};
+use crate::backend::retry::{CircuitBreaker, RetryPolicy};
 use crate::backend::{default_http_scheme, BackendError, BackendResult, BlobBackend, BlobReader};
 
 const HEADER_DATE: &str = "Date";
@@ -145,23 +147,26 @@ impl OssState {
     }
 }
 
+type OssContext = ConnectionContext<OssState>;
+
 struct OssReader {
     blob_id: String,
-    connection: Arc<Connection>,
-    state: Arc<OssState>,
+    context: Arc<ArcSwap<OssContext>>,
     metrics: Arc<BackendMetrics>,
 }
 
 impl BlobReader for OssReader {
     fn blob_size(&self) -> BackendResult<u64> {
-        let (resource, url) = self.state.url(&self.blob_id, &[]);
+        let context = self.context.load_full();
+        let (resource, url) = context.state.url(&self.blob_id, &[]);
         let mut headers = HeaderMap::new();
 
-        self.state
+        context
+            .state
             .sign(Method::HEAD, &mut headers, resource.as_str())
             .map_err(OssError::Auth)?;
 
-        let resp = self
+        let resp = context
             .connection
             .call::<&[u8]>(Method::HEAD, url.as_str(), None, None, headers, true)
             .map_err(OssError::Request)?;
@@ -178,8 +183,9 @@ impl BlobReader for OssReader {
     }
 
     fn try_read(&self, mut buf: &mut [u8], offset: u64) -> BackendResult<usize> {
+        let context = self.context.load_full();
         let query = &[];
-        let (resource, url) = self.state.url(&self.blob_id, query);
+        let (resource, url) = context.state.url(&self.blob_id, query);
         let mut headers = HeaderMap::new();
         let end_at = offset + buf.len() as u64 - 1;
         let range = format!("bytes={}-{}", offset, end_at);
@@ -191,12 +197,13 @@ impl BlobReader for OssReader {
                 .parse()
                 .map_err(|e| OssError::ConstructHeader(format!("{}", e)))?,
         );
-        self.state
+        context
+            .state
             .sign(Method::GET, &mut headers, resource.as_str())
             .map_err(OssError::Auth)?;
 
         // Safe because the the call() is a synchronous operation.
-        let mut resp = self
+        let mut resp = context
             .connection
             .call::<&[u8]>(Method::GET, url.as_str(), None, None, headers, true)
             .map_err(OssError::Request)?;
@@ -223,15 +230,22 @@ impl BlobReader for OssReader {
     }
 
     fn retry_limit(&self) -> u8 {
-        self.state.retry_limit
+        self.context.load().state.retry_limit
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.context.load().connection.retry_policy()
+    }
+
+    fn circuit_breaker(&self) -> Option<Arc<CircuitBreaker>> {
+        self.context.load().connection.circuit_breaker()
     }
 }
 
 /// Storage backend to access data stored in OSS.
 #[derive(Debug)]
 pub struct Oss {
-    connection: Arc<Connection>,
-    state: Arc<OssState>,
+    context: Arc<ArcSwap<OssContext>>,
     metrics: Option<Arc<BackendMetrics>>,
     #[allow(unused)]
     id: Option<String>,
@@ -240,12 +254,31 @@ pub struct Oss {
 impl Oss {
     /// Create a new OSS storage backend.
     pub fn new(config: serde_json::value::Value, id: Option<&str>) -> Result<Oss> {
+        let metrics = id.map(|i| BackendMetrics::new(i, "oss"));
+        let context = Self::new_context(config, metrics.clone(), None).map_err(|e| {
+            if let Some(metrics) = metrics.as_ref() {
+                metrics.release().unwrap_or_else(|e| error!("{:?}", e));
+            }
+            e
+        })?;
+
+        Ok(Oss {
+            context: Arc::new(ArcSwap::new(Arc::new(context))),
+            metrics,
+            id: id.map(|i| i.to_string()),
+        })
+    }
+
+    fn new_context(
+        config: serde_json::value::Value,
+        metrics: Option<Arc<BackendMetrics>>,
+        previous: Option<&Connection>,
+    ) -> Result<OssContext> {
         let common_config: RegistryOssConfig =
             serde_json::from_value(config.clone()).map_err(|e| einval!(e))?;
         let retry_limit = common_config.retry_limit;
-        let connection = Connection::new(&common_config)?;
         let oss_config: OssConfig = serde_json::from_value(config).map_err(|e| einval!(e))?;
-        let state = Arc::new(OssState {
+        let state = OssState {
             scheme: oss_config.scheme,
             object_prefix: oss_config.object_prefix,
             endpoint: oss_config.endpoint,
@@ -253,21 +286,19 @@ impl Oss {
             access_key_secret: oss_config.access_key_secret,
             bucket_name: oss_config.bucket_name,
             retry_limit,
-        });
-        let metrics = id.map(|i| BackendMetrics::new(i, "oss"));
+        };
+        let connection = match previous {
+            Some(connection) => connection.renew(&common_config)?,
+            None => Connection::new(&common_config, metrics)?,
+        };
 
-        Ok(Oss {
-            state,
-            connection,
-            metrics,
-            id: id.map(|i| i.to_string()),
-        })
+        Ok(OssContext { state, connection })
     }
 }
 
 impl BlobBackend for Oss {
     fn shutdown(&self) {
-        self.connection.shutdown();
+        self.context.load().connection.shutdown();
     }
 
     fn metrics(&self) -> &BackendMetrics {
@@ -280,8 +311,7 @@ impl BlobBackend for Oss {
         if let Some(metrics) = self.metrics.as_ref() {
             Ok(Arc::new(OssReader {
                 blob_id: blob_id.to_string(),
-                state: self.state.clone(),
-                connection: self.connection.clone(),
+                context: self.context.clone(),
                 metrics: metrics.clone(),
             }))
         } else {
@@ -290,6 +320,13 @@ impl BlobBackend for Oss {
             ))
         }
     }
+
+    fn update_config(&self, config: serde_json::value::Value) -> Result<()> {
+        let previous = self.context.load();
+        let context = Self::new_context(config, self.metrics.clone(), Some(&previous.connection))?;
+        self.context.store(Arc::new(context));
+        Ok(())
+    }
 }
 
 impl Drop for Oss {
@@ -337,14 +374,22 @@ mod tests {
     #[test]
     fn test_oss_new() {
         let json_str = "{\"access_key_id\":\"key\",\"access_key_secret\":\"secret\",\"bucket_name\":\"images\",\"endpoint\":\"/oss\",\"object_prefix\":\"nydus\",\"scheme\":\"\",\"proxy\":{\"url\":\"\",\"ping_url\":\"\",\"fallback\":true,\"check_interval\":5},\"timeout\":5,\"connect_timeout\":5,\"retry_limit\":5}";
-        let json: Value = serde_json::from_str(json_str).unwrap();
-        let oss = Oss::new(json, Some("test-image")).unwrap();
+        let mut json: Value = serde_json::from_str(json_str).unwrap();
+        let oss = Oss::new(json.clone(), Some("test-image")).unwrap();
 
         oss.metrics();
 
         let reader = oss.get_reader("test").unwrap();
         assert_eq!(reader.retry_limit(), 5);
 
+        // Existing readers switch to the updated configuration.
+        json["access_key_secret"] = Value::from("secret2");
+        json["retry_limit"] = Value::from(3);
+        oss.update_config(json).unwrap();
+        assert_eq!(reader.retry_limit(), 3);
+        assert!(oss.update_config(Value::from("invalid")).is_err());
+        assert_eq!(reader.retry_limit(), 3);
+
         oss.shutdown();
     }
 }
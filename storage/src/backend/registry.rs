@@ -4,9 +4,14 @@
 
 //! Storage backend driver to access blobs on container image registry.
 use std::collections::HashMap;
-use std::io::{Error, Read, Result};
-use std::sync::{Arc, RwLock};
-
+use std::fs;
+use std::io::{Error, Read, Result, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwap;
 use reqwest::blocking::Response;
 pub use reqwest::header::HeaderMap;
 use reqwest::header::{HeaderValue, CONTENT_LENGTH};
@@ -17,13 +22,22 @@ use nydus_api::http::RegistryOssConfig;
 use nydus_utils::metrics::BackendMetrics;
 
 use crate::backend::connection::{
-    is_success_status, respond, Connection, ConnectionError, ReqBody,
+    is_success_status, respond, Connection, ConnectionContext, ConnectionError, ReqBody,
 };
+use crate::backend::retry::{CircuitBreaker, RetryPolicy};
 use crate::backend::{default_http_scheme, BackendError, BackendResult, BlobBackend, BlobReader};
 
 const REGISTRY_CLIENT_ID: &str = "nydus-registry-client";
 const HEADER_AUTHORIZATION: &str = "Authorization";
 const HEADER_WWW_AUTHENTICATE: &str = "www-authenticate";
+/// Default lifetime of registry bearer tokens, in seconds.
+const REGISTRY_TOKEN_EXPIRES_IN: u64 = 60;
+/// Interval to retry refreshing registry bearer token after failure.
+const REGISTRY_TOKEN_REFRESH_RETRY: Duration = Duration::from_secs(10);
+/// Username returned by docker credential helpers for identity tokens.
+const DOCKER_IDENTITY_TOKEN_USERNAME: &str = "<token>";
+/// Server address of Docker Hub in docker configuration.
+const DOCKER_HUB_SERVER: &str = "https://index.docker.io/v1/";
 
 /// Error codes related to registry storage backend operations.
 #[derive(Debug)]
@@ -116,11 +130,174 @@ pub struct RegistryConfig {
     pub blob_url_scheme: String,
     #[serde(default)]
     pub blob_redirected_host: String,
+    // Path of docker `config.json` to get registry credentials from, including credentials
+    // managed by docker credential helpers. It's used only if `auth` isn't given.
+    #[serde(default)]
+    pub docker_config: String,
 }
 
 #[derive(Clone, Deserialize)]
 struct TokenResponse {
+    // Registry authentication servers may return the token in `token` or `access_token`.
+    #[serde(default)]
     token: String,
+    #[serde(default)]
+    access_token: String,
+    #[serde(default = "default_token_expires_in")]
+    expires_in: u64,
+}
+
+fn default_token_expires_in() -> u64 {
+    REGISTRY_TOKEN_EXPIRES_IN
+}
+
+impl TokenResponse {
+    fn token(&self) -> &str {
+        if self.token.is_empty() {
+            &self.access_token
+        } else {
+            &self.token
+        }
+    }
+}
+
+/// Registry credentials in docker configuration.
+#[derive(Default, Deserialize)]
+struct DockerConfig {
+    #[serde(default)]
+    auths: HashMap<String, DockerAuthConfig>,
+    #[serde(default, rename = "credsStore")]
+    creds_store: String,
+    #[serde(default, rename = "credHelpers")]
+    cred_helpers: HashMap<String, String>,
+}
+
+#[derive(Default, Deserialize)]
+struct DockerAuthConfig {
+    #[serde(default)]
+    auth: String,
+    #[serde(default)]
+    identitytoken: String,
+}
+
+/// Credentials returned by docker credential helpers.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HelperCredential {
+    username: String,
+    secret: String,
+}
+
+#[derive(PartialEq)]
+enum DockerCredential {
+    // Base64 encoded `username:password`.
+    Auth(String),
+    // OAuth2 refresh token to get bearer tokens from registry authentication server.
+    IdentityToken(String),
+}
+
+impl DockerConfig {
+    fn load(path: &str) -> Result<Self> {
+        let data = fs::read(path)
+            .map_err(|e| einval!(format!("failed to read docker config {}, {}", path, e)))?;
+        serde_json::from_slice(&data)
+            .map_err(|e| einval!(format!("invalid docker config {}, {}", path, e)))
+    }
+
+    /// Get credentials of registry `host`, from credential helpers or `auths`.
+    fn credential(&self, host: &str) -> Result<Option<DockerCredential>> {
+        let server = docker_server(host);
+        let helper = self
+            .cred_helpers
+            .iter()
+            .find(|(k, _)| docker_server(k) == server)
+            .map(|(_, v)| v.as_str())
+            .or_else(|| (!self.creds_store.is_empty()).then(|| self.creds_store.as_str()));
+        if let Some(helper) = helper {
+            let server = if server == docker_server(DOCKER_HUB_SERVER) {
+                DOCKER_HUB_SERVER
+            } else {
+                server
+            };
+            return run_credential_helper(&format!("docker-credential-{}", helper), server);
+        }
+
+        for (key, config) in self.auths.iter() {
+            if docker_server(key) != server {
+                continue;
+            }
+            if !config.identitytoken.is_empty() {
+                return Ok(Some(DockerCredential::IdentityToken(
+                    config.identitytoken.clone(),
+                )));
+            } else if !config.auth.is_empty() {
+                return Ok(Some(DockerCredential::Auth(config.auth.clone())));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Normalize registry server address in docker configuration into host name.
+fn docker_server(server: &str) -> &str {
+    let server = server
+        .trim_start_matches("https://")
+        .trim_start_matches("http://");
+    let server = server.split('/').next().unwrap_or_default();
+    match server {
+        "docker.io" | "registry-1.docker.io" => "index.docker.io",
+        _ => server,
+    }
+}
+
+/// Get credentials of registry `server` by docker credential helper `program`.
+fn run_credential_helper(program: &str, server: &str) -> Result<Option<DockerCredential>> {
+    let mut child = Command::new(program)
+        .arg("get")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            einval!(format!(
+                "failed to run credential helper {}, {}",
+                program, e
+            ))
+        })?;
+    // Close stdin after writing the server address.
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(server.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        let msg = String::from_utf8_lossy(&output.stdout);
+        if msg.contains("credentials not found") {
+            return Ok(None);
+        }
+        return Err(eother!(format!(
+            "credential helper {} failed, {}{}",
+            program,
+            msg.trim(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let cred: HelperCredential = serde_json::from_slice(&output.stdout).map_err(|e| {
+        einval!(format!(
+            "invalid output of credential helper {}, {}",
+            program, e
+        ))
+    })?;
+    if cred.username == DOCKER_IDENTITY_TOKEN_USERNAME {
+        Ok(Some(DockerCredential::IdentityToken(cred.secret)))
+    } else {
+        Ok(Some(DockerCredential::Auth(base64::encode(format!(
+            "{}:{}",
+            cred.username, cred.secret
+        )))))
+    }
 }
 
 #[derive(Debug)]
@@ -129,12 +306,11 @@ struct BasicAuth {
     realm: String,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 struct BearerAuth {
     realm: String,
     service: String,
     scope: String,
-    header: Option<HeaderValue>,
 }
 
 #[derive(Debug)]
@@ -143,16 +319,62 @@ enum Auth {
     Bearer(BearerAuth),
 }
 
+/// Challenge to get a new bearer token before the cached one expires.
+struct BearerToken {
+    auth: BearerAuth,
+    refresh_at: Instant,
+}
+
+/// Credentials to access the registry.
+#[derive(Clone, PartialEq)]
+struct RegistryCredential {
+    // Base64 encoded registry auth
+    auth: Option<String>,
+    username: String,
+    password: String,
+    // OAuth2 refresh token to get bearer tokens, used instead of username and password
+    identity_token: Option<String>,
+}
+
+impl RegistryCredential {
+    /// Use the configured `auth`, or get credentials of registry `host` from `docker_config`.
+    fn new(auth: Option<String>, docker_config: &str, host: &str) -> Result<Self> {
+        let mut auth = auth;
+        let mut identity_token = None;
+        if auth.is_none() && !docker_config.is_empty() {
+            match DockerConfig::load(docker_config)?.credential(host)? {
+                Some(DockerCredential::Auth(v)) => auth = trim(Some(v)),
+                Some(DockerCredential::IdentityToken(v)) => identity_token = Some(v),
+                None => info!(
+                    "no credentials for registry {} in docker config {}",
+                    host, docker_config
+                ),
+            }
+        }
+        let (username, password) = Registry::get_authorization_info(&auth)?;
+
+        Ok(RegistryCredential {
+            auth,
+            username,
+            password,
+            identity_token,
+        })
+    }
+}
+
 struct RegistryState {
     // HTTP scheme like: https, http
     scheme: String,
     host: String,
     // Image repo name like: library/ubuntu
     repo: String,
-    // Base64 encoded registry auth
-    auth: Option<String>,
-    username: String,
-    password: String,
+    // Credentials to get bearer tokens or for basic authentication
+    credential: RwLock<RegistryCredential>,
+    // Path of docker config to get credentials again when they are rejected, which may have
+    // been rotated by credential helpers. It's empty if `auth` is configured.
+    docker_config: String,
+    // Time when credentials were got from the docker config again
+    credential_reloaded_at: Mutex<Option<Instant>>,
     // Retry limit for read operation
     retry_limit: u8,
     // Scheme specified for blob server
@@ -165,6 +387,10 @@ struct RegistryState {
     // Example: RwLock<"Bearer <token>">
     //          RwLock<"Basic base64(<username:password>)">
     cached_auth: Cache,
+    // Challenge to refresh the cached bearer token ahead of its expiration
+    cached_token: RwLock<Option<BearerToken>>,
+    // Whether a reader is refreshing the cached bearer token
+    refreshing_token: AtomicBool,
     // Cache 30X redirect url
     // Example: RwLock<HashMap<"<blob_id>", "<redirected_url>">>
     cached_redirect: HashCache,
@@ -185,7 +411,12 @@ impl RegistryState {
     }
 
     /// Request registry authentication server to get bearer token
-    fn get_token(&self, auth: BearerAuth, connection: &Arc<Connection>) -> Result<String> {
+    fn get_token(&self, auth: BearerAuth, connection: &Arc<Connection>) -> Result<TokenResponse> {
+        let credential = self.credential.read().unwrap().clone();
+        if let Some(identity_token) = credential.identity_token.as_ref() {
+            return self.get_oauth_token(auth, identity_token, connection);
+        }
+
         // The information needed for getting token needs to be placed both in
         // the query and in the body to be compatible with different registry
         // implementations, which have been tested on these platforms:
@@ -194,8 +425,8 @@ impl RegistryState {
             ("service", auth.service.as_str()),
             ("scope", auth.scope.as_str()),
             ("grant_type", "password"),
-            ("username", self.username.as_str()),
-            ("password", self.password.as_str()),
+            ("username", credential.username.as_str()),
+            ("password", credential.password.as_str()),
             ("client_id", REGISTRY_CLIENT_ID),
         ];
 
@@ -205,8 +436,10 @@ impl RegistryState {
         }
 
         let mut headers = HeaderMap::new();
-        if let Some(auth_header) = &auth.header {
-            headers.insert(HEADER_AUTHORIZATION, auth_header.clone());
+        if let Some(auth) = credential.auth.as_ref() {
+            let mut header = HeaderValue::from_str(&format!("Basic {}", auth)).unwrap();
+            header.set_sensitive(true);
+            headers.insert(HEADER_AUTHORIZATION, header);
         }
 
         let token_resp = connection
@@ -219,32 +452,170 @@ impl RegistryState {
                 true,
             )
             .map_err(|e| einval!(format!("registry auth server request failed {:?}", e)))?;
-        let ret: TokenResponse = token_resp.json().map_err(|e| {
+        Self::decode_token(token_resp)
+    }
+
+    /// Request registry authentication server to get bearer token by OAuth2 refresh token.
+    fn get_oauth_token(
+        &self,
+        auth: BearerAuth,
+        identity_token: &str,
+        connection: &Arc<Connection>,
+    ) -> Result<TokenResponse> {
+        let mut form = HashMap::new();
+        form.insert("grant_type".to_string(), "refresh_token".to_string());
+        form.insert("refresh_token".to_string(), identity_token.to_string());
+        form.insert("service".to_string(), auth.service);
+        form.insert("scope".to_string(), auth.scope);
+        form.insert("client_id".to_string(), REGISTRY_CLIENT_ID.to_string());
+
+        let token_resp = connection
+            .call::<&[u8]>(
+                Method::POST,
+                auth.realm.as_str(),
+                None,
+                Some(ReqBody::Form(form)),
+                HeaderMap::new(),
+                true,
+            )
+            .map_err(|e| einval!(format!("registry auth server request failed {:?}", e)))?;
+        Self::decode_token(token_resp)
+    }
+
+    fn decode_token(resp: Response) -> Result<TokenResponse> {
+        let ret: TokenResponse = resp.json().map_err(|e| {
             einval!(format!(
                 "registry auth server response decode failed: {:?}",
                 e
             ))
         })?;
-        Ok(ret.token)
+        if ret.token().is_empty() {
+            return Err(einval!("registry auth server returned empty token"));
+        }
+
+        Ok(ret)
+    }
+
+    /// Remember the challenge to refresh the bearer token after 80% of its lifetime.
+    fn cache_token(&self, auth: BearerAuth, resp: &TokenResponse) {
+        let refresh_at = Instant::now() + Duration::from_secs(resp.expires_in * 4 / 5);
+        *self.cached_token.write().unwrap() = Some(BearerToken { auth, refresh_at });
+    }
+
+    /// Keep using the bearer token got by the state to be replaced, unless a registry token is
+    /// configured or the registry or repository is changed. The token will be refreshed with new
+    /// credentials ahead of its expiration.
+    fn inherit_token(&self, previous: &RegistryState) {
+        if !self.cached_auth.get().is_empty()
+            || self.scheme != previous.scheme
+            || self.host != previous.host
+            || self.repo != previous.repo
+        {
+            return;
+        }
+        if let Some(token) = previous.cached_token.read().unwrap().as_ref() {
+            self.cached_auth.set("", previous.cached_auth.get());
+            *self.cached_token.write().unwrap() = Some(BearerToken {
+                auth: token.auth.clone(),
+                refresh_at: token.refresh_at,
+            });
+        }
     }
 
-    fn get_auth_header(&self, auth: Auth, connection: &Arc<Connection>) -> Result<String> {
+    /// Refresh the cached bearer token ahead of its expiration.
+    ///
+    /// Only one reader refreshes the token at a time, others keep using the cached token which
+    /// is still valid.
+    fn refresh_token(&self, connection: &Arc<Connection>) {
+        let auth = match self.cached_token.read().unwrap().as_ref() {
+            Some(token) if Instant::now() >= token.refresh_at => token.auth.clone(),
+            _ => return,
+        };
+        if self.refreshing_token.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let mut ret = self.get_token(auth.clone(), connection);
+        if ret.is_err() && self.reload_credential() {
+            ret = self.get_token(auth.clone(), connection);
+        }
+        match ret {
+            Ok(resp) => {
+                let last_cached_auth = self.cached_auth.get();
+                self.cached_auth
+                    .set(&last_cached_auth, format!("Bearer {}", resp.token()));
+                self.cache_token(auth, &resp);
+                debug!("registry bearer token refreshed");
+            }
+            Err(e) => {
+                warn!("failed to refresh registry bearer token, {}", e);
+                if let Some(token) = self.cached_token.write().unwrap().as_mut() {
+                    token.refresh_at = Instant::now() + REGISTRY_TOKEN_REFRESH_RETRY;
+                }
+            }
+        }
+        self.refreshing_token.store(false, Ordering::Release);
+    }
+
+    /// Get credentials from the docker config again, return true if they are changed.
+    ///
+    /// Credentials managed by docker credential helpers may be rotated, so credential helpers
+    /// are called again when the registry rejects the credentials, at most once in
+    /// `REGISTRY_TOKEN_REFRESH_RETRY`.
+    fn reload_credential(&self) -> bool {
+        if self.docker_config.is_empty() {
+            return false;
+        }
+        let mut reloaded_at = self.credential_reloaded_at.lock().unwrap();
+        if matches!(*reloaded_at, Some(t) if t.elapsed() < REGISTRY_TOKEN_REFRESH_RETRY) {
+            return false;
+        }
+        *reloaded_at = Some(Instant::now());
+
+        match RegistryCredential::new(None, &self.docker_config, &self.host) {
+            Ok(credential) => {
+                let mut current = self.credential.write().unwrap();
+                if *current == credential {
+                    return false;
+                }
+                *current = credential;
+                info!(
+                    "registry credentials updated from docker config {}",
+                    self.docker_config
+                );
+                true
+            }
+            Err(e) => {
+                warn!(
+                    "failed to get registry credentials from docker config {}, {}",
+                    self.docker_config, e
+                );
+                false
+            }
+        }
+    }
+
+    fn get_auth_header(&self, auth: &Auth, connection: &Arc<Connection>) -> Result<String> {
         match auth {
             Auth::Basic(_) => self
+                .credential
+                .read()
+                .unwrap()
                 .auth
                 .as_ref()
                 .map(|auth| format!("Basic {}", auth))
                 .ok_or_else(|| einval!("invalid auth config")),
             Auth::Bearer(auth) => {
-                let token = self.get_token(auth, connection)?;
-                Ok(format!("Bearer {}", token))
+                let resp = self.get_token(auth.clone(), connection)?;
+                self.cache_token(auth.clone(), &resp);
+                Ok(format!("Bearer {}", resp.token()))
             }
         }
     }
 
     /// Parse `www-authenticate` response header respond from registry server
     /// The header format like: `Bearer realm="https://auth.my-registry.com/token",service="my-registry.com",scope="repository:test/repo:pull,push"`
-    fn parse_auth(source: &HeaderValue, auth: &Option<String>) -> Option<Auth> {
+    fn parse_auth(source: &HeaderValue) -> Option<Auth> {
         let source = source.to_str().unwrap();
         let source: Vec<&str> = source.splitn(2, ' ').collect();
         if source.len() < 2 {
@@ -281,15 +652,10 @@ impl RegistryState {
                     return None;
                 }
 
-                let header = auth
-                    .as_ref()
-                    .map(|auth| HeaderValue::from_str(&format!("Basic {}", auth)).unwrap());
-
                 Some(Auth::Bearer(BearerAuth {
                     realm: (*paras.get("realm").unwrap()).to_string(),
                     service: (*paras.get("service").unwrap()).to_string(),
                     scope: (*paras.get("scope").unwrap()).to_string(),
-                    header,
                 }))
             }
             _ => None,
@@ -297,10 +663,11 @@ impl RegistryState {
     }
 }
 
+type RegistryContext = ConnectionContext<RegistryState>;
+
 struct RegistryReader {
     blob_id: String,
-    connection: Arc<Connection>,
-    state: Arc<RegistryState>,
+    context: Arc<ArcSwap<RegistryContext>>,
     metrics: Arc<BackendMetrics>,
 }
 
@@ -333,62 +700,83 @@ impl RegistryReader {
     /// Response: status: 200 Ok
     fn request<R: Read + Send + 'static>(
         &self,
+        context: &RegistryContext,
         method: Method,
         url: &str,
         data: Option<ReqBody<R>>,
         mut headers: HeaderMap,
         catch_status: bool,
     ) -> RegistryResult<Response> {
+        context.state.refresh_token(&context.connection);
+
         // Try get authorization header from cache for this request
         let mut last_cached_auth = String::new();
-        let cached_auth = self.state.cached_auth.get();
+        let cached_auth = context.state.cached_auth.get();
         if !cached_auth.is_empty() {
             last_cached_auth = cached_auth.clone();
-            headers.insert(
-                HEADER_AUTHORIZATION,
-                HeaderValue::from_str(cached_auth.as_str()).unwrap(),
-            );
+            let mut header = HeaderValue::from_str(cached_auth.as_str()).unwrap();
+            header.set_sensitive(true);
+            headers.insert(HEADER_AUTHORIZATION, header);
         }
 
         // For upload request with payload, the auth header should be cached
         // after create_upload(), so we can request registry server directly
         if let Some(data) = data {
-            return self
+            return context
                 .connection
                 .call(method, url, None, Some(data), headers, catch_status)
                 .map_err(RegistryError::Request);
         }
 
         // Try to request registry server with `authorization` header
-        let resp = self
+        let resp = context
             .connection
             .call::<&[u8]>(method.clone(), url, None, None, headers.clone(), false)
             .map_err(RegistryError::Request)?;
         if resp.status() == StatusCode::UNAUTHORIZED {
             if let Some(resp_auth_header) = resp.headers().get(HEADER_WWW_AUTHENTICATE) {
                 // Get token from registry authorization server
-                if let Some(auth) = RegistryState::parse_auth(resp_auth_header, &self.state.auth) {
-                    let auth_header = self
-                        .state
-                        .get_auth_header(auth, &self.connection)
-                        .map_err(|e| RegistryError::Common(e.to_string()))?;
-                    headers.insert(
-                        HEADER_AUTHORIZATION,
-                        HeaderValue::from_str(auth_header.as_str()).unwrap(),
-                    );
-
-                    // Try to request registry server with `authorization` header again
-                    let resp = self
-                        .connection
-                        .call(method, url, None, data, headers, catch_status)
-                        .map_err(RegistryError::Request)?;
-
-                    let status = resp.status();
-                    if is_success_status(status) {
-                        // Cache authorization header for next request
-                        self.state.cached_auth.set(&last_cached_auth, auth_header)
+                if let Some(auth) = RegistryState::parse_auth(resp_auth_header) {
+                    // Credentials may have been rotated, get them again and retry once if they
+                    // are rejected.
+                    let mut reloaded = false;
+                    loop {
+                        let auth_header =
+                            match context.state.get_auth_header(&auth, &context.connection) {
+                                Ok(v) => v,
+                                Err(_) if !reloaded && context.state.reload_credential() => {
+                                    reloaded = true;
+                                    continue;
+                                }
+                                Err(e) => return Err(RegistryError::Common(e.to_string())),
+                            };
+                        let mut header = HeaderValue::from_str(auth_header.as_str()).unwrap();
+                        header.set_sensitive(true);
+                        headers.insert(HEADER_AUTHORIZATION, header);
+
+                        // Try to request registry server with `authorization` header again
+                        let resp = context
+                            .connection
+                            .call::<&[u8]>(method.clone(), url, None, None, headers.clone(), false)
+                            .map_err(RegistryError::Request)?;
+
+                        let status = resp.status();
+                        if status == StatusCode::UNAUTHORIZED
+                            && !reloaded
+                            && context.state.reload_credential()
+                        {
+                            reloaded = true;
+                            continue;
+                        }
+                        if is_success_status(status) {
+                            // Cache authorization header for next request
+                            context
+                                .state
+                                .cached_auth
+                                .set(&last_cached_auth, auth_header)
+                        }
+                        return respond(resp, catch_status).map_err(RegistryError::Request);
                     }
-                    return respond(resp, catch_status).map_err(RegistryError::Request);
                 }
             }
         }
@@ -409,12 +797,13 @@ impl RegistryReader {
     /// If responding 403, we need to repeat step one
     fn _try_read(
         &self,
+        context: &RegistryContext,
         mut buf: &mut [u8],
         offset: u64,
         allow_retry: bool,
     ) -> RegistryResult<usize> {
         let url = format!("/blobs/sha256:{}", self.blob_id);
-        let url = self
+        let url = context
             .state
             .url(url.as_str(), &[])
             .map_err(RegistryError::Url)?;
@@ -424,10 +813,10 @@ impl RegistryReader {
         headers.insert("Range", range.parse().unwrap());
 
         let mut resp;
-        let cached_redirect = self.state.cached_redirect.get(&self.blob_id);
+        let cached_redirect = context.state.cached_redirect.get(&self.blob_id);
 
         if let Some(cached_redirect) = cached_redirect {
-            resp = self
+            resp = context
                 .connection
                 .call::<&[u8]>(
                     Method::GET,
@@ -447,13 +836,19 @@ impl RegistryReader {
                     "The redirected link has expired: {}, will retry read",
                     cached_redirect.as_str()
                 );
-                self.state.cached_redirect.remove(&self.blob_id);
+                context.state.cached_redirect.remove(&self.blob_id);
                 // Try read again only once
-                return self._try_read(buf, offset, false);
+                return self._try_read(context, buf, offset, false);
             }
         } else {
-            resp =
-                self.request::<&[u8]>(Method::GET, url.as_str(), None, headers.clone(), false)?;
+            resp = self.request::<&[u8]>(
+                context,
+                Method::GET,
+                url.as_str(),
+                None,
+                headers.clone(),
+                false,
+            )?;
             let status = resp.status();
             // Handle redirect request and cache redirect url
             if vec![
@@ -467,34 +862,35 @@ impl RegistryReader {
                     let mut location = Url::parse(location).map_err(RegistryError::Url)?;
                     // Note: Some P2P proxy server supports only scheme specified origin blob server,
                     // so we need change scheme to `blob_url_scheme` here
-                    if !self.state.blob_url_scheme.is_empty() {
+                    if !context.state.blob_url_scheme.is_empty() {
                         location
-                            .set_scheme(&self.state.blob_url_scheme)
+                            .set_scheme(&context.state.blob_url_scheme)
                             .map_err(|_| {
-                                RegistryError::Scheme(self.state.blob_url_scheme.clone())
+                                RegistryError::Scheme(context.state.blob_url_scheme.clone())
                             })?;
                     }
-                    if !self.state.blob_redirected_host.is_empty() {
+                    if !context.state.blob_redirected_host.is_empty() {
                         location
-                            .set_host(Some(self.state.blob_redirected_host.as_str()))
+                            .set_host(Some(context.state.blob_redirected_host.as_str()))
                             .map_err(|e| {
                                 error!(
                                     "Failed to set blob redirected host to {}: {:?}",
-                                    self.state.blob_redirected_host.as_str(),
+                                    context.state.blob_redirected_host.as_str(),
                                     e
                                 );
                                 RegistryError::Url(e)
                             })?;
                         debug!("New redirected location {:?}", location.host_str());
                     }
-                    let resp_ret = self
+                    let resp_ret = context
                         .connection
                         .call::<&[u8]>(Method::GET, location.as_str(), None, None, headers, true)
                         .map_err(RegistryError::Request);
                     match resp_ret {
                         Ok(_resp) => {
                             resp = _resp;
-                            self.state
+                            context
+                                .state
                                 .cached_redirect
                                 .set(self.blob_id.clone(), location.as_str().to_string())
                         }
@@ -516,12 +912,19 @@ impl RegistryReader {
 
 impl BlobReader for RegistryReader {
     fn blob_size(&self) -> BackendResult<u64> {
-        let url = self
+        let context = self.context.load_full();
+        let url = context
             .state
             .url(&format!("/blobs/sha256:{}", self.blob_id), &[])
             .map_err(RegistryError::Url)?;
-        let resp =
-            self.request::<&[u8]>(Method::HEAD, url.as_str(), None, HeaderMap::new(), true)?;
+        let resp = self.request::<&[u8]>(
+            &context,
+            Method::HEAD,
+            url.as_str(),
+            None,
+            HeaderMap::new(),
+            true,
+        )?;
         let content_length = resp
             .headers()
             .get(CONTENT_LENGTH)
@@ -535,7 +938,8 @@ impl BlobReader for RegistryReader {
     }
 
     fn try_read(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
-        self._try_read(buf, offset, true)
+        let context = self.context.load_full();
+        self._try_read(&context, buf, offset, true)
             .map_err(BackendError::Registry)
     }
 
@@ -556,29 +960,57 @@ impl BlobReader for RegistryReader {
     }
 
     fn retry_limit(&self) -> u8 {
-        self.state.retry_limit
+        self.context.load().state.retry_limit
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.context.load().connection.retry_policy()
+    }
+
+    fn circuit_breaker(&self) -> Option<Arc<CircuitBreaker>> {
+        self.context.load().connection.circuit_breaker()
     }
 }
 
 /// Storage backend based on image registry.
 pub struct Registry {
-    connection: Arc<Connection>,
-    state: Arc<RegistryState>,
+    context: Arc<ArcSwap<RegistryContext>>,
     metrics: Arc<BackendMetrics>,
 }
 
 impl Registry {
-    #[allow(clippy::useless_let_if_seq)]
     pub fn new(config: serde_json::value::Value, id: Option<&str>) -> Result<Registry> {
         let id = id.ok_or_else(|| einval!("Registry backend requires blob_id"))?;
+        let metrics = BackendMetrics::new(id, "registry");
+        let context = Self::new_context(config, metrics.clone(), None).map_err(|e| {
+            metrics.release().unwrap_or_else(|e| error!("{:?}", e));
+            e
+        })?;
+
+        Ok(Registry {
+            context: Arc::new(ArcSwap::new(Arc::new(context))),
+            metrics,
+        })
+    }
+
+    #[allow(clippy::useless_let_if_seq)]
+    fn new_context(
+        config: serde_json::value::Value,
+        metrics: Arc<BackendMetrics>,
+        previous: Option<&Connection>,
+    ) -> Result<RegistryContext> {
         let common_config: RegistryOssConfig =
             serde_json::from_value(config.clone()).map_err(|e| einval!(e))?;
         let retry_limit = common_config.retry_limit;
-        let connection = Connection::new(&common_config)?;
         let config: RegistryConfig = serde_json::from_value(config).map_err(|e| einval!(e))?;
         let auth = trim(config.auth);
+        let docker_config = if auth.is_none() {
+            config.docker_config
+        } else {
+            String::new()
+        };
+        let credential = RegistryCredential::new(auth, &docker_config, &config.host)?;
         let registry_token = trim(config.registry_token);
-        let (username, password) = Self::get_authorization_info(&auth)?;
         let cached_auth = if let Some(registry_token) = registry_token {
             // Store the registry bearer token to cached_auth, prefer to
             // use the token stored in cached_auth to request registry.
@@ -587,25 +1019,27 @@ impl Registry {
             Cache::new(String::new())
         };
 
-        let state = Arc::new(RegistryState {
+        let state = RegistryState {
             scheme: config.scheme,
             host: config.host,
             repo: config.repo,
-            auth,
+            credential: RwLock::new(credential),
+            docker_config,
+            credential_reloaded_at: Mutex::new(None),
             cached_auth,
-            username,
-            password,
             retry_limit,
             blob_url_scheme: config.blob_url_scheme,
             blob_redirected_host: config.blob_redirected_host,
+            cached_token: RwLock::new(None),
+            refreshing_token: AtomicBool::new(false),
             cached_redirect: HashCache::new(),
-        });
+        };
+        let connection = match previous {
+            Some(connection) => connection.renew(&common_config)?,
+            None => Connection::new(&common_config, Some(metrics))?,
+        };
 
-        Ok(Registry {
-            connection,
-            state,
-            metrics: BackendMetrics::new(id, "registry"),
-        })
+        Ok(RegistryContext { state, connection })
     }
 
     fn get_authorization_info(auth: &Option<String>) -> Result<(String, String)> {
@@ -636,7 +1070,7 @@ impl Registry {
 
 impl BlobBackend for Registry {
     fn shutdown(&self) {
-        self.connection.shutdown();
+        self.context.load().connection.shutdown();
     }
 
     fn metrics(&self) -> &BackendMetrics {
@@ -646,11 +1080,18 @@ impl BlobBackend for Registry {
     fn get_reader(&self, blob_id: &str) -> BackendResult<Arc<dyn BlobReader>> {
         Ok(Arc::new(RegistryReader {
             blob_id: blob_id.to_owned(),
-            state: self.state.clone(),
-            connection: self.connection.clone(),
+            context: self.context.clone(),
             metrics: self.metrics.clone(),
         }))
     }
+
+    fn update_config(&self, config: serde_json::value::Value) -> Result<()> {
+        let previous = self.context.load();
+        let context = Self::new_context(config, self.metrics.clone(), Some(&previous.connection))?;
+        context.state.inherit_token(&previous.state);
+        self.context.store(Arc::new(context));
+        Ok(())
+    }
 }
 
 impl Drop for Registry {
@@ -677,6 +1118,11 @@ fn trim(value: Option<String>) -> Option<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test::mock_http::{start_mock_http_server, MockHttpResponse};
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+    use vmm_sys_util::tempdir::TempDir;
 
     #[test]
     fn test_string_cache() {
@@ -709,13 +1155,20 @@ mod tests {
             scheme: "http".to_string(),
             host: "alibaba-inc.com".to_string(),
             repo: "nydus".to_string(),
-            auth: None,
-            username: "test".to_string(),
-            password: "password".to_string(),
+            credential: RwLock::new(RegistryCredential {
+                auth: None,
+                username: "test".to_string(),
+                password: "password".to_string(),
+                identity_token: None,
+            }),
+            docker_config: String::new(),
+            credential_reloaded_at: Mutex::new(None),
             retry_limit: 5,
             blob_url_scheme: "https".to_string(),
             blob_redirected_host: "oss.alibaba-inc.com".to_string(),
             cached_auth: Default::default(),
+            cached_token: RwLock::new(None),
+            refreshing_token: AtomicBool::new(false),
             cached_redirect: Default::default(),
         };
 
@@ -736,7 +1189,7 @@ mod tests {
     fn test_parse_auth() {
         let str = "Bearer realm=\"https://auth.my-registry.com/token\",service=\"my-registry.com\",scope=\"repository:test/repo:pull,push\"";
         let header = HeaderValue::from_str(str).unwrap();
-        let auth = RegistryState::parse_auth(&header, &None).unwrap();
+        let auth = RegistryState::parse_auth(&header).unwrap();
         match auth {
             Auth::Bearer(auth) => {
                 assert_eq!(&auth.realm, "https://auth.my-registry.com/token");
@@ -748,7 +1201,7 @@ mod tests {
 
         let str = "Basic realm=\"https://auth.my-registry.com/token\"";
         let header = HeaderValue::from_str(str).unwrap();
-        let auth = RegistryState::parse_auth(&header, &None).unwrap();
+        let auth = RegistryState::parse_auth(&header).unwrap();
         match auth {
             Auth::Basic(auth) => assert_eq!(&auth.realm, "https://auth.my-registry.com/token"),
             _ => panic!("failed to pase `Bearer` authentication header"),
@@ -756,7 +1209,215 @@ mod tests {
 
         let str = "Base realm=\"https://auth.my-registry.com/token\"";
         let header = HeaderValue::from_str(str).unwrap();
-        assert!(RegistryState::parse_auth(&header, &None).is_none());
+        assert!(RegistryState::parse_auth(&header).is_none());
+    }
+
+    #[test]
+    fn test_docker_config() {
+        let config: DockerConfig = serde_json::from_str(
+            r#"{"auths":{"https://index.docker.io/v1/":{"auth":"dXNlcjpwYXNz"},
+            "my-registry:5000":{"identitytoken":"identity"},"other":{}}}"#,
+        )
+        .unwrap();
+        assert!(
+            config.credential("registry-1.docker.io").unwrap()
+                == Some(DockerCredential::Auth("dXNlcjpwYXNz".to_string()))
+        );
+        assert!(
+            config.credential("my-registry:5000").unwrap()
+                == Some(DockerCredential::IdentityToken("identity".to_string()))
+        );
+        assert!(config.credential("other").unwrap().is_none());
+        assert!(config.credential("my-registry").unwrap().is_none());
+
+        assert_eq!(
+            docker_server("https://index.docker.io/v1/"),
+            "index.docker.io"
+        );
+        assert_eq!(docker_server("docker.io"), "index.docker.io");
+        assert_eq!(
+            docker_server("http://my-registry:5000/v2"),
+            "my-registry:5000"
+        );
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join("config.json");
+        fs::write(
+            &path,
+            r#"{"auths":{"my-registry":{"auth":"dXNlcjpwYXNz"}}}"#,
+        )
+        .unwrap();
+        let json = serde_json::json!({
+            "host": "my-registry",
+            "repo": "test/repo",
+            "docker_config": path.to_str().unwrap(),
+        });
+        let registry = Registry::new(json.clone(), Some("test-docker-config")).unwrap();
+        let credential = |registry: &Registry| {
+            let context = registry.context.load();
+            let credential = context.state.credential.read().unwrap().clone();
+            (credential.username, credential.password)
+        };
+        assert_eq!(
+            credential(&registry),
+            ("user".to_string(), "pass".to_string())
+        );
+
+        // Pick up rotated credentials from the docker config.
+        fs::write(
+            &path,
+            r#"{"auths":{"my-registry":{"auth":"dXNlcjpwYXNzMg=="}}}"#,
+        )
+        .unwrap();
+        registry.update_config(json).unwrap();
+        assert_eq!(credential(&registry).1, "pass2");
+    }
+
+    #[test]
+    fn test_credential_helper() {
+        let dir = TempDir::new().unwrap();
+        let helper = |name: &str, script: &str| {
+            let path = dir.as_path().join(name);
+            fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let password = helper(
+            "password",
+            r#"read server; echo "{\"ServerURL\":\"$server\",\"Username\":\"user\",\"Secret\":\"$server\"}""#,
+        );
+        let token = helper(
+            "token",
+            r#"echo '{"Username":"<token>","Secret":"identity"}'"#,
+        );
+        let missing = helper(
+            "missing",
+            "echo 'credentials not found in native keychain'; exit 1",
+        );
+        let broken = helper("broken", "echo 'broken' >&2; exit 1");
+
+        assert!(
+            run_credential_helper(&password, "my-registry").unwrap()
+                == Some(DockerCredential::Auth(base64::encode("user:my-registry")))
+        );
+        assert!(
+            run_credential_helper(&token, "my-registry").unwrap()
+                == Some(DockerCredential::IdentityToken("identity".to_string()))
+        );
+        assert!(run_credential_helper(&missing, "my-registry")
+            .unwrap()
+            .is_none());
+        assert!(run_credential_helper(&broken, "my-registry").is_err());
+        assert!(run_credential_helper("docker-credential-nonexistent", "my-registry").is_err());
+    }
+
+    #[test]
+    fn test_token_refresh() {
+        let token_count = Arc::new(AtomicUsize::new(0));
+        let auth_headers = Arc::new(Mutex::new(Vec::new()));
+        let addr = Arc::new(Mutex::new(String::new()));
+        let (count, headers, realm) = (token_count.clone(), auth_headers.clone(), addr.clone());
+        let server = start_mock_http_server(move |req| {
+            if req.path.starts_with("/token") {
+                let token = count.fetch_add(1, Ordering::AcqRel) + 1;
+                let body = format!(r#"{{"token":"token{}","expires_in":2}}"#, token);
+                return MockHttpResponse::new(200).body(body.into_bytes());
+            }
+            match req.headers.get("authorization") {
+                Some(auth) => {
+                    headers.lock().unwrap().push(auth.clone());
+                    MockHttpResponse::new(200).header("Content-Length", "4096")
+                }
+                None => {
+                    let challenge = format!(
+                        r#"Bearer realm="http://{}/token",service="registry",scope="repository:repo:pull""#,
+                        realm.lock().unwrap()
+                    );
+                    MockHttpResponse::new(401).header("WWW-Authenticate", &challenge)
+                }
+            }
+        });
+        *addr.lock().unwrap() = server.clone();
+
+        let json = serde_json::json!({"host": server, "repo": "repo", "scheme": "http"});
+        let registry = Registry::new(json, Some("test-token-refresh")).unwrap();
+        let reader = registry.get_reader("blob").unwrap();
+        assert_eq!(reader.blob_size().unwrap(), 4096);
+        assert_eq!(reader.blob_size().unwrap(), 4096);
+        assert_eq!(token_count.load(Ordering::Acquire), 1);
+
+        // Refresh the token after 80% of its lifetime, before it expires.
+        thread::sleep(Duration::from_millis(1700));
+        assert_eq!(reader.blob_size().unwrap(), 4096);
+        assert_eq!(token_count.load(Ordering::Acquire), 2);
+        assert_eq!(
+            *auth_headers.lock().unwrap(),
+            vec!["Bearer token1", "Bearer token1", "Bearer token2"]
+        );
+        registry.shutdown();
+    }
+
+    #[test]
+    fn test_reload_credential() {
+        let password = Arc::new(Mutex::new("pass1".to_string()));
+        let addr = Arc::new(Mutex::new(String::new()));
+        let (valid, realm) = (password.clone(), addr.clone());
+        let server = start_mock_http_server(move |req| {
+            let valid = valid.lock().unwrap().clone();
+            let auth = req
+                .headers
+                .get("authorization")
+                .cloned()
+                .unwrap_or_default();
+            if req.path.starts_with("/token") {
+                if auth != format!("Basic {}", base64::encode(format!("user:{}", valid))) {
+                    return MockHttpResponse::new(401);
+                }
+                let body = format!(r#"{{"token":"token-{}"}}"#, valid);
+                return MockHttpResponse::new(200).body(body.into_bytes());
+            }
+            if auth == format!("Bearer token-{}", valid) {
+                MockHttpResponse::new(200).header("Content-Length", "4096")
+            } else {
+                let challenge = format!(
+                    r#"Bearer realm="http://{}/token",service="registry",scope="repository:repo:pull""#,
+                    realm.lock().unwrap()
+                );
+                MockHttpResponse::new(401).header("WWW-Authenticate", &challenge)
+            }
+        });
+        *addr.lock().unwrap() = server.clone();
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join("config.json");
+        let rotate = |secret: &str| {
+            let auth = base64::encode(format!("user:{}", secret));
+            let config = format!(r#"{{"auths":{{"{}":{{"auth":"{}"}}}}}}"#, server, auth);
+            fs::write(&path, config).unwrap();
+            *password.lock().unwrap() = secret.to_string();
+        };
+        rotate("pass1");
+        let json = serde_json::json!({
+            "host": server,
+            "repo": "repo",
+            "scheme": "http",
+            "docker_config": path.to_str().unwrap(),
+        });
+        let registry = Registry::new(json, Some("test-reload-credential")).unwrap();
+        let reader = registry.get_reader("blob").unwrap();
+        assert_eq!(reader.blob_size().unwrap(), 4096);
+
+        // Credentials rejected by the registry are got from the docker config again.
+        rotate("pass2");
+        assert_eq!(reader.blob_size().unwrap(), 4096);
+        let context = registry.context.load();
+        assert_eq!(context.state.credential.read().unwrap().password, "pass2");
+
+        // But not more than once in `REGISTRY_TOKEN_REFRESH_RETRY`.
+        rotate("pass3");
+        assert!(reader.blob_size().is_err());
+        assert_eq!(context.state.credential.read().unwrap().password, "pass2");
+        registry.shutdown();
     }
 
     #[test]
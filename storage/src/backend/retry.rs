@@ -0,0 +1,391 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Policies to retry failed backend requests and to fail fast when the backend is unavailable.
+//!
+//! Failed requests are retried with exponential backoff and jitter, so a struggling server won't
+//! be hammered by requests retrying back-to-back or in lockstep. A [CircuitBreaker] per storage
+//! backend counts failures in a row and opens the circuit once reaching the limit, then requests
+//! fail fast instead of waiting for timeouts. After the recovery interval, a single request is
+//! let through to probe the backend, and the circuit closes again if the probe succeeds.
+
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use nydus_api::http::{CircuitBreakerConfig, RegistryOssConfig};
+use nydus_utils::metrics::BackendMetrics;
+use vmm_sys_util::rand::xor_pseudo_rng_u32;
+
+/// Policy to retry failed backend requests.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RetryPolicy {
+    /// Retry count when the request failed.
+    pub limit: u8,
+    /// Delay before the first retry, doubled on each following retry.
+    pub backoff: Duration,
+    /// Maximum delay between retries.
+    pub backoff_max: Duration,
+    /// Stop retrying once the request has taken so long.
+    pub deadline: Option<Duration>,
+}
+
+impl RetryPolicy {
+    /// Create a policy to retry `limit` times without delay.
+    pub fn new(limit: u8) -> Self {
+        RetryPolicy {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    /// Create a policy from the storage backend configuration.
+    pub fn from_config(config: &RegistryOssConfig) -> Self {
+        RetryPolicy {
+            limit: config.retry_limit,
+            backoff: Duration::from_millis(config.retry_backoff_ms),
+            backoff_max: Duration::from_millis(config.retry_backoff_max_ms),
+            deadline: if config.retry_deadline > 0 {
+                Some(Duration::from_secs(config.retry_deadline))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Get the delay before the `retry`-th retry, starting from 0.
+    ///
+    /// Half of the exponential backoff delay is randomized, so concurrent requests failed at the
+    /// same time won't retry at the same time.
+    pub fn backoff(&self, retry: u8) -> Duration {
+        let base = self.backoff.as_millis() as u64;
+        if base == 0 {
+            return Duration::ZERO;
+        }
+        let max = cmp::max(self.backoff_max.as_millis() as u64, base);
+        let delay = cmp::min(base.saturating_mul(1u64 << cmp::min(retry, 32)), max);
+        let half = delay / 2;
+        let jitter = xor_pseudo_rng_u32() as u64 % (delay - half + 1);
+
+        Duration::from_millis(half + jitter)
+    }
+
+    /// Check whether a request started at `begin` may retry after waiting for `delay`.
+    pub fn before_deadline(&self, begin: Instant, delay: Duration) -> bool {
+        match self.deadline {
+            Some(deadline) => begin.elapsed() + delay < deadline,
+            None => true,
+        }
+    }
+}
+
+/// State of a [CircuitBreaker].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CircuitState {
+    /// Requests are sent to the backend.
+    Closed,
+    /// Requests fail fast.
+    Open,
+    /// A probing request is in flight, other requests fail fast.
+    HalfOpen,
+}
+
+impl CircuitState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CircuitStatus {
+    state: CircuitState,
+    failures: u32,
+    opened_at: Instant,
+}
+
+/// Circuit breaker to fail requests fast while a storage backend is known to be unavailable.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_limit: u32,
+    recovery_interval: Duration,
+    status: Mutex<CircuitStatus>,
+    metrics: Option<Arc<BackendMetrics>>,
+}
+
+impl CircuitBreaker {
+    /// Create a circuit breaker opening after `failure_limit` failures in a row.
+    pub fn new(
+        failure_limit: u32,
+        recovery_interval: Duration,
+        metrics: Option<Arc<BackendMetrics>>,
+    ) -> Self {
+        if let Some(metrics) = metrics.as_ref() {
+            metrics.set_circuit_state(CircuitState::Closed.as_str());
+        }
+
+        CircuitBreaker {
+            failure_limit: cmp::max(failure_limit, 1),
+            recovery_interval,
+            status: Mutex::new(CircuitStatus {
+                state: CircuitState::Closed,
+                failures: 0,
+                opened_at: Instant::now(),
+            }),
+            metrics,
+        }
+    }
+
+    /// Create a circuit breaker from the configuration, return `None` if it's disabled.
+    pub fn from_config(
+        config: &CircuitBreakerConfig,
+        metrics: Option<Arc<BackendMetrics>>,
+    ) -> Option<Arc<Self>> {
+        if config.enable {
+            Some(Arc::new(Self::new(
+                config.failure_limit as u32,
+                Duration::from_secs(config.recovery_interval),
+                metrics,
+            )))
+        } else {
+            if let Some(metrics) = metrics.as_ref() {
+                metrics.set_circuit_state("disabled");
+            }
+            None
+        }
+    }
+
+    /// Get current state of the circuit.
+    pub fn state(&self) -> CircuitState {
+        self.status.lock().unwrap().state
+    }
+
+    /// Check whether a request may be sent to the backend.
+    ///
+    /// Once the recovery interval elapses, the circuit becomes half open and the caller is
+    /// responsible for probing the backend, and reporting the result by `succeed()` or `fail()`.
+    pub fn allow(&self) -> bool {
+        let mut status = self.status.lock().unwrap();
+        match status.state {
+            CircuitState::Closed => true,
+            CircuitState::Open if status.opened_at.elapsed() >= self.recovery_interval => {
+                self.transit(&mut status, CircuitState::HalfOpen);
+                true
+            }
+            _ => {
+                if let Some(metrics) = self.metrics.as_ref() {
+                    metrics.circuit_rejected();
+                }
+                false
+            }
+        }
+    }
+
+    /// Report a successful request.
+    pub fn succeed(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.failures = 0;
+        if status.state != CircuitState::Closed {
+            info!("Storage backend recovered, close the circuit");
+            self.transit(&mut status, CircuitState::Closed);
+        }
+    }
+
+    /// Report a failed request.
+    pub fn fail(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.failures = status.failures.saturating_add(1);
+        let trip = match status.state {
+            CircuitState::Closed => status.failures >= self.failure_limit,
+            CircuitState::HalfOpen => true,
+            CircuitState::Open => false,
+        };
+        if trip {
+            warn!(
+                "Storage backend failed {} times in a row, open the circuit",
+                status.failures
+            );
+            status.opened_at = Instant::now();
+            self.transit(&mut status, CircuitState::Open);
+            if let Some(metrics) = self.metrics.as_ref() {
+                metrics.circuit_opened();
+            }
+        }
+    }
+
+    fn transit(&self, status: &mut CircuitStatus, state: CircuitState) {
+        status.state = state;
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.set_circuit_state(state.as_str());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{BackendError, BackendResult, BlobReader};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_retry_backoff() {
+        let policy = RetryPolicy::new(3);
+        assert_eq!(policy.backoff(0), Duration::ZERO);
+        assert!(policy.before_deadline(Instant::now(), Duration::from_secs(3600)));
+
+        let config = RegistryOssConfig {
+            retry_limit: 5,
+            retry_backoff_ms: 100,
+            retry_backoff_max_ms: 1000,
+            retry_deadline: 1,
+            ..Default::default()
+        };
+        let policy = RetryPolicy::from_config(&config);
+        assert_eq!(policy.limit, 5);
+        for (retry, delay) in [(0, 100), (1, 200), (3, 800), (4, 1000), (200, 1000)] {
+            let backoff = policy.backoff(retry);
+            assert!(backoff >= Duration::from_millis(delay / 2));
+            assert!(backoff <= Duration::from_millis(delay));
+        }
+        assert!(policy.before_deadline(Instant::now(), Duration::from_millis(500)));
+        assert!(!policy.before_deadline(Instant::now(), Duration::from_millis(1000)));
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let metrics = BackendMetrics::new("test-circuit-breaker", "test");
+        let config = CircuitBreakerConfig::default();
+        assert!(CircuitBreaker::from_config(&config, Some(metrics.clone())).is_none());
+        assert_eq!(metrics.circuit_state(), "disabled");
+
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(100), Some(metrics.clone()));
+        assert_eq!(metrics.circuit_state(), "closed");
+        breaker.fail();
+        breaker.succeed();
+        breaker.fail();
+        assert!(breaker.allow());
+        breaker.fail();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(metrics.circuit_state(), "open");
+        assert!(!breaker.allow());
+        assert_eq!(metrics.circuit_rejected_count(), 1);
+
+        // Only one request is allowed to probe the backend after the recovery interval.
+        thread::sleep(Duration::from_millis(150));
+        assert!(breaker.allow());
+        assert_eq!(metrics.circuit_state(), "half_open");
+        assert!(!breaker.allow());
+        breaker.fail();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allow());
+
+        thread::sleep(Duration::from_millis(150));
+        assert!(breaker.allow());
+        breaker.succeed();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_eq!(metrics.circuit_state(), "closed");
+        assert!(breaker.allow());
+
+        metrics.release().unwrap();
+    }
+
+    struct FlakyReader {
+        failures: usize,
+        count: AtomicUsize,
+        policy: RetryPolicy,
+        breaker: Option<Arc<CircuitBreaker>>,
+        metrics: Arc<BackendMetrics>,
+    }
+
+    impl BlobReader for FlakyReader {
+        fn blob_size(&self) -> BackendResult<u64> {
+            Ok(0x1000)
+        }
+
+        fn try_read(&self, buf: &mut [u8], _offset: u64) -> BackendResult<usize> {
+            if self.count.fetch_add(1, Ordering::Relaxed) < self.failures {
+                Err(BackendError::Unsupported("flaky".to_string()))
+            } else {
+                Ok(buf.len())
+            }
+        }
+
+        fn prefetch_blob_data_range(&self, _ra_offset: u64, _ra_size: u64) -> BackendResult<()> {
+            Ok(())
+        }
+
+        fn stop_data_prefetch(&self) -> BackendResult<()> {
+            Ok(())
+        }
+
+        fn metrics(&self) -> &BackendMetrics {
+            &self.metrics
+        }
+
+        fn retry_policy(&self) -> RetryPolicy {
+            self.policy
+        }
+
+        fn circuit_breaker(&self) -> Option<Arc<CircuitBreaker>> {
+            self.breaker.clone()
+        }
+    }
+
+    #[test]
+    fn test_read_with_retry() {
+        let metrics = BackendMetrics::new("test-read-with-retry", "test");
+        let mut reader = FlakyReader {
+            failures: 2,
+            count: AtomicUsize::new(0),
+            policy: RetryPolicy {
+                limit: 2,
+                backoff: Duration::from_millis(20),
+                backoff_max: Duration::from_millis(100),
+                deadline: None,
+            },
+            breaker: None,
+            metrics: metrics.clone(),
+        };
+        let mut buf = vec![0u8; 0x100];
+        let begin = Instant::now();
+        assert_eq!(reader.read(&mut buf, 0).unwrap(), 0x100);
+        assert_eq!(reader.count.load(Ordering::Relaxed), 3);
+        assert!(begin.elapsed() >= Duration::from_millis(30));
+
+        // Give up once the deadline is reached.
+        reader.count.store(0, Ordering::Relaxed);
+        reader.policy.backoff = Duration::from_millis(2000);
+        reader.policy.backoff_max = Duration::from_millis(2000);
+        reader.policy.deadline = Some(Duration::from_millis(500));
+        assert!(reader.read(&mut buf, 0).is_err());
+        assert_eq!(reader.count.load(Ordering::Relaxed), 1);
+
+        // Fail fast once the circuit is open, each failed request counts once after retries.
+        reader.count.store(0, Ordering::Relaxed);
+        reader.failures = 100;
+        reader.policy.backoff = Duration::from_millis(1);
+        reader.policy.backoff_max = Duration::from_millis(1);
+        reader.policy.deadline = None;
+        reader.breaker = Some(Arc::new(CircuitBreaker::new(
+            3,
+            Duration::from_secs(3600),
+            Some(metrics.clone()),
+        )));
+        for _ in 0..3 {
+            assert!(reader.read(&mut buf, 0).is_err());
+        }
+        assert_eq!(reader.count.load(Ordering::Relaxed), 9);
+        assert!(matches!(
+            reader.read(&mut buf, 0),
+            Err(BackendError::CircuitOpen)
+        ));
+        assert_eq!(reader.count.load(Ordering::Relaxed), 9);
+
+        metrics.release().unwrap();
+    }
+}
@@ -9,22 +9,30 @@
 //! performance. It may be used by both the userspace `FileCacheMgr` or the `FsCacheMgr` based
 //! on the in-kernel fscache system.
 
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::{ErrorKind, Result, Seek, SeekFrom};
 use std::mem::ManuallyDrop;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::slice;
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 
 use fuse_backend_rs::transport::FileVolatileSlice;
+use nix::fcntl::{fallocate, FallocateFlags};
 use nix::sys::uio;
 use nix::unistd::dup;
 use nydus_utils::metrics::{BlobcacheMetrics, Metric};
-use nydus_utils::{compress, digest};
+use nydus_utils::slowio::{IoStage, StageTimer};
+use nydus_utils::{compress, digest, round_down_4k, round_up};
 use tokio::runtime::Runtime;
 
 use crate::backend::BlobReader;
+use crate::cache::filecache::ChunkRefMap;
+use crate::cache::hotchunk::HOT_CHUNK_CACHE;
+use crate::cache::singleflight::SingleFlight;
 use crate::cache::state::ChunkMap;
 use crate::cache::worker::{AsyncPrefetchConfig, AsyncPrefetchMessage, AsyncWorkerMgr};
 use crate::cache::{BlobCache, BlobIoMergeState};
@@ -33,18 +41,195 @@ use crate::device::{
     BlobIoVec, BlobObject, BlobPrefetchRequest,
 };
 use crate::meta::{BlobMetaChunk, BlobMetaInfo};
-use crate::utils::{alloc_buf, copyv, readv, MemSliceCursor};
+use crate::utils::{alloc_buf, copyv, digest_check, readv, MemSliceCursor};
 use crate::{StorageError, StorageResult, RAFS_DEFAULT_CHUNK_SIZE};
 
+/// Lightweight record of last access time for chunks, to support reclaiming cold chunks.
+pub(crate) struct ChunkAccessRecord {
+    base: Instant,
+    // Last access time of each chunk, in seconds since `base`.
+    atime: Vec<AtomicU32>,
+    // User IO holds the read lock and reclamation holds the write lock, to avoid reading data
+    // from holes just punched.
+    lock: RwLock<()>,
+}
+
+impl ChunkAccessRecord {
+    pub fn new(chunk_count: u32) -> Self {
+        let mut atime = Vec::with_capacity(chunk_count as usize);
+        atime.resize_with(chunk_count as usize, || AtomicU32::new(0));
+
+        ChunkAccessRecord {
+            base: Instant::now(),
+            atime,
+            lock: RwLock::new(()),
+        }
+    }
+
+    fn now(&self) -> u32 {
+        std::cmp::min(self.base.elapsed().as_secs(), u32::MAX as u64) as u32
+    }
+
+    fn touch(&self, index: u32) {
+        if let Some(v) = self.atime.get(index as usize) {
+            v.store(self.now(), Ordering::Relaxed);
+        }
+    }
+
+    fn is_cold(&self, index: u32, threshold: u64) -> bool {
+        match self.atime.get(index as usize) {
+            Some(v) => {
+                let atime = v.load(Ordering::Relaxed);
+                (self.now().saturating_sub(atime) as u64) >= threshold
+            }
+            None => false,
+        }
+    }
+}
+
+/// Chunk information with digests seen in user IO, to support verifying cached chunks.
+///
+/// The storage subsystem can't get chunk digests by itself, so chunk information objects carrying
+/// digests are provided by the filesystem with the bootstrap, and also recorded when they are
+/// accessed for the first time.
+pub(crate) struct ChunkInfoRecord {
+    chunks: RwLock<Vec<Option<BlobIoChunk>>>,
+}
+
+impl ChunkInfoRecord {
+    pub fn new(chunk_count: u32) -> Self {
+        let mut chunks = Vec::with_capacity(chunk_count as usize);
+        chunks.resize_with(chunk_count as usize, || None);
+
+        ChunkInfoRecord {
+            chunks: RwLock::new(chunks),
+        }
+    }
+
+    fn record(&self, chunk: &BlobIoChunk) {
+        // Only chunk information objects from Rafs v5 inodes or the Rafs v6 chunk table carry
+        // digests.
+        if let BlobIoChunk::V5(_) = chunk {
+            let index = chunk.id() as usize;
+            if matches!(self.chunks.read().unwrap().get(index), Some(None)) {
+                if let Some(v) = self.chunks.write().unwrap().get_mut(index) {
+                    *v = Some(chunk.clone());
+                }
+            }
+        }
+    }
+
+    fn get(&self, index: u32) -> Option<BlobIoChunk> {
+        self.chunks
+            .read()
+            .unwrap()
+            .get(index as usize)
+            .cloned()
+            .flatten()
+    }
+}
+
+#[derive(Default)]
+struct ChunkLruState {
+    // Decompressed data of cached chunks and their sequence number of last access.
+    chunks: HashMap<u32, (u64, Arc<Vec<u8>>)>,
+    // Cached chunks ordered by last access, from the least recently used one.
+    lru: BTreeMap<u64, u32>,
+    seq: u64,
+    size: u64,
+}
+
+/// Size bounded LRU cache of decompressed chunks, to avoid decompressing hot chunks from the
+/// compressed cache file again and again.
+pub(crate) struct ChunkLru {
+    capacity: u64,
+    state: Mutex<ChunkLruState>,
+}
+
+impl ChunkLru {
+    pub fn new(capacity: u64) -> Self {
+        ChunkLru {
+            capacity,
+            state: Mutex::new(ChunkLruState::default()),
+        }
+    }
+
+    fn get(&self, index: u32) -> Option<Arc<Vec<u8>>> {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        state.seq += 1;
+        let (seq, data) = state.chunks.get_mut(&index)?;
+        state.lru.remove(seq);
+        state.lru.insert(state.seq, index);
+        *seq = state.seq;
+
+        Some(data.clone())
+    }
+
+    // Check whether chunk `index` is cached, without accounting an access.
+    #[cfg(test)]
+    pub(crate) fn contains(&self, index: u32) -> bool {
+        self.state.lock().unwrap().chunks.contains_key(&index)
+    }
+
+    fn remove(&self, index: u32) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        if let Some((seq, data)) = state.chunks.remove(&index) {
+            state.lru.remove(&seq);
+            state.size -= data.len() as u64;
+        }
+    }
+
+    fn put(&self, index: u32, data: Arc<Vec<u8>>) {
+        let size = data.len() as u64;
+        if size > self.capacity {
+            return;
+        }
+
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        state.seq += 1;
+        if let Some((seq, old)) = state.chunks.insert(index, (state.seq, data)) {
+            state.lru.remove(&seq);
+            state.size -= old.len() as u64;
+        }
+        state.lru.insert(state.seq, index);
+        state.size += size;
+        while state.size > self.capacity {
+            let (seq, idx) = match state.lru.iter().next() {
+                Some((seq, idx)) => (*seq, *idx),
+                None => break,
+            };
+            state.lru.remove(&seq);
+            if let Some((_, v)) = state.chunks.remove(&idx) {
+                state.size -= v.len() as u64;
+            }
+        }
+    }
+}
+
 pub(crate) struct FileCacheEntry {
     pub(crate) blob_info: Arc<BlobInfo>,
     pub(crate) chunk_map: Arc<dyn ChunkMap>,
+    // Access time of chunks, only available when reclaiming cold chunks is enabled.
+    pub(crate) chunk_access: Option<ChunkAccessRecord>,
+    // Chunks with digests seen in user IO, only available when scrubbing is enabled.
+    pub(crate) chunk_infos: Option<ChunkInfoRecord>,
+    // Decompressed chunks, only available when caching compressed data with chunk LRU enabled.
+    pub(crate) chunk_lru: Option<ChunkLru>,
+    // References to chunks in the shared chunk store, only available when deduplicating chunks.
+    pub(crate) chunk_refs: Option<ChunkRefMap>,
+    // Whether a checkpoint of the chunk map is in progress.
+    pub(crate) checkpointing: Arc<AtomicBool>,
     pub(crate) file: Arc<File>,
     pub(crate) meta: Option<Arc<BlobMetaInfo>>,
     pub(crate) metrics: Arc<BlobcacheMetrics>,
     pub(crate) prefetch_state: Arc<AtomicU32>,
     pub(crate) reader: Arc<dyn BlobReader>,
     pub(crate) runtime: Arc<Runtime>,
+    // Coalesce concurrent backend reads of overlapping ranges.
+    pub(crate) single_flight: SingleFlight,
     pub(crate) workers: Arc<AsyncWorkerMgr>,
 
     pub(crate) blob_size: u64,
@@ -76,6 +261,181 @@ impl FileCacheEntry {
 
         Ok(size)
     }
+
+    /// Punch holes in the cache file for ready chunks not accessed in the last `threshold` seconds.
+    ///
+    /// The chunks will be marked as not ready, so they will be fetched from the backend again on
+    /// next access. Return number of reclaimed chunks and size of the punched holes.
+    pub(crate) fn reclaim_cold_chunks(&self, threshold: u64) -> Result<(u32, u64)> {
+        let (record, meta) = match (self.chunk_access.as_ref(), self.meta.as_ref()) {
+            (Some(r), Some(m)) => (r, m),
+            _ => return Ok((0, 0)),
+        };
+        let mut count = 0;
+        let mut size = 0;
+
+        for index in 0..self.blob_info.chunk_count() {
+            if !record.is_cold(index, threshold) {
+                continue;
+            }
+            let chunk = BlobMetaChunk::new(index as usize, &meta.state);
+            if !self.chunk_map.is_ready(chunk.as_base())? {
+                continue;
+            }
+
+            let _guard = record.lock.write().unwrap();
+            // Check again in case the chunk has just been accessed.
+            if !record.is_cold(index, threshold) || !self.chunk_map.clear_ready(chunk.as_base())? {
+                continue;
+            }
+            let (offset, len) = if self.is_compressed {
+                (chunk.compress_offset(), chunk.compress_size())
+            } else {
+                (chunk.uncompress_offset(), chunk.uncompress_size())
+            };
+            size += self.punch_hole(offset, len as u64)?;
+            count += 1;
+        }
+
+        if count > 0 {
+            self.metrics.reclaimed_chunks.add(count as u64);
+            self.metrics.reclaimed_size.add(size);
+            debug!(
+                "filecache: reclaim {} cold chunks, {} bytes from blob {}",
+                count,
+                size,
+                self.blob_info.blob_id()
+            );
+        }
+
+        Ok((count, size))
+    }
+
+    /// Verify data of ready chunks in the cache file, and drop corrupted chunks from the cache so
+    /// they will be fetched from the backend again.
+    ///
+    /// Chunks with known digests are verified by digest, other chunks cached in compressed form
+    /// are verified by decompression, and the remaining chunks are skipped. `throttle` is called
+    /// with the amount of data to read before verifying each chunk, and scrubbing stops if it
+    /// returns false.
+    pub(crate) fn scrub(&self, throttle: &dyn Fn(u64) -> bool) -> Result<()> {
+        if self.is_stargz {
+            return Ok(());
+        }
+        let report = &self.metrics.scrub;
+        // Chunks without digests are located by the chunk information array, which works only if
+        // the chunk map is indexed by chunk index and validating chunk digest isn't required.
+        let meta = self.meta.as_ref().filter(|_| {
+            self.is_direct_chunkmap && self.chunk_refs.is_none() && !self.need_validate
+        });
+
+        for index in 0..self.blob_info.chunk_count() {
+            let chunk = match (self.chunk_infos.as_ref().and_then(|r| r.get(index)), meta) {
+                (Some(chunk), _) => chunk,
+                (None, Some(meta)) => {
+                    BlobIoChunk::Base(Arc::new(BlobMetaChunk::new(index as usize, &meta.state)))
+                }
+                (None, None) => continue,
+            };
+            let has_digest = matches!(chunk, BlobIoChunk::V5(_));
+            if !self.chunk_map.is_ready(chunk.as_base())? {
+                continue;
+            }
+            if !has_digest && !self.is_compressed {
+                report.skipped_chunks.inc();
+                continue;
+            }
+
+            let size = if self.is_compressed {
+                chunk.compress_size()
+            } else {
+                chunk.uncompress_size()
+            } as u64;
+            if !throttle(size) {
+                break;
+            }
+            report.scanned_chunks.inc();
+            report.scanned_size.add(size);
+            if self.verify_cached_chunk(&chunk, has_digest) {
+                continue;
+            }
+
+            let repaired = self.drop_corrupted_chunk(&chunk).unwrap_or_else(|e| {
+                warn!(
+                    "filecache: failed to drop corrupted chunk {} of blob {}, {}",
+                    index,
+                    self.blob_info.blob_id(),
+                    e
+                );
+                false
+            });
+            warn!(
+                "filecache: chunk {} of blob {} is corrupted, repaired {}",
+                index,
+                self.blob_info.blob_id(),
+                repaired
+            );
+            report.record_corruption(self.blob_info.blob_id(), index, repaired);
+        }
+
+        Ok(())
+    }
+
+    // Check whether data of the ready chunk in the cache file is intact.
+    fn verify_cached_chunk(&self, chunk: &BlobIoChunk, has_digest: bool) -> bool {
+        // Avoid reading data from holes just punched for cold chunks.
+        let _guard = self.chunk_access.as_ref().map(|r| r.lock.read().unwrap());
+        let mut buf = alloc_buf(chunk.uncompress_size() as usize);
+        match self.read_file_cache(chunk, &mut buf) {
+            Ok(()) => !has_digest || digest_check(&buf, chunk.chunk_id(), self.digester),
+            Err(e) => {
+                debug!("filecache: failed to read chunk {}, {}", chunk.id(), e);
+                false
+            }
+        }
+    }
+
+    // Drop the corrupted chunk from the cache, return true if it will be fetched again.
+    fn drop_corrupted_chunk(&self, chunk: &BlobIoChunk) -> Result<bool> {
+        if let Some(refs) = self.chunk_refs.as_ref() {
+            refs.invalidate(chunk.as_base())?;
+        }
+        HOT_CHUNK_CACHE.remove(self.blob_info.blob_id(), chunk.id());
+        if let Some(lru) = self.chunk_lru.as_ref() {
+            lru.remove(chunk.id());
+        }
+
+        if !self.chunk_map.clear_ready(chunk.as_base())? {
+            return Ok(false);
+        }
+        // The kernel reads data cached by fscache directly and only asks for data in holes. Chunks
+        // of Rafs v6 blobs start at block boundary, so the hole covers the padding after the chunk.
+        if self.blob_info.get_fscache_file().is_some() {
+            let len = round_up(chunk.uncompress_size() as u64, 0x1000);
+            self.punch_hole(chunk.uncompress_offset(), len)?;
+        }
+
+        Ok(true)
+    }
+
+    // Punch a hole in the cache file for data in range `[offset, offset + len)`, rounded inward to
+    // avoid zeroing data of neighboring chunks sharing the same block. Return size of the hole.
+    fn punch_hole(&self, offset: u64, len: u64) -> Result<u64> {
+        let start = round_up(offset, 0x1000);
+        let end = round_down_4k(offset + len);
+        if end <= start {
+            return Ok(0);
+        }
+        fallocate(
+            self.file.as_raw_fd(),
+            FallocateFlags::FALLOC_FL_PUNCH_HOLE | FallocateFlags::FALLOC_FL_KEEP_SIZE,
+            start as i64,
+            (end - start) as i64,
+        )
+        .map_err(|e| eio!(format!("failed to punch hole in cache file, {}", e)))?;
+
+        Ok(end - start)
+    }
 }
 
 impl AsRawFd for FileCacheEntry {
@@ -113,6 +473,11 @@ impl BlobCache for FileCacheEntry {
         &*self.reader
     }
 
+    fn read_backend(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.single_flight
+            .read(&*self.reader, buf, offset, &self.metrics)
+    }
+
     fn get_chunk_map(&self) -> &Arc<dyn ChunkMap> {
         &self.chunk_map
     }
@@ -199,6 +564,18 @@ impl BlobCache for FileCacheEntry {
         }
     }
 
+    fn need_chunk_infos(&self) -> bool {
+        self.chunk_infos.is_some()
+    }
+
+    fn record_chunk_infos(&self, bios: &[BlobIoDesc]) {
+        if let Some(record) = self.chunk_infos.as_ref() {
+            for b in bios.iter() {
+                record.record(&b.chunkinfo);
+            }
+        }
+    }
+
     fn prefetch_range(&self, range: &BlobIoRange) -> Result<usize> {
         let mut pending = Vec::with_capacity(range.chunks.len());
         if !self.chunk_map.is_persist() {
@@ -217,7 +594,7 @@ impl BlobCache for FileCacheEntry {
                 // For digested chunk map, we must check whether the cached data is valid because
                 // the digested chunk map cannot persist readiness state.
                 let d_size = c.uncompress_size() as usize;
-                match self.read_raw_chunk(c, &mut buf[0..d_size], true, None) {
+                match self.read_file_cache(c, &mut buf[0..d_size]) {
                     Ok(_v) => {
                         // The cached data is valid, set the chunk as ready.
                         let _ = self
@@ -252,18 +629,33 @@ impl BlobCache for FileCacheEntry {
 
             // Find a range with continuous chunk id
             let blob_offset = pending[start].compress_offset();
-            let blob_end = pending[end].compress_offset() + pending[end].compress_size() as u64;
+            let blob_end =
+                pending[end - 1].compress_offset() + pending[end - 1].compress_size() as u64;
             let blob_size = (blob_end - blob_offset) as usize;
-            match self.read_chunks(blob_offset, blob_size, &pending[start..end]) {
-                Ok(v) => {
+            match self.read_chunks_for_cache(blob_offset, blob_size, &pending[start..end]) {
+                Ok((_, Some(raw))) => {
                     total_size += blob_size;
-                    for idx in start..end {
-                        let offset = if self.is_compressed {
-                            pending[idx].compress_offset()
+                    // Compressed chunks are continuous in the cache file too, persist them at once.
+                    let ret = Self::persist_chunk(&self.file, blob_offset, &raw);
+                    for chunk in pending.iter().take(end).skip(start) {
+                        if ret.is_ok() {
+                            let _ = self.chunk_map.set_ready_and_clear_pending(chunk);
                         } else {
-                            pending[idx].uncompress_offset()
+                            self.chunk_map.clear_pending(chunk);
+                        }
+                    }
+                }
+                Ok((v, None)) => {
+                    total_size += blob_size;
+                    for idx in start..end {
+                        let ret = match self.chunk_refs.as_ref() {
+                            Some(refs) => refs.persist(pending[idx].as_base(), &v[idx - start]),
+                            None => {
+                                let offset = pending[idx].uncompress_offset();
+                                Self::persist_chunk(&self.file, offset, &v[idx - start])
+                            }
                         };
-                        match Self::persist_chunk(&self.file, offset, &v[idx - start]) {
+                        match ret {
                             Ok(_) => {
                                 let _ = self.chunk_map.set_ready_and_clear_pending(&pending[idx]);
                             }
@@ -281,6 +673,8 @@ impl BlobCache for FileCacheEntry {
             start = end;
         }
 
+        self.schedule_checkpoint();
+
         Ok(total_size)
     }
 
@@ -288,7 +682,13 @@ impl BlobCache for FileCacheEntry {
         debug_assert!(iovec.validate());
         self.metrics.total.inc();
         self.workers.consume_prefetch_budget(buffers);
+        let _guard = self.chunk_access.as_ref().map(|r| r.lock.read().unwrap());
 
+        if let Some(record) = self.chunk_infos.as_ref() {
+            for b in iovec.bi_vec.iter() {
+                record.record(&b.chunkinfo);
+            }
+        }
         if let Some(ref chunks_meta) = self.meta {
             // TODO: the first blob backend io triggers chunks array download.
             // Convert `BlocIoChunk::Address` to `BlobIoChunk::Base`.
@@ -300,7 +700,7 @@ impl BlobCache for FileCacheEntry {
             }
         }
 
-        if iovec.bi_vec.is_empty() {
+        let ret = if iovec.bi_vec.is_empty() {
             Ok(0)
         } else if iovec.bi_vec.len() == 1 {
             let mut state = FileIoMergeState::new();
@@ -310,7 +710,10 @@ impl BlobCache for FileCacheEntry {
             self.dispatch_one_range(&req, &mut cursor, &mut state)
         } else {
             self.read_iter(&mut iovec.bi_vec, buffers)
-        }
+        };
+        self.schedule_checkpoint();
+
+        ret
     }
 }
 
@@ -388,7 +791,7 @@ impl FileCacheEntry {
                 chunks[end_idx].compress_offset() + chunks[end_idx].compress_size() as u64;
             let blob_size = (blob_end - blob_offset) as usize;
 
-            match self.read_chunks(blob_offset, blob_size, &chunks[start_idx..=end_idx]) {
+            match self.read_chunks(blob_offset, blob_size, &chunks[start_idx..=end_idx], None) {
                 Ok(mut v) => {
                     total_size += blob_size;
                     trace!(
@@ -425,6 +828,7 @@ impl FileCacheEntry {
 
             start = end;
         }
+        self.schedule_checkpoint();
 
         if !bitmap.wait_for_range_ready(chunk_index, count)? {
             Err(eio!("failed to read data from storage backend"))
@@ -433,6 +837,24 @@ impl FileCacheEntry {
         }
     }
 
+    // Take a checkpoint of the chunk map in background, keeping syncs out of IO paths.
+    fn schedule_checkpoint(&self) {
+        if self.chunk_map.need_checkpoint() && !self.checkpointing.swap(true, Ordering::AcqRel) {
+            let chunk_map = self.chunk_map.clone();
+            let checkpointing = self.checkpointing.clone();
+            self.runtime.spawn_blocking(move || {
+                Self::checkpoint_chunk_map(chunk_map.as_ref(), &checkpointing);
+            });
+        }
+    }
+
+    fn checkpoint_chunk_map(chunk_map: &dyn ChunkMap, checkpointing: &AtomicBool) {
+        if let Err(e) = chunk_map.checkpoint() {
+            warn!("failed to checkpoint chunk map, {}", e);
+        }
+        checkpointing.store(false, Ordering::Release);
+    }
+
     fn adjust_buffer_for_dio(&self, buf: &mut Vec<u8>) {
         debug_assert!(buf.capacity() % 0x1000 == 0);
         if buf.len() != buf.capacity() {
@@ -479,18 +901,38 @@ impl FileCacheEntry {
 
         trace!("dispatch single io range {:?}", req);
         for (i, chunk) in req.chunks.iter().enumerate() {
+            if let Some(record) = self.chunk_access.as_ref() {
+                record.touch(chunk.id());
+            }
+            // Serve user IO from in-memory chunk data if possible. The chunk data is carried by
+            // the region, so it won't be affected by eviction before dispatching.
+            if req.tags[i].is_user_io() {
+                if let Some(data) = self.get_chunk_in_memory(chunk) {
+                    state.push_memory(
+                        chunk.uncompress_offset(),
+                        chunk.uncompress_size(),
+                        req.tags[i].clone(),
+                        chunk.clone(),
+                        data,
+                    )?;
+                    continue;
+                }
+            }
+            let timer = StageTimer::new(IoStage::ChunkMap);
             let is_ready = match self.chunk_map.check_ready_and_mark_pending(chunk.as_base()) {
                 Ok(true) => true,
                 Ok(false) => false,
                 Err(StorageError::Timeout) => false, // Retry if waiting for inflight IO timeouts
                 Err(e) => return Err(einval!(e)),
             };
+            drop(timer);
 
             // Directly read data from the file cache into the user buffer iff:
             // - the chunk is ready in the file cache
             // - the data in the file cache is uncompressed.
             // - data validation is disabled
-            if is_ready && !self.is_compressed && !self.need_validate {
+            // - the chunk isn't stored in the chunk store, which isn't indexed by blob address.
+            if is_ready && !self.is_compressed && !self.need_validate && self.chunk_refs.is_none() {
                 // Internal IO should not be committed to local cache region, just
                 // commit this region without pushing any chunk to avoid discontinuous
                 // chunks in a region.
@@ -551,12 +993,36 @@ impl FileCacheEntry {
                 CacheFast => self.dispatch_cache_fast(cursor, r)?,
                 CacheSlow => self.dispatch_cache_slow(cursor, r)?,
                 Backend => self.dispatch_backend(cursor, r)?,
+                Memory => self.dispatch_memory(cursor, r)?,
             }
         }
 
         Ok(total_read)
     }
 
+    // Copy data of hot chunks, fetched from the hot chunk cache when dispatching the request,
+    // into the user memory buffer.
+    fn dispatch_memory(&self, mem_cursor: &mut MemSliceCursor, region: &Region) -> Result<usize> {
+        self.metrics.whole_hits.add(region.data.len() as u64);
+        let buffers: Vec<&[u8]> = region.data.iter().map(|v| v.as_slice()).collect();
+        let total_read = copyv(
+            &buffers,
+            mem_cursor.mem_slice,
+            region.seg.offset as usize,
+            region.seg.len as usize,
+            mem_cursor.index,
+            mem_cursor.offset,
+        )
+        .map(|(n, _)| n)
+        .map_err(|e| {
+            error!("failed to copy from chunk buf to buf: {:?}", e);
+            eio!(e)
+        })?;
+        mem_cursor.move_cursor(total_read);
+
+        Ok(total_read)
+    }
+
     // Directly read data requested by user from the file cache into the user memory buffer.
     fn dispatch_cache_fast(&self, cursor: &mut MemSliceCursor, region: &Region) -> Result<usize> {
         let offset = region.blob_address + region.seg.offset as u64;
@@ -564,6 +1030,7 @@ impl FileCacheEntry {
         let iovec = cursor.consume(size);
 
         self.metrics.partial_hits.inc();
+        let _timer = StageTimer::new(IoStage::CacheRead);
         readv(self.file.as_raw_fd(), &iovec, offset)
     }
 
@@ -595,17 +1062,27 @@ impl FileCacheEntry {
 
         let blob_size = region.blob_len as usize;
         debug!("total backend data {}KB", blob_size / 1024);
-        let mut chunks = self.read_chunks(region.blob_address, blob_size, &region.chunks)?;
+        let (mut chunks, raw) =
+            self.read_chunks_for_cache(region.blob_address, blob_size, &region.chunks)?;
         assert_eq!(region.chunks.len(), chunks.len());
+        if let Some(raw) = raw {
+            let d = Arc::new(DataBuffer::Allocated(raw));
+            self.delay_persist(region.chunks.clone(), region.blob_address, d);
+        }
 
         let mut chunk_buffers = Vec::with_capacity(region.chunks.len());
         let mut buffer_holder = Vec::with_capacity(region.chunks.len());
         for (i, v) in chunks.drain(..).enumerate() {
             let d = Arc::new(DataBuffer::Allocated(v));
             if region.tags[i] {
+                self.cache_chunk_in_memory(&region.chunks[i], d.slice());
                 buffer_holder.push(d.clone());
             }
-            self.delay_persist(region.chunks[i].clone(), d);
+            if !self.is_compressed {
+                let chunk = region.chunks[i].clone();
+                let offset = chunk.uncompress_offset();
+                self.delay_persist(vec![chunk], offset, d);
+            }
         }
         for d in buffer_holder.iter() {
             chunk_buffers.push(d.as_ref().slice());
@@ -629,36 +1106,47 @@ impl FileCacheEntry {
         Ok(total_read)
     }
 
-    fn delay_persist(&self, chunk_info: BlobIoChunk, buffer: Arc<DataBuffer>) {
+    // Persist `buffer` at `offset` of the cache file in background, and then mark `chunks` as ready.
+    fn delay_persist(&self, chunks: Vec<BlobIoChunk>, offset: u64, buffer: Arc<DataBuffer>) {
         let delayed_chunk_map = self.chunk_map.clone();
+        let checkpointing = self.checkpointing.clone();
+        let chunk_refs = self.chunk_refs.clone();
         let file = self.file.clone();
-        let offset = if self.is_compressed {
-            chunk_info.compress_offset()
-        } else {
-            chunk_info.uncompress_offset()
-        };
         let metrics = self.metrics.clone();
 
         metrics.buffered_backend_size.add(buffer.size() as u64);
         self.runtime.spawn_blocking(move || {
             metrics.buffered_backend_size.sub(buffer.size() as u64);
-            match Self::persist_chunk(&file, offset, buffer.slice()) {
-                Ok(_) => delayed_chunk_map
-                    .set_ready_and_clear_pending(chunk_info.as_base())
-                    .unwrap_or_else(|e| {
-                        error!(
-                            "Failed change caching state for chunk of offset {}, {:?}",
-                            chunk_info.compress_offset(),
-                            e
-                        )
-                    }),
+            let ret = match chunk_refs.as_ref() {
+                // Only decompressed data of a single chunk is persisted with the chunk store.
+                Some(refs) => refs.persist(chunks[0].as_base(), buffer.slice()),
+                None => Self::persist_chunk(&file, offset, buffer.slice()),
+            };
+            match ret {
+                Ok(_) => {
+                    for chunk_info in chunks.iter() {
+                        delayed_chunk_map
+                            .set_ready_and_clear_pending(chunk_info.as_base())
+                            .unwrap_or_else(|e| {
+                                error!(
+                                    "Failed change caching state for chunk of offset {}, {:?}",
+                                    chunk_info.compress_offset(),
+                                    e
+                                )
+                            })
+                    }
+                    // Already in background, so take the checkpoint inline.
+                    if delayed_chunk_map.need_checkpoint()
+                        && !checkpointing.swap(true, Ordering::AcqRel)
+                    {
+                        Self::checkpoint_chunk_map(delayed_chunk_map.as_ref(), &checkpointing);
+                    }
+                }
                 Err(e) => {
-                    error!(
-                        "Persist chunk of offset {} failed, {:?}",
-                        chunk_info.compress_offset(),
-                        e
-                    );
-                    delayed_chunk_map.clear_pending(chunk_info.as_base())
+                    error!("Persist chunks of offset {} failed, {:?}", offset, e);
+                    for chunk_info in chunks.iter() {
+                        delayed_chunk_map.clear_pending(chunk_info.as_base())
+                    }
                 }
             }
         });
@@ -667,6 +1155,7 @@ impl FileCacheEntry {
     /// Persist a single chunk into local blob cache file. We have to write to the cache
     /// file in unit of chunk size
     fn persist_chunk(file: &Arc<File>, offset: u64, buffer: &[u8]) -> Result<()> {
+        let _timer = StageTimer::new(IoStage::CacheWrite);
         let fd = file.as_raw_fd();
 
         let n = loop {
@@ -692,6 +1181,26 @@ impl FileCacheEntry {
         }
     }
 
+    // Read a continuous range of chunks from the backend, and get raw data of the whole range to
+    // be persisted at `blob_offset` of the cache file if chunks are cached in compressed form.
+    fn read_chunks_for_cache(
+        &self,
+        blob_offset: u64,
+        blob_size: usize,
+        chunks: &[BlobIoChunk],
+    ) -> Result<(Vec<Vec<u8>>, Option<Vec<u8>>)> {
+        if !self.is_compressed {
+            let buffers = self.read_chunks(blob_offset, blob_size, chunks, None)?;
+            return Ok((buffers, None));
+        }
+
+        let raw = RefCell::new(Vec::new());
+        let raw_hook = |buf: &[u8]| raw.borrow_mut().extend_from_slice(buf);
+        let buffers = self.read_chunks(blob_offset, blob_size, chunks, Some(&raw_hook))?;
+
+        Ok((buffers, Some(raw.into_inner())))
+    }
+
     fn read_single_chunk(
         &self,
         chunk: &BlobIoChunk,
@@ -701,17 +1210,30 @@ impl FileCacheEntry {
     ) -> Result<usize> {
         debug!("single bio, blob offset {}", chunk.compress_offset());
 
+        let timer = StageTimer::new(IoStage::ChunkMap);
         let is_ready = self.chunk_map.is_ready(chunk.as_base())?;
+        drop(timer);
         let buffer_holder;
+        let mem_holder;
+        let mem_data = self.get_chunk_in_memory(chunk);
         let d_size = chunk.uncompress_size() as usize;
-        let mut d = DataBuffer::Allocated(alloc_buf(d_size));
+        let mut d = if mem_data.is_some() {
+            DataBuffer::Allocated(Vec::new())
+        } else {
+            DataBuffer::Allocated(alloc_buf(d_size))
+        };
 
         // Try to read and validate data from cache if:
         // - it's an stargz image and the chunk is ready.
         // - chunk data validation is enabled.
         // - digested or dummy chunk map is used.
         let try_cache = is_ready || (!self.is_stargz && !self.is_direct_chunkmap);
-        let buffer = if try_cache && self.read_file_cache(chunk, d.mut_slice()).is_ok() {
+        let buffer = if let Some(data) = mem_data {
+            // The chunk has been decompressed recently and is still kept in memory.
+            self.metrics.whole_hits.inc();
+            mem_holder = data;
+            mem_holder.as_slice()
+        } else if try_cache && self.read_file_cache(chunk, d.mut_slice()).is_ok() {
             self.metrics.whole_hits.inc();
             self.chunk_map
                 .set_ready_and_clear_pending(chunk.as_base())?;
@@ -722,12 +1244,18 @@ impl FileCacheEntry {
                 user_offset,
                 size,
             );
-            &d
+            self.cache_chunk_in_memory(chunk, d.slice());
+            d.slice()
         } else if !self.is_compressed {
             self.read_raw_chunk(chunk, d.mut_slice(), false, None)?;
             buffer_holder = Arc::new(d.convert_to_owned_buffer());
-            self.delay_persist(chunk.clone(), buffer_holder.clone());
-            buffer_holder.as_ref()
+            self.delay_persist(
+                vec![chunk.clone()],
+                chunk.uncompress_offset(),
+                buffer_holder.clone(),
+            );
+            self.cache_chunk_in_memory(chunk, buffer_holder.slice());
+            buffer_holder.slice()
         } else {
             let persist_compressed = |buffer: &[u8]| match Self::persist_chunk(
                 &self.file,
@@ -745,12 +1273,13 @@ impl FileCacheEntry {
                 }
             };
             self.read_raw_chunk(chunk, d.mut_slice(), false, Some(&persist_compressed))?;
-            &d
+            self.cache_chunk_in_memory(chunk, d.slice());
+            d.slice()
         };
 
         let dst_buffers = mem_cursor.inner_slice();
         let read_size = copyv(
-            &[buffer.slice()],
+            &[buffer],
             dst_buffers,
             user_offset as usize,
             size as usize,
@@ -767,6 +1296,32 @@ impl FileCacheEntry {
         Ok(read_size)
     }
 
+    // Get decompressed chunk data kept in memory. The process wide hot chunk cache supersedes
+    // the per blob chunk LRU cache when it's enabled, so chunk data is kept in memory only once.
+    fn get_chunk_in_memory(&self, chunk: &BlobIoChunk) -> Option<Arc<Vec<u8>>> {
+        if HOT_CHUNK_CACHE.is_enabled() {
+            let data = HOT_CHUNK_CACHE.get(self.blob_id(), chunk.id());
+            if data.is_some() {
+                self.metrics.hot_chunk_hits.inc();
+            } else {
+                self.metrics.hot_chunk_misses.inc();
+            }
+            data
+        } else {
+            self.chunk_lru.as_ref().and_then(|lru| lru.get(chunk.id()))
+        }
+    }
+
+    // Keep decompressed chunk data in the hot chunk cache, or in the chunk LRU cache if the hot
+    // chunk cache is disabled.
+    pub(crate) fn cache_chunk_in_memory(&self, chunk: &BlobIoChunk, data: &[u8]) {
+        if HOT_CHUNK_CACHE.is_enabled() {
+            HOT_CHUNK_CACHE.put(self.blob_id(), chunk.id(), data);
+        } else if let Some(lru) = self.chunk_lru.as_ref() {
+            lru.put(chunk.id(), Arc::new(data.to_vec()));
+        }
+    }
+
     fn read_file_cache(&self, chunk: &BlobIoChunk, buffer: &mut [u8]) -> Result<()> {
         let offset = if self.is_compressed {
             chunk.compress_offset()
@@ -788,6 +1343,7 @@ impl FileCacheEntry {
             unsafe { slice::from_raw_parts_mut(buffer.as_mut_ptr(), buffer.len()) }
         };
 
+        let timer = StageTimer::new(IoStage::CacheRead);
         let mut raw_stream = None;
         if self.is_stargz {
             debug!("using blobcache file offset {} as data stream", offset,);
@@ -797,6 +1353,8 @@ impl FileCacheEntry {
             let mut f = unsafe { File::from_raw_fd(fd) };
             f.seek(SeekFrom::Start(offset)).map_err(|_| last_error!())?;
             raw_stream = Some(f)
+        } else if let Some(refs) = self.chunk_refs.as_ref() {
+            refs.read(chunk.as_base(), raw_buffer)?;
         } else {
             debug!(
                 "reading blob cache file offset {} size {}",
@@ -809,14 +1367,17 @@ impl FileCacheEntry {
                 return Err(einval!());
             }
         }
+        drop(timer);
 
+        // Chunks not compressed in the blob are also cached as is in compressed mode.
+        let need_decompress = self.is_stargz || (self.is_compressed && chunk.is_compressed());
         // Try to validate data just fetched from backend inside.
         self.process_raw_chunk(
             chunk,
             raw_buffer,
             raw_stream,
             buffer,
-            self.is_compressed,
+            need_decompress,
             false,
         )?;
 
@@ -905,6 +1466,8 @@ enum RegionType {
     CacheSlow,
     // Need to read data from storage backend.
     Backend,
+    // Data of hot chunks has been fetched from the in-memory hot chunk cache.
+    Memory,
 }
 
 impl RegionType {
@@ -922,6 +1485,8 @@ struct Region {
 
     chunks: Vec<BlobIoChunk>,
     tags: Vec<bool>,
+    // Data of chunks in a `Memory` region.
+    data: Vec<Arc<Vec<u8>>>,
 
     // The range [blob_address, blob_address + blob_len) specifies data to be read from backend.
     blob_address: u64,
@@ -938,6 +1503,7 @@ impl Region {
             count: 0,
             chunks: Vec::with_capacity(8),
             tags: Vec::with_capacity(8),
+            data: Vec::new(),
             blob_address: 0,
             blob_len: 0,
             seg: Default::default(),
@@ -1025,6 +1591,20 @@ impl FileIoMergeState {
             .map_err(|e| einval!(e))
     }
 
+    fn push_memory(
+        &mut self,
+        start: u64,
+        len: u32,
+        tag: BlobIoTag,
+        chunk: BlobIoChunk,
+        data: Arc<Vec<u8>>,
+    ) -> Result<()> {
+        self.push(RegionType::Memory, start, len, tag, Some(chunk))?;
+        let idx = self.regions.len() - 1;
+        self.regions[idx].data.push(data);
+        Ok(())
+    }
+
     // Committing current region ensures a new region will be created when more
     // chunks has to be added since `push` checks if newly pushed chunk is continuous
     // After committing, following `push` will create a new region.
@@ -1048,6 +1628,87 @@ impl FileIoMergeState {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::device::v5::BlobV5ChunkInfo;
+    use crate::test::MockChunkInfo;
+
+    #[test]
+    fn test_chunk_lru() {
+        let lru = ChunkLru::new(0x3000);
+        assert!(lru.get(0).is_none());
+        // Chunks bigger than the capacity are not cached.
+        lru.put(0, Arc::new(vec![0u8; 0x4000]));
+        assert!(lru.get(0).is_none());
+
+        lru.put(1, Arc::new(vec![1u8; 0x1000]));
+        lru.put(2, Arc::new(vec![2u8; 0x1000]));
+        lru.put(3, Arc::new(vec![3u8; 0x1000]));
+        assert_eq!(lru.get(1).unwrap()[0], 1);
+        // Chunk 2 is the least recently used one.
+        lru.put(4, Arc::new(vec![4u8; 0x1000]));
+        assert!(lru.get(2).is_none());
+        assert_eq!(lru.get(3).unwrap()[0], 3);
+        assert_eq!(lru.get(4).unwrap()[0], 4);
+
+        // Replace chunk 1 with a bigger buffer, chunk 3 and 4 are evicted.
+        lru.put(1, Arc::new(vec![5u8; 0x3000]));
+        assert!(lru.get(3).is_none());
+        assert!(lru.get(4).is_none());
+        assert_eq!(lru.get(1).unwrap().len(), 0x3000);
+        let state = lru.state.lock().unwrap();
+        assert_eq!(state.size, 0x3000);
+        assert_eq!(state.chunks.len(), 1);
+        assert_eq!(state.lru.len(), 1);
+        drop(state);
+
+        lru.remove(1);
+        lru.remove(2);
+        assert!(lru.get(1).is_none());
+        let state = lru.state.lock().unwrap();
+        assert_eq!(state.size, 0);
+        assert!(state.lru.is_empty());
+    }
+
+    #[test]
+    fn test_chunk_info_record() {
+        let record = ChunkInfoRecord::new(4);
+        let mut chunk = MockChunkInfo::new();
+        chunk.index = 1;
+        let chunk: Arc<dyn BlobV5ChunkInfo> = Arc::new(chunk);
+        record.record(&BlobIoChunk::V5(chunk));
+        // Chunks without digests are ignored.
+        record.record(&BlobIoChunk::Address(0, 2));
+        let mut chunk = MockChunkInfo::new();
+        chunk.index = 4;
+        let chunk: Arc<dyn BlobV5ChunkInfo> = Arc::new(chunk);
+        record.record(&BlobIoChunk::V5(chunk));
+
+        assert!(record.get(0).is_none());
+        assert_eq!(record.get(1).unwrap().id(), 1);
+        assert!(record.get(2).is_none());
+        assert!(record.get(4).is_none());
+    }
+
+    #[test]
+    fn test_chunk_access_record() {
+        let record = ChunkAccessRecord::new(4);
+        assert_eq!(record.atime.len(), 4);
+        assert!(record.is_cold(0, 0));
+        assert!(!record.is_cold(0, 1));
+        assert!(!record.is_cold(4, 0));
+
+        // Reset the base time to pretend the record has been created for a while.
+        let mut record = record;
+        record.base = record
+            .base
+            .checked_sub(std::time::Duration::from_secs(100))
+            .unwrap();
+        assert!(record.is_cold(1, 100));
+        record.touch(1);
+        record.touch(4);
+        assert!(!record.is_cold(1, 100));
+        assert!(record.is_cold(1, 0));
+        assert!(record.is_cold(2, 100));
+    }
 
     #[test]
     fn test_data_buffer() {
@@ -1072,6 +1733,9 @@ mod tests {
         assert!(!RegionType::CacheSlow.joinable(RegionType::Backend));
         assert!(!RegionType::Backend.joinable(RegionType::CacheFast));
         assert!(!RegionType::Backend.joinable(RegionType::CacheSlow));
+        assert!(RegionType::Memory.joinable(RegionType::Memory));
+        assert!(!RegionType::Memory.joinable(RegionType::CacheSlow));
+        assert!(!RegionType::CacheSlow.joinable(RegionType::Memory));
     }
 
     #[test]
@@ -1165,5 +1829,24 @@ mod tests {
             .push(RegionType::CacheSlow, 0x5000, 0x2000, tag, None)
             .unwrap();
         assert_eq!(state.regions.len(), 2);
+
+        // Data of hot chunks is carried by the region.
+        for idx in 0..2u32 {
+            let offset = 0x7000 + idx as u64 * 0x1000;
+            let tag = BlobIoTag::User(BlobIoSegment {
+                offset: offset as u32,
+                len: 0x1000,
+            });
+            let chunk = BlobIoChunk::Address(0, idx);
+            let data = Arc::new(vec![idx as u8; 0x1000]);
+            state.push_memory(offset, 0x1000, tag, chunk, data).unwrap();
+        }
+        assert_eq!(state.regions.len(), 3);
+        let region = &state.regions[2];
+        assert!(region.r#type == RegionType::Memory);
+        assert_eq!(region.chunks.len(), 2);
+        assert_eq!(region.data.len(), 2);
+        assert_eq!(region.data[1][0], 1);
+        assert_eq!(region.seg.len, 0x2000);
     }
 }
@@ -23,9 +23,11 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use fuse_backend_rs::transport::FileVolatileSlice;
+use nydus_utils::metrics::{BlobcacheMetrics, Metric};
 use nydus_utils::{compress, digest};
 
 use crate::backend::{BlobBackend, BlobReader};
+use crate::cache::hotchunk::HOT_CHUNK_CACHE;
 use crate::cache::state::{ChunkMap, NoopChunkMap};
 use crate::cache::{BlobCache, BlobCacheMgr};
 use crate::device::{BlobChunkInfo, BlobInfo, BlobIoDesc, BlobIoVec, BlobPrefetchRequest};
@@ -36,6 +38,7 @@ use crate::{StorageError, StorageResult};
 struct DummyCache {
     blob_id: String,
     chunk_map: Arc<dyn ChunkMap>,
+    metrics: Arc<BlobcacheMetrics>,
     reader: Arc<dyn BlobReader>,
     compressor: compress::Algorithm,
     digester: digest::Algorithm,
@@ -131,26 +134,59 @@ impl BlobCache for DummyCache {
                 return Ok(0);
             }
             let buf = unsafe { std::slice::from_raw_parts_mut(bufs[0].as_ptr(), d_size) };
-            return self.read_raw_chunk(&bios[0].chunkinfo, buf, false, None);
+            if let Some(data) = self.get_hot_chunk(&bios[0].chunkinfo) {
+                buf.copy_from_slice(&data);
+                return Ok(d_size);
+            }
+            let size = self.read_raw_chunk(&bios[0].chunkinfo, buf, false, None)?;
+            HOT_CHUNK_CACHE.put(&self.blob_id, bios[0].chunkinfo.id(), buf);
+            return Ok(size);
         }
 
         let mut user_size = 0;
-        let mut buffer_holder: Vec<Vec<u8>> = Vec::with_capacity(bios.len());
+        let mut buffer_holder: Vec<Arc<Vec<u8>>> = Vec::with_capacity(bios.len());
         for bio in bios.iter() {
             if bio.user_io {
-                let mut d = alloc_buf(bio.chunkinfo.uncompress_size() as usize);
-                self.read_raw_chunk(&bio.chunkinfo, d.as_mut_slice(), false, None)?;
+                let d = match self.get_hot_chunk(&bio.chunkinfo) {
+                    Some(v) => v,
+                    None => {
+                        let mut d = alloc_buf(bio.chunkinfo.uncompress_size() as usize);
+                        self.read_raw_chunk(&bio.chunkinfo, d.as_mut_slice(), false, None)?;
+                        HOT_CHUNK_CACHE.put(&self.blob_id, bio.chunkinfo.id(), &d);
+                        Arc::new(d)
+                    }
+                };
                 buffer_holder.push(d);
                 user_size += bio.size;
             }
         }
+        let buffers: Vec<&[u8]> = buffer_holder.iter().map(|v| v.as_slice()).collect();
 
-        copyv(&buffer_holder, bufs, offset as usize, user_size, 0, 0)
+        copyv(&buffers, bufs, offset as usize, user_size, 0, 0)
             .map(|(n, _)| n)
             .map_err(|e| eother!(e))
     }
 }
 
+impl DummyCache {
+    // Get chunk data from the in-memory hot chunk cache.
+    fn get_hot_chunk(&self, chunk: &dyn BlobChunkInfo) -> Option<Arc<Vec<u8>>> {
+        if !HOT_CHUNK_CACHE.is_enabled() {
+            return None;
+        }
+        match HOT_CHUNK_CACHE.get(&self.blob_id, chunk.id()) {
+            Some(v) => {
+                self.metrics.hot_chunk_hits.inc();
+                Some(v)
+            }
+            None => {
+                self.metrics.hot_chunk_misses.inc();
+                None
+            }
+        }
+    }
+}
+
 /// A dummy implementation of [BlobCacheMgr](../trait.BlobCacheMgr.html), simply reporting each
 /// chunk as cached or not cached according to configuration.
 ///
@@ -159,6 +195,7 @@ impl BlobCache for DummyCache {
 /// the data to the clients.
 pub struct DummyCacheMgr {
     backend: Arc<dyn BlobBackend>,
+    metrics: Arc<BlobcacheMetrics>,
     cached: bool,
     prefetch: bool,
     validate: bool,
@@ -172,9 +209,11 @@ impl DummyCacheMgr {
         backend: Arc<dyn BlobBackend>,
         cached: bool,
         enable_prefetch: bool,
+        id: &str,
     ) -> Result<DummyCacheMgr> {
         Ok(DummyCacheMgr {
             backend,
+            metrics: BlobcacheMetrics::new(id, ""),
             cached,
             validate: config.cache_validate,
             prefetch: enable_prefetch,
@@ -192,6 +231,7 @@ impl BlobCacheMgr for DummyCacheMgr {
         if !self.closed.load(Ordering::Acquire) {
             self.closed.store(true, Ordering::Release);
             self.backend().shutdown();
+            self.metrics.release().unwrap_or_else(|e| error!("{:?}", e));
         }
     }
 
@@ -210,6 +250,7 @@ impl BlobCacheMgr for DummyCacheMgr {
         Ok(Arc::new(DummyCache {
             blob_id,
             chunk_map: Arc::new(NoopChunkMap::new(self.cached)),
+            metrics: self.metrics.clone(),
             reader,
             compressor: blob_info.compressor(),
             digester: blob_info.digester(),
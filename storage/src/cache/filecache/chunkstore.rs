@@ -0,0 +1,973 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Content-addressed chunk store to deduplicate cached chunk data across blobs.
+//!
+//! Different blobs may contain identical chunks, which have the same chunk digest. With the chunk
+//! store enabled, decompressed chunk data of all blobs in the cache working directory is stored
+//! once in a shared data file and indexed by chunk digest, so a chunk fetched for one blob is a
+//! cache hit for all other blobs containing it.
+//!
+//! The chunk store consists of following files in the working directory:
+//! - `.chunkstore.data`: chunk data, each chunk occupies a 4K aligned extent.
+//! - `.chunkstore.index`: a header followed by an array of fixed size records, each record holds
+//!   digest, location and reference count of a stored chunk.
+//! - `$blob_id.chunk_refs`: a header followed by an `u32` for each chunk of the blob, holding the
+//!   record slot plus one of the chunk referenced by the blob, or zero if not referenced.
+//!
+//! Each blob holds at most one reference to a stored chunk, and the extent of a chunk is released
+//! when the last blob referencing it is evicted. Names of the shared files start with a dot, so
+//! they won't be treated as cached files of any blob. The chunk store is shared by cache managers
+//! in the same process only. The index file is locked exclusively while the chunk store is open,
+//! and opening a chunk store locked by another process, for example the previous nydusd instance
+//! during live upgrade, fails if the lock is not released in ten seconds.
+//!
+//! Data, index and reference files are synced to disk by checkpoints taken in background, after
+//! which the index file is marked as clean. The index file is marked as not clean again before
+//! the next update, and a chunk store not clean when opened is reset, because its files may be
+//! inconsistent after a crash. Data of chunks is also verified against chunk digests when read.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Result};
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+use nix::errno::Errno;
+use nix::fcntl::{fallocate, flock, FallocateFlags, FlockArg};
+use nydus_utils::digest::{self, RafsDigest};
+use nydus_utils::metrics::{BlobcacheMetrics, Metric};
+use nydus_utils::round_up;
+
+use crate::cache::state::{ChunkIndexGetter, ChunkMap, CHECKPOINT_CHUNKS, CHECKPOINT_INTERVAL};
+use crate::device::BlobChunkInfo;
+
+/// Name of the data file of the chunk store.
+const DATA_FILE_NAME: &str = ".chunkstore.data";
+/// Name of the index file of the chunk store.
+const INDEX_FILE_NAME: &str = ".chunkstore.index";
+/// The name suffix of chunk reference files, named as `$blob_id.chunk_refs`.
+pub(crate) const REFS_FILE_SUFFIX: &str = "chunk_refs";
+
+const INDEX_MAGIC: u64 = 0x4e59_4458_4353_4932;
+const REFS_MAGIC: u64 = 0x4e59_4458_4352_4632;
+/// Size of the header of index and reference files, holding magic, generation number and flags.
+const HEADER_SIZE: u64 = 24;
+/// The index file is consistent with data and reference files, set by checkpoints.
+const FLAG_CLEAN: u64 = 0x1;
+/// Size of an index record: digest, offset, size and reference count.
+const RECORD_SIZE: u64 = 48;
+const EXTENT_ALIGNMENT: u64 = 0x1000;
+/// Time to wait for a chunk store locked by another process to be released.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    // Chunk stores opened by this process, indexed by working directory.
+    static ref CHUNK_STORES: Mutex<HashMap<String, Weak<ChunkStore>>> = Mutex::new(HashMap::new());
+}
+
+// Read the header, and return generation number and flags.
+fn read_header(file: &File, magic: u64) -> Option<(u64, u64)> {
+    let mut buf = [0u8; HEADER_SIZE as usize];
+    file.read_exact_at(&mut buf, 0).ok()?;
+    let mut v = [0u8; 8];
+    v.copy_from_slice(&buf[..8]);
+    if u64::from_le_bytes(v) != magic {
+        return None;
+    }
+    v.copy_from_slice(&buf[8..16]);
+    let generation = u64::from_le_bytes(v);
+    v.copy_from_slice(&buf[16..]);
+
+    Some((generation, u64::from_le_bytes(v)))
+}
+
+// Lock the index file exclusively, so the chunk store is only used by one process at a time.
+// The lock is released when the index file is closed, including on process exit.
+fn lock_index(index: &File, work_dir: &Path, timeout: Duration) -> Result<()> {
+    let start = Instant::now();
+    loop {
+        match flock(index.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            Ok(()) => return Ok(()),
+            Err(Errno::EWOULDBLOCK) if start.elapsed() < timeout => {
+                thread::sleep(Duration::from_millis(100))
+            }
+            Err(Errno::EWOULDBLOCK) => return Err(eother!(format!(
+                "chunk store in {:?} is used by another process, stop it or use another work_dir",
+                work_dir
+            ))),
+            Err(e) => return Err(eother!(e)),
+        }
+    }
+}
+
+fn write_header(file: &File, magic: u64, generation: u64, flags: u64) -> Result<()> {
+    let mut buf = [0u8; HEADER_SIZE as usize];
+    buf[..8].copy_from_slice(&magic.to_le_bytes());
+    buf[8..16].copy_from_slice(&generation.to_le_bytes());
+    buf[16..].copy_from_slice(&flags.to_le_bytes());
+    file.write_all_at(&buf, 0)
+}
+
+#[derive(Clone, Copy, Default)]
+struct ChunkRecord {
+    digest: RafsDigest,
+    offset: u64,
+    size: u32,
+    refs: u32,
+}
+
+impl ChunkRecord {
+    fn from_bytes(buf: &[u8]) -> Self {
+        let mut digest = RafsDigest::default();
+        digest.data.copy_from_slice(&buf[..32]);
+        let mut v = [0u8; 8];
+        v.copy_from_slice(&buf[32..40]);
+        let offset = u64::from_le_bytes(v);
+        let mut v = [0u8; 4];
+        v.copy_from_slice(&buf[40..44]);
+        let size = u32::from_le_bytes(v);
+        v.copy_from_slice(&buf[44..48]);
+        let refs = u32::from_le_bytes(v);
+
+        ChunkRecord {
+            digest,
+            offset,
+            size,
+            refs,
+        }
+    }
+
+    fn to_bytes(self) -> [u8; RECORD_SIZE as usize] {
+        let mut buf = [0u8; RECORD_SIZE as usize];
+        buf[..32].copy_from_slice(&self.digest.data);
+        buf[32..40].copy_from_slice(&self.offset.to_le_bytes());
+        buf[40..44].copy_from_slice(&self.size.to_le_bytes());
+        buf[44..48].copy_from_slice(&self.refs.to_le_bytes());
+        buf
+    }
+
+    fn extent_size(&self) -> u64 {
+        round_up(self.size as u64, EXTENT_ALIGNMENT)
+    }
+}
+
+#[derive(Default)]
+struct ChunkStoreState {
+    records: Vec<ChunkRecord>,
+    // Slots of stored chunks indexed by chunk digest.
+    chunks: HashMap<RafsDigest, u32>,
+    // Unused slots indexed by size of their extents, which are reused together.
+    free: HashMap<u64, Vec<u32>>,
+    // End of allocated extents in the data file.
+    data_end: u64,
+}
+
+// Updates to the chunk store since the last checkpoint.
+struct CheckpointState {
+    // The index file has been marked as not clean.
+    dirty: bool,
+    // Number of updates started, and those not finished yet.
+    updates: u64,
+    inflight: u32,
+    // Number of updates covered by the last checkpoint.
+    checkpointed: u64,
+    time: Instant,
+    // Reference files updated since the last checkpoint.
+    refs_files: Vec<Arc<File>>,
+}
+
+/// Content-addressed store of decompressed chunk data shared by all blobs in a working directory.
+pub(crate) struct ChunkStore {
+    data: Arc<File>,
+    index: File,
+    // Generation number to detect reference files created for a previous incarnation of the store.
+    generation: u64,
+    state: Mutex<ChunkStoreState>,
+    checkpoint: Mutex<CheckpointState>,
+}
+
+impl ChunkStore {
+    /// Open the chunk store in `work_dir`, which is shared by all cache managers of the process.
+    pub fn open(work_dir: &str) -> Result<Arc<ChunkStore>> {
+        let key = fs::canonicalize(work_dir)
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|_| work_dir.to_string());
+        let mut stores = CHUNK_STORES.lock().unwrap();
+        if let Some(store) = stores.get(&key).and_then(|v| v.upgrade()) {
+            return Ok(store);
+        }
+
+        let store = Arc::new(Self::new(Path::new(work_dir))?);
+        stores.insert(key, Arc::downgrade(&store));
+
+        Ok(store)
+    }
+
+    fn new(work_dir: &Path) -> Result<Self> {
+        let open = |name: &str| {
+            OpenOptions::new()
+                .create(true)
+                .read(true)
+                .write(true)
+                .open(work_dir.join(name))
+        };
+        let mut index = open(INDEX_FILE_NAME)?;
+        lock_index(&index, work_dir, LOCK_TIMEOUT)?;
+        let data = open(DATA_FILE_NAME)?;
+
+        let mut state = ChunkStoreState::default();
+        let generation = match read_header(&index, INDEX_MAGIC) {
+            Some((generation, flags)) if flags & FLAG_CLEAN != 0 => {
+                let mut buf = Vec::new();
+                index.read_to_end(&mut buf)?;
+                // Ignore the partially written record at the end, if any.
+                for (slot, v) in buf[HEADER_SIZE as usize..]
+                    .chunks_exact(RECORD_SIZE as usize)
+                    .enumerate()
+                {
+                    let mut record = ChunkRecord::from_bytes(v);
+                    let slot = slot as u32;
+                    state.data_end =
+                        std::cmp::max(state.data_end, record.offset + record.extent_size());
+                    if record.refs > 0 && !state.chunks.contains_key(&record.digest) {
+                        state.chunks.insert(record.digest, slot);
+                    } else {
+                        // Drop duplicated records, which shouldn't happen.
+                        record.refs = 0;
+                        state
+                            .free
+                            .entry(record.extent_size())
+                            .or_default()
+                            .push(slot);
+                    }
+                    state.records.push(record);
+                }
+                generation
+            }
+            header => {
+                if header.is_some() {
+                    warn!("filecache: chunk store isn't clean, reset the chunk store");
+                } else if index.metadata()?.len() > 0 {
+                    warn!("filecache: invalid chunk store index, reset the chunk store");
+                }
+                let generation = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_nanos() as u64)
+                    .unwrap_or_default();
+                data.set_len(0)?;
+                index.set_len(0)?;
+                write_header(&index, INDEX_MAGIC, generation, FLAG_CLEAN)?;
+                index.sync_all()?;
+                generation
+            }
+        };
+        info!(
+            "filecache: open chunk store in {:?} with {} chunks",
+            work_dir,
+            state.chunks.len()
+        );
+
+        Ok(ChunkStore {
+            data: Arc::new(data),
+            index,
+            generation,
+            state: Mutex::new(state),
+            checkpoint: Mutex::new(CheckpointState {
+                dirty: false,
+                updates: 0,
+                inflight: 0,
+                checkpointed: 0,
+                time: Instant::now(),
+                refs_files: Vec::new(),
+            }),
+        })
+    }
+
+    /// Get the data file of the chunk store.
+    pub fn data_file(&self) -> Arc<File> {
+        self.data.clone()
+    }
+
+    /// Get disk space in bytes used by the data and index files.
+    pub fn disk_usage(&self) -> u64 {
+        [&*self.data, &self.index]
+            .iter()
+            .filter_map(|f| f.metadata().ok())
+            .map(|md| md.blocks().saturating_mul(512))
+            .sum()
+    }
+
+    /// Get number of chunks in the store.
+    pub fn chunk_count(&self) -> usize {
+        self.state.lock().unwrap().chunks.len()
+    }
+
+    /// Take a reference to the chunk with `digest`, and return its slot if it's in the store.
+    pub fn get_ref(&self, digest: &RafsDigest) -> Result<Option<u32>> {
+        let mut state = self.state.lock().unwrap();
+        let slot = match state.chunks.get(digest) {
+            Some(v) => *v,
+            None => return Ok(None),
+        };
+        let mut record = state.records[slot as usize];
+        record.refs += 1;
+        self.write_record(slot, &record)?;
+        state.records[slot as usize] = record;
+
+        Ok(Some(slot))
+    }
+
+    /// Store chunk `data` with `digest` and take a reference to it, return its slot.
+    ///
+    /// Only a reference is taken if a chunk with the same digest is already in the store.
+    pub fn insert(&self, digest: &RafsDigest, data: &[u8]) -> Result<u32> {
+        if data.is_empty() || data.len() > u32::MAX as usize {
+            return Err(einval!(format!("invalid chunk size {}", data.len())));
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state.chunks.contains_key(digest) {
+            drop(state);
+            // The chunk may be released concurrently, so try again if it disappears.
+            return match self.get_ref(digest)? {
+                Some(slot) => Ok(slot),
+                None => self.insert(digest, data),
+            };
+        }
+
+        let extent = round_up(data.len() as u64, EXTENT_ALIGNMENT);
+        let reused = state.free.get_mut(&extent).and_then(|v| v.pop());
+        let (slot, offset) = match reused {
+            Some(slot) => (slot, state.records[slot as usize].offset),
+            None => (state.records.len() as u32, state.data_end),
+        };
+        let record = ChunkRecord {
+            digest: *digest,
+            offset,
+            size: data.len() as u32,
+            refs: 1,
+        };
+        // Write data before the record, data is synced to disk before the record is trusted by
+        // checkpoints.
+        let ret = self.update(None, || {
+            self.data
+                .write_all_at(data, offset)
+                .and_then(|_| self.write_index(slot, &record))
+        });
+        if let Err(e) = ret {
+            if let Some(slot) = reused {
+                state.free.entry(extent).or_default().push(slot);
+            }
+            return Err(e);
+        }
+
+        if reused.is_some() {
+            state.records[slot as usize] = record;
+        } else {
+            state.records.push(record);
+            state.data_end += extent;
+        }
+        state.chunks.insert(*digest, slot);
+
+        Ok(slot)
+    }
+
+    /// Drop a reference to the chunk at `slot`, and return size of disk space released.
+    pub fn release(&self, slot: u32) -> Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        let mut record = match state.records.get(slot as usize) {
+            Some(v) if v.refs > 0 => *v,
+            _ => return Err(einval!(format!("chunk store slot {} isn't in use", slot))),
+        };
+        record.refs -= 1;
+        self.write_record(slot, &record)?;
+        state.records[slot as usize] = record;
+        if record.refs > 0 {
+            return Ok(0);
+        }
+
+        let extent = record.extent_size();
+        // The digest may have been mapped to another slot if the chunk has been invalidated.
+        if state.chunks.get(&record.digest) == Some(&slot) {
+            state.chunks.remove(&record.digest);
+        }
+        state.free.entry(extent).or_default().push(slot);
+        fallocate(
+            self.data.as_raw_fd(),
+            FallocateFlags::FALLOC_FL_PUNCH_HOLE | FallocateFlags::FALLOC_FL_KEEP_SIZE,
+            record.offset as i64,
+            extent as i64,
+        )
+        .map_err(|e| eio!(format!("failed to punch hole in chunk store, {}", e)))?;
+
+        Ok(extent)
+    }
+
+    /// Stop taking new references to the chunk at `slot`, because its data is corrupted.
+    ///
+    /// Chunk data with the same digest will be stored into another slot, and the slot is released
+    /// with the last existing reference.
+    pub fn invalidate(&self, slot: u32) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(record) = state.records.get(slot as usize).copied() {
+            if state.chunks.get(&record.digest) == Some(&slot) {
+                state.chunks.remove(&record.digest);
+            }
+        }
+    }
+
+    /// Read data of the chunk with `digest` at `slot` into `buf`.
+    pub fn read(&self, slot: u32, digest: &RafsDigest, buf: &mut [u8]) -> Result<()> {
+        let offset = match self.state.lock().unwrap().records.get(slot as usize) {
+            Some(r) if r.refs > 0 && &r.digest == digest && r.size as usize == buf.len() => {
+                r.offset
+            }
+            _ => return Err(einval!(format!("chunk store slot {} mismatches", slot))),
+        };
+
+        self.data.read_exact_at(buf, offset)
+    }
+
+    /// Drop all references recorded in the reference file of a blob, and return size of disk space
+    /// released.
+    pub fn release_refs_file(&self, path: &Path) -> Result<u64> {
+        let mut file = File::open(path)?;
+        if read_header(&file, REFS_MAGIC).map(|v| v.0) != Some(self.generation) {
+            return Ok(0);
+        }
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+
+        let mut released = 0;
+        for v in buf[HEADER_SIZE as usize..].chunks_exact(4) {
+            let slot = u32::from_le_bytes([v[0], v[1], v[2], v[3]]);
+            if slot > 0 {
+                released += self.release(slot - 1).unwrap_or_else(|e| {
+                    warn!("filecache: failed to release chunk of {:?}, {}", path, e);
+                    0
+                });
+            }
+        }
+
+        Ok(released)
+    }
+
+    fn is_in_use(&self, slot: u32) -> bool {
+        matches!(self.state.lock().unwrap().records.get(slot as usize), Some(r) if r.refs > 0)
+    }
+
+    /// Check whether a checkpoint should be taken by [ChunkStore::checkpoint()].
+    pub fn need_checkpoint(&self) -> bool {
+        // Don't wait for a running checkpoint.
+        match self.checkpoint.try_lock() {
+            Ok(state) => {
+                state.dirty
+                    && (state.updates - state.checkpointed >= CHECKPOINT_CHUNKS as u64
+                        || state.time.elapsed() >= CHECKPOINT_INTERVAL)
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Sync data, index and reference files to disk, and then mark the index file as clean.
+    ///
+    /// The index file is left unclean if the store is being updated, and the checkpoint should be
+    /// taken again later.
+    pub fn checkpoint(&self) -> Result<()> {
+        let (updates, refs_files) = {
+            let mut state = self.checkpoint.lock().unwrap();
+            if !state.dirty {
+                return Ok(());
+            } else if state.inflight > 0 {
+                state.time = Instant::now();
+                return Ok(());
+            }
+            (state.updates, state.refs_files.clone())
+        };
+
+        self.data.sync_data()?;
+        for file in refs_files.iter() {
+            file.sync_data()?;
+        }
+        self.index.sync_data()?;
+
+        let mut state = self.checkpoint.lock().unwrap();
+        state.time = Instant::now();
+        if state.dirty && state.updates == updates {
+            write_header(&self.index, INDEX_MAGIC, self.generation, FLAG_CLEAN)?;
+            self.index.sync_data()?;
+            state.dirty = false;
+            state.checkpointed = updates;
+            state.refs_files.clear();
+        }
+
+        Ok(())
+    }
+
+    // Update files of the chunk store by `f`, marking the index file as not clean beforehand.
+    fn update<F: FnOnce() -> Result<()>>(&self, refs_file: Option<&Arc<File>>, f: F) -> Result<()> {
+        {
+            let mut state = self.checkpoint.lock().unwrap();
+            if !state.dirty {
+                write_header(&self.index, INDEX_MAGIC, self.generation, 0)?;
+                self.index.sync_data()?;
+                state.dirty = true;
+            }
+            if let Some(file) = refs_file {
+                if !state.refs_files.iter().any(|v| Arc::ptr_eq(v, file)) {
+                    state.refs_files.push(file.clone());
+                }
+            }
+            state.updates += 1;
+            state.inflight += 1;
+        }
+        let ret = f();
+        self.checkpoint.lock().unwrap().inflight -= 1;
+
+        ret
+    }
+
+    fn write_record(&self, slot: u32, record: &ChunkRecord) -> Result<()> {
+        self.update(None, || self.write_index(slot, record))
+    }
+
+    fn write_index(&self, slot: u32, record: &ChunkRecord) -> Result<()> {
+        self.index
+            .write_all_at(&record.to_bytes(), HEADER_SIZE + slot as u64 * RECORD_SIZE)
+    }
+}
+
+impl Drop for ChunkStore {
+    fn drop(&mut self) {
+        if let Err(e) = self.checkpoint() {
+            warn!("filecache: failed to take checkpoint of chunk store, {}", e);
+        }
+    }
+}
+
+/// An implementation of [ChunkMap] for blobs with chunk data in the [ChunkStore].
+///
+/// A chunk is ready if the blob holds a reference to it in the chunk store. When a chunk isn't
+/// ready, `is_ready()` also tries to reference the same chunk stored for other blobs.
+#[derive(Clone)]
+pub(crate) struct ChunkRefMap {
+    store: Arc<ChunkStore>,
+    file: Arc<File>,
+    // Record slot plus one of referenced chunks, indexed by chunk index.
+    refs: Arc<Vec<AtomicU32>>,
+    digester: digest::Algorithm,
+    metrics: Arc<BlobcacheMetrics>,
+}
+
+impl ChunkRefMap {
+    /// Create a new instance of `ChunkRefMap`, with reference file `$blob_path.chunk_refs`.
+    pub fn new(
+        store: Arc<ChunkStore>,
+        blob_path: &str,
+        chunk_count: u32,
+        digester: digest::Algorithm,
+        metrics: Arc<BlobcacheMetrics>,
+    ) -> Result<Self> {
+        let filename = format!("{}.{}", blob_path, REFS_FILE_SUFFIX);
+        let file = Arc::new(
+            OpenOptions::new()
+                .create(true)
+                .read(true)
+                .write(true)
+                .open(&filename)?,
+        );
+        let expected_size = HEADER_SIZE + chunk_count as u64 * 4;
+
+        let mut refs = Vec::with_capacity(chunk_count as usize);
+        if read_header(&file, REFS_MAGIC).map(|v| v.0) == Some(store.generation)
+            && file.metadata()?.len() == expected_size
+        {
+            let mut buf = Vec::new();
+            (&*file).read_to_end(&mut buf)?;
+            for (index, v) in buf[HEADER_SIZE as usize..].chunks_exact(4).enumerate() {
+                let mut slot = u32::from_le_bytes([v[0], v[1], v[2], v[3]]);
+                if slot > 0 && !store.is_in_use(slot - 1) {
+                    slot = 0;
+                    store.update(Some(&file), || {
+                        file.write_all_at(&[0u8; 4], HEADER_SIZE + index as u64 * 4)
+                    })?;
+                }
+                refs.push(AtomicU32::new(slot));
+            }
+        } else {
+            // The chunk store has been reset, so references recorded in the file are invalid.
+            store.update(Some(&file), || {
+                file.set_len(0)?;
+                write_header(&file, REFS_MAGIC, store.generation, 0)?;
+                file.set_len(expected_size)
+            })?;
+            refs.resize_with(chunk_count as usize, || AtomicU32::new(0));
+        }
+
+        Ok(ChunkRefMap {
+            store,
+            file,
+            refs: Arc::new(refs),
+            digester,
+            metrics,
+        })
+    }
+
+    /// Store decompressed `data` of the chunk, and reference it from the blob.
+    pub fn persist(&self, chunk: &dyn BlobChunkInfo, data: &[u8]) -> Result<()> {
+        if self.get_ref(chunk)? != 0 {
+            return Ok(());
+        }
+        // The store is shared with other blobs, so never store corrupted data.
+        if &RafsDigest::from_buf(data, self.digester) != chunk.chunk_id() {
+            return Err(eio!(format!(
+                "digest of chunk {} mismatches, refuse to store it",
+                chunk.id()
+            )));
+        }
+
+        let slot = self.store.insert(chunk.chunk_id(), data)?;
+        if !self.set_ref(chunk.id(), 0, slot + 1)? {
+            self.store.release(slot)?;
+        }
+
+        Ok(())
+    }
+
+    /// Prevent the chunk referenced by the blob from being shared any more, because its data is
+    /// corrupted. The reference should then be dropped by `clear_ready()`.
+    pub fn invalidate(&self, chunk: &dyn BlobChunkInfo) -> Result<()> {
+        let slot = self.get_ref(chunk)?;
+        if slot != 0 {
+            self.store.invalidate(slot - 1);
+        }
+
+        Ok(())
+    }
+
+    /// Read decompressed data of the chunk from the store.
+    ///
+    /// The reference to the chunk is dropped if its data is corrupted, so it will be stored again.
+    pub fn read(&self, chunk: &dyn BlobChunkInfo, buf: &mut [u8]) -> Result<()> {
+        let slot = match self.get_ref(chunk)? {
+            0 => {
+                return Err(enoent!(format!(
+                    "chunk {} isn't in the chunk store",
+                    chunk.id()
+                )))
+            }
+            v => v,
+        };
+        self.store.read(slot - 1, chunk.chunk_id(), buf)?;
+
+        if &RafsDigest::from_buf(buf, self.digester) != chunk.chunk_id() {
+            warn!(
+                "filecache: digest of chunk {} in chunk store mismatches, drop it",
+                chunk.id()
+            );
+            self.store.invalidate(slot - 1);
+            if self.set_ref(chunk.id(), slot, 0)? {
+                self.store.release(slot - 1)?;
+            }
+            return Err(eio!(format!(
+                "digest of chunk {} in chunk store mismatches",
+                chunk.id()
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn get_ref(&self, chunk: &dyn BlobChunkInfo) -> Result<u32> {
+        self.refs
+            .get(chunk.id() as usize)
+            .map(|v| v.load(Ordering::Acquire))
+            .ok_or_else(|| einval!(format!("chunk index {} is out of range", chunk.id())))
+    }
+
+    // Change the reference of chunk `index` from `old` to `new`, return false if it has been
+    // changed by others.
+    fn set_ref(&self, index: u32, old: u32, new: u32) -> Result<bool> {
+        if self.refs[index as usize]
+            .compare_exchange(old, new, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return Ok(false);
+        }
+        self.store.update(Some(&self.file), || {
+            self.file
+                .write_all_at(&new.to_le_bytes(), HEADER_SIZE + index as u64 * 4)
+        })?;
+
+        Ok(true)
+    }
+}
+
+impl ChunkMap for ChunkRefMap {
+    fn is_ready(&self, chunk: &dyn BlobChunkInfo) -> Result<bool> {
+        if self.get_ref(chunk)? != 0 {
+            return Ok(true);
+        }
+
+        // Try to reference the same chunk stored for other blobs.
+        match self.store.get_ref(chunk.chunk_id())? {
+            Some(slot) => {
+                if self.set_ref(chunk.id(), 0, slot + 1)? {
+                    self.metrics.dedup_hits.inc();
+                } else {
+                    self.store.release(slot)?;
+                }
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn set_ready_and_clear_pending(&self, chunk: &dyn BlobChunkInfo) -> Result<()> {
+        // Chunks become ready by `persist()`, which stores chunk data.
+        match self.get_ref(chunk)? {
+            0 => Err(einval!(format!(
+                "chunk {} isn't in the chunk store",
+                chunk.id()
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn clear_ready(&self, chunk: &dyn BlobChunkInfo) -> Result<bool> {
+        let slot = self.get_ref(chunk)?;
+        if slot == 0 || !self.set_ref(chunk.id(), slot, 0)? {
+            return Ok(false);
+        }
+        self.store.release(slot - 1)?;
+
+        Ok(true)
+    }
+
+    fn need_checkpoint(&self) -> bool {
+        self.store.need_checkpoint()
+    }
+
+    fn checkpoint(&self) -> Result<()> {
+        self.store.checkpoint()
+    }
+
+    fn is_persist(&self) -> bool {
+        true
+    }
+}
+
+impl ChunkIndexGetter for ChunkRefMap {
+    type Index = u32;
+
+    fn get_index(chunk: &dyn BlobChunkInfo) -> Self::Index {
+        chunk.id()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::MockChunkInfo;
+    use vmm_sys_util::tempdir::TempDir;
+
+    fn new_chunk(index: u32, data: &[u8]) -> MockChunkInfo {
+        MockChunkInfo {
+            block_id: RafsDigest::from_buf(data, digest::Algorithm::Blake3),
+            index,
+            uncompress_size: data.len() as u32,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_chunk_store() {
+        let tmp_dir = TempDir::new().unwrap();
+        let work_dir = tmp_dir.as_path().to_str().unwrap();
+        let data1 = vec![1u8; 0x1000];
+        let data2 = vec![2u8; 0x1800];
+        let digest1 = RafsDigest::from_buf(&data1, digest::Algorithm::Blake3);
+        let digest2 = RafsDigest::from_buf(&data2, digest::Algorithm::Blake3);
+
+        let store = ChunkStore::open(work_dir).unwrap();
+        assert!(Arc::ptr_eq(&store, &ChunkStore::open(work_dir).unwrap()));
+        assert!(store.get_ref(&digest1).unwrap().is_none());
+        assert!(store.insert(&digest1, &[]).is_err());
+        assert_eq!(store.insert(&digest1, &data1).unwrap(), 0);
+        assert_eq!(store.insert(&digest2, &data2).unwrap(), 1);
+        assert_eq!(store.insert(&digest1, &data1).unwrap(), 0);
+        assert_eq!(store.get_ref(&digest1).unwrap(), Some(0));
+        assert_eq!(store.chunk_count(), 2);
+
+        let mut buf = vec![0u8; 0x1800];
+        store.read(1, &digest2, &mut buf).unwrap();
+        assert_eq!(buf, data2);
+        assert!(store.read(1, &digest1, &mut buf).is_err());
+        assert!(store.read(0, &digest1, &mut buf).is_err());
+
+        // The extent is released with the last reference, and reused by chunks of the same size.
+        assert_eq!(store.release(0).unwrap(), 0);
+        assert_eq!(store.release(0).unwrap(), 0);
+        assert_eq!(store.release(0).unwrap(), 0x1000);
+        assert!(store.release(0).is_err());
+        assert!(store.get_ref(&digest1).unwrap().is_none());
+        let data3 = vec![3u8; 0x800];
+        let digest3 = RafsDigest::from_buf(&data3, digest::Algorithm::Blake3);
+        assert_eq!(store.insert(&digest3, &data3).unwrap(), 0);
+
+        // Index and data survive restart.
+        drop(store);
+        let store = ChunkStore::open(work_dir).unwrap();
+        assert_eq!(store.chunk_count(), 2);
+        assert_eq!(store.get_ref(&digest2).unwrap(), Some(1));
+        let mut buf = vec![0u8; 0x800];
+        store.read(0, &digest3, &mut buf).unwrap();
+        assert_eq!(buf, data3);
+        assert_eq!(store.insert(&digest1, &data1).unwrap(), 2);
+
+        // Invalidated chunks are stored again, and released with their last references.
+        assert_eq!(store.get_ref(&digest1).unwrap(), Some(2));
+        store.invalidate(2);
+        assert!(store.get_ref(&digest1).unwrap().is_none());
+        assert_eq!(store.insert(&digest1, &data1).unwrap(), 3);
+        assert_eq!(store.release(2).unwrap(), 0);
+        assert_eq!(store.release(2).unwrap(), 0x1000);
+        assert_eq!(store.get_ref(&digest1).unwrap(), Some(3));
+    }
+
+    #[test]
+    fn test_chunk_ref_map() {
+        let tmp_dir = TempDir::new().unwrap();
+        let work_dir = tmp_dir.as_path().to_str().unwrap();
+        let blob1 = format!("{}/blob1", work_dir);
+        let blob2 = format!("{}/blob2", work_dir);
+        let metrics = BlobcacheMetrics::new("test_chunk_ref_map", work_dir);
+        let data = vec![1u8; 0x1000];
+        let chunk1 = new_chunk(0, &data);
+        let chunk2 = new_chunk(3, &data);
+
+        let store = ChunkStore::open(work_dir).unwrap();
+        let new_map = |path: &str| {
+            ChunkRefMap::new(
+                store.clone(),
+                path,
+                4,
+                digest::Algorithm::Blake3,
+                metrics.clone(),
+            )
+            .unwrap()
+        };
+        let map1 = new_map(&blob1);
+        let map2 = new_map(&blob2);
+        assert!(!map1.is_ready(&chunk1).unwrap());
+        assert!(map1.set_ready_and_clear_pending(&chunk1).is_err());
+        assert!(map1.persist(&chunk1, &[2u8; 0x1000]).is_err());
+        map1.persist(&chunk1, &data).unwrap();
+        assert!(map1.is_ready(&chunk1).unwrap());
+        map1.set_ready_and_clear_pending(&chunk1).unwrap();
+
+        // The chunk fetched for blob1 is a cache hit for blob2.
+        assert!(map2.is_ready(&chunk2).unwrap());
+        assert_eq!(metrics.dedup_hits.count(), 1);
+        let mut buf = vec![0u8; 0x1000];
+        map2.read(&chunk2, &mut buf).unwrap();
+        assert_eq!(buf, data);
+        assert!(map2.read(&chunk1, &mut buf).is_err());
+
+        // References survive restart, and the chunk is released with the last reference.
+        drop(map1);
+        let map1 = new_map(&blob1);
+        assert_eq!(map1.get_ref(&chunk1).unwrap(), 1);
+        assert!(map1.clear_ready(&chunk1).unwrap());
+        assert!(!map1.clear_ready(&chunk1).unwrap());
+        assert_eq!(store.chunk_count(), 1);
+        let refs = format!("{}.{}", blob2, REFS_FILE_SUFFIX);
+        assert_eq!(store.release_refs_file(Path::new(&refs)).unwrap(), 0x1000);
+        assert_eq!(store.chunk_count(), 0);
+
+        // Corrupted chunks aren't shared any more.
+        let map3 = new_map(&format!("{}/blob3", work_dir));
+        map1.persist(&chunk1, &data).unwrap();
+        map1.invalidate(&chunk1).unwrap();
+        assert!(!map3.is_ready(&chunk2).unwrap());
+        map3.persist(&chunk2, &data).unwrap();
+        assert!(map1.clear_ready(&chunk1).unwrap());
+        assert_eq!(store.chunk_count(), 1);
+        map3.read(&chunk2, &mut buf).unwrap();
+        assert_eq!(buf, data);
+
+        // Chunks with corrupted data are dropped when read, and can be stored again.
+        let slot = map3.get_ref(&chunk2).unwrap() - 1;
+        let offset = store.state.lock().unwrap().records[slot as usize].offset;
+        store.data.write_all_at(&[0xffu8; 16], offset).unwrap();
+        assert!(map3.read(&chunk2, &mut buf).is_err());
+        assert_eq!(map3.get_ref(&chunk2).unwrap(), 0);
+        assert_eq!(store.chunk_count(), 0);
+        map3.persist(&chunk2, &data).unwrap();
+        map3.read(&chunk2, &mut buf).unwrap();
+        assert_eq!(buf, data);
+        metrics.release().unwrap();
+    }
+
+    #[test]
+    fn test_chunk_store_crash() {
+        let tmp_dir = TempDir::new().unwrap();
+        let data1 = vec![1u8; 0x1000];
+        let data2 = vec![2u8; 0x1000];
+        let digest1 = RafsDigest::from_buf(&data1, digest::Algorithm::Blake3);
+        let digest2 = RafsDigest::from_buf(&data2, digest::Algorithm::Blake3);
+
+        let store = ChunkStore::new(tmp_dir.as_path()).unwrap();
+        assert!(!store.need_checkpoint());
+        store.insert(&digest1, &data1).unwrap();
+        // Checkpoints are left to the owner, unless many chunks have been updated.
+        assert!(!store.need_checkpoint());
+        store.checkpoint().unwrap();
+        drop(store);
+
+        let store = ChunkStore::new(tmp_dir.as_path()).unwrap();
+        assert_eq!(store.get_ref(&digest1).unwrap(), Some(0));
+        store.insert(&digest2, &data2).unwrap();
+        // Simulate a crash before the next checkpoint, so the store can't be trusted. The lock is
+        // released by the kernel when the process exits.
+        flock(store.index.as_raw_fd(), FlockArg::Unlock).unwrap();
+        std::mem::forget(store);
+
+        let store = ChunkStore::new(tmp_dir.as_path()).unwrap();
+        assert_eq!(store.chunk_count(), 0);
+        assert_eq!(store.insert(&digest2, &data2).unwrap(), 0);
+        drop(store);
+
+        // The store is checkpointed when dropped.
+        let store = ChunkStore::new(tmp_dir.as_path()).unwrap();
+        assert_eq!(store.chunk_count(), 1);
+        let mut buf = vec![0u8; 0x1000];
+        store.read(0, &digest2, &mut buf).unwrap();
+        assert_eq!(buf, data2);
+    }
+
+    #[test]
+    fn test_chunk_store_lock() {
+        let tmp_dir = TempDir::new().unwrap();
+        let work_dir = tmp_dir.as_path();
+        let store = ChunkStore::new(work_dir).unwrap();
+
+        // Locks are held by open files, so another open file behaves like another process.
+        let index = File::open(work_dir.join(INDEX_FILE_NAME)).unwrap();
+        let timeout = Duration::from_millis(200);
+        let start = Instant::now();
+        assert!(lock_index(&index, work_dir, timeout).is_err());
+        assert!(start.elapsed() >= timeout);
+
+        drop(store);
+        lock_index(&index, work_dir, timeout).unwrap();
+    }
+}
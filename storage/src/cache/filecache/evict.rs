@@ -0,0 +1,433 @@
+// Copyright (C) 2022 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Capacity management for the file cache.
+//!
+//! All cached files for a blob live in the cache working directory, named as `$blob_id` or
+//! `$blob_id.compressed` for data and `$blob_id.$suffix` for state and metadata. When the disk
+//! space used by those files exceeds the configured capacity, the `FileCacheEvictor` removes all
+//! files belonging to the least recently used blobs which are not referenced by any `BlobCache`
+//! object. Other files in the working directory are never accounted or removed.
+//!
+//! The `FileCacheEvictor` may also periodically reclaim disk space of cold chunks inside cached
+//! blob files in use, by punching holes for chunks not accessed for a while.
+//!
+//! With the chunk store enabled, disk space used by the chunk store is counted too, and references
+//! recorded in `$blob_id.chunk_refs` are dropped when evicting a blob, so chunks not referenced
+//! by any other blob are released from the chunk store.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Result;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nix::sys::statvfs::statvfs;
+use nydus_api::http::FileCacheConfig;
+use nydus_utils::metrics::{BlobcacheMetrics, Metric};
+
+use crate::cache::cachedfile::FileCacheEntry;
+use crate::cache::filecache::chunkstore::{ChunkStore, REFS_FILE_SUFFIX};
+use crate::cache::filecache::COMPRESSED_FILE_SUFFIX;
+
+/// Interval in seconds to check disk space usage of the cache working directory.
+const EVICTION_CHECK_INTERVAL: u64 = 10;
+/// Evict blobs until the disk space usage drops below this percentage of the capacity.
+const EVICTION_LOW_WATERMARK: u64 = 90;
+/// Suffixes of state and metadata files cached for a blob, named as `$blob_id.$suffix`.
+const STATE_FILE_SUFFIXES: [&str; 5] = [
+    "chunk_map",
+    "compressed.chunk_map",
+    "range_map",
+    "blob.meta",
+    REFS_FILE_SUFFIX,
+];
+
+// Blob ids are hex strings of sha256 or blake3 digests.
+fn is_blob_id(id: &str) -> bool {
+    id.len() == 64 && id.bytes().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Compute the capacity in bytes of the cache working directory, zero means no limit.
+pub(crate) fn get_cache_capacity(config: &FileCacheConfig, work_dir: &str) -> Result<u64> {
+    if config.capacity_percent > 100 {
+        return Err(einval!(format!(
+            "invalid file cache capacity percent {}",
+            config.capacity_percent
+        )));
+    }
+
+    let mut capacity = config.capacity;
+    if config.capacity_percent > 0 {
+        let stat = statvfs(work_dir).map_err(|e| {
+            eother!(format!(
+                "failed to statvfs file cache work_dir {}, {}",
+                work_dir, e
+            ))
+        })?;
+        let total = (stat.blocks() as u64).saturating_mul(stat.fragment_size() as u64);
+        let limit = total / 100 * config.capacity_percent as u64;
+        if capacity == 0 || limit < capacity {
+            capacity = limit;
+        }
+    }
+
+    Ok(capacity)
+}
+
+/// Files cached for a blob.
+struct CachedBlobFiles {
+    files: Vec<PathBuf>,
+    size: u64,
+    last_access: SystemTime,
+}
+
+/// Evictor to keep disk space usage of the cache working directory under the capacity.
+pub(crate) struct FileCacheEvictor {
+    blobs: Arc<RwLock<HashMap<String, Arc<FileCacheEntry>>>>,
+    metrics: Arc<BlobcacheMetrics>,
+    work_dir: String,
+    capacity: u64,
+    cold_chunk_secs: u64,
+    chunk_store: Option<Arc<ChunkStore>>,
+    closed: Arc<AtomicBool>,
+    // Time when blobs were last referenced by `BlobCache` objects.
+    last_access: Mutex<HashMap<String, SystemTime>>,
+    // Serialize cache entry creation with eviction, so files won't be removed while opening.
+    creation_lock: Mutex<()>,
+}
+
+impl FileCacheEvictor {
+    /// Create a new instance of `FileCacheEvictor`.
+    pub fn new(
+        blobs: Arc<RwLock<HashMap<String, Arc<FileCacheEntry>>>>,
+        metrics: Arc<BlobcacheMetrics>,
+        work_dir: &str,
+        capacity: u64,
+        cold_chunk_secs: u64,
+        chunk_store: Option<Arc<ChunkStore>>,
+        closed: Arc<AtomicBool>,
+    ) -> Self {
+        FileCacheEvictor {
+            blobs,
+            metrics,
+            work_dir: work_dir.to_string(),
+            capacity,
+            cold_chunk_secs,
+            chunk_store,
+            closed,
+            last_access: Mutex::new(HashMap::new()),
+            creation_lock: Mutex::new(()),
+        }
+    }
+
+    /// Start a background thread to periodically check and evict cached blobs.
+    pub fn start(evictor: Arc<FileCacheEvictor>) -> Result<()> {
+        thread::Builder::new()
+            .name("filecache_evictor".to_string())
+            .spawn(move || evictor.run_loop())
+            .map(|_| ())
+    }
+
+    /// Lock to prevent eviction while creating a new cache entry.
+    pub fn lock_creation(&self) -> MutexGuard<()> {
+        self.creation_lock.lock().unwrap()
+    }
+
+    /// Record that the blob is being used.
+    pub fn touch(&self, blob_id: &str) {
+        self.last_access
+            .lock()
+            .unwrap()
+            .insert(blob_id.to_string(), SystemTime::now());
+    }
+
+    /// Evict least recently used blobs if disk space usage exceeds the capacity.
+    ///
+    /// Return disk space in bytes reclaimed by eviction.
+    pub fn evict(&self) -> Result<u64> {
+        if self.capacity == 0 {
+            return Ok(0);
+        }
+
+        let mut cached = self.scan_work_dir()?;
+        let mut usage: u64 = cached.values().map(|v| v.size).sum();
+        if let Some(store) = self.chunk_store.as_ref() {
+            usage += store.disk_usage();
+        }
+        if usage <= self.capacity {
+            return Ok(0);
+        }
+
+        let target = self.capacity / 100 * EVICTION_LOW_WATERMARK;
+        let _guard = self.lock_creation();
+        let mut blobs = self.blobs.write().unwrap();
+        let mut last_access = self.last_access.lock().unwrap();
+        let now = SystemTime::now();
+
+        let mut candidates = Vec::with_capacity(cached.len());
+        for (id, files) in cached.iter() {
+            match blobs.get(id) {
+                // The blob is still used by some `BlobCache` objects.
+                Some(entry) if Arc::strong_count(entry) > 1 => {
+                    last_access.insert(id.to_string(), now);
+                }
+                _ => {
+                    let time = last_access.get(id).copied().unwrap_or(files.last_access);
+                    candidates.push((time, id.to_string()));
+                }
+            }
+        }
+        candidates.sort();
+
+        let mut reclaimed = 0;
+        for (_, id) in candidates {
+            if usage <= target {
+                break;
+            }
+            // Safe to unwrap because all candidates come from `cached`.
+            let files = cached.remove(&id).unwrap();
+            // Drop the cache entry to close the data file and unmap the chunk map file.
+            blobs.remove(&id);
+            last_access.remove(&id);
+            self.metrics.underlying_files.lock().unwrap().remove(&id);
+            let mut size = files.size;
+            for f in files.files.iter() {
+                if let Some(store) = self.chunk_store.as_ref() {
+                    if f.extension().and_then(|v| v.to_str()) == Some(REFS_FILE_SUFFIX) {
+                        match store.release_refs_file(f) {
+                            Ok(v) => size += v,
+                            Err(e) => warn!(
+                                "filecache: failed to release chunks referenced by {:?}, {}",
+                                f, e
+                            ),
+                        }
+                    }
+                }
+                if let Err(e) = fs::remove_file(f) {
+                    warn!("filecache: failed to remove cached file {:?}, {}", f, e);
+                }
+            }
+
+            info!(
+                "filecache: evict blob {}, reclaim {} bytes of disk space",
+                id, size
+            );
+            usage = usage.saturating_sub(size);
+            reclaimed += size;
+            self.metrics.evicted_blobs.inc();
+            self.metrics.evicted_size.add(size);
+        }
+
+        if usage > self.capacity {
+            warn!(
+                "filecache: disk space usage {} still exceeds capacity {} after eviction",
+                usage, self.capacity
+            );
+        }
+
+        Ok(reclaimed)
+    }
+
+    /// Reclaim disk space of cold chunks for all cached blobs.
+    pub fn reclaim_cold_chunks(&self) -> u64 {
+        if self.cold_chunk_secs == 0 {
+            return 0;
+        }
+
+        let entries: Vec<Arc<FileCacheEntry>> =
+            self.blobs.read().unwrap().values().cloned().collect();
+        let mut reclaimed = 0;
+        for entry in entries {
+            match entry.reclaim_cold_chunks(self.cold_chunk_secs) {
+                Ok((_, size)) => reclaimed += size,
+                Err(e) => warn!(
+                    "filecache: failed to reclaim cold chunks of blob {}, {}",
+                    entry.blob_info.blob_id(),
+                    e
+                ),
+            }
+        }
+
+        reclaimed
+    }
+
+    fn run_loop(&self) {
+        let mut elapsed = 0;
+        while !self.closed.load(Ordering::Acquire) {
+            thread::sleep(Duration::from_secs(1));
+            elapsed += 1;
+            if elapsed >= EVICTION_CHECK_INTERVAL {
+                elapsed = 0;
+                self.reclaim_cold_chunks();
+                if let Err(e) = self.evict() {
+                    warn!("filecache: failed to evict cached blobs, {}", e);
+                }
+            }
+        }
+        info!("filecache: evictor thread exits");
+    }
+
+    // Group files in the working directory by blob id, and collect disk usage and access time.
+    fn scan_work_dir(&self) -> Result<HashMap<String, CachedBlobFiles>> {
+        let mut cached: HashMap<String, CachedBlobFiles> = HashMap::new();
+
+        for entry in fs::read_dir(&self.work_dir)? {
+            let entry = entry?;
+            let md = match entry.metadata() {
+                Ok(md) => md,
+                // The file may have been removed concurrently.
+                Err(_) => continue,
+            };
+            if !md.is_file() {
+                continue;
+            }
+            let name = entry.file_name();
+            let name = match name.to_str() {
+                Some(v) => v,
+                None => continue,
+            };
+            // Only account files created by the file cache, the working directory may be shared.
+            let (blob_id, suffix) = match name.split_once('.') {
+                Some((id, suffix)) => (id, Some(suffix)),
+                None => (name, None),
+            };
+            let is_data_file = suffix.is_none() || suffix == Some(COMPRESSED_FILE_SUFFIX);
+            if !is_blob_id(blob_id)
+                || !(is_data_file || STATE_FILE_SUFFIXES.contains(&suffix.unwrap()))
+            {
+                continue;
+            }
+
+            let size = md.blocks().saturating_mul(512);
+            let time = std::cmp::max(md.atime(), md.mtime());
+            let time = UNIX_EPOCH + Duration::from_secs(std::cmp::max(time, 0) as u64);
+            let files = cached
+                .entry(blob_id.to_string())
+                .or_insert_with(|| CachedBlobFiles {
+                    files: Vec::new(),
+                    size: 0,
+                    last_access: UNIX_EPOCH,
+                });
+            files.size += size;
+            files.last_access = std::cmp::max(files.last_access, time);
+            // Put state files ahead of data files, so they will be removed first.
+            if is_data_file {
+                files.files.push(entry.path());
+            } else {
+                files.files.insert(0, entry.path());
+            }
+        }
+
+        Ok(cached)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use vmm_sys_util::tempdir::TempDir;
+
+    fn create_file(dir: &TempDir, name: &str, size: usize) {
+        let mut file = fs::File::create(dir.as_path().join(name)).unwrap();
+        file.write_all(&vec![0x5au8; size]).unwrap();
+        file.sync_all().unwrap();
+    }
+
+    #[test]
+    fn test_get_cache_capacity() {
+        let tmp_dir = TempDir::new().unwrap();
+        let work_dir = tmp_dir.as_path().to_str().unwrap();
+        let mut config: FileCacheConfig = serde_json::from_str("{}").unwrap();
+
+        assert_eq!(get_cache_capacity(&config, work_dir).unwrap(), 0);
+        config.capacity = 0x10_0000;
+        assert_eq!(get_cache_capacity(&config, work_dir).unwrap(), 0x10_0000);
+        config.capacity = u64::MAX;
+        config.capacity_percent = 50;
+        let capacity = get_cache_capacity(&config, work_dir).unwrap();
+        assert!(capacity > 0 && capacity < u64::MAX);
+        config.capacity_percent = 101;
+        assert!(get_cache_capacity(&config, work_dir).is_err());
+    }
+
+    #[test]
+    fn test_evict_lru_blobs() {
+        let tmp_dir = TempDir::new().unwrap();
+        let work_dir = tmp_dir.as_path().to_str().unwrap();
+        let blob1 = "1".repeat(64);
+        let blob2 = "2".repeat(64);
+        let blob3 = "3".repeat(64);
+        create_file(&tmp_dir, &blob1, 0x10000);
+        create_file(&tmp_dir, &format!("{}.chunk_map", blob1), 0x1000);
+        create_file(&tmp_dir, &blob2, 0x10000);
+        create_file(&tmp_dir, &format!("{}.chunk_map", blob2), 0x1000);
+        create_file(&tmp_dir, &format!("{}.compressed", blob3), 0x10000);
+        create_file(&tmp_dir, &format!("{}.compressed.chunk_map", blob3), 0x1000);
+        // Files not created by the file cache are left alone.
+        create_file(&tmp_dir, "blob4", 0x10000);
+        create_file(&tmp_dir, &format!("{}.bak", blob2), 0x1000);
+
+        let metrics = BlobcacheMetrics::new("test_evict_lru_blobs", work_dir);
+        let evictor = FileCacheEvictor::new(
+            Arc::new(RwLock::new(HashMap::new())),
+            metrics.clone(),
+            work_dir,
+            0x30000,
+            0,
+            None,
+            Arc::new(AtomicBool::new(false)),
+        );
+        let cached = evictor.scan_work_dir().unwrap();
+        assert_eq!(cached.len(), 3);
+        assert_eq!(cached.get(&blob1).unwrap().files.len(), 2);
+        assert!(cached.get(&blob1).unwrap().files[0]
+            .to_str()
+            .unwrap()
+            .ends_with("chunk_map"));
+        assert_eq!(cached.get(&blob2).unwrap().files.len(), 2);
+        assert_eq!(cached.get(&blob3).unwrap().files.len(), 2);
+        assert!(cached.get(&blob3).unwrap().files[1]
+            .to_str()
+            .unwrap()
+            .ends_with(&format!("{}.compressed", blob3)));
+
+        // blob2 is the least recently used one, then blob1.
+        let now = SystemTime::now();
+        evictor.last_access.lock().unwrap().insert(
+            blob2.clone(),
+            now.checked_sub(Duration::from_secs(20)).unwrap(),
+        );
+        evictor.last_access.lock().unwrap().insert(
+            blob1.clone(),
+            now.checked_sub(Duration::from_secs(10)).unwrap(),
+        );
+        evictor.touch(&blob3);
+
+        assert!(evictor.evict().unwrap() > 0);
+        assert!(!tmp_dir.as_path().join(&blob2).exists());
+        assert!(!tmp_dir
+            .as_path()
+            .join(format!("{}.chunk_map", blob2))
+            .exists());
+        assert!(tmp_dir.as_path().join(format!("{}.bak", blob2)).exists());
+        assert!(tmp_dir.as_path().join("blob4").exists());
+        assert!(tmp_dir
+            .as_path()
+            .join(format!("{}.compressed", blob3))
+            .exists());
+        assert_eq!(metrics.evicted_blobs.count(), 1);
+        assert!(metrics.evicted_size.count() >= 0x11000);
+
+        // Usage is under the capacity now.
+        assert_eq!(evictor.evict().unwrap(), 0);
+        assert_eq!(evictor.reclaim_cold_chunks(), 0);
+        metrics.release().unwrap();
+    }
+}
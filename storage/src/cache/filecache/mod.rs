@@ -4,7 +4,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::collections::HashMap;
-use std::fs::OpenOptions;
+use std::fs::{File, OpenOptions};
 use std::io::Result;
 use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::{Arc, RwLock};
@@ -15,7 +15,9 @@ use nydus_api::http::FileCacheConfig;
 use nydus_utils::metrics::BlobcacheMetrics;
 
 use crate::backend::BlobBackend;
-use crate::cache::cachedfile::FileCacheEntry;
+use crate::cache::cachedfile::{ChunkAccessRecord, ChunkInfoRecord, ChunkLru, FileCacheEntry};
+use crate::cache::scrub::CacheScrubber;
+use crate::cache::singleflight::SingleFlight;
 use crate::cache::state::{BlobStateMap, ChunkMap, DigestedChunkMap, IndexedChunkMap};
 use crate::cache::worker::{AsyncPrefetchConfig, AsyncWorkerMgr};
 use crate::cache::{BlobCache, BlobCacheMgr};
@@ -23,8 +25,30 @@ use crate::device::{BlobFeatures, BlobInfo};
 use crate::factory::CacheConfig;
 use crate::meta::BlobMetaInfo;
 
+mod chunkstore;
+mod evict;
+
+use self::chunkstore::ChunkStore;
+use self::evict::{get_cache_capacity, FileCacheEvictor};
+
+pub(crate) use self::chunkstore::ChunkRefMap;
+
+/// The name suffix of cache files holding compressed chunk data, named as `$blob_id.compressed`.
+///
+/// Layout of cache files differs in compressed and uncompressed mode, so use different files to
+/// avoid misusing cache files created in the other mode.
+pub(crate) const COMPRESSED_FILE_SUFFIX: &str = "compressed";
+
 /// An implementation of [BlobCacheMgr](../trait.BlobCacheMgr.html) to improve performance by
-/// caching uncompressed blob with local storage.
+/// caching blob data with local storage.
+///
+/// Chunk data is cached in uncompressed form at the uncompressed offset by default. If compressed
+/// mode is enabled, chunk data is cached as is at the compressed offset to save disk space, and
+/// decompressed on every read from the cache file.
+///
+/// If chunk deduplication is enabled, decompressed chunk data of all blobs is stored in a
+/// content-addressed chunk store shared by all blobs in the working directory instead, so chunks
+/// with the same digest are cached only once.
 #[derive(Clone)]
 pub struct FileCacheMgr {
     blobs: Arc<RwLock<HashMap<String, Arc<FileCacheEntry>>>>,
@@ -37,7 +61,12 @@ pub struct FileCacheMgr {
     validate: bool,
     disable_indexed_map: bool,
     is_compressed: bool,
+    cold_chunk_secs: u64,
+    chunk_lru_size: u64,
+    chunk_store: Option<Arc<ChunkStore>>,
     closed: Arc<AtomicBool>,
+    evictor: Option<Arc<FileCacheEvictor>>,
+    scrubber: Option<Arc<CacheScrubber>>,
 }
 
 impl FileCacheMgr {
@@ -54,9 +83,46 @@ impl FileCacheMgr {
         let metrics = BlobcacheMetrics::new(id, work_dir);
         let prefetch_config: Arc<AsyncPrefetchConfig> = Arc::new(config.prefetch_config.into());
         let worker_mgr = AsyncWorkerMgr::new(metrics.clone(), prefetch_config.clone())?;
+        let blobs = Arc::new(RwLock::new(HashMap::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let capacity = get_cache_capacity(&blob_config, work_dir)?;
+        let cold_chunk_secs = blob_config.cold_chunk_secs;
+        let chunk_store = if blob_config.dedup_chunks {
+            if config.cache_compressed {
+                return Err(einval!(
+                    "chunk deduplication isn't supported in compressed mode"
+                ));
+            }
+            Some(ChunkStore::open(work_dir)?)
+        } else {
+            None
+        };
+        let evictor = if capacity > 0 || cold_chunk_secs > 0 {
+            Some(Arc::new(FileCacheEvictor::new(
+                blobs.clone(),
+                metrics.clone(),
+                work_dir,
+                capacity,
+                cold_chunk_secs,
+                chunk_store.clone(),
+                closed.clone(),
+            )))
+        } else {
+            None
+        };
+        let scrubber = if blob_config.scrub.interval > 0 {
+            Some(Arc::new(CacheScrubber::new(
+                blobs.clone(),
+                metrics.clone(),
+                &blob_config.scrub,
+                closed.clone(),
+            )))
+        } else {
+            None
+        };
 
         Ok(FileCacheMgr {
-            blobs: Arc::new(RwLock::new(HashMap::new())),
+            blobs,
             backend,
             metrics,
             prefetch_config,
@@ -66,7 +132,12 @@ impl FileCacheMgr {
             disable_indexed_map: blob_config.disable_indexed_map,
             validate: config.cache_validate,
             is_compressed: config.cache_compressed,
-            closed: Arc::new(AtomicBool::new(false)),
+            cold_chunk_secs,
+            chunk_lru_size: blob_config.chunk_lru_size,
+            chunk_store,
+            closed,
+            evictor,
+            scrubber,
         })
     }
 
@@ -78,10 +149,16 @@ impl FileCacheMgr {
     // Create a file cache entry for the specified blob object if not present, otherwise
     // return the existing one.
     fn get_or_create_cache_entry(&self, blob: &Arc<BlobInfo>) -> Result<Arc<FileCacheEntry>> {
+        if let Some(evictor) = self.evictor.as_ref() {
+            evictor.touch(blob.blob_id());
+        }
         if let Some(entry) = self.get(blob) {
             return Ok(entry);
         }
 
+        // Prevent the evictor from removing cache files of the blob while creating the entry.
+        let _guard = self.evictor.as_ref().map(|v| v.lock_creation());
+
         let entry = FileCacheEntry::new_file_cache(
             self,
             blob.clone(),
@@ -107,7 +184,14 @@ impl FileCacheMgr {
 
 impl BlobCacheMgr for FileCacheMgr {
     fn init(&self) -> Result<()> {
-        AsyncWorkerMgr::start(self.worker_mgr.clone())
+        AsyncWorkerMgr::start(self.worker_mgr.clone())?;
+        if let Some(evictor) = self.evictor.as_ref() {
+            FileCacheEvictor::start(evictor.clone())?;
+        }
+        if let Some(scrubber) = self.scrubber.as_ref() {
+            CacheScrubber::start(scrubber.clone())?;
+        }
+        Ok(())
     }
 
     fn destroy(&self) {
@@ -143,6 +227,12 @@ impl BlobCacheMgr for FileCacheMgr {
             guard.remove(key);
         }
 
+        if let Some(evictor) = self.evictor.as_ref() {
+            if let Err(e) = evictor.evict() {
+                warn!("filecache: failed to evict cached blobs, {}", e);
+            }
+        }
+
         self.blobs.read().unwrap().len() == 0
     }
 
@@ -171,17 +261,36 @@ impl FileCacheEntry {
         workers: Arc<AsyncWorkerMgr>,
     ) -> Result<Self> {
         let blob_file_path = format!("{}/{}", mgr.work_dir, blob_info.blob_id());
-        let file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .read(true)
-            .open(&blob_file_path)?;
-        let (chunk_map, is_direct_chunkmap) =
-            Self::create_chunk_map(mgr, &blob_info, &blob_file_path)?;
+        let cache_file_path = if mgr.is_compressed {
+            format!("{}.{}", blob_file_path, COMPRESSED_FILE_SUFFIX)
+        } else {
+            blob_file_path.clone()
+        };
         let reader = mgr
             .backend
             .get_reader(blob_info.blob_id())
             .map_err(|_e| eio!("failed to get blob reader"))?;
+        let chunk_refs = Self::create_chunk_refs(mgr, &blob_info, &blob_file_path)?;
+        let (file, chunk_map, is_direct_chunkmap) = match chunk_refs.as_ref() {
+            // Chunk data lives in the shared data file of the chunk store.
+            Some(refs) => (
+                mgr.chunk_store.as_ref().unwrap().data_file(),
+                Arc::new(BlobStateMap::from(refs.clone())) as Arc<dyn ChunkMap>,
+                true,
+            ),
+            None => {
+                let file = Arc::new(
+                    OpenOptions::new()
+                        .create(true)
+                        .write(true)
+                        .read(true)
+                        .open(&cache_file_path)?,
+                );
+                let (chunk_map, is_direct_chunkmap) =
+                    Self::create_chunk_map(mgr, &blob_info, &cache_file_path, &file)?;
+                (file, chunk_map, is_direct_chunkmap)
+            }
+        };
 
         let blob_size = Self::get_blob_size(&reader, &blob_info)?;
         let compressor = blob_info.compressor();
@@ -189,7 +298,8 @@ impl FileCacheEntry {
         let is_stargz = blob_info.is_stargz();
         let is_compressed = mgr.is_compressed || is_stargz;
         let need_validate = (mgr.validate || !is_direct_chunkmap) && !is_stargz;
-        let is_get_blob_object_supported = !mgr.is_compressed && is_direct_chunkmap && !is_stargz;
+        let is_get_blob_object_supported =
+            !mgr.is_compressed && is_direct_chunkmap && !is_stargz && chunk_refs.is_none();
 
         trace!(
             "comp {} direct {} startgz {}",
@@ -197,13 +307,23 @@ impl FileCacheEntry {
             is_direct_chunkmap,
             is_stargz
         );
-        let meta = if is_get_blob_object_supported && blob_info.meta_ci_is_valid() {
+        // Chunks are located by the chunk information array in compressed mode too.
+        let use_meta = is_get_blob_object_supported || (mgr.is_compressed && !is_stargz);
+        let meta = if use_meta && blob_info.meta_ci_is_valid() {
             // Set cache file to its expected size.
+            let expected_size = if mgr.is_compressed {
+                blob_info.compressed_size()
+            } else {
+                blob_info.uncompressed_size()
+            };
             let file_size = file.metadata()?.len();
             if file_size == 0 {
-                file.set_len(blob_info.uncompressed_size())?;
-            } else {
-                assert_eq!(file_size, blob_info.uncompressed_size());
+                file.set_len(expected_size)?;
+            } else if file_size != expected_size {
+                return Err(einval!(format!(
+                    "size of cache file {} is {}, expect {}",
+                    cache_file_path, file_size, expected_size
+                )));
             }
 
             Some(Arc::new(BlobMetaInfo::new(
@@ -214,16 +334,40 @@ impl FileCacheEntry {
         } else {
             None
         };
+        // Reclaiming cold chunks depends on the chunk information array to locate chunk data.
+        let chunk_access = if mgr.cold_chunk_secs > 0 && meta.is_some() {
+            Some(ChunkAccessRecord::new(blob_info.chunk_count()))
+        } else {
+            None
+        };
+        // Chunk digests are provided by the filesystem, except for stargz images.
+        let chunk_infos = if mgr.scrubber.is_some() && !is_stargz {
+            Some(ChunkInfoRecord::new(blob_info.chunk_count()))
+        } else {
+            None
+        };
+        // Chunks are indexed by chunk id in the LRU cache, which needs direct chunk map.
+        let chunk_lru = if mgr.chunk_lru_size > 0 && is_compressed && is_direct_chunkmap {
+            Some(ChunkLru::new(mgr.chunk_lru_size))
+        } else {
+            None
+        };
 
         Ok(FileCacheEntry {
             blob_info,
             chunk_map,
-            file: Arc::new(file),
+            chunk_access,
+            chunk_infos,
+            chunk_lru,
+            chunk_refs,
+            checkpointing: Arc::new(AtomicBool::new(false)),
+            file,
             meta,
             metrics: mgr.metrics.clone(),
             prefetch_state: Arc::new(AtomicU32::new(0)),
             reader,
             runtime,
+            single_flight: SingleFlight::new(),
             workers,
 
             blob_size,
@@ -239,10 +383,44 @@ impl FileCacheEntry {
         })
     }
 
+    // Create the chunk reference map if chunk deduplication is enabled and supported by the blob.
+    fn create_chunk_refs(
+        mgr: &FileCacheMgr,
+        blob_info: &BlobInfo,
+        blob_file: &str,
+    ) -> Result<Option<ChunkRefMap>> {
+        let store = match mgr.chunk_store.as_ref() {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        // Chunks are identified by digest in the chunk store, and chunk references are indexed by
+        // chunk index.
+        if blob_info.is_stargz()
+            || blob_info.has_feature(BlobFeatures::V5_NO_EXT_BLOB_TABLE)
+            || blob_info.has_feature(BlobFeatures::V6_NO_CHUNK_DIGEST)
+        {
+            info!(
+                "filecache: chunk deduplication isn't supported by blob {}",
+                blob_info.blob_id()
+            );
+            return Ok(None);
+        }
+
+        ChunkRefMap::new(
+            store.clone(),
+            blob_file,
+            blob_info.chunk_count(),
+            blob_info.digester(),
+            mgr.metrics.clone(),
+        )
+        .map(Some)
+    }
+
     fn create_chunk_map(
         mgr: &FileCacheMgr,
         blob_info: &BlobInfo,
         blob_file: &str,
+        file: &Arc<File>,
     ) -> Result<(Arc<dyn ChunkMap>, bool)> {
         let mut direct_chunkmap = true;
         // The builder now records the number of chunks in the blob table, so we can
@@ -255,11 +433,9 @@ impl FileCacheEntry {
             direct_chunkmap = false;
             Arc::new(BlobStateMap::from(DigestedChunkMap::new()))
         } else {
-            Arc::new(BlobStateMap::from(IndexedChunkMap::new(
-                blob_file,
-                blob_info.chunk_count(),
-                true,
-            )?))
+            let mut map = IndexedChunkMap::new(blob_file, blob_info.chunk_count(), true)?;
+            map.set_data_file(file.clone());
+            Arc::new(BlobStateMap::from(map))
         };
 
         Ok((chunk_map, direct_chunkmap))
@@ -291,10 +467,19 @@ pub mod blob_cache_tests {
     };
     */
 
+    use std::os::unix::fs::FileExt;
+
+    use nydus_api::http::BlobPrefetchConfig;
+    use nydus_utils::digest::{self, RafsDigest};
+    use nydus_utils::metrics::{BackendMetrics, Metric};
     use vmm_sys_util::tempdir::TempDir;
     use vmm_sys_util::tempfile::TempFile;
 
     use super::*;
+    use crate::cache::HOT_CHUNK_CACHE;
+    use crate::device::v5::BlobV5ChunkInfo;
+    use crate::device::{BlobChunkInfo, BlobIoChunk, BlobIoDesc};
+    use crate::test::{MockBackend, MockChunkInfo};
 
     #[test]
     fn test_blob_cache_config() {
@@ -326,6 +511,103 @@ pub mod blob_cache_tests {
         assert!(blob_config.get_work_dir().is_err());
     }
 
+    #[test]
+    fn test_scrub_corrupted_chunk() {
+        let tmp_dir = TempDir::new().unwrap();
+        let config = CacheConfig {
+            cache_type: "blobcache".to_string(),
+            cache_compressed: true,
+            cache_config: serde_json::json!({
+                "work_dir": tmp_dir.as_path().to_str().unwrap(),
+                "chunk_lru_size": 0x10000,
+                "scrub": { "interval": 3600 },
+            }),
+            cache_validate: false,
+            prefetch_config: BlobPrefetchConfig::default(),
+        };
+        let backend = Arc::new(MockBackend {
+            metrics: BackendMetrics::new("test_scrub_corrupted_chunk", "mock"),
+        });
+        let runtime = Arc::new(Runtime::new().unwrap());
+        let mgr =
+            FileCacheMgr::new(config, backend, runtime, "test_scrub_corrupted_chunk").unwrap();
+
+        let blob_id = "1".repeat(64);
+        let blob = Arc::new(BlobInfo::new(
+            0,
+            blob_id.clone(),
+            0x2000,
+            0x2000,
+            0x1000,
+            2,
+            BlobFeatures::empty(),
+        ));
+        let entry = mgr.get_or_create_cache_entry(&blob).unwrap();
+        assert!(entry.chunk_infos.is_some());
+        assert!(entry.chunk_lru.is_some());
+
+        // Cache two chunks with digests provided by the filesystem, and keep them in memory. The
+        // chunk LRU cache is bypassed when the hot chunk cache is enabled.
+        HOT_CHUNK_CACHE.set_capacity(0x10_0000);
+        let data = vec![0x5au8; 0x1000];
+        let mut chunks = Vec::new();
+        for index in 0..2u32 {
+            let chunk = MockChunkInfo {
+                block_id: RafsDigest::from_buf(&data, digest::Algorithm::Blake3),
+                compress_size: 0x1000,
+                uncompress_size: 0x1000,
+                compress_offset: index as u64 * 0x1000,
+                uncompress_offset: index as u64 * 0x1000,
+                index,
+                ..Default::default()
+            };
+            let chunk = BlobIoChunk::from(Arc::new(chunk) as Arc<dyn BlobV5ChunkInfo>);
+            entry
+                .file
+                .write_all_at(&data, chunk.compress_offset())
+                .unwrap();
+            entry
+                .chunk_map
+                .set_ready_and_clear_pending(chunk.as_base())
+                .unwrap();
+            entry.cache_chunk_in_memory(&chunk, &data);
+            chunks.push(chunk);
+        }
+        let bios: Vec<BlobIoDesc> = chunks
+            .iter()
+            .map(|c| BlobIoDesc::new(blob.clone(), c.clone(), 0, 0x1000, true))
+            .collect();
+        entry.record_chunk_infos(&bios);
+
+        // Corrupt the second chunk in the cache file.
+        entry.file.write_all_at(&[0xa5u8; 0x10], 0x1000).unwrap();
+        entry.scrub(&|_| true).unwrap();
+
+        let report = &mgr.metrics.scrub;
+        assert_eq!(report.scanned_chunks.count(), 2);
+        assert_eq!(report.skipped_chunks.count(), 0);
+        assert_eq!(report.corrupted_chunks.count(), 1);
+        assert_eq!(report.repaired_chunks.count(), 1);
+        {
+            let corruptions = report.recent_corruptions.lock().unwrap();
+            assert_eq!(corruptions.len(), 1);
+            assert_eq!(corruptions[0].blob_id, blob_id);
+            assert_eq!(corruptions[0].chunk_index, 1);
+            assert!(corruptions[0].repaired);
+        }
+
+        assert!(entry.chunk_map.is_ready(chunks[0].as_base()).unwrap());
+        assert!(!entry.chunk_map.is_ready(chunks[1].as_base()).unwrap());
+        assert!(HOT_CHUNK_CACHE.contains(&blob_id, 0));
+        assert!(!HOT_CHUNK_CACHE.contains(&blob_id, 1));
+        let lru = entry.chunk_lru.as_ref().unwrap();
+        assert!(!lru.contains(0));
+        assert!(!lru.contains(1));
+
+        HOT_CHUNK_CACHE.set_capacity(0);
+        mgr.metrics.release().unwrap();
+    }
+
     /*
        #[test]
        fn test_add() {
@@ -13,7 +13,9 @@ use nydus_api::http::FsCacheConfig;
 use nydus_utils::metrics::BlobcacheMetrics;
 
 use crate::backend::BlobBackend;
-use crate::cache::cachedfile::FileCacheEntry;
+use crate::cache::cachedfile::{ChunkInfoRecord, FileCacheEntry};
+use crate::cache::scrub::CacheScrubber;
+use crate::cache::singleflight::SingleFlight;
 use crate::cache::state::{BlobStateMap, IndexedChunkMap};
 use crate::cache::worker::{AsyncPrefetchConfig, AsyncWorkerMgr};
 use crate::cache::{BlobCache, BlobCacheMgr};
@@ -34,6 +36,7 @@ pub struct FsCacheMgr {
     work_dir: String,
     validate: bool,
     closed: Arc<AtomicBool>,
+    scrubber: Option<Arc<CacheScrubber>>,
 }
 
 impl FsCacheMgr {
@@ -50,9 +53,21 @@ impl FsCacheMgr {
         let metrics = BlobcacheMetrics::new(id, work_dir);
         let prefetch_config: Arc<AsyncPrefetchConfig> = Arc::new(config.prefetch_config.into());
         let worker_mgr = AsyncWorkerMgr::new(metrics.clone(), prefetch_config.clone())?;
+        let blobs = Arc::new(RwLock::new(HashMap::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let scrubber = if blob_config.scrub.interval > 0 {
+            Some(Arc::new(CacheScrubber::new(
+                blobs.clone(),
+                metrics.clone(),
+                &blob_config.scrub,
+                closed.clone(),
+            )))
+        } else {
+            None
+        };
 
         Ok(FsCacheMgr {
-            blobs: Arc::new(RwLock::new(HashMap::new())),
+            blobs,
             backend,
             metrics,
             prefetch_config,
@@ -60,7 +75,8 @@ impl FsCacheMgr {
             worker_mgr: Arc::new(worker_mgr),
             work_dir: work_dir.to_owned(),
             validate: config.cache_validate,
-            closed: Arc::new(AtomicBool::new(false)),
+            closed,
+            scrubber,
         })
     }
 
@@ -101,7 +117,11 @@ impl FsCacheMgr {
 
 impl BlobCacheMgr for FsCacheMgr {
     fn init(&self) -> Result<()> {
-        AsyncWorkerMgr::start(self.worker_mgr.clone())
+        AsyncWorkerMgr::start(self.worker_mgr.clone())?;
+        if let Some(scrubber) = self.scrubber.as_ref() {
+            CacheScrubber::start(scrubber.clone())?;
+        }
+        Ok(())
     }
 
     fn destroy(&self) {
@@ -197,12 +217,21 @@ impl FileCacheEntry {
         Ok(FileCacheEntry {
             blob_info: blob_info.clone(),
             chunk_map,
+            chunk_access: None,
+            chunk_infos: mgr
+                .scrubber
+                .as_ref()
+                .map(|_| ChunkInfoRecord::new(blob_info.chunk_count())),
+            chunk_lru: None,
+            chunk_refs: None,
+            checkpointing: Arc::new(AtomicBool::new(false)),
             file,
             meta,
             metrics: mgr.metrics.clone(),
             prefetch_state: Arc::new(AtomicU32::new(0)),
             reader,
             runtime,
+            single_flight: SingleFlight::new(),
             workers,
 
             blob_size,
@@ -218,3 +247,105 @@ impl FileCacheEntry {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::FileExt;
+
+    use nydus_api::http::BlobPrefetchConfig;
+    use nydus_utils::digest::{self, RafsDigest};
+    use nydus_utils::metrics::{BackendMetrics, Metric};
+    use vmm_sys_util::tempdir::TempDir;
+    use vmm_sys_util::tempfile::TempFile;
+
+    use super::*;
+    use crate::cache::state::ChunkMap;
+    use crate::device::v5::BlobV5ChunkInfo;
+    use crate::device::{BlobChunkInfo, BlobIoChunk, BlobIoDesc};
+    use crate::test::{MockBackend, MockChunkInfo};
+
+    #[test]
+    fn test_fscache_scrub() {
+        let tmp_dir = TempDir::new().unwrap();
+        let config = CacheConfig {
+            cache_type: "fscache".to_string(),
+            cache_compressed: false,
+            cache_config: serde_json::json!({
+                "work_dir": tmp_dir.as_path().to_str().unwrap(),
+                "scrub": { "interval": 3600 },
+            }),
+            cache_validate: false,
+            prefetch_config: BlobPrefetchConfig::default(),
+        };
+        let backend = Arc::new(MockBackend {
+            metrics: BackendMetrics::new("test_fscache_scrub", "mock"),
+        });
+        let runtime = Arc::new(Runtime::new().unwrap());
+        let mgr = FsCacheMgr::new(config, backend, runtime, "test_fscache_scrub").unwrap();
+
+        let mut blob = BlobInfo::new(
+            0,
+            "2".repeat(64),
+            0x2000,
+            0x2000,
+            0x1000,
+            2,
+            BlobFeatures::V6_NO_CHUNK_DIGEST,
+        );
+        let cache_file = TempFile::new().unwrap().into_file();
+        blob.set_fscache_file(Some(Arc::new(cache_file)));
+        let blob = Arc::new(blob);
+        let entry = mgr.get_or_create_cache_entry(&blob).unwrap();
+        assert!(entry.chunk_infos.is_some());
+
+        // Cache two chunks, with digests provided by the chunk table of the bootstrap.
+        let data = vec![0x5au8; 0x1000];
+        let mut bios = Vec::new();
+        for index in 0..2u32 {
+            let chunk = MockChunkInfo {
+                block_id: RafsDigest::from_buf(&data, digest::Algorithm::Blake3),
+                compress_size: 0x1000,
+                uncompress_size: 0x1000,
+                compress_offset: index as u64 * 0x1000,
+                uncompress_offset: index as u64 * 0x1000,
+                index,
+                ..Default::default()
+            };
+            let chunk = BlobIoChunk::from(Arc::new(chunk) as Arc<dyn BlobV5ChunkInfo>);
+            entry
+                .file
+                .write_all_at(&data, chunk.uncompress_offset())
+                .unwrap();
+            entry
+                .chunk_map
+                .set_ready_and_clear_pending(chunk.as_base())
+                .unwrap();
+            bios.push(BlobIoDesc::new(blob.clone(), chunk, 0, 0x1000, false));
+        }
+        entry.record_chunk_infos(&bios);
+
+        // Corrupt the second chunk in the cache file.
+        entry.file.write_all_at(&[0xa5u8; 0x10], 0x1000).unwrap();
+        entry.scrub(&|_| true).unwrap();
+
+        let report = &mgr.metrics.scrub;
+        assert_eq!(report.scanned_chunks.count(), 2);
+        assert_eq!(report.corrupted_chunks.count(), 1);
+        assert_eq!(report.repaired_chunks.count(), 1);
+        assert!(entry
+            .chunk_map
+            .is_ready(bios[0].chunkinfo.as_base())
+            .unwrap());
+        assert!(!entry
+            .chunk_map
+            .is_ready(bios[1].chunkinfo.as_base())
+            .unwrap());
+
+        // The corrupted chunk is punched out, so the kernel will ask for it again.
+        let mut buf = vec![0xffu8; 0x1000];
+        entry.file.read_exact_at(&mut buf, 0x1000).unwrap();
+        assert!(buf.iter().all(|v| *v == 0));
+        entry.file.read_exact_at(&mut buf, 0).unwrap();
+        assert_eq!(buf, data);
+    }
+}
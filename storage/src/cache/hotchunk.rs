@@ -0,0 +1,366 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! In-memory cache tier for hot chunks, shared by all blob caches.
+//!
+//! Decompressed data of frequently accessed chunks is kept in memory, to avoid reading and
+//! decompressing them from the cache file or the storage backend again on page cache misses.
+//! Chunks are indexed by blob id and chunk index, and memory used by cached chunk data is limited
+//! by a global budget shared by all mounted filesystems.
+//!
+//! Chunks are evicted in LRU order, and a TinyLFU admission policy is used to avoid polluting the
+//! cache with chunks accessed only once, such as those read by a sequential scan. Access frequency
+//! of chunks is estimated by a count-min sketch, which is halved periodically to age out history.
+//! A new chunk is only admitted if it has been accessed more frequently than chunks to be evicted.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use lazy_static::lazy_static;
+
+/// Number of rows of the count-min sketch.
+const SKETCH_DEPTH: usize = 4;
+/// Maximum value of frequency counters.
+const SKETCH_MAX_FREQ: u8 = 15;
+/// Minimum number of counters per row of the count-min sketch.
+const SKETCH_MIN_WIDTH: usize = 1024;
+/// Maximum number of counters per row of the count-min sketch.
+const SKETCH_MAX_WIDTH: usize = 1 << 20;
+/// Assumed minimum chunk size to estimate number of cached chunks.
+const SKETCH_CHUNK_SIZE: u64 = 0x1000;
+const SKETCH_SEEDS: [u64; SKETCH_DEPTH] = [
+    0x9e37_79b9_7f4a_7c15,
+    0xc2b2_ae3d_27d4_eb4f,
+    0x1656_67b1_9e37_79f9,
+    0x27d4_eb2f_1656_67c5,
+];
+
+lazy_static! {
+    /// The in-memory hot chunk cache shared by all blob caches, disabled by default.
+    pub static ref HOT_CHUNK_CACHE: HotChunkCache = HotChunkCache::default();
+}
+
+// Count-min sketch to estimate access frequency of chunks.
+#[derive(Default)]
+struct FrequencySketch {
+    counters: Vec<u8>,
+    width: usize,
+    // Number of increments since frequencies were halved last time.
+    additions: usize,
+    // Halve all frequencies after so many increments.
+    sample_size: usize,
+}
+
+impl FrequencySketch {
+    fn new(capacity: u64) -> Self {
+        let chunks = std::cmp::min(capacity / SKETCH_CHUNK_SIZE, SKETCH_MAX_WIDTH as u64) as usize;
+        let width = chunks
+            .next_power_of_two()
+            .clamp(SKETCH_MIN_WIDTH, SKETCH_MAX_WIDTH);
+
+        FrequencySketch {
+            counters: vec![0u8; width * SKETCH_DEPTH],
+            width,
+            additions: 0,
+            sample_size: width * 10,
+        }
+    }
+
+    fn index(&self, hash: u64, row: usize) -> usize {
+        let h = (hash ^ SKETCH_SEEDS[row]).wrapping_mul(SKETCH_SEEDS[(row + 1) % SKETCH_DEPTH]);
+        row * self.width + ((h >> 32) as usize & (self.width - 1))
+    }
+
+    fn frequency(&self, hash: u64) -> u8 {
+        if self.width == 0 {
+            return 0;
+        }
+        (0..SKETCH_DEPTH)
+            .map(|row| self.counters[self.index(hash, row)])
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn increment(&mut self, hash: u64) {
+        if self.width == 0 {
+            return;
+        }
+        let mut added = false;
+        for row in 0..SKETCH_DEPTH {
+            let idx = self.index(hash, row);
+            if self.counters[idx] < SKETCH_MAX_FREQ {
+                self.counters[idx] += 1;
+                added = true;
+            }
+        }
+
+        if added {
+            self.additions += 1;
+            if self.additions >= self.sample_size {
+                self.counters.iter_mut().for_each(|v| *v >>= 1);
+                self.additions /= 2;
+            }
+        }
+    }
+}
+
+struct HotChunk {
+    blob_id: String,
+    index: u32,
+    // Sequence number of last access.
+    seq: u64,
+    data: Arc<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct HotChunkState {
+    sketch: FrequencySketch,
+    // Cached chunks indexed by hash of blob id and chunk index.
+    chunks: HashMap<u64, HotChunk>,
+    // Cached chunks ordered by last access, from the least recently used one.
+    lru: BTreeMap<u64, u64>,
+    seq: u64,
+    size: u64,
+}
+
+impl HotChunkState {
+    fn touch(&mut self, hash: u64) {
+        self.seq += 1;
+        if let Some(chunk) = self.chunks.get_mut(&hash) {
+            self.lru.remove(&chunk.seq);
+            self.lru.insert(self.seq, hash);
+            chunk.seq = self.seq;
+        }
+    }
+
+    fn remove(&mut self, hash: u64) {
+        if let Some(chunk) = self.chunks.remove(&hash) {
+            self.lru.remove(&chunk.seq);
+            self.size -= chunk.data.len() as u64;
+        }
+    }
+}
+
+/// In-memory cache of decompressed data of hot chunks, with a global memory budget.
+#[derive(Default)]
+pub struct HotChunkCache {
+    capacity: AtomicU64,
+    state: Mutex<HotChunkState>,
+}
+
+impl HotChunkCache {
+    /// Set maximum memory in bytes used to cache chunk data, zero means disabled.
+    ///
+    /// All cached chunks and access history are dropped.
+    pub fn set_capacity(&self, capacity: u64) {
+        let mut state = self.state.lock().unwrap();
+        *state = HotChunkState::default();
+        if capacity > 0 {
+            state.sketch = FrequencySketch::new(capacity);
+        }
+        self.capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    /// Get maximum memory in bytes used to cache chunk data.
+    pub fn capacity(&self) -> u64 {
+        self.capacity.load(Ordering::Relaxed)
+    }
+
+    /// Get memory in bytes used by cached chunk data.
+    pub fn size(&self) -> u64 {
+        self.state.lock().unwrap().size
+    }
+
+    /// Check whether the hot chunk cache is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.capacity() > 0
+    }
+
+    /// Check whether chunk `index` of blob `blob_id` is cached, without accounting an access.
+    #[cfg(test)]
+    pub(crate) fn contains(&self, blob_id: &str, index: u32) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        let hash = Self::hash(blob_id, index);
+        let state = self.state.lock().unwrap();
+        matches!(state.chunks.get(&hash), Some(c) if c.index == index && c.blob_id == blob_id)
+    }
+
+    /// Get cached data of chunk `index` of blob `blob_id`.
+    ///
+    /// An access to the chunk is accounted on cache hit, otherwise it should be accounted by
+    /// [HotChunkCache::put()] once the chunk data is available.
+    pub(crate) fn get(&self, blob_id: &str, index: u32) -> Option<Arc<Vec<u8>>> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let hash = Self::hash(blob_id, index);
+        let mut state = self.state.lock().unwrap();
+        let data = match state.chunks.get(&hash) {
+            Some(c) if c.index == index && c.blob_id == blob_id => c.data.clone(),
+            _ => return None,
+        };
+        state.sketch.increment(hash);
+        state.touch(hash);
+
+        Some(data)
+    }
+
+    /// Account an access to chunk `index` of blob `blob_id`, and try to cache its data if it's
+    /// accessed more frequently than chunks to be evicted.
+    ///
+    /// Return true if the chunk is cached.
+    pub(crate) fn put(&self, blob_id: &str, index: u32, data: &[u8]) -> bool {
+        let capacity = self.capacity();
+        if capacity == 0 {
+            return false;
+        }
+        let hash = Self::hash(blob_id, index);
+        let size = data.len() as u64;
+        let mut state = self.state.lock().unwrap();
+        state.sketch.increment(hash);
+
+        match state.chunks.get(&hash) {
+            // Cached by another thread concurrently.
+            Some(c) if c.index == index && c.blob_id == blob_id => return true,
+            // Evict the chunk with hash conflict.
+            Some(_) => state.remove(hash),
+            None => {}
+        }
+        if size > capacity {
+            return false;
+        }
+
+        // Find least recently used chunks to make room for the new chunk, and admit the new chunk
+        // only if it's accessed more frequently than all of them.
+        let freq = state.sketch.frequency(hash);
+        let mut victims = Vec::new();
+        let mut free = capacity.saturating_sub(state.size);
+        for (_, victim) in state.lru.iter() {
+            if free >= size {
+                break;
+            }
+            if state.sketch.frequency(*victim) >= freq {
+                return false;
+            }
+            free += state.chunks[victim].data.len() as u64;
+            victims.push(*victim);
+        }
+        for victim in victims {
+            state.remove(victim);
+        }
+
+        state.seq += 1;
+        let seq = state.seq;
+        state.chunks.insert(
+            hash,
+            HotChunk {
+                blob_id: blob_id.to_string(),
+                index,
+                seq,
+                data: Arc::new(data.to_vec()),
+            },
+        );
+        state.lru.insert(seq, hash);
+        state.size += size;
+
+        true
+    }
+
+    /// Drop cached data of chunk `index` of blob `blob_id`.
+    pub(crate) fn remove(&self, blob_id: &str, index: u32) {
+        if !self.is_enabled() {
+            return;
+        }
+        let hash = Self::hash(blob_id, index);
+        let mut state = self.state.lock().unwrap();
+        if matches!(state.chunks.get(&hash), Some(c) if c.index == index && c.blob_id == blob_id) {
+            state.remove(hash);
+        }
+    }
+
+    fn hash(blob_id: &str, index: u32) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        blob_id.hash(&mut hasher);
+        index.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frequency_sketch() {
+        let mut sketch = FrequencySketch::new(0);
+        assert_eq!(sketch.width, SKETCH_MIN_WIDTH);
+        assert_eq!(sketch.frequency(1), 0);
+        sketch.increment(1);
+        sketch.increment(1);
+        assert_eq!(sketch.frequency(1), 2);
+        for _ in 0..100 {
+            sketch.increment(2);
+        }
+        assert_eq!(sketch.frequency(2), SKETCH_MAX_FREQ);
+
+        // Frequencies are halved periodically.
+        for v in 0..sketch.sample_size as u64 {
+            sketch.increment(v + 1000);
+        }
+        assert!(sketch.frequency(2) < SKETCH_MAX_FREQ);
+
+        let sketch = FrequencySketch::new(u64::MAX);
+        assert_eq!(sketch.width, SKETCH_MAX_WIDTH);
+    }
+
+    #[test]
+    fn test_hot_chunk_cache() {
+        let cache = HotChunkCache::default();
+        assert!(!cache.is_enabled());
+        assert!(!cache.put("blob1", 0, &[1u8; 0x1000]));
+        assert!(cache.get("blob1", 0).is_none());
+
+        cache.set_capacity(0x3000);
+        assert!(cache.is_enabled());
+        // Chunks are admitted freely when there's enough room.
+        assert!(cache.put("blob1", 0, &[1u8; 0x1000]));
+        assert!(cache.put("blob1", 1, &[2u8; 0x1000]));
+        assert!(cache.put("blob2", 0, &[3u8; 0x1000]));
+        assert!(cache.contains("blob1", 1));
+        assert!(!cache.contains("blob2", 1));
+        assert_eq!(cache.size(), 0x3000);
+        assert_eq!(cache.get("blob1", 0).unwrap()[0], 1);
+        assert_eq!(cache.get("blob2", 0).unwrap()[0], 3);
+        assert!(!cache.put("blob4", 0, &[0u8; 0x4000]));
+
+        // Chunks accessed once can't evict chunks accessed as frequently.
+        assert!(!cache.put("blob3", 0, &[4u8; 0x1000]));
+        assert!(cache.get("blob3", 0).is_none());
+        assert!(cache.contains("blob1", 1));
+
+        // Chunks accessed more frequently evict the least recently used one.
+        assert!(cache.put("blob3", 0, &[4u8; 0x1000]));
+        assert!(!cache.contains("blob1", 1));
+        assert_eq!(cache.get("blob3", 0).unwrap()[0], 4);
+        assert!(cache.contains("blob1", 0));
+        assert!(cache.contains("blob2", 0));
+        assert_eq!(cache.size(), 0x3000);
+
+        cache.remove("blob2", 0);
+        cache.remove("blob2", 1);
+        assert!(!cache.contains("blob2", 0));
+        assert!(cache.contains("blob1", 0));
+        assert_eq!(cache.size(), 0x2000);
+
+        cache.set_capacity(0);
+        assert!(!cache.is_enabled());
+        assert!(!cache.contains("blob1", 0));
+        assert_eq!(cache.size(), 0);
+    }
+}
@@ -23,6 +23,7 @@ use std::slice;
 use std::sync::Arc;
 
 use fuse_backend_rs::transport::FileVolatileSlice;
+use nydus_utils::slowio::{IoStage, StageTimer};
 use nydus_utils::{compress, digest};
 
 use crate::backend::{BlobBackend, BlobReader};
@@ -38,6 +39,9 @@ mod cachedfile;
 mod dummycache;
 mod filecache;
 mod fscache;
+mod hotchunk;
+mod scrub;
+mod singleflight;
 mod worker;
 
 pub mod state;
@@ -45,6 +49,7 @@ pub mod state;
 pub use dummycache::DummyCacheMgr;
 pub use filecache::FileCacheMgr;
 pub use fscache::FsCacheMgr;
+pub use hotchunk::{HotChunkCache, HOT_CHUNK_CACHE};
 
 /// Timeout in milli-seconds to retrieve blob data from backend storage.
 pub const SINGLE_INFLIGHT_WAIT_TIMEOUT: u64 = 2000;
@@ -142,6 +147,13 @@ pub trait BlobCache: Send + Sync {
     /// Get the [BlobReader](../backend/trait.BlobReader.html) to read data from storage backend.
     fn reader(&self) -> &dyn BlobReader;
 
+    /// Read data at `offset` from the storage backend into `buf`.
+    ///
+    /// Implementations may override it to deduplicate concurrent backend requests.
+    fn read_backend(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.reader().read(buf, offset).map_err(|e| eio!(e))
+    }
+
     /// Get the underlying `ChunkMap` object.
     fn get_chunk_map(&self) -> &Arc<dyn ChunkMap>;
 
@@ -173,6 +185,14 @@ pub trait BlobCache: Send + Sync {
         Err(enosys!("doesn't support prefetch_range()"))
     }
 
+    /// Check whether chunk information objects carrying digests are needed to verify cached data.
+    fn need_chunk_infos(&self) -> bool {
+        false
+    }
+
+    /// Record chunk information objects carrying digests, to verify cached data.
+    fn record_chunk_infos(&self, _bios: &[BlobIoDesc]) {}
+
     /// Read chunk data described by the blob Io descriptors from the blob cache into the buffer.
     fn read(&self, iovec: &mut BlobIoVec, buffers: &[FileVolatileSlice]) -> Result<usize>;
 
@@ -183,6 +203,8 @@ pub trait BlobCache: Send + Sync {
     /// continuous range, and the range exactly matches [`blob_offset`..`blob_offset` + `blob_size`].
     /// Function `read_chunks()` returns one buffer containing decompressed chunk data for each
     /// entry in the `chunks` array in corresponding order.
+    /// `raw_hook` provides caller a chance to read the fetched raw data of the whole range, after
+    /// all chunks in the range have been successfully processed.
     ///
     /// This method returns success only if all requested data are successfully fetched.
     fn read_chunks(
@@ -190,13 +212,11 @@ pub trait BlobCache: Send + Sync {
         blob_offset: u64,
         blob_size: usize,
         chunks: &[BlobIoChunk],
+        raw_hook: Option<&dyn Fn(&[u8])>,
     ) -> Result<Vec<Vec<u8>>> {
         // Read requested data from the backend by altogether.
         let mut c_buf = alloc_buf(blob_size);
-        let nr_read = self
-            .reader()
-            .read(c_buf.as_mut_slice(), blob_offset)
-            .map_err(|e| eio!(e))?;
+        let nr_read = self.read_backend(c_buf.as_mut_slice(), blob_offset)?;
         if nr_read != blob_size {
             return Err(eio!(format!(
                 "request for {} bytes but got {} bytes",
@@ -230,6 +250,9 @@ pub trait BlobCache: Send + Sync {
             buffers.push(buffer);
             last = offset + size as u64;
         }
+        if let Some(hook) = raw_hook {
+            hook(&c_buf)
+        }
 
         Ok(buffers)
     }
@@ -268,7 +291,7 @@ pub trait BlobCache: Send + Sync {
             unsafe { slice::from_raw_parts_mut(buffer.as_mut_ptr(), buffer.len()) }
         };
 
-        let size = self.reader().read(raw_chunk, offset).map_err(|e| eio!(e))?;
+        let size = self.read_backend(raw_chunk, offset)?;
         if size != raw_chunk.len() {
             return Err(eio!("storage backend returns less data than requested"));
         }
@@ -301,6 +324,7 @@ pub trait BlobCache: Send + Sync {
         need_decompress: bool,
         force_validation: bool,
     ) -> Result<usize> {
+        let _timer = StageTimer::new(IoStage::Decompress);
         if need_decompress {
             compress::decompress(raw_buffer, raw_stream, buffer, self.compressor()).map_err(
                 |e| {
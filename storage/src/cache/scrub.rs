@@ -0,0 +1,168 @@
+// Copyright (C) 2022 Alibaba Cloud. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Background scrubbing of cached blob data.
+//!
+//! Unless data validation is enabled, chunk data in cache files is trusted once it has been marked
+//! as ready, so corruption caused by disk errors or crashes in the middle of writing goes
+//! unnoticed until applications read the corrupted data. The `CacheScrubber` periodically re-reads
+//! ready chunks of cached blobs at a limited rate, and drops corrupted chunks from the cache by
+//! clearing their readiness state in the chunk map, so they will be fetched from the backend again.
+//!
+//! Chunks are verified against their digests with the digester of the image. The storage subsystem
+//! can't get chunk digests by itself, they are provided by the filesystem with the bootstrap or
+//! recorded when accessed. Other chunks cached in compressed form are verified by decompression,
+//! and the remaining chunks are reported as skipped.
+
+use std::collections::HashMap;
+use std::io::Result;
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use futures::executor::block_on;
+use governor::clock::QuantaClock;
+use governor::state::{InMemoryState, NotKeyed};
+use governor::{Quota, RateLimiter};
+use nydus_api::http::ScrubConfig;
+use nydus_utils::metrics::BlobcacheMetrics;
+
+use crate::cache::cachedfile::FileCacheEntry;
+use crate::RAFS_MAX_CHUNK_SIZE;
+
+/// Scrubber to periodically verify chunks cached by a blob cache manager.
+pub(crate) struct CacheScrubber {
+    blobs: Arc<RwLock<HashMap<String, Arc<FileCacheEntry>>>>,
+    metrics: Arc<BlobcacheMetrics>,
+    interval: u64,
+    limiter: Option<RateLimiter<NotKeyed, InMemoryState, QuantaClock>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl CacheScrubber {
+    /// Create a new instance of `CacheScrubber`.
+    pub fn new(
+        blobs: Arc<RwLock<HashMap<String, Arc<FileCacheEntry>>>>,
+        metrics: Arc<BlobcacheMetrics>,
+        config: &ScrubConfig,
+        closed: Arc<AtomicBool>,
+    ) -> Self {
+        // Chunks bigger than the burst size of the limiter can't be throttled, so ensure bandwidth
+        // is bigger than the maximum chunk size.
+        let bandwidth_rate = if config.bandwidth_rate != 0 {
+            std::cmp::max(RAFS_MAX_CHUNK_SIZE as u32, config.bandwidth_rate)
+        } else {
+            0
+        };
+        let limiter =
+            NonZeroU32::new(bandwidth_rate).map(|v| RateLimiter::direct(Quota::per_second(v)));
+
+        CacheScrubber {
+            blobs,
+            metrics,
+            interval: config.interval,
+            limiter,
+            closed,
+        }
+    }
+
+    /// Start a background thread to periodically scrub cached blobs.
+    pub fn start(scrubber: Arc<CacheScrubber>) -> Result<()> {
+        thread::Builder::new()
+            .name("cache_scrubber".to_string())
+            .spawn(move || scrubber.run_loop())
+            .map(|_| ())
+    }
+
+    /// Verify chunks of all cached blobs once.
+    pub fn scrub(&self) {
+        let mut ids: Vec<String> = self.blobs.read().unwrap().keys().cloned().collect();
+        ids.sort();
+
+        for id in ids {
+            if self.closed.load(Ordering::Acquire) {
+                return;
+            }
+            // Take the cache entry one by one, so others may be garbage-collected in the meantime.
+            let entry = match self.blobs.read().unwrap().get(&id) {
+                Some(v) => v.clone(),
+                None => continue,
+            };
+            if let Err(e) = entry.scrub(&|size| self.throttle(size)) {
+                warn!("storage: failed to scrub cached blob {}, {}", id, e);
+            }
+        }
+
+        self.metrics.scrub.finish_pass();
+    }
+
+    // Wait until `size` bytes of cached data may be read, return false if the scrubber is closed.
+    fn throttle(&self, size: u64) -> bool {
+        if let Some(limiter) = self.limiter.as_ref() {
+            let size = std::cmp::min(size, u32::MAX as u64) as u32;
+            if let Some(cells) = NonZeroU32::new(size) {
+                if let Err(e) = block_on(limiter.until_n_ready(cells)) {
+                    // `InsufficientCapacity` is the only possible error, give up rate-limiting.
+                    debug!("storage: {}, give up rate-limiting cache scrubbing", e);
+                }
+            }
+        }
+
+        !self.closed.load(Ordering::Acquire)
+    }
+
+    fn run_loop(&self) {
+        let mut elapsed = 0;
+        while !self.closed.load(Ordering::Acquire) {
+            thread::sleep(Duration::from_secs(1));
+            elapsed += 1;
+            if elapsed >= self.interval {
+                elapsed = 0;
+                self.scrub();
+            }
+        }
+        info!("storage: cache scrubber thread exits");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nydus_utils::metrics::Metric;
+    use std::time::Instant;
+
+    #[test]
+    fn test_cache_scrubber() {
+        let metrics = BlobcacheMetrics::new("test_cache_scrubber", "/tmp");
+        let closed = Arc::new(AtomicBool::new(false));
+        let config = ScrubConfig {
+            interval: 1,
+            bandwidth_rate: 1,
+        };
+        let scrubber = CacheScrubber::new(
+            Arc::new(RwLock::new(HashMap::new())),
+            metrics.clone(),
+            &config,
+            closed.clone(),
+        );
+
+        scrubber.scrub();
+        assert_eq!(metrics.scrub.passes.count(), 1);
+        assert!(metrics.scrub.last_pass_secs.load(Ordering::Relaxed) > 0);
+
+        // The bandwidth is at least the maximum chunk size.
+        let begin = Instant::now();
+        assert!(scrubber.throttle(RAFS_MAX_CHUNK_SIZE));
+        assert!(scrubber.throttle(0));
+        assert!(begin.elapsed() < Duration::from_secs(1));
+
+        closed.store(true, Ordering::Release);
+        assert!(!scrubber.throttle(0x1000));
+        scrubber.scrub();
+        assert_eq!(metrics.scrub.passes.count(), 1);
+        metrics.release().unwrap();
+    }
+}
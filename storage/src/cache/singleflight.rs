@@ -0,0 +1,308 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Coalesce concurrent reads of overlapping ranges from the storage backend.
+//!
+//! When many containers start from the same image, several fuse threads and prefetch workers may
+//! miss the cache on the same chunks at the same time. With [SingleFlight], the first requester
+//! fetches data from the storage backend and later requesters for overlapping ranges wait for
+//! the inflight request and share its result, instead of issuing duplicated backend requests.
+
+use std::cmp;
+use std::io::Result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+use nydus_utils::metrics::{BlobcacheMetrics, Metric};
+use nydus_utils::slowio::{IoStage, StageTimer};
+
+use crate::backend::BlobReader;
+
+/// An inflight backend request for range `[offset, offset + size)`.
+struct InflightRead {
+    offset: u64,
+    size: usize,
+    // Number of requesters waiting for the result, only changed with the `SingleFlight` lock held.
+    waiters: AtomicUsize,
+    // `None` means pending, `Some(None)` means failure and `Some(Some(data))` means success.
+    result: Mutex<Option<Option<Arc<Vec<u8>>>>>,
+    cond: Condvar,
+}
+
+impl InflightRead {
+    fn new(offset: u64, size: usize) -> Self {
+        InflightRead {
+            offset,
+            size,
+            waiters: AtomicUsize::new(0),
+            result: Mutex::new(None),
+            cond: Condvar::new(),
+        }
+    }
+
+    fn end(&self) -> u64 {
+        self.offset + self.size as u64
+    }
+
+    fn complete(&self, data: Option<Arc<Vec<u8>>>) {
+        *self.result.lock().unwrap() = Some(data);
+        self.cond.notify_all();
+    }
+
+    fn wait(&self) -> Option<Arc<Vec<u8>>> {
+        let mut guard = self.result.lock().unwrap();
+        while guard.is_none() {
+            guard = self.cond.wait(guard).unwrap();
+        }
+        guard.as_ref().unwrap().clone()
+    }
+}
+
+/// A range of the request to be fetched from the storage backend or shared from an inflight
+/// request.
+enum Segment {
+    Fetch(Arc<InflightRead>),
+    Share(Arc<InflightRead>, u64, usize),
+}
+
+/// Deduplicate concurrent backend reads of a blob.
+#[derive(Default)]
+pub(crate) struct SingleFlight {
+    inflights: Mutex<Vec<Arc<InflightRead>>>,
+}
+
+impl SingleFlight {
+    /// Create a new instance of `SingleFlight`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read data at `offset` from the storage backend into `buf`.
+    ///
+    /// Parts of the range being fetched by other requesters are shared instead of being fetched
+    /// again, and only the remaining parts are fetched from the storage backend.
+    pub fn read(
+        &self,
+        reader: &dyn BlobReader,
+        buf: &mut [u8],
+        offset: u64,
+        metrics: &BlobcacheMetrics,
+    ) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let segments = self.plan(offset, buf.len());
+        if segments.len() == 1 {
+            if let Segment::Fetch(req) = &segments[0] {
+                // Fast path: nothing to share with other requesters.
+                let result = reader.read(buf, offset).map_err(|e| eio!(e));
+                let data = match result.as_ref() {
+                    Ok(sz) if *sz == buf.len() => Some(&buf[..]),
+                    _ => None,
+                };
+                self.complete(req, data);
+                return result;
+            }
+        }
+
+        // Fetch ranges owned by ourself first, so others waiting for us are not blocked by
+        // ranges we are waiting for.
+        let mut failed = false;
+        for seg in segments.iter() {
+            if let Segment::Fetch(req) = seg {
+                let start = (req.offset - offset) as usize;
+                let data = &mut buf[start..start + req.size];
+                match reader.read(data, req.offset) {
+                    Ok(sz) if sz == req.size => self.complete(req, Some(&data[..])),
+                    _ => {
+                        self.complete(req, None);
+                        failed = true;
+                    }
+                }
+            }
+        }
+        if failed {
+            return Err(eio!("failed to read data from storage backend"));
+        }
+
+        for seg in segments.iter() {
+            if let Segment::Share(req, start, size) = seg {
+                let pos = (start - offset) as usize;
+                let data = &mut buf[pos..pos + size];
+                let timer = StageTimer::new(IoStage::Backend);
+                let result = req.wait();
+                drop(timer);
+                match result {
+                    Some(v) => {
+                        let from = (start - req.offset) as usize;
+                        data.copy_from_slice(&v[from..from + size]);
+                        metrics.coalesced_requests.inc();
+                        metrics.coalesced_size.add(*size as u64);
+                    }
+                    None => {
+                        // The inflight request failed, fetch data by ourself.
+                        let sz = reader.read(data, *start).map_err(|e| eio!(e))?;
+                        if sz != *size {
+                            return Err(eio!(format!(
+                                "request for {} bytes but got {} bytes",
+                                size, sz
+                            )));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    // Split range `[offset, offset + size)` into segments to share from overlapping inflight
+    // requests and segments to fetch, and register the latter as new inflight requests.
+    fn plan(&self, offset: u64, size: usize) -> Vec<Segment> {
+        let end = offset + size as u64;
+        let mut inflights = self.inflights.lock().unwrap();
+        let mut overlaps: Vec<Arc<InflightRead>> = inflights
+            .iter()
+            .filter(|r| r.offset < end && r.end() > offset)
+            .cloned()
+            .collect();
+        overlaps.sort_by_key(|r| r.offset);
+
+        let mut segments = Vec::new();
+        let mut pos = offset;
+        for req in overlaps {
+            if req.end() <= pos {
+                continue;
+            }
+            if req.offset > pos {
+                let fetch = Arc::new(InflightRead::new(pos, (req.offset - pos) as usize));
+                inflights.push(fetch.clone());
+                segments.push(Segment::Fetch(fetch));
+                pos = req.offset;
+            }
+            let share_end = cmp::min(req.end(), end);
+            req.waiters.fetch_add(1, Ordering::Relaxed);
+            segments.push(Segment::Share(req, pos, (share_end - pos) as usize));
+            pos = share_end;
+        }
+        if pos < end {
+            let fetch = Arc::new(InflightRead::new(pos, (end - pos) as usize));
+            inflights.push(fetch.clone());
+            segments.push(Segment::Fetch(fetch));
+        }
+
+        segments
+    }
+
+    // Unregister the inflight request and wake up requesters waiting for it.
+    fn complete(&self, req: &Arc<InflightRead>, data: Option<&[u8]>) {
+        let mut inflights = self.inflights.lock().unwrap();
+        inflights.retain(|r| !Arc::ptr_eq(r, req));
+        // No more waiters after the request has been removed from the list.
+        let waiters = req.waiters.load(Ordering::Relaxed);
+        drop(inflights);
+
+        // Only copy the data if there are requesters waiting for it.
+        let data = match data {
+            Some(v) if waiters > 0 => Some(Arc::new(v.to_vec())),
+            _ => None,
+        };
+        req.complete(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::BackendResult;
+    use nydus_utils::metrics::BackendMetrics;
+    use std::thread;
+    use std::time::Duration;
+
+    struct SlowReader {
+        count: AtomicUsize,
+        metrics: Arc<BackendMetrics>,
+    }
+
+    impl BlobReader for SlowReader {
+        fn blob_size(&self) -> BackendResult<u64> {
+            Ok(0x10000)
+        }
+
+        fn try_read(&self, buf: &mut [u8], offset: u64) -> BackendResult<usize> {
+            self.count.fetch_add(1, Ordering::AcqRel);
+            thread::sleep(Duration::from_millis(200));
+            for (i, v) in buf.iter_mut().enumerate() {
+                *v = (offset as usize + i) as u8;
+            }
+            Ok(buf.len())
+        }
+
+        fn prefetch_blob_data_range(&self, _ra_offset: u64, _ra_size: u64) -> BackendResult<()> {
+            Ok(())
+        }
+
+        fn stop_data_prefetch(&self) -> BackendResult<()> {
+            Ok(())
+        }
+
+        fn metrics(&self) -> &BackendMetrics {
+            &self.metrics
+        }
+    }
+
+    #[test]
+    fn test_single_flight_read() {
+        let reader = Arc::new(SlowReader {
+            count: AtomicUsize::new(0),
+            metrics: BackendMetrics::new("test-single-flight", "test"),
+        });
+        let metrics = BlobcacheMetrics::new("test-single-flight", "/tmp");
+        let sf = Arc::new(SingleFlight::new());
+
+        let (r, m, s) = (reader.clone(), metrics.clone(), sf.clone());
+        let first = thread::spawn(move || {
+            let mut buf = vec![0u8; 0x2000];
+            assert_eq!(s.read(r.as_ref(), &mut buf, 0x1000, &m).unwrap(), 0x2000);
+            buf
+        });
+        thread::sleep(Duration::from_millis(50));
+
+        // Fully covered by the inflight request.
+        let (r, m, s) = (reader.clone(), metrics.clone(), sf.clone());
+        let second = thread::spawn(move || {
+            let mut buf = vec![0u8; 0x800];
+            assert_eq!(s.read(r.as_ref(), &mut buf, 0x1800, &m).unwrap(), 0x800);
+            buf
+        });
+        // Partially overlapped with the inflight request.
+        let mut buf = vec![0u8; 0x2000];
+        assert_eq!(
+            sf.read(reader.as_ref(), &mut buf, 0x2000, &metrics)
+                .unwrap(),
+            0x2000
+        );
+
+        let first = first.join().unwrap();
+        let second = second.join().unwrap();
+        for (i, v) in first.iter().enumerate() {
+            assert_eq!(*v, (0x1000 + i) as u8);
+        }
+        for (i, v) in second.iter().enumerate() {
+            assert_eq!(*v, (0x1800 + i) as u8);
+        }
+        for (i, v) in buf.iter().enumerate() {
+            assert_eq!(*v, (0x2000 + i) as u8);
+        }
+        // One request for the first read, and one for range [0x3000, 0x4000) of the last read.
+        assert_eq!(reader.count.load(Ordering::Acquire), 2);
+        assert_eq!(metrics.coalesced_requests.count(), 2);
+        assert_eq!(metrics.coalesced_size.count(), 0x1800);
+        assert!(sf.inflights.lock().unwrap().is_empty());
+
+        reader.metrics.release().unwrap();
+        metrics.release().unwrap();
+    }
+}
@@ -154,6 +154,25 @@ where
         }
     }
 
+    fn clear_ready(&self, chunk: &dyn BlobChunkInfo) -> Result<bool> {
+        let index = C::get_index(chunk);
+        // Hold the lock to prevent the chunk from being marked as pending concurrently.
+        let guard = self.inflight_tracer.lock().unwrap();
+        if guard.contains_key(&index) {
+            Ok(false)
+        } else {
+            self.c.clear_ready(chunk)
+        }
+    }
+
+    fn need_checkpoint(&self) -> bool {
+        self.c.need_checkpoint()
+    }
+
+    fn checkpoint(&self) -> Result<()> {
+        self.c.checkpoint()
+    }
+
     fn is_persist(&self) -> bool {
         self.c.is_persist()
     }
@@ -792,4 +811,84 @@ pub(crate) mod tests {
         assert!(map.is_range_ready(9, 1).unwrap());
         assert!(map.is_range_all_ready());
     }
+
+    #[test]
+    fn test_clear_ready() {
+        let chunk: Arc<dyn BlobChunkInfo> = Arc::new({
+            let mut c = MockChunkInfo::new();
+            c.index = 1;
+            c
+        });
+        let tmp_file = TempFile::new().unwrap();
+        let map = Arc::new(BlobStateMap::from(
+            IndexedChunkMap::new(tmp_file.as_path().to_str().unwrap(), 10, true).unwrap(),
+        ));
+
+        // Pending chunks can't be cleared.
+        assert!(!map.check_ready_and_mark_pending(chunk.as_ref()).unwrap());
+        assert!(!map.clear_ready(chunk.as_ref()).unwrap());
+        map.set_ready_and_clear_pending(chunk.as_ref()).unwrap();
+        assert!(map.is_ready(chunk.as_ref()).unwrap());
+
+        assert!(map.clear_ready(chunk.as_ref()).unwrap());
+        assert!(!map.is_ready(chunk.as_ref()).unwrap());
+        assert!(!map.clear_ready(chunk.as_ref()).unwrap());
+        assert!(!map.check_ready_and_mark_pending(chunk.as_ref()).unwrap());
+        map.clear_pending(chunk.as_ref());
+
+        let digested_map = BlobStateMap::from(DigestedChunkMap::new());
+        assert!(!digested_map.clear_ready(chunk.as_ref()).unwrap());
+        digested_map
+            .set_ready_and_clear_pending(chunk.as_ref())
+            .unwrap();
+        assert!(digested_map.clear_ready(chunk.as_ref()).unwrap());
+        assert!(!digested_map.is_ready(chunk.as_ref()).unwrap());
+    }
+
+    #[test]
+    fn test_crash_consistency() {
+        let dir = TempDir::new().unwrap();
+        let blob_path = dir.as_path().join("blob-1");
+        let blob_path = blob_path.as_os_str().to_str().unwrap().to_string();
+        let chunk = Chunk::new(1);
+        let data_file = Arc::new(TempFile::new().unwrap().into_file());
+        let open_map = |with_data_file: bool| {
+            let mut map = IndexedChunkMap::new(&blob_path, 10, true).unwrap();
+            if with_data_file {
+                map.set_data_file(data_file.clone());
+            }
+            BlobStateMap::from(map)
+        };
+
+        let map = open_map(true);
+        assert!(!map.check_ready_and_mark_pending(chunk.as_ref()).unwrap());
+        map.set_ready_and_clear_pending(chunk.as_ref()).unwrap();
+        assert!(map.is_ready(chunk.as_ref()).unwrap());
+        // The checkpoint is left to the owner of the map.
+        assert!(map.need_checkpoint());
+        // Simulate a crash before any checkpoint of the chunk map.
+        std::mem::forget(map);
+
+        let map = open_map(true);
+        assert!(!map.is_ready(chunk.as_ref()).unwrap());
+        assert!(!map.check_ready_and_mark_pending(chunk.as_ref()).unwrap());
+        map.set_ready_and_clear_pending(chunk.as_ref()).unwrap();
+        drop(map);
+
+        let map = open_map(true);
+        assert!(map.is_ready(chunk.as_ref()).unwrap());
+        assert!(!map.need_checkpoint());
+        drop(map);
+
+        // Maps without a data file are never trusted.
+        let map = open_map(false);
+        assert!(map.is_ready(chunk.as_ref()).unwrap());
+        let chunk2 = Chunk::new(2);
+        map.set_ready_and_clear_pending(chunk2.as_ref()).unwrap();
+        assert!(!map.need_checkpoint());
+        map.checkpoint().unwrap();
+        drop(map);
+        let map = open_map(true);
+        assert!(!map.is_ready(chunk2.as_ref()).unwrap());
+    }
 }
@@ -50,6 +50,10 @@ impl ChunkMap for DigestedChunkMap {
         self.cache.write().unwrap().insert(*chunk.chunk_id());
         Ok(())
     }
+
+    fn clear_ready(&self, chunk: &dyn BlobChunkInfo) -> Result<bool> {
+        Ok(self.cache.write().unwrap().remove(chunk.chunk_id()))
+    }
 }
 
 impl ChunkIndexGetter for DigestedChunkMap {
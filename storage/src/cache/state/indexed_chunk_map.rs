@@ -8,7 +8,9 @@
 //! This module provides a chunk state tracking driver based on a bitmap file. There's a state bit
 //! in the bitmap file for each chunk, and atomic operations are used to manipulate the bitmap.
 //! So it supports concurrent downloading.
+use std::fs::File;
 use std::io::Result;
+use std::sync::Arc;
 
 use crate::cache::state::persist_map::PersistMap;
 use crate::cache::state::{ChunkIndexGetter, ChunkMap, RangeMap};
@@ -28,6 +30,11 @@ const FILE_SUFFIX: &str = "chunk_map";
 /// This approach can be used to share chunk ready state between multiple nydusd instances.
 /// For example: the bitmap file layout is [0b00000000, 0b00000000], when blobcache calls
 /// set_ready(3), the layout should be changed to [0b00010000, 0b00000000].
+///
+/// Ready bits survive crashes only if they have been covered by a checkpoint, which syncs the
+/// cache file and the bitmap file to disk. Checkpoints are taken in background by owners of the
+/// map, and when the map is dropped. Without a cache file set by `set_data_file()`, checkpoints
+/// are never taken and ready bits are never trusted after reopening the map.
 pub struct IndexedChunkMap {
     map: PersistMap,
 }
@@ -47,6 +54,12 @@ impl IndexedChunkMap {
         PersistMap::open(&filename, blob_info.chunk_count(), false, true)
             .map(|map| IndexedChunkMap { map })
     }
+
+    /// Set the cache file holding chunk data, which is synced to disk before ready bits of chunks
+    /// are trusted across crashes.
+    pub fn set_data_file(&mut self, file: Arc<File>) {
+        self.map.set_data_file(file);
+    }
 }
 
 impl ChunkMap for IndexedChunkMap {
@@ -63,6 +76,18 @@ impl ChunkMap for IndexedChunkMap {
         self.map.set_chunk_ready(chunk.id())
     }
 
+    fn clear_ready(&self, chunk: &dyn BlobChunkInfo) -> Result<bool> {
+        self.map.clear_chunk_ready(chunk.id())
+    }
+
+    fn need_checkpoint(&self) -> bool {
+        self.map.need_checkpoint()
+    }
+
+    fn checkpoint(&self) -> Result<()> {
+        self.map.checkpoint()
+    }
+
     fn is_persist(&self) -> bool {
         true
     }
@@ -145,6 +170,7 @@ impl ChunkIndexGetter for IndexedChunkMap {
 mod tests {
     use std::fs::OpenOptions;
     use std::io::Write;
+    use std::os::unix::fs::FileExt;
     use std::sync::atomic::Ordering;
     use vmm_sys_util::tempdir::TempDir;
 
@@ -279,7 +305,10 @@ mod tests {
             version: 1,
             magic2: MAGIC2,
             all_ready: MAGIC_ALL_READY,
-            reserved: [0x0u8; HEADER_RESERVED_SIZE],
+            generation: 0,
+            region_size: 0,
+            checksum: 0,
+            region_checksums: [0u32; HEADER_REGION_SLOTS],
         };
 
         // write file header and sync to disk.
@@ -298,6 +327,29 @@ mod tests {
         assert!(map.is_ready(chunk.as_base()).unwrap());
     }
 
+    #[test]
+    fn test_indexed_clear_ready() {
+        let dir = TempDir::new().unwrap();
+        let blob_path = dir.as_path().join("blob-1");
+        let blob_path = blob_path.as_os_str().to_str().unwrap().to_string();
+        let chunk = MockChunkInfo::new();
+
+        let map = open_map(&blob_path, 1);
+        assert!(!map.clear_ready(chunk.as_base()).unwrap());
+        map.set_ready_and_clear_pending(chunk.as_base()).unwrap();
+        assert!(map.is_range_all_ready());
+        assert!(map.clear_ready(chunk.as_base()).unwrap());
+        assert!(!map.is_range_all_ready());
+        assert!(!map.is_ready(chunk.as_base()).unwrap());
+        assert_eq!(map.map.not_ready_count.load(Ordering::Acquire), 1);
+        drop(map);
+
+        // The all ready flag in the header should have been cleared too.
+        let map = open_map(&blob_path, 1);
+        assert!(!map.is_range_all_ready());
+        assert!(!map.is_ready(chunk.as_base()).unwrap());
+    }
+
     #[test]
     fn test_indexed_new_load_v0() {
         let dir = TempDir::new().unwrap();
@@ -324,7 +376,10 @@ mod tests {
             version: 0,
             magic2: 0,
             all_ready: 0,
-            reserved: [0x0u8; HEADER_RESERVED_SIZE],
+            generation: 0,
+            region_size: 0,
+            checksum: 0,
+            region_checksums: [0u32; HEADER_REGION_SLOTS],
         };
 
         // write file header and sync to disk.
@@ -343,4 +398,105 @@ mod tests {
         map.set_ready_and_clear_pending(chunk.as_base()).unwrap();
         assert!(map.is_ready(chunk.as_base()).unwrap());
     }
+
+    fn new_chunk(index: u32) -> MockChunkInfo {
+        let mut chunk = MockChunkInfo::new();
+        chunk.index = index;
+        chunk
+    }
+
+    fn open_map(blob_path: &str, chunk_count: u32) -> IndexedChunkMap {
+        let mut map = IndexedChunkMap::new(blob_path, chunk_count, true).unwrap();
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(blob_path)
+            .unwrap();
+        map.set_data_file(Arc::new(file));
+        map
+    }
+
+    #[test]
+    fn test_indexed_crash_before_checkpoint() {
+        let dir = TempDir::new().unwrap();
+        let blob_path = dir.as_path().join("blob-1");
+        let blob_path = blob_path.as_os_str().to_str().unwrap().to_string();
+        // Chunk 4096 is in another region of the bitmap.
+        let chunks = [new_chunk(0), new_chunk(1), new_chunk(4096)];
+
+        let map = open_map(&blob_path, 8192);
+        map.set_ready_and_clear_pending(chunks[0].as_base())
+            .unwrap();
+        map.set_ready_and_clear_pending(chunks[1].as_base())
+            .unwrap();
+        map.map.checkpoint().unwrap();
+        map.set_ready_and_clear_pending(chunks[2].as_base())
+            .unwrap();
+        // Simulate a crash, the ready bit of chunk 4096 reached the disk without a checkpoint.
+        std::mem::forget(map);
+
+        let map = open_map(&blob_path, 8192);
+        assert!(map.is_ready(chunks[0].as_base()).unwrap());
+        assert!(map.is_ready(chunks[1].as_base()).unwrap());
+        assert!(!map.is_ready(chunks[2].as_base()).unwrap());
+        assert_eq!(map.map.not_ready_count.load(Ordering::Acquire), 8190);
+
+        // A checkpoint is taken on clean shutdown.
+        map.set_ready_and_clear_pending(chunks[2].as_base())
+            .unwrap();
+        drop(map);
+        let map = open_map(&blob_path, 8192);
+        assert!(map.is_ready(chunks[2].as_base()).unwrap());
+        assert_eq!(map.map.not_ready_count.load(Ordering::Acquire), 8189);
+    }
+
+    #[test]
+    fn test_indexed_crash_after_clear() {
+        let dir = TempDir::new().unwrap();
+        let blob_path = dir.as_path().join("blob-1");
+        let blob_path = blob_path.as_os_str().to_str().unwrap().to_string();
+        let cache_path = format!("{}.{}", blob_path, FILE_SUFFIX);
+        let chunk = new_chunk(3);
+
+        let map = open_map(&blob_path, 16);
+        map.set_ready_and_clear_pending(chunk.as_base()).unwrap();
+        map.map.checkpoint().unwrap();
+        assert!(map.clear_ready(chunk.as_base()).unwrap());
+        // Simulate a crash before the cleared bit reaches the disk.
+        let file = OpenOptions::new().write(true).open(&cache_path).unwrap();
+        file.write_all_at(&[0x10u8], HEADER_SIZE as u64).unwrap();
+        std::mem::forget(map);
+
+        let map = open_map(&blob_path, 16);
+        assert!(!map.is_ready(chunk.as_base()).unwrap());
+        assert_eq!(map.map.not_ready_count.load(Ordering::Acquire), 16);
+    }
+
+    #[test]
+    fn test_indexed_torn_header() {
+        let dir = TempDir::new().unwrap();
+        let blob_path = dir.as_path().join("blob-1");
+        let blob_path = blob_path.as_os_str().to_str().unwrap().to_string();
+        let cache_path = format!("{}.{}", blob_path, FILE_SUFFIX);
+        let chunk = new_chunk(3);
+
+        let map = open_map(&blob_path, 16);
+        map.set_ready_and_clear_pending(chunk.as_base()).unwrap();
+        drop(map);
+        let map = open_map(&blob_path, 16);
+        assert!(map.is_ready(chunk.as_base()).unwrap());
+        drop(map);
+
+        // Simulate a torn header.
+        let file = OpenOptions::new().write(true).open(&cache_path).unwrap();
+        file.write_all_at(&[0xffu8; 8], 16).unwrap();
+        let map = open_map(&blob_path, 16);
+        assert!(!map.is_ready(chunk.as_base()).unwrap());
+
+        // The header has been rewritten.
+        map.set_ready_and_clear_pending(chunk.as_base()).unwrap();
+        drop(map);
+        let map = open_map(&blob_path, 16);
+        assert!(map.is_ready(chunk.as_base()).unwrap());
+    }
 }
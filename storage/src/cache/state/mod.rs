@@ -44,6 +44,8 @@ pub use indexed_chunk_map::IndexedChunkMap;
 pub use noop_chunk_map::NoopChunkMap;
 pub use range_map::BlobRangeMap;
 
+pub(crate) use persist_map::{CHECKPOINT_CHUNKS, CHECKPOINT_INTERVAL};
+
 mod blob_state_map;
 mod digested_chunk_map;
 mod indexed_chunk_map;
@@ -77,6 +79,27 @@ pub trait ChunkMap: Any + Send + Sync {
         panic!("no support of clear_pending()");
     }
 
+    /// Clear the ready state of the chunk, so it will be fetched from the backend again.
+    ///
+    /// Return `Ok(true)` if the chunk was ready, and `Ok(false)` if the chunk wasn't ready or
+    /// is pending.
+    fn clear_ready(&self, _chunk: &dyn BlobChunkInfo) -> Result<bool> {
+        Err(enosys!())
+    }
+
+    /// Check whether a checkpoint should be taken by [ChunkMap::checkpoint()].
+    fn need_checkpoint(&self) -> bool {
+        false
+    }
+
+    /// Take a checkpoint, so persisted state changed so far will be trusted after crashes.
+    ///
+    /// It syncs data and state files to disk, so it should be called in background instead of IO
+    /// paths.
+    fn checkpoint(&self) -> Result<()> {
+        Ok(())
+    }
+
     /// Check whether the implementation supports state persistence.
     fn is_persist(&self) -> bool {
         false
@@ -8,8 +8,10 @@ use std::fs::{File, OpenOptions};
 use std::io::{Result, Write};
 use std::os::unix::io::AsRawFd;
 use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use nydus_utils::div_round_up;
+use nydus_utils::{div_round_up, round_up};
 
 use crate::utils::readahead;
 
@@ -17,7 +19,31 @@ pub(crate) const MAGIC1: u32 = 0x424D_4150;
 pub(crate) const MAGIC2: u32 = 0x434D_4150;
 pub(crate) const MAGIC_ALL_READY: u32 = 0x4D4D_4150;
 pub(crate) const HEADER_SIZE: usize = 4096;
-pub(crate) const HEADER_RESERVED_SIZE: usize = HEADER_SIZE - 16;
+/// Number of bitmap region checksums in the header.
+pub(crate) const HEADER_REGION_SLOTS: usize = (HEADER_SIZE - 32) / 4;
+/// The first version carrying checkpoint information in the header.
+const VERSION_CHECKPOINT: u32 = 2;
+/// Minimum size of bitmap regions covered by a checksum, in bytes.
+const REGION_SIZE_MIN: u64 = 512;
+/// Take a checkpoint once so many chunks have changed state since the last one.
+pub(crate) const CHECKPOINT_CHUNKS: u32 = 1024;
+/// Take a checkpoint if chunks changed state since the last one taken so long ago.
+pub(crate) const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+const FNV_OFFSET_BASIS: u32 = 0x811c_9dc5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+// FNV-1a hash, to detect torn or stale content instead of malicious modification.
+fn checksum(seed: u32, data: &[u8]) -> u32 {
+    data.iter()
+        .fold(seed, |sum, v| (sum ^ *v as u32).wrapping_mul(FNV_PRIME))
+}
+
+// Size of bitmap regions, so checksums of all regions fit in the header.
+fn region_size(bitmap_size: u64) -> u64 {
+    let size = div_round_up(bitmap_size, HEADER_REGION_SLOTS as u64);
+    std::cmp::max(REGION_SIZE_MIN, round_up(size, REGION_SIZE_MIN))
+}
 
 /// The blob chunk map file header, 4096 bytes.
 #[repr(C)]
@@ -27,10 +53,35 @@ pub(crate) struct Header {
     pub version: u32,
     pub magic2: u32,
     pub all_ready: u32,
-    pub reserved: [u8; HEADER_RESERVED_SIZE],
+    /// Number of checkpoints taken, since version 2.
+    pub generation: u64,
+    /// Size of bitmap regions covered by `region_checksums`, since version 2.
+    pub region_size: u32,
+    /// Checksum of all other fields of the header, since version 2.
+    pub checksum: u32,
+    /// Checksums of bitmap regions at the last checkpoint, since version 2.
+    pub region_checksums: [u32; HEADER_REGION_SLOTS],
 }
 
 impl Header {
+    /// Create a header of the latest version for an empty bitmap of `bitmap_size` bytes.
+    pub fn new(bitmap_size: u64) -> Self {
+        let mut header = Header {
+            magic: MAGIC1,
+            version: VERSION_CHECKPOINT,
+            magic2: MAGIC2,
+            all_ready: 0,
+            generation: 0,
+            region_size: region_size(bitmap_size) as u32,
+            checksum: 0,
+            region_checksums: [0u32; HEADER_REGION_SLOTS],
+        };
+        header.update_region_checksums(&vec![0u8; bitmap_size as usize]);
+        header.checksum = header.compute_checksum();
+
+        header
+    }
+
     pub fn as_slice(&self) -> &[u8] {
         unsafe {
             std::slice::from_raw_parts(
@@ -39,13 +90,63 @@ impl Header {
             )
         }
     }
+
+    fn compute_checksum(&self) -> u32 {
+        let fields = [
+            self.magic,
+            self.version,
+            self.magic2,
+            self.all_ready,
+            self.generation as u32,
+            (self.generation >> 32) as u32,
+            self.region_size,
+        ];
+
+        fields
+            .iter()
+            .chain(self.region_checksums.iter())
+            .fold(FNV_OFFSET_BASIS, |sum, v| checksum(sum, &v.to_le_bytes()))
+    }
+
+    fn update_region_checksums(&mut self, bitmap: &[u8]) {
+        let regions = bitmap.chunks(self.region_size as usize);
+        for (slot, region) in self.region_checksums.iter_mut().zip(regions) {
+            *slot = checksum(FNV_OFFSET_BASIS, region);
+        }
+    }
+}
+
+struct CheckpointState {
+    // Time when the last checkpoint was taken.
+    time: Instant,
+    // Regions whose checksum has been invalidated since the last checkpoint.
+    invalidated: Vec<bool>,
 }
 
+/// A bitmap file to persist readiness state of chunks or data ranges.
+///
+/// Ready bits are set in the memory-mapped bitmap file, which may reach the disk at any time, even
+/// before the data they describe. So the file header records checksums of bitmap regions at the
+/// last checkpoint, taken after syncing the data file and the bitmap to disk. When opening the
+/// file, ready bits in regions which have changed since the last checkpoint, or in all regions if
+/// the header is torn, can't be trusted and are cleared.
+///
+/// Checkpoints sync files to disk, so they are not taken when chunks become ready. Instead, owners
+/// of the map should check [PersistMap::need_checkpoint()] and call [PersistMap::checkpoint()] in
+/// background. Maps without a data file never take checkpoints, so their ready bits are never
+/// trusted after reopening.
 pub(crate) struct PersistMap {
     pub count: u32,
     pub size: usize,
     pub base: *const u8,
     pub not_ready_count: AtomicU32,
+    // The file holding data tracked by the bitmap, synced to disk before taking checkpoints.
+    data_file: Option<Arc<File>>,
+    // Number of state changes since the last checkpoint.
+    dirty_count: AtomicU32,
+    persist: bool,
+    region_size: usize,
+    checkpoint: Mutex<CheckpointState>,
 }
 
 impl PersistMap {
@@ -103,7 +204,23 @@ impl PersistMap {
             return Err(ebadf!("failed to mmap blob chunk_map"));
         }
 
-        let header = unsafe { &mut *(base as *mut Header) };
+        let region_size = region_size(bitmap_size);
+        let map = PersistMap {
+            count: chunk_count,
+            size: expected_size as usize,
+            base: base as *const u8,
+            not_ready_count: AtomicU32::new(chunk_count),
+            data_file: None,
+            dirty_count: AtomicU32::new(0),
+            persist,
+            region_size: region_size as usize,
+            checkpoint: Mutex::new(CheckpointState {
+                time: Instant::now(),
+                invalidated: vec![false; div_round_up(bitmap_size, region_size) as usize],
+            }),
+        };
+
+        let header = map.header();
         if header.magic != MAGIC1 {
             if !create {
                 return Err(enoent!());
@@ -135,49 +252,41 @@ impl PersistMap {
                     filename
                 )));
             }
-            if header.all_ready == MAGIC_ALL_READY {
-                not_ready_count = 0;
-            } else if new_content {
-                not_ready_count = chunk_count;
-            } else {
-                let mut ready_count = 0;
-                for idx in HEADER_SIZE..expected_size as usize {
-                    let current = unsafe { &*(base.add(idx) as *const AtomicU8) };
-                    let val = current.load(Ordering::Acquire);
-                    ready_count += val.count_ones() as u32;
+            if !new_content {
+                if header.version >= VERSION_CHECKPOINT {
+                    map.recover(filename)?;
+                } else {
+                    // There's no checkpoint information, trust the content as older versions did.
+                    map.upgrade()?;
                 }
 
-                if ready_count >= chunk_count {
-                    header.all_ready = MAGIC_ALL_READY;
-                    let _ = file.sync_all();
+                if header.all_ready == MAGIC_ALL_READY {
                     not_ready_count = 0;
                 } else {
-                    not_ready_count = chunk_count - ready_count;
+                    let ready_count: u32 = map.snapshot().iter().map(|v| v.count_ones()).sum();
+                    if ready_count >= chunk_count {
+                        header.all_ready = MAGIC_ALL_READY;
+                        map.commit_header()?;
+                        not_ready_count = 0;
+                    } else {
+                        not_ready_count = chunk_count - ready_count;
+                    }
                 }
             }
         }
+        map.not_ready_count
+            .store(not_ready_count, Ordering::Release);
 
         readahead(fd, 0, expected_size);
         if !persist {
             let _ = std::fs::remove_file(filename);
         }
 
-        Ok(Self {
-            count: chunk_count,
-            size: expected_size as usize,
-            base: base as *const u8,
-            not_ready_count: AtomicU32::new(not_ready_count),
-        })
+        Ok(map)
     }
 
     fn write_header(file: &mut File, size: u64) -> Result<()> {
-        let header = Header {
-            magic: MAGIC1,
-            version: 1,
-            magic2: MAGIC2,
-            all_ready: 0,
-            reserved: [0x0u8; HEADER_RESERVED_SIZE],
-        };
+        let header = Header::new(size - HEADER_SIZE as u64);
 
         // Set file size to expected value and sync to disk.
         file.set_len(size)?;
@@ -189,6 +298,180 @@ impl PersistMap {
         Ok(())
     }
 
+    /// Set the file holding data tracked by the bitmap, which is synced before taking checkpoints.
+    pub fn set_data_file(&mut self, file: Arc<File>) {
+        self.data_file = Some(file);
+    }
+
+    /// Check whether a checkpoint should be taken, because many chunks have changed state, all
+    /// chunks are ready, or chunks changed state a while ago since the last checkpoint.
+    pub fn need_checkpoint(&self) -> bool {
+        if !self.persist || self.data_file.is_none() {
+            return false;
+        }
+        let dirty_count = self.dirty_count.load(Ordering::Acquire);
+        if dirty_count == 0 {
+            false
+        } else if dirty_count >= CHECKPOINT_CHUNKS || self.is_range_all_ready() {
+            true
+        } else {
+            // Don't wait for a running checkpoint.
+            match self.checkpoint.try_lock() {
+                Ok(state) => state.time.elapsed() >= CHECKPOINT_INTERVAL,
+                Err(_) => false,
+            }
+        }
+    }
+
+    /// Take a checkpoint, so ready bits set so far will be trusted after crashes.
+    ///
+    /// It syncs the data file and the bitmap file to disk, so it shouldn't be called in IO paths.
+    pub fn checkpoint(&self) -> Result<()> {
+        if !self.persist || self.data_file.is_none() {
+            return Ok(());
+        }
+        let mut state = self.checkpoint.lock().unwrap();
+        if self.dirty_count.load(Ordering::Acquire) == 0 {
+            // Taken by others concurrently.
+            return Ok(());
+        }
+        self.do_checkpoint(&mut state)
+    }
+
+    fn do_checkpoint(&self, state: &mut CheckpointState) -> Result<()> {
+        let dirty_count = self.dirty_count.swap(0, Ordering::AcqRel);
+        // Ready bits are set after writing data, so data of chunks marked as ready in the snapshot
+        // is covered by the following data sync.
+        let snapshot = self.snapshot();
+        if let Err(e) = self.sync_data().and_then(|_| self.sync_bitmap()) {
+            self.dirty_count.fetch_add(dirty_count, Ordering::AcqRel);
+            return Err(e);
+        }
+
+        let header = self.header();
+        let ready_count: u32 = snapshot.iter().map(|v| v.count_ones()).sum();
+        header.all_ready = if ready_count >= self.count {
+            MAGIC_ALL_READY
+        } else {
+            0
+        };
+        header.update_region_checksums(&snapshot);
+        header.generation = header.generation.wrapping_add(1);
+        state.time = Instant::now();
+        state.invalidated.iter_mut().for_each(|v| *v = false);
+
+        self.commit_header()
+    }
+
+    // Clear ready bits in regions which have changed since the last checkpoint.
+    fn recover(&self, filename: &str) -> Result<()> {
+        let header = self.header();
+        let snapshot = self.snapshot();
+        let valid = header.checksum == header.compute_checksum()
+            && header.region_size as usize == self.region_size;
+        let mut invalidated = 0;
+
+        for (idx, region) in snapshot.chunks(self.region_size).enumerate() {
+            if valid && header.region_checksums[idx] == checksum(FNV_OFFSET_BASIS, region) {
+                continue;
+            }
+            for (pos, v) in region.iter().enumerate() {
+                if *v != 0 {
+                    self.bitmap_u8(idx * self.region_size + pos)
+                        .store(0, Ordering::Release);
+                    invalidated += v.count_ones();
+                }
+            }
+        }
+
+        if !valid || invalidated > 0 {
+            warn!(
+                "untrusted ready state in blob chunk_map file {:?}, header valid {}, {} chunks invalidated",
+                filename, valid, invalidated
+            );
+            header.region_size = self.region_size as u32;
+            header.all_ready = 0;
+            self.sync_bitmap()?;
+            header.update_region_checksums(&self.snapshot());
+            header.generation = header.generation.wrapping_add(1);
+            self.commit_header()?;
+        }
+
+        Ok(())
+    }
+
+    // Upgrade the header to the latest version, trusting current content of the bitmap.
+    fn upgrade(&self) -> Result<()> {
+        let header = self.header();
+        header.version = VERSION_CHECKPOINT;
+        header.generation = 0;
+        header.region_size = self.region_size as u32;
+        self.sync_bitmap()?;
+        header.update_region_checksums(&self.snapshot());
+
+        self.commit_header()
+    }
+
+    // Make sure the region holding chunk `index` won't be trusted after crashes any more. It must
+    // be called before clearing the ready bit, because the bitmap may reach the disk at any time.
+    fn invalidate_region(&self, state: &mut CheckpointState, index: u32) -> Result<()> {
+        let header = self.header();
+        let region = (index as usize >> 3) / self.region_size;
+
+        if !state.invalidated[region] || header.all_ready == MAGIC_ALL_READY {
+            // Content of the region at the last checkpoint won't match the checksum any more.
+            header.region_checksums[region] = !header.region_checksums[region];
+            header.all_ready = 0;
+            self.commit_header()?;
+            state.invalidated[region] = true;
+        }
+
+        Ok(())
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn header(&self) -> &mut Header {
+        unsafe { &mut *(self.base as *mut Header) }
+    }
+
+    #[inline]
+    fn bitmap_u8(&self, pos: usize) -> &AtomicU8 {
+        unsafe { &*(self.base.add(HEADER_SIZE + pos) as *const AtomicU8) }
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        (0..self.size - HEADER_SIZE)
+            .map(|pos| self.bitmap_u8(pos).load(Ordering::Acquire))
+            .collect()
+    }
+
+    fn sync_data(&self) -> Result<()> {
+        match self.data_file.as_ref() {
+            Some(file) => file.sync_data(),
+            None => Ok(()),
+        }
+    }
+
+    fn sync_bitmap(&self) -> Result<()> {
+        let base = self.base as *const c_void as *mut c_void;
+        if unsafe { libc::msync(base, self.size, libc::MS_SYNC) } != 0 {
+            return Err(last_error!("failed to sync blob chunk_map"));
+        }
+
+        Ok(())
+    }
+
+    fn commit_header(&self) -> Result<()> {
+        let header = self.header();
+        header.checksum = header.compute_checksum();
+        let base = self.base as *const c_void as *mut c_void;
+        if unsafe { libc::msync(base, HEADER_SIZE, libc::MS_SYNC) } != 0 {
+            return Err(last_error!("failed to sync blob chunk_map header"));
+        }
+
+        Ok(())
+    }
+
     #[inline]
     pub fn validate_index(&self, idx: u32) -> Result<u32> {
         if idx < self.count {
@@ -221,6 +504,18 @@ impl PersistMap {
             .is_ok()
     }
 
+    #[inline]
+    fn clear_u8(&self, idx: u32, current: u8) -> bool {
+        let mask = Self::index_to_mask(idx);
+        let expected = current & !mask;
+        let start = HEADER_SIZE + (idx as usize >> 3);
+        let atomic_value = unsafe { &*(self.base.add(start) as *const AtomicU8) };
+
+        atomic_value
+            .compare_exchange(current, expected, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
     #[inline]
     fn index_to_mask(index: u32) -> u8 {
         let pos = 8 - ((index & 0b111) + 1);
@@ -247,9 +542,10 @@ impl PersistMap {
             }
 
             if self.write_u8(index, current) {
-                if self.not_ready_count.fetch_sub(1, Ordering::AcqRel) == 1 {
-                    self.mark_all_ready();
+                if self.persist {
+                    self.dirty_count.fetch_add(1, Ordering::AcqRel);
                 }
+                self.not_ready_count.fetch_sub(1, Ordering::AcqRel);
                 break;
             }
         }
@@ -257,13 +553,28 @@ impl PersistMap {
         Ok(())
     }
 
-    fn mark_all_ready(&self) {
-        let base = self.base as *const c_void as *mut c_void;
-        unsafe {
-            if libc::msync(base, self.size, libc::MS_SYNC) == 0 {
-                let header = &mut *(self.base as *mut Header);
-                header.all_ready = MAGIC_ALL_READY;
-                let _ = libc::msync(base, HEADER_SIZE, libc::MS_SYNC);
+    /// Clear the ready state of the chunk, return true if the chunk was ready.
+    pub fn clear_chunk_ready(&self, index: u32) -> Result<bool> {
+        let index = self.validate_index(index)?;
+        let mut state = self.checkpoint.lock().unwrap();
+        if !self.is_chunk_ready(index).0 {
+            return Ok(false);
+        }
+        if self.persist {
+            self.invalidate_region(&mut state, index)?;
+        }
+
+        // Loop to atomically update the state bit corresponding to the chunk index.
+        loop {
+            let (ready, current) = self.is_chunk_ready(index);
+            if !ready {
+                return Ok(false);
+            }
+
+            if self.clear_u8(index, current) {
+                self.not_ready_count.fetch_add(1, Ordering::AcqRel);
+                self.dirty_count.fetch_add(1, Ordering::AcqRel);
+                return Ok(true);
             }
         }
     }
@@ -277,6 +588,11 @@ impl PersistMap {
 impl Drop for PersistMap {
     fn drop(&mut self) {
         if !self.base.is_null() {
+            if self.persist && self.dirty_count.load(Ordering::Acquire) > 0 {
+                if let Err(e) = self.checkpoint() {
+                    warn!("failed to take checkpoint of blob chunk_map, {}", e);
+                }
+            }
             unsafe { libc::munmap(self.base as *mut libc::c_void, self.size) };
             self.base = std::ptr::null();
         }
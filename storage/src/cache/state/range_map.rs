@@ -2,7 +2,9 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use std::fs::File;
 use std::io::Result;
+use std::sync::Arc;
 
 use crate::cache::state::persist_map::PersistMap;
 use crate::cache::state::RangeMap;
@@ -17,6 +19,9 @@ const FILE_SUFFIX: &str = "range_map";
 /// `$blob_id.range_map` to record whether a data range has been cached by the blob cache, and
 /// atomic bitmap operations are used to manipulate the state bit. The bitmap file will be persisted
 /// to disk.
+///
+/// Like [IndexedChunkMap](struct.IndexedChunkMap.html), ready bits are trusted across crashes only
+/// if they have been covered by a checkpoint, which needs the data file set by `set_data_file()`.
 pub struct BlobRangeMap {
     pub(crate) shift: u32,
     map: PersistMap,
@@ -39,6 +44,22 @@ impl BlobRangeMap {
         PersistMap::open(&filename, count, false, true).map(|map| BlobRangeMap { shift, map })
     }
 
+    /// Set the file holding cached data, which is synced to disk before ready bits of ranges are
+    /// trusted across crashes.
+    pub fn set_data_file(&mut self, file: Arc<File>) {
+        self.map.set_data_file(file);
+    }
+
+    /// Check whether a checkpoint should be taken by [BlobRangeMap::checkpoint()].
+    pub fn need_checkpoint(&self) -> bool {
+        self.map.need_checkpoint()
+    }
+
+    /// Sync the data file and the bitmap file to disk, so ready ranges survive crashes.
+    pub fn checkpoint(&self) -> Result<()> {
+        self.map.checkpoint()
+    }
+
     pub(crate) fn get_range(&self, start: u64, count: u64) -> Result<(u32, u32)> {
         if let Some(end) = start.checked_add(count) {
             let start_index = start >> self.shift as u64;
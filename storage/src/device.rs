@@ -32,10 +32,11 @@ use fuse_backend_rs::api::filesystem::ZeroCopyWriter;
 use fuse_backend_rs::transport::{FileReadWriteVolatile, FileVolatileSlice};
 use nydus_utils::compress;
 use nydus_utils::digest::{self, RafsDigest};
+use nydus_utils::slowio::{trace_chunk, IoStage, StageTimer};
 use vm_memory::Bytes;
 
 use crate::cache::BlobCache;
-use crate::factory::{FactoryConfig, BLOB_FACTORY};
+use crate::factory::{BackendConfig, FactoryConfig, BLOB_FACTORY};
 
 static ZEROS: &[u8] = &[0u8; 4096]; // why 4096? volatile slice default size, unfortunately
 
@@ -44,6 +45,8 @@ bitflags! {
     pub struct BlobFeatures: u32 {
         /// Rafs V5 image without extended blob table.
         const V5_NO_EXT_BLOB_TABLE = 0x0000_0001;
+        /// Rafs V6 image, whose chunks are addressed by chunk index without chunk digest.
+        const V6_NO_CHUNK_DIGEST = 0x0000_0002;
     }
 }
 
@@ -548,6 +551,9 @@ impl BlobIoDesc {
 }
 
 /// Scatter/gather list for blob IO operation, containing zero or more blob IO descriptors
+///
+/// A `BlobIoVec` object without any blob IO descriptor but with non-zero `bi_size` represents
+/// a file hole, which should be filled with zeros instead of reading data from blobs.
 #[derive(Default)]
 pub struct BlobIoVec {
     /// Blob IO flags.
@@ -566,6 +572,19 @@ impl BlobIoVec {
         }
     }
 
+    /// Create a new blob IO scatter/gather list object for a file hole of `size` bytes.
+    pub fn new_hole(size: usize) -> Self {
+        BlobIoVec {
+            bi_size: size,
+            ..Default::default()
+        }
+    }
+
+    /// Check whether the blob io vector represents a file hole.
+    pub fn is_hole(&self) -> bool {
+        self.bi_vec.is_empty() && self.bi_size != 0
+    }
+
     /// Append another blob io vector to current one.
     pub fn append(&mut self, mut desc: BlobIoVec) {
         self.bi_vec.append(desc.bi_vec.as_mut());
@@ -823,6 +842,7 @@ pub struct BlobDevice {
     //meta: ArcSwap<Arc<dyn BlobCache>>,
     blobs: ArcSwap<Vec<Arc<dyn BlobCache>>>,
     blob_count: usize,
+    config: ArcSwap<FactoryConfig>,
 }
 
 impl BlobDevice {
@@ -840,6 +860,7 @@ impl BlobDevice {
         Ok(BlobDevice {
             blobs: ArcSwap::new(Arc::new(blobs)),
             blob_count: blob_infos.len(),
+            config: ArcSwap::new(config.clone()),
         })
     }
 
@@ -869,6 +890,7 @@ impl BlobDevice {
             self.stop_prefetch();
         }
         self.blobs.store(Arc::new(blobs));
+        self.config.store(config.clone());
         if fs_prefetch {
             self.start_prefetch();
         }
@@ -876,6 +898,14 @@ impl BlobDevice {
         Ok(())
     }
 
+    /// Update credentials and connection settings of the storage backend of the blob device.
+    ///
+    /// Different from `update()`, blob cache objects are kept and only the storage backend
+    /// switches to the new configuration.
+    pub fn update_backend(&self, backend: BackendConfig) -> io::Result<()> {
+        BLOB_FACTORY.update_backend(&self.config.load_full(), backend)
+    }
+
     /// Close the blob device.
     pub fn close(&self) -> io::Result<()> {
         Ok(())
@@ -891,13 +921,19 @@ impl BlobDevice {
             if desc.bi_size == 0 {
                 Ok(0)
             } else {
-                Err(einval!("BlobIoVec size doesn't match."))
+                // Fill file holes with zeros.
+                let size = desc.bi_size;
+                let mut f = BlobDeviceIoVec::new(self, desc);
+                w.write_from(&mut f, size, 0)
             }
         } else if !desc.validate() {
             Err(einval!("BlobIoVec targets multiple blobs."))
         } else if desc.bi_vec[0].blob.blob_index() as usize >= self.blob_count {
             Err(einval!("BlobIoVec has out of range blob_index."))
         } else {
+            for bio in desc.bi_vec.iter() {
+                trace_chunk(bio.blob.blob_id(), bio.chunkinfo.id());
+            }
             let size = desc.bi_size;
             let mut f = BlobDeviceIoVec::new(self, desc);
             // The `off` parameter to w.write_from() is actually ignored by
@@ -906,6 +942,28 @@ impl BlobDevice {
         }
     }
 
+    /// Read a range of data from blob into the provided buffer.
+    ///
+    /// It's for users which don't speak the fuse `ZeroCopyWriter` protocol, such as image tools.
+    pub fn read_to_buf(&self, desc: &mut BlobIoVec, buf: &mut [u8]) -> io::Result<usize> {
+        if desc.bi_size > buf.len() {
+            Err(einval!("buffer is too small for BlobIoVec"))
+        } else if desc.bi_size == 0 {
+            Ok(0)
+        } else if !desc.bi_vec.is_empty() && !desc.validate() {
+            Err(einval!("BlobIoVec targets multiple blobs."))
+        } else if !desc.bi_vec.is_empty()
+            && desc.bi_vec[0].blob.blob_index() as usize >= self.blob_count
+        {
+            Err(einval!("BlobIoVec has out of range blob_index."))
+        } else {
+            // Safe because the slice is within the range of `buf`.
+            let slice = unsafe { FileVolatileSlice::new(buf.as_mut_ptr(), desc.bi_size) };
+            let mut f = BlobDeviceIoVec::new(self, desc);
+            f.read_vectored_at_volatile(&[slice], 0)
+        }
+    }
+
     /// Try to prefetch specified blob data.
     pub fn prefetch(
         &self,
@@ -917,7 +975,7 @@ impl BlobDevice {
                 let _ = blob.prefetch(blob.clone(), &prefetches[idx..idx + 1], &[]);
             }
         }
-        for io_vec in io_vecs.iter() {
+        for io_vec in io_vecs.iter().filter(|v| !v.is_hole()) {
             if let Some(blob) = self.get_blob_by_iovec(io_vec) {
                 let _ = blob
                     .prefetch(blob.clone(), &[], &io_vec.bi_vec)
@@ -928,6 +986,21 @@ impl BlobDevice {
         Ok(())
     }
 
+    /// Check whether chunk information objects are needed to verify cached blob data.
+    pub fn need_chunk_infos(&self) -> bool {
+        self.blobs.load().iter().any(|b| b.need_chunk_infos())
+    }
+
+    /// Record chunk information objects of blob IO vectors to verify cached blob data, because
+    /// the storage subsystem can't get chunk digests by itself.
+    pub fn record_chunk_infos(&self, io_vecs: &[BlobIoVec]) {
+        for io_vec in io_vecs.iter().filter(|v| !v.is_hole()) {
+            if let Some(blob) = self.get_blob_by_iovec(io_vec) {
+                blob.record_chunk_infos(&io_vec.bi_vec);
+            }
+        }
+    }
+
     /// Start the background blob data prefetch task.
     pub fn start_prefetch(&self) {
         for blob in self.blobs.load().iter() {
@@ -944,7 +1017,8 @@ impl BlobDevice {
 
     /// Check all chunks related to the blob io vector are ready.
     pub fn all_chunks_ready(&self, io_vecs: &[BlobIoVec]) -> bool {
-        for io_vec in io_vecs.iter() {
+        let _timer = StageTimer::new(IoStage::ChunkMap);
+        for io_vec in io_vecs.iter().filter(|v| !v.is_hole()) {
             if let Some(blob) = self.get_blob_by_iovec(io_vec) {
                 let chunk_map = blob.get_chunk_map();
                 for desc in io_vec.bi_vec.iter() {
@@ -1029,7 +1103,6 @@ impl<'a> BlobDeviceIoVec<'a> {
     }
 }
 
-#[allow(dead_code)]
 impl BlobDeviceIoVec<'_> {
     fn fill_hole(&self, bufs: &[FileVolatileSlice], size: usize) -> Result<usize, Error> {
         let mut count: usize = 0;
@@ -1078,6 +1151,10 @@ impl FileReadWriteVolatile for BlobDeviceIoVec<'_> {
         buffers: &[FileVolatileSlice],
         _offset: u64,
     ) -> Result<usize, Error> {
+        if self.iovec.is_hole() {
+            return self.fill_hole(buffers, self.iovec.bi_size);
+        }
+
         // BlobDevice::read_to() has validated that:
         // - bi_vec[0] is valid
         // - bi_vec[0].blob.blob_index() is valid
@@ -1163,6 +1240,38 @@ mod tests {
         assert!(!iochunk.is_hole());
     }
 
+    #[test]
+    fn test_blob_io_vec_hole() {
+        let desc = BlobIoVec::new();
+        assert!(!desc.is_hole());
+        assert!(desc.get_target_blob_index().is_none());
+
+        let mut desc = BlobIoVec::new_hole(0x1000);
+        assert!(desc.is_hole());
+        assert_eq!(desc.bi_size, 0x1000);
+        assert!(desc.get_target_blob_index().is_none());
+        desc.append(BlobIoVec::new_hole(0x2000));
+        assert!(desc.is_hole());
+        assert_eq!(desc.bi_size, 0x3000);
+    }
+
+    #[test]
+    fn test_read_hole_to_buf() {
+        let config = Arc::new(FactoryConfig::default());
+        let device = BlobDevice::new(&config, &[]).unwrap();
+        let mut buf = vec![0xa5u8; 0x2000];
+
+        let mut desc = BlobIoVec::new_hole(0x1000);
+        assert_eq!(device.read_to_buf(&mut desc, &mut buf).unwrap(), 0x1000);
+        assert!(buf[..0x1000].iter().all(|v| *v == 0));
+        assert_eq!(buf[0x1000], 0xa5);
+
+        let mut desc = BlobIoVec::new_hole(0x3000);
+        assert!(device.read_to_buf(&mut desc, &mut buf).is_err());
+        let mut desc = BlobIoVec::new();
+        assert_eq!(device.read_to_buf(&mut desc, &mut buf).unwrap(), 0);
+    }
+
     #[test]
     fn test_is_all_chunk_ready() {
         // TODO
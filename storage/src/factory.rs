@@ -25,12 +25,20 @@ use tokio::runtime::{Builder, Runtime};
 
 use nydus_api::http::BlobPrefetchConfig;
 
+#[cfg(feature = "backend-composite")]
+use crate::backend::composite;
+#[cfg(feature = "backend-http")]
+use crate::backend::http;
 #[cfg(feature = "backend-localfs")]
 use crate::backend::localfs;
+#[cfg(feature = "backend-oci")]
+use crate::backend::oci;
 #[cfg(feature = "backend-oss")]
 use crate::backend::oss;
 #[cfg(feature = "backend-registry")]
 use crate::backend::registry;
+#[cfg(feature = "backend-s3")]
+use crate::backend::s3;
 use crate::backend::BlobBackend;
 use crate::cache::{BlobCache, BlobCacheMgr, DummyCacheMgr, FileCacheMgr, FsCacheMgr};
 use crate::device::BlobInfo;
@@ -99,7 +107,7 @@ pub struct CacheConfig {
     /// Type of blob cache: "blobcache", "fscache" or ""
     #[serde(default, rename = "type")]
     pub cache_type: String,
-    /// Whether the data from the cache is compressed, not used anymore.
+    /// Whether to keep chunk data in compressed form in the cache, only supported by blobcache.
     #[serde(default, rename = "compressed")]
     pub cache_compressed: bool,
     /// Blob cache manager specific configuration: FileCacheConfig, FsCacheConfig.
@@ -199,7 +207,8 @@ impl BlobFactory {
                 Arc::new(mgr) as Arc<dyn BlobCacheMgr>
             }
             _ => {
-                let mgr = DummyCacheMgr::new(config.cache.clone(), backend, false, false)?;
+                let mgr =
+                    DummyCacheMgr::new(config.cache.clone(), backend, false, false, &config.id)?;
                 mgr.init()?;
                 Arc::new(mgr) as Arc<dyn BlobCacheMgr>
             }
@@ -246,6 +255,38 @@ impl BlobFactory {
         }
     }
 
+    /// Update credentials and connection settings of the storage backend used by blob caches
+    /// created with configuration `config`.
+    ///
+    /// Blob caches and blob readers are kept, only the following requests to the storage backend
+    /// use the new configuration. The type of storage backend can't be changed. The storage
+    /// backend is shared by all blob devices created with the same configuration, so all of them
+    /// are affected.
+    pub fn update_backend(
+        &self,
+        config: &Arc<FactoryConfig>,
+        backend: BackendConfig,
+    ) -> IOResult<()> {
+        if backend.backend_type != config.backend.backend_type {
+            return Err(einval!(format!(
+                "can't change storage backend type from '{}' to '{}'",
+                config.backend.backend_type, backend.backend_type
+            )));
+        }
+        let key = BlobCacheMgrKey {
+            config: config.clone(),
+        };
+        let mgr = self
+            .mgrs
+            .lock()
+            .unwrap()
+            .get(&key)
+            .cloned()
+            .ok_or_else(|| enoent!("no blob cache manager for the configuration"))?;
+
+        mgr.backend().update_config(backend.backend_config)
+    }
+
     /// Create a storage backend for the blob with id `blob_id`.
     #[allow(unused_variables)]
     pub fn new_backend(
@@ -263,11 +304,28 @@ impl BlobFactory {
                 config.backend_config,
                 Some(blob_id),
             )?)),
+            #[cfg(feature = "backend-s3")]
+            "s3" => Ok(Arc::new(s3::S3::new(config.backend_config, Some(blob_id))?)),
+            #[cfg(feature = "backend-http")]
+            "http" => Ok(Arc::new(http::Http::new(
+                config.backend_config,
+                Some(blob_id),
+            )?)),
             #[cfg(feature = "backend-localfs")]
             "localfs" => Ok(Arc::new(localfs::LocalFs::new(
                 config.backend_config,
                 Some(blob_id),
             )?)),
+            #[cfg(feature = "backend-oci")]
+            "oci" => Ok(Arc::new(oci::Oci::new(
+                config.backend_config,
+                Some(blob_id),
+            )?)),
+            #[cfg(feature = "backend-composite")]
+            "composite" => Ok(Arc::new(composite::Composite::new(
+                config.backend_config,
+                Some(blob_id),
+            )?)),
             _ => Err(einval!(format!(
                 "unsupported backend type '{}'",
                 config.backend_type
@@ -297,4 +355,27 @@ mod tests {
 
         assert_eq!(config, config2);
     }
+
+    #[test]
+    fn test_update_backend() {
+        let factory = BlobFactory::new();
+        let config = Arc::new(FactoryConfig {
+            id: "test-update-backend".to_string(),
+            backend: BackendConfig {
+                backend_type: "localfs".to_string(),
+                backend_config: Default::default(),
+            },
+            cache: Default::default(),
+        });
+        let backend = BackendConfig {
+            backend_type: "oss".to_string(),
+            backend_config: Default::default(),
+        };
+        let err = factory.update_backend(&config, backend).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+        let err = factory
+            .update_backend(&config, config.backend.clone())
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+    }
 }
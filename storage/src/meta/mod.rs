@@ -168,6 +168,9 @@ impl BlobMetaHeaderOndisk {
 }
 
 /// Blob chunk compression information on disk format.
+///
+/// Chunks may be of different sizes, for example when generated by content defined chunking, but
+/// uncompressed offsets of chunks must be 4k aligned.
 #[repr(C)]
 #[derive(Clone, Copy, Default)]
 pub struct BlobChunkInfoOndisk {
@@ -904,6 +907,71 @@ mod tests {
         assert!(info.get_chunks_uncompressed(0x104000, 0x1, 0).is_err());
     }
 
+    #[test]
+    fn test_get_variable_size_chunks() {
+        // Chunks generated by content defined chunking, with 4k aligned uncompressed offsets.
+        let layout = [
+            (0x0u64, 0x1234u32, 0x0u64, 0x800u32),
+            (0x2000, 0x9000, 0x800, 0x3000),
+            (0xb000, 0x2f01, 0x3800, 0x1000),
+            (0xe000, 0x40000, 0x4800, 0x20000),
+        ];
+        let chunks = layout
+            .iter()
+            .map(|(u_off, u_size, c_off, c_size)| {
+                let mut chunk = BlobChunkInfoOndisk::default();
+                chunk.set_uncompressed_offset(*u_off);
+                chunk.set_uncompressed_size(*u_size);
+                chunk.set_compressed_offset(*c_off);
+                chunk.set_compressed_size(*c_size);
+                chunk
+            })
+            .collect();
+        let state = BlobMetaState {
+            blob_index: 1,
+            compressed_size: 0x24800,
+            uncompressed_size: 0x4e000,
+            chunk_count: 4,
+            chunks: ManuallyDrop::new(chunks),
+            base: std::ptr::null(),
+            unmap_len: 0,
+        };
+        let info = BlobMetaInfo {
+            state: Arc::new(state),
+        };
+
+        let vec = info.get_chunks_uncompressed(0x1233, 0x1, 0).unwrap();
+        assert_eq!(vec.len(), 1);
+        assert_eq!(vec[0].id(), 0);
+        assert_eq!(vec[0].uncompress_size(), 0x1234);
+        // Padding between chunks isn't covered by any chunk.
+        assert!(info.get_chunks_uncompressed(0x1234, 0x1, 0).is_err());
+
+        let vec = info.get_chunks_uncompressed(0x5000, 0x8000, 0).unwrap();
+        assert_eq!(vec.len(), 2);
+        assert_eq!(vec[0].id(), 1);
+        assert_eq!(vec[0].uncompress_offset(), 0x2000);
+        assert_eq!(vec[0].uncompress_size(), 0x9000);
+        assert_eq!(vec[1].id(), 2);
+        assert_eq!(vec[1].uncompress_offset(), 0xb000);
+        assert_eq!(vec[1].uncompress_size(), 0x2f01);
+        assert_eq!(vec[1].compress_size(), 0x1000);
+
+        let vec = info.get_chunks_uncompressed(0x0, 0x4e000, 0).unwrap();
+        assert_eq!(vec.len(), 4);
+        assert_eq!(vec[3].uncompress_size(), 0x40000);
+        // Batch size doesn't pull in the big chunk following the range.
+        let vec = info
+            .get_chunks_uncompressed(0x2000, 0x1000, 0x10000)
+            .unwrap();
+        assert_eq!(vec.len(), 2);
+
+        let vec = info.get_chunks_compressed(0x800, 0x3001, 0).unwrap();
+        assert_eq!(vec.len(), 2);
+        assert_eq!(vec[0].id(), 1);
+        assert_eq!(vec[1].id(), 2);
+    }
+
     #[test]
     fn test_round_up_4k() {
         assert_eq!(round_up_4k(0), 0x0u32);
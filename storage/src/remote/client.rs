@@ -181,7 +181,8 @@ impl RemoteBlob {
     ) -> Result<Self> {
         let blob_path = format!("{}/{}", work_dir, blob_info.blob_id());
         let count = (blob_info.uncompressed_size() + RANGE_MAP_MASK) >> RANGE_MAP_SHIFT;
-        let map = BlobRangeMap::new(&blob_path, count as u32, RANGE_MAP_SHIFT as u32)?;
+        let mut map = BlobRangeMap::new(&blob_path, count as u32, RANGE_MAP_SHIFT as u32)?;
+        map.set_data_file(file.clone());
         debug_assert!(count <= u32::MAX as u64);
 
         Ok(RemoteBlob {
@@ -122,3 +122,163 @@ impl BlobV5ChunkInfo for MockChunkInfo {
     impl_getter!(file_offset, file_offset, u64);
     impl_getter!(flags, flags, BlobChunkFlags);
 }
+
+/// Mock HTTP server to test storage backends.
+#[cfg(any(
+    feature = "backend-http",
+    feature = "backend-oss",
+    feature = "backend-registry",
+    feature = "backend-s3"
+))]
+pub(crate) mod mock_http {
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    /// A HTTP request received by the mock HTTP server, header names are in lower case.
+    pub(crate) struct MockHttpRequest {
+        pub method: String,
+        pub path: String,
+        pub headers: HashMap<String, String>,
+        pub body: Vec<u8>,
+    }
+
+    impl MockHttpRequest {
+        /// Get the byte range `[start, end]` requested by the `Range` header.
+        pub fn range(&self) -> Option<(usize, usize)> {
+            let (start, end) = self
+                .headers
+                .get("range")?
+                .trim_start_matches("bytes=")
+                .split_once('-')?;
+            Some((start.parse().ok()?, end.parse().ok()?))
+        }
+    }
+
+    /// A HTTP response to be sent by the mock HTTP server.
+    pub(crate) struct MockHttpResponse {
+        pub status: u16,
+        pub headers: Vec<(String, String)>,
+        pub body: Vec<u8>,
+    }
+
+    impl MockHttpResponse {
+        pub fn new(status: u16) -> Self {
+            MockHttpResponse {
+                status,
+                headers: Vec::new(),
+                body: Vec::new(),
+            }
+        }
+
+        pub fn header(mut self, name: &str, value: &str) -> Self {
+            self.headers.push((name.to_string(), value.to_string()));
+            self
+        }
+
+        pub fn body(mut self, body: Vec<u8>) -> Self {
+            self.body = body;
+            self
+        }
+
+        /// Respond to a `HEAD` or ranged `GET` request for `data`.
+        pub fn blob(req: &MockHttpRequest, data: &[u8]) -> Self {
+            if req.method == "HEAD" {
+                return MockHttpResponse::new(200)
+                    .header("Content-Length", &data.len().to_string());
+            }
+            match req.range() {
+                Some((start, end)) => {
+                    let end = std::cmp::min(end, data.len() - 1);
+                    MockHttpResponse::new(206).body(data[start..=end].to_vec())
+                }
+                None => MockHttpResponse::new(200).body(data.to_vec()),
+            }
+        }
+    }
+
+    /// Start a mock HTTP server on a random local port, and return the `host:port` address.
+    ///
+    /// The server keeps running in background threads until the test process exits.
+    pub(crate) fn start_mock_http_server<F>(handler: F) -> String
+    where
+        F: Fn(&MockHttpRequest) -> MockHttpResponse + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handler = Arc::new(handler);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(v) => v,
+                    Err(_) => continue,
+                };
+                let handler = handler.clone();
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                            break;
+                        }
+                        let mut parts = line.split_whitespace();
+                        let method = parts.next().unwrap_or_default().to_string();
+                        let path = parts.next().unwrap_or_default().to_string();
+                        let mut headers = HashMap::new();
+                        loop {
+                            let mut header = String::new();
+                            if reader.read_line(&mut header).unwrap_or(0) == 0 {
+                                return;
+                            }
+                            let header = header.trim_end();
+                            if header.is_empty() {
+                                break;
+                            }
+                            if let Some((name, value)) = header.split_once(':') {
+                                headers.insert(name.to_lowercase(), value.trim().to_string());
+                            }
+                        }
+                        let length = headers
+                            .get("content-length")
+                            .and_then(|v| v.parse::<usize>().ok())
+                            .unwrap_or(0);
+                        let mut body = vec![0u8; length];
+                        if reader.read_exact(&mut body).is_err() {
+                            break;
+                        }
+                        let req = MockHttpRequest {
+                            method,
+                            path,
+                            headers,
+                            body,
+                        };
+
+                        let resp = handler(&req);
+                        let mut data = format!("HTTP/1.1 {} Mock\r\n", resp.status);
+                        let mut has_length = false;
+                        for (name, value) in resp.headers.iter() {
+                            has_length |= name.eq_ignore_ascii_case("content-length");
+                            data.push_str(&format!("{}: {}\r\n", name, value));
+                        }
+                        if !has_length {
+                            data.push_str(&format!("Content-Length: {}\r\n", resp.body.len()));
+                        }
+                        data.push_str("\r\n");
+                        let mut data = data.into_bytes();
+                        if req.method != "HEAD" {
+                            data.extend_from_slice(&resp.body);
+                        }
+                        if stream.write_all(&data).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        addr
+    }
+}
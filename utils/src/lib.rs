@@ -23,6 +23,7 @@ pub mod exec;
 pub mod inode_bitmap;
 pub mod metrics;
 pub mod mpmc;
+pub mod slowio;
 pub mod types;
 
 /// Round up and divide the value `n` by `d`.
@@ -9,8 +9,12 @@
 //! - Storage backend metrics of type ['BackendMetrics']
 //! - Blobcache metrics of type ['BlobcacheMetrics']
 //! - Filesystem metrics of type ['FsIoStats`], supported by Rafs in fuse/virtiofs only.
+//!
+//! All metrics can also be exported in the Prometheus text exposition format by
+//! [`export_prometheus_metrics()`].
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Write};
 use std::ops::{Deref, Drop};
 use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
@@ -20,6 +24,7 @@ use serde_json::Error as SerdeError;
 
 use nydus_error::logger::ErrorHolder;
 
+use crate::slowio::SlowIoRecorder;
 use crate::InodeBitmap;
 
 /// Type of `inode`.
@@ -238,6 +243,9 @@ pub struct FsIoStats {
     // record regular file read
     #[serde(skip_serializing, skip_deserializing)]
     recent_read_files: InodeBitmap,
+    // record slowest read requests with per-stage latency breakdown
+    #[serde(skip_serializing, skip_deserializing)]
+    slow_io: SlowIoRecorder,
 }
 
 macro_rules! impl_iostat_option {
@@ -287,6 +295,11 @@ impl FsIoStats {
         record_latest_read_files_enabled
     );
 
+    /// Get the recorder for slow read requests.
+    pub fn slow_io(&self) -> &SlowIoRecorder {
+        &self.slow_io
+    }
+
     /// Prepare for recording statistics information about `ino`.
     pub fn new_file_counter(&self, ino: Inode) {
         if self.files_enabled() {
@@ -410,6 +423,10 @@ impl FsIoStats {
         .map_err(IoStatsError::Serialize)
     }
 
+    fn export_slow_io(&self) -> Result<String, IoStatsError> {
+        serde_json::to_string(&self.slow_io.records()).map_err(IoStatsError::Serialize)
+    }
+
     fn export_fs_stats(&self) -> Result<String, IoStatsError> {
         serde_json::to_string(self).map_err(IoStatsError::Serialize)
     }
@@ -508,6 +525,25 @@ pub fn export_files_access_pattern(name: &Option<String>) -> Result<String, IoSt
     }
 }
 
+/// Export slowest read requests of a filesystem.
+pub fn export_slow_io(name: &Option<String>) -> Result<String, IoStatsError> {
+    let fs_metrics = FS_METRICS.read().unwrap();
+    match name {
+        Some(k) => fs_metrics
+            .get(k)
+            .ok_or(IoStatsError::NoCounter)
+            .map(|v| v.export_slow_io())?,
+        None => {
+            if fs_metrics.len() == 1 {
+                if let Some(ios) = fs_metrics.values().next() {
+                    return ios.export_slow_io();
+                }
+            }
+            Err(IoStatsError::NoCounter)
+        }
+    }
+}
+
 /// Export filesystem metrics.
 pub fn export_global_stats(name: &Option<String>) -> Result<String, IoStatsError> {
     // With only one rafs instance, we allow caller to ask for an unknown ios name.
@@ -549,6 +585,26 @@ pub fn export_backend_metrics(name: &Option<String>) -> IoStatsResult<String> {
     }
 }
 
+/// Export report of blob cache scrubbing.
+pub fn export_blobcache_scrub_report(id: &Option<String>) -> IoStatsResult<String> {
+    let metrics = BLOBCACHE_METRICS.read().unwrap();
+
+    match id {
+        Some(k) => metrics
+            .get(k)
+            .ok_or(IoStatsError::NoCounter)
+            .map(|v| v.export_scrub_report())?,
+        None => {
+            if metrics.len() == 1 {
+                if let Some(m) = metrics.values().next() {
+                    return m.export_scrub_report();
+                }
+            }
+            Err(IoStatsError::NoCounter)
+        }
+    }
+}
+
 /// Export blob cache metircs.
 pub fn export_blobcache_metrics(id: &Option<String>) -> IoStatsResult<String> {
     let metrics = BLOBCACHE_METRICS.read().unwrap();
@@ -569,6 +625,463 @@ pub fn export_blobcache_metrics(id: &Option<String>) -> IoStatsResult<String> {
     }
 }
 
+// Upper bounds of latency ranges in seconds, see `latency_millis_range_index()`.
+const LATENCY_MILLIS_BOUNDS: [f64; READ_LATENCY_RANGE_MAX - 1] =
+    [0.001, 0.02, 0.05, 0.1, 0.5, 1.0, 2.0];
+// Upper bounds of latency ranges in seconds, see `latency_micros_range_index()`.
+const LATENCY_MICROS_BOUNDS: [f64; READ_LATENCY_RANGE_MAX - 1] =
+    [0.0002, 0.001, 0.02, 0.05, 0.5, 1.0, 2.0];
+// Lower bounds of block size ranges, see `request_size_index()`.
+const BLOCK_READ_SIZES: [&str; BLOCK_READ_SIZES_MAX] =
+    ["0", "1K", "4K", "16K", "64K", "128K", "512K", "1M"];
+const STATS_FOP_NAMES: [&str; StatsFop::Max as usize] = [
+    "getattr",
+    "readlink",
+    "open",
+    "release",
+    "read",
+    "statfs",
+    "getxattr",
+    "listxattr",
+    "opendir",
+    "lookup",
+    "readdir",
+    "readdirplus",
+    "access",
+    "forget",
+    "batch_forget",
+];
+
+type Labels<'a> = Vec<(&'a str, &'a str)>;
+
+/// Helper to generate metrics in the Prometheus text exposition format.
+#[derive(Default)]
+struct PrometheusWriter {
+    buf: String,
+}
+
+impl PrometheusWriter {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.buf, "# HELP {} {}", name, help);
+        let _ = writeln!(self.buf, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample<V: Display>(&mut self, name: &str, labels: &[(&str, &str)], value: V) {
+        self.buf.push_str(name);
+        for (idx, (k, v)) in labels.iter().enumerate() {
+            self.buf.push(if idx == 0 { '{' } else { ',' });
+            let v = v
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            let _ = write!(self.buf, "{}=\"{}\"", k, v);
+        }
+        if !labels.is_empty() {
+            self.buf.push('}');
+        }
+        let _ = writeln!(self.buf, " {}", value);
+    }
+
+    // Generate a metric family with one sample for each object.
+    fn simple<T>(
+        &mut self,
+        name: &str,
+        kind: &str,
+        help: &str,
+        objects: &[(Labels, &T)],
+        value: impl Fn(&T) -> u64,
+    ) {
+        self.family(name, kind, help);
+        for (labels, o) in objects {
+            self.sample(name, labels, value(*o));
+        }
+    }
+
+    // Convert latency ranges into cumulative histogram buckets.
+    fn histogram(
+        &mut self,
+        name: &str,
+        labels: &[(&str, &str)],
+        bounds: &[f64],
+        ranges: &[BasicMetric],
+        sum: f64,
+    ) {
+        let bucket = format!("{}_bucket", name);
+        let mut count = 0;
+        for (idx, range) in ranges.iter().enumerate() {
+            count += range.count();
+            let le = bounds
+                .get(idx)
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| "+Inf".to_string());
+            let mut labels = labels.to_vec();
+            labels.push(("le", le.as_str()));
+            self.sample(&bucket, &labels, count);
+        }
+        self.sample(&format!("{}_sum", name), labels, sum);
+        self.sample(&format!("{}_count", name), labels, count);
+    }
+}
+
+fn export_prometheus_fs_metrics(w: &mut PrometheusWriter, metrics: &[Arc<FsIoStats>]) {
+    let objects: Vec<(Labels, &FsIoStats)> = metrics
+        .iter()
+        .map(|m| (vec![("mount_id", m.id.as_str())], m.as_ref()))
+        .collect();
+
+    w.simple(
+        "nydus_fs_open_files",
+        "gauge",
+        "Number of files currently open.",
+        &objects,
+        |m| m.nr_opens.count(),
+    );
+    w.simple(
+        "nydus_fs_read_bytes_total",
+        "counter",
+        "Total amount of data read from the filesystem.",
+        &objects,
+        |m| m.data_read.count(),
+    );
+
+    w.family(
+        "nydus_fs_read_requests_total",
+        "counter",
+        "Total number of read requests, by request size.",
+    );
+    for (labels, m) in objects.iter() {
+        for (idx, size) in BLOCK_READ_SIZES.iter().enumerate() {
+            let mut labels = labels.clone();
+            labels.push(("block_size", *size));
+            w.sample(
+                "nydus_fs_read_requests_total",
+                &labels,
+                m.block_count_read[idx].count(),
+            );
+        }
+    }
+
+    for (name, help, errors) in [
+        (
+            "nydus_fs_fop_total",
+            "Total number of successful file operations.",
+            false,
+        ),
+        (
+            "nydus_fs_fop_errors_total",
+            "Total number of failed file operations.",
+            true,
+        ),
+    ] {
+        w.family(name, "counter", help);
+        for (labels, m) in objects.iter() {
+            let counters = if errors { &m.fop_errors } else { &m.fop_hits };
+            for (idx, fop) in STATS_FOP_NAMES.iter().enumerate() {
+                let mut labels = labels.clone();
+                labels.push(("fop", *fop));
+                w.sample(name, &labels, counters[idx].count());
+            }
+        }
+    }
+
+    w.family(
+        "nydus_fs_fop_latency_seconds",
+        "histogram",
+        "Latency of file operations.",
+    );
+    for (labels, m) in objects.iter() {
+        let sum: u64 = m
+            .fop_cumulative_latency_total
+            .iter()
+            .map(|v| v.count())
+            .sum();
+        w.histogram(
+            "nydus_fs_fop_latency_seconds",
+            labels,
+            &LATENCY_MICROS_BOUNDS,
+            &m.read_latency_dist,
+            sum as f64 / 1_000_000f64,
+        );
+    }
+}
+
+fn export_prometheus_backend_metrics(w: &mut PrometheusWriter, metrics: &[Arc<BackendMetrics>]) {
+    let objects: Vec<(Labels, &BackendMetrics)> = metrics
+        .iter()
+        .map(|m| {
+            let labels = vec![
+                ("blob_id", m.id.as_str()),
+                ("backend_type", m.backend_type.as_str()),
+            ];
+            (labels, m.as_ref())
+        })
+        .collect();
+
+    w.simple(
+        "nydus_backend_read_requests_total",
+        "counter",
+        "Total number of read requests to the storage backend.",
+        &objects,
+        |m| m.read_count.count(),
+    );
+    w.simple(
+        "nydus_backend_read_errors_total",
+        "counter",
+        "Total number of failed read requests to the storage backend.",
+        &objects,
+        |m| m.read_errors.count(),
+    );
+    w.simple(
+        "nydus_backend_read_bytes_total",
+        "counter",
+        "Total amount of data read from the storage backend.",
+        &objects,
+        |m| m.read_amount_total.count(),
+    );
+
+    w.family(
+        "nydus_backend_read_latency_seconds",
+        "histogram",
+        "Latency of read requests to the storage backend, by request size.",
+    );
+    for (labels, m) in objects.iter() {
+        for (idx, size) in BLOCK_READ_SIZES.iter().enumerate() {
+            let mut labels = labels.clone();
+            labels.push(("block_size", *size));
+            w.histogram(
+                "nydus_backend_read_latency_seconds",
+                &labels,
+                &LATENCY_MILLIS_BOUNDS,
+                &m.read_latency_sizes_dist[idx],
+                m.read_cumulative_latency_millis_dist[idx].count() as f64 / 1000f64,
+            );
+        }
+    }
+
+    for (name, help, label, mirrors) in [
+        (
+            "nydus_backend_mirror_errors_total",
+            "Total number of failed requests to registry mirrors.",
+            "mirror",
+            true,
+        ),
+        (
+            "nydus_backend_tier_reads_total",
+            "Total number of read requests served by tiers of the composite backend.",
+            "tier",
+            false,
+        ),
+    ] {
+        w.family(name, "counter", help);
+        for (labels, m) in objects.iter() {
+            let counters = if mirrors {
+                m.mirror_errors.read().unwrap()
+            } else {
+                m.tier_reads.read().unwrap()
+            };
+            for (key, counter) in counters.iter() {
+                let mut labels = labels.clone();
+                labels.push((label, key.as_str()));
+                w.sample(name, &labels, counter.count());
+            }
+        }
+    }
+
+    w.family(
+        "nydus_backend_circuit_state",
+        "gauge",
+        "Current state of the circuit breaker.",
+    );
+    for (labels, m) in objects.iter() {
+        let state = m.circuit_state();
+        let mut labels = labels.clone();
+        labels.push(("state", state.as_str()));
+        w.sample("nydus_backend_circuit_state", &labels, 1);
+    }
+    w.simple(
+        "nydus_backend_circuit_open_total",
+        "counter",
+        "Total number of the circuit breaker tripping open.",
+        &objects,
+        |m| m.circuit_open_count.count(),
+    );
+    w.simple(
+        "nydus_backend_circuit_rejected_total",
+        "counter",
+        "Total number of read requests failed fast by the open circuit breaker.",
+        &objects,
+        |m| m.circuit_rejected.count(),
+    );
+}
+
+fn export_prometheus_blobcache_metrics(
+    w: &mut PrometheusWriter,
+    metrics: &[Arc<BlobcacheMetrics>],
+) {
+    let objects: Vec<(Labels, &BlobcacheMetrics)> = metrics
+        .iter()
+        .map(|m| (vec![("blob_id", m.id.as_str())], m.as_ref()))
+        .collect();
+    let counters: [(&str, &str, &str, fn(&BlobcacheMetrics) -> u64); 20] = [
+        (
+            "nydus_blobcache_read_requests_total",
+            "counter",
+            "Total number of read requests to the blob cache.",
+            |m| m.total.count(),
+        ),
+        (
+            "nydus_blobcache_partial_hits_total",
+            "counter",
+            "Total number of read requests partially served from the blob cache.",
+            |m| m.partial_hits.count(),
+        ),
+        (
+            "nydus_blobcache_whole_hits_total",
+            "counter",
+            "Total number of read requests fully served from the blob cache.",
+            |m| m.whole_hits.count(),
+        ),
+        (
+            "nydus_blobcache_entries",
+            "gauge",
+            "Number of chunks ready in the blob cache.",
+            |m| m.entries_count.count(),
+        ),
+        (
+            "nydus_blobcache_evicted_blobs_total",
+            "counter",
+            "Total number of blob cache files evicted.",
+            |m| m.evicted_blobs.count(),
+        ),
+        (
+            "nydus_blobcache_evicted_bytes_total",
+            "counter",
+            "Total amount of disk space reclaimed by evicting blob cache files.",
+            |m| m.evicted_size.count(),
+        ),
+        (
+            "nydus_blobcache_reclaimed_chunks_total",
+            "counter",
+            "Total number of cold chunks reclaimed.",
+            |m| m.reclaimed_chunks.count(),
+        ),
+        (
+            "nydus_blobcache_reclaimed_bytes_total",
+            "counter",
+            "Total amount of disk space reclaimed for cold chunks.",
+            |m| m.reclaimed_size.count(),
+        ),
+        (
+            "nydus_blobcache_prefetch_bytes_total",
+            "counter",
+            "Total amount of data prefetched.",
+            |m| m.prefetch_data_amount.count(),
+        ),
+        (
+            "nydus_blobcache_prefetch_requests_total",
+            "counter",
+            "Total number of merged prefetch requests.",
+            |m| m.prefetch_mr_count.count(),
+        ),
+        (
+            "nydus_blobcache_prefetch_workers",
+            "gauge",
+            "Number of active prefetch workers.",
+            |m| m.prefetch_workers.load(Ordering::Relaxed) as u64,
+        ),
+        (
+            "nydus_blobcache_buffered_backend_bytes",
+            "gauge",
+            "Amount of data buffered from the storage backend.",
+            |m| m.buffered_backend_size.count(),
+        ),
+        (
+            "nydus_blobcache_coalesced_requests_total",
+            "counter",
+            "Total number of backend reads avoided by sharing inflight requests.",
+            |m| m.coalesced_requests.count(),
+        ),
+        (
+            "nydus_blobcache_coalesced_bytes_total",
+            "counter",
+            "Total amount of data shared from inflight requests.",
+            |m| m.coalesced_size.count(),
+        ),
+        (
+            "nydus_blobcache_hot_chunk_hits_total",
+            "counter",
+            "Total number of chunks served from the in-memory hot chunk cache.",
+            |m| m.hot_chunk_hits.count(),
+        ),
+        (
+            "nydus_blobcache_hot_chunk_misses_total",
+            "counter",
+            "Total number of chunks not found in the in-memory hot chunk cache.",
+            |m| m.hot_chunk_misses.count(),
+        ),
+        (
+            "nydus_blobcache_dedup_hits_total",
+            "counter",
+            "Total number of chunks found in the chunk store shared with other blobs.",
+            |m| m.dedup_hits.count(),
+        ),
+        (
+            "nydus_blobcache_scrubbed_chunks_total",
+            "counter",
+            "Total number of cached chunks verified by the scrubber.",
+            |m| m.scrub.scanned_chunks.count(),
+        ),
+        (
+            "nydus_blobcache_scrubbed_bytes_total",
+            "counter",
+            "Total amount of cached data read by the scrubber.",
+            |m| m.scrub.scanned_size.count(),
+        ),
+        (
+            "nydus_blobcache_corrupted_chunks_total",
+            "counter",
+            "Total number of corrupted chunks found by the scrubber.",
+            |m| m.scrub.corrupted_chunks.count(),
+        ),
+    ];
+
+    for (name, kind, help, value) in counters {
+        w.simple(name, kind, help, &objects, value);
+    }
+}
+
+/// Export metrics of all filesystems, storage backends and blob caches in the Prometheus text
+/// exposition format.
+pub fn export_prometheus_metrics() -> String {
+    // Sort by id to generate stable output.
+    let mut fs_metrics: Vec<Arc<FsIoStats>> =
+        FS_METRICS.read().unwrap().values().cloned().collect();
+    fs_metrics.sort_by(|a, b| a.id.cmp(&b.id));
+    let mut backend_metrics: Vec<Arc<BackendMetrics>> =
+        BACKEND_METRICS.read().unwrap().values().cloned().collect();
+    backend_metrics.sort_by(|a, b| a.id.cmp(&b.id));
+    let mut blobcache_metrics: Vec<Arc<BlobcacheMetrics>> = BLOBCACHE_METRICS
+        .read()
+        .unwrap()
+        .values()
+        .cloned()
+        .collect();
+    blobcache_metrics.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let mut w = PrometheusWriter::default();
+    if !fs_metrics.is_empty() {
+        export_prometheus_fs_metrics(&mut w, &fs_metrics);
+    }
+    if !backend_metrics.is_empty() {
+        export_prometheus_backend_metrics(&mut w, &backend_metrics);
+    }
+    if !blobcache_metrics.is_empty() {
+        export_prometheus_blobcache_metrics(&mut w, &blobcache_metrics);
+    }
+
+    w.buf
+}
+
 /// Export global error events.
 pub fn export_events() -> IoStatsResult<String> {
     serde_json::to_string(ERROR_HOLDER.lock().unwrap().deref()).map_err(IoStatsError::Serialize)
@@ -630,6 +1143,17 @@ pub struct BackendMetrics {
     read_count_block_size_dist: [BasicMetric; BLOCK_READ_SIZES_MAX],
     // Categorize metrics as per their latency and request size
     read_latency_sizes_dist: [[BasicMetric; READ_LATENCY_RANGE_MAX]; BLOCK_READ_SIZES_MAX],
+    // Cumulative count of request failure to each registry mirror, indexed by mirror host.
+    mirror_errors: RwLock<BTreeMap<String, BasicMetric>>,
+    // Cumulative count of read requests served by each tier of the composite backend, indexed by
+    // tier name.
+    tier_reads: RwLock<BTreeMap<String, BasicMetric>>,
+    // State of the circuit breaker, `disabled`, `closed`, `open` or `half_open`.
+    circuit_state: RwLock<String>,
+    // Cumulative count of the circuit breaker tripping open.
+    circuit_open_count: BasicMetric,
+    // Cumulative count of read requests failed fast by the open circuit breaker.
+    circuit_rejected: BasicMetric,
 }
 
 impl BackendMetrics {
@@ -638,6 +1162,7 @@ impl BackendMetrics {
         let backend_metrics = Arc::new(Self {
             id: id.to_string(),
             backend_type: backend_type.to_string(),
+            circuit_state: RwLock::new("disabled".to_string()),
             ..Default::default()
         });
 
@@ -684,6 +1209,79 @@ impl BackendMetrics {
         }
     }
 
+    /// Count a request failure of the registry mirror `host`.
+    pub fn mirror_error(&self, host: &str) {
+        if let Some(errors) = self.mirror_errors.read().unwrap().get(host) {
+            errors.inc();
+            return;
+        }
+        self.mirror_errors
+            .write()
+            .unwrap()
+            .entry(host.to_string())
+            .or_default()
+            .inc();
+    }
+
+    /// Get count of request failures of the registry mirror `host`.
+    pub fn mirror_error_count(&self, host: &str) -> u64 {
+        self.mirror_errors
+            .read()
+            .unwrap()
+            .get(host)
+            .map(|v| v.count())
+            .unwrap_or_default()
+    }
+
+    /// Count a read request served by the tier `tier` of the composite backend.
+    pub fn tier_read(&self, tier: &str) {
+        if let Some(reads) = self.tier_reads.read().unwrap().get(tier) {
+            reads.inc();
+            return;
+        }
+        self.tier_reads
+            .write()
+            .unwrap()
+            .entry(tier.to_string())
+            .or_default()
+            .inc();
+    }
+
+    /// Get count of read requests served by the tier `tier` of the composite backend.
+    pub fn tier_read_count(&self, tier: &str) -> u64 {
+        self.tier_reads
+            .read()
+            .unwrap()
+            .get(tier)
+            .map(|v| v.count())
+            .unwrap_or_default()
+    }
+
+    /// Update state of the circuit breaker.
+    pub fn set_circuit_state(&self, state: &str) {
+        *self.circuit_state.write().unwrap() = state.to_string();
+    }
+
+    /// Get state of the circuit breaker.
+    pub fn circuit_state(&self) -> String {
+        self.circuit_state.read().unwrap().clone()
+    }
+
+    /// Count the circuit breaker tripping open.
+    pub fn circuit_opened(&self) {
+        self.circuit_open_count.inc();
+    }
+
+    /// Count a read request failed fast by the open circuit breaker.
+    pub fn circuit_rejected(&self) {
+        self.circuit_rejected.inc();
+    }
+
+    /// Get count of read requests failed fast by the open circuit breaker.
+    pub fn circuit_rejected_count(&self) -> u64 {
+        self.circuit_rejected.count()
+    }
+
     fn export_metrics(&self) -> IoStatsResult<String> {
         serde_json::to_string(self).map_err(IoStatsError::Serialize)
     }
@@ -726,9 +1324,16 @@ pub struct BlobcacheMetrics {
     // How many `read` requests are processed by the blobcache instance.
     // This metric will be helpful when comparing with cache hits times.
     pub total: BasicMetric,
-    // Scale of blobcache. Blobcache does not evict entries.
-    // Means the number of chunks in ready status.
+    // Scale of blobcache, means the number of chunks in ready status.
     pub entries_count: BasicMetric,
+    // Number of blob cache files evicted to keep the cache under its capacity.
+    pub evicted_blobs: BasicMetric,
+    // Disk space reclaimed by evicting blob cache files, in unit of Bytes.
+    pub evicted_size: BasicMetric,
+    // Number of cold chunks reclaimed by punching holes in blob cache files.
+    pub reclaimed_chunks: BasicMetric,
+    // Disk space reclaimed by punching holes for cold chunks, in unit of Bytes.
+    pub reclaimed_size: BasicMetric,
     // Together with below two fields, we can figure out average merging size thus
     // to estimate the possibility to merge backend IOs.
     // In unit of Bytes
@@ -737,6 +1342,19 @@ pub struct BlobcacheMetrics {
     pub prefetch_workers: AtomicUsize,
     pub prefetch_unmerged_chunks: BasicMetric,
     pub buffered_backend_size: BasicMetric,
+    // Number of backend reads avoided by sharing data fetched by concurrent inflight requests.
+    pub coalesced_requests: BasicMetric,
+    // Amount of data shared from concurrent inflight requests, in unit of Bytes.
+    pub coalesced_size: BasicMetric,
+    // Number of chunks served from the in-memory hot chunk cache.
+    pub hot_chunk_hits: BasicMetric,
+    // Number of chunks not found in the in-memory hot chunk cache when it's enabled.
+    pub hot_chunk_misses: BasicMetric,
+    // Number of chunks found in the chunk store, which have been cached for other blobs.
+    pub dedup_hits: BasicMetric,
+    // Report of the background scrubber verifying cached chunks.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub scrub: ScrubReport,
 }
 
 impl BlobcacheMetrics {
@@ -773,6 +1391,80 @@ impl BlobcacheMetrics {
     pub fn export_metrics(&self) -> IoStatsResult<String> {
         serde_json::to_string(self).map_err(IoStatsError::Serialize)
     }
+
+    /// Export report of blobcache scrubbing.
+    pub fn export_scrub_report(&self) -> IoStatsResult<String> {
+        serde_json::to_string(&self.scrub).map_err(IoStatsError::Serialize)
+    }
+}
+
+/// Maximum number of corrupted chunks kept in [`ScrubReport`].
+const SCRUB_CORRUPTIONS_MAX: usize = 64;
+
+/// Corrupted chunk found by the blobcache scrubber.
+#[derive(Clone, Debug, Serialize)]
+pub struct ScrubCorruption {
+    pub blob_id: String,
+    pub chunk_index: u32,
+    // Whether the chunk has been dropped from the cache, to be fetched from backend again.
+    pub repaired: bool,
+    pub timestamp_secs: u64,
+}
+
+/// Report of the background scrubber verifying chunks cached by a blobcache instance.
+#[derive(Debug, Default, Serialize)]
+pub struct ScrubReport {
+    // Number of completed scrubbing passes over all cached blobs.
+    pub passes: BasicMetric,
+    // Time when the last scrubbing pass completed, in seconds since the Unix epoch.
+    pub last_pass_secs: AtomicU64,
+    // Number of cached chunks verified.
+    pub scanned_chunks: BasicMetric,
+    // Amount of cached data read for verification, in unit of Bytes.
+    pub scanned_size: BasicMetric,
+    // Number of cached chunks which can't be verified, due to lack of chunk digest.
+    pub skipped_chunks: BasicMetric,
+    // Number of corrupted chunks found.
+    pub corrupted_chunks: BasicMetric,
+    // Number of corrupted chunks dropped from the cache.
+    pub repaired_chunks: BasicMetric,
+    // Most recently found corrupted chunks.
+    pub recent_corruptions: Mutex<VecDeque<ScrubCorruption>>,
+}
+
+impl ScrubReport {
+    /// Record a corrupted chunk found by the scrubber.
+    pub fn record_corruption(&self, blob_id: &str, chunk_index: u32, repaired: bool) {
+        self.corrupted_chunks.inc();
+        if repaired {
+            self.repaired_chunks.inc();
+        }
+
+        let mut corruptions = self.recent_corruptions.lock().unwrap();
+        if corruptions.len() >= SCRUB_CORRUPTIONS_MAX {
+            corruptions.pop_front();
+        }
+        corruptions.push_back(ScrubCorruption {
+            blob_id: blob_id.to_string(),
+            chunk_index,
+            repaired,
+            timestamp_secs: unix_now_secs(),
+        });
+    }
+
+    /// Record completion of a scrubbing pass.
+    pub fn finish_pass(&self) {
+        self.passes.inc();
+        self.last_pass_secs
+            .store(unix_now_secs(), Ordering::Relaxed);
+    }
+}
+
+fn unix_now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
 }
 
 #[cfg(test)]
@@ -821,4 +1513,84 @@ mod tests {
         g.fop_update(StatsFop::Read, 2015520, true);
         assert_eq!(g.block_count_read[3].count(), 2);
     }
+
+    #[test]
+    fn test_backend_mirror_errors() {
+        let metrics = BackendMetrics::default();
+        assert_eq!(metrics.mirror_error_count("http://mirror1"), 0);
+        metrics.mirror_error("http://mirror1");
+        metrics.mirror_error("http://mirror1");
+        metrics.mirror_error("http://mirror2");
+        assert_eq!(metrics.mirror_error_count("http://mirror1"), 2);
+        assert_eq!(metrics.mirror_error_count("http://mirror2"), 1);
+
+        let exported = metrics.export_metrics().unwrap();
+        assert!(exported.contains(r#""mirror_errors":{"http://mirror1":2,"http://mirror2":1}"#));
+    }
+
+    #[test]
+    fn test_scrub_report() {
+        let metrics = BlobcacheMetrics::new("test-scrub", "/tmp");
+        metrics.scrub.scanned_chunks.add(3);
+        for idx in 0..SCRUB_CORRUPTIONS_MAX as u32 + 2 {
+            metrics.scrub.record_corruption("blob1", idx, idx % 2 == 0);
+        }
+        metrics.scrub.finish_pass();
+        assert_eq!(
+            metrics.scrub.corrupted_chunks.count(),
+            SCRUB_CORRUPTIONS_MAX as u64 + 2
+        );
+        assert_eq!(
+            metrics.scrub.repaired_chunks.count(),
+            SCRUB_CORRUPTIONS_MAX as u64 / 2 + 1
+        );
+        let corruptions = metrics.scrub.recent_corruptions.lock().unwrap();
+        assert_eq!(corruptions.len(), SCRUB_CORRUPTIONS_MAX);
+        assert_eq!(corruptions.front().unwrap().chunk_index, 2);
+        drop(corruptions);
+
+        let exported = export_blobcache_scrub_report(&Some("test-scrub".to_string())).unwrap();
+        assert!(exported.contains(r#""passes":1"#));
+        assert!(exported.contains(r#""scanned_chunks":3"#));
+        assert!(!metrics.export_metrics().unwrap().contains("scanned_chunks"));
+        assert!(export_prometheus_metrics()
+            .contains(r#"nydus_blobcache_corrupted_chunks_total{blob_id="test-scrub"} 66"#));
+
+        metrics.release().unwrap();
+        assert!(export_blobcache_scrub_report(&Some("test-scrub".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_export_prometheus_metrics() {
+        let metrics = BackendMetrics::new("test-prometheus", "registry");
+        let begin = metrics.begin();
+        metrics.end(&begin, 0x1000, false);
+        metrics.end(&begin, 0x1000, true);
+        metrics.mirror_error("http://mirror\"1");
+
+        let exported = export_prometheus_metrics();
+        assert!(exported.contains("# TYPE nydus_backend_read_latency_seconds histogram\n"));
+        assert!(exported.contains(
+            r#"nydus_backend_read_requests_total{blob_id="test-prometheus",backend_type="registry"} 2"#
+        ));
+        assert!(exported.contains(
+            r#"nydus_backend_read_errors_total{blob_id="test-prometheus",backend_type="registry"} 1"#
+        ));
+        assert!(exported.contains(
+            r#"nydus_backend_read_latency_seconds_bucket{blob_id="test-prometheus",backend_type="registry",block_size="4K",le="+Inf"} 2"#
+        ));
+        assert!(exported.contains(
+            r#"nydus_backend_read_latency_seconds_count{blob_id="test-prometheus",backend_type="registry",block_size="4K"} 2"#
+        ));
+        assert!(exported.contains(
+            r#"nydus_backend_mirror_errors_total{blob_id="test-prometheus",backend_type="registry",mirror="http://mirror\"1"} 1"#
+        ));
+        assert!(exported.contains(
+            r#"nydus_backend_circuit_state{blob_id="test-prometheus",backend_type="registry",state="disabled"} 1"#
+        ));
+
+        metrics.release().unwrap();
+        let exported = export_prometheus_metrics();
+        assert!(!exported.contains("test-prometheus"));
+    }
 }
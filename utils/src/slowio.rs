@@ -0,0 +1,393 @@
+// Copyright 2022 Ant Group. All rights reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Slow IO tracing with per-stage latency breakdown.
+//!
+//! A read request is served by the same thread from the filesystem down to the storage backend,
+//! passing through `Rafs`, `BlobDevice`, the blob cache and `BlobReader`. So the filesystem starts
+//! a [SlowIoTrace] on the current thread when receiving a read request, lower layers account time
+//! spent in each [IoStage] and chunks accessed to the thread local trace by [StageTimer] and
+//! [trace_chunk()], and the [SlowIoRecorder] keeps the slowest requests when the request is done.
+//!
+//! The fuse server thread marks the time when it receives a request from the fuse device by
+//! [mark_request_received()], so time spent before the filesystem starts serving the request is
+//! accounted to the [IoStage::FuseQueue] stage.
+//!
+//! Work done by other threads, such as prefetching and asynchronously persisting data into the
+//! cache file, is not accounted to any request.
+
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Maximum number of chunks recorded for a request.
+const SLOW_IO_MAX_CHUNKS: usize = 64;
+
+/// Stages of a read request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IoStage {
+    /// Waiting from reading the request from the fuse device until the filesystem serves it.
+    FuseQueue,
+    /// Mapping the file range to chunks by filesystem metadata.
+    MapChunks,
+    /// Checking chunk readiness, including waiting for inflight requests of other threads.
+    ChunkMap,
+    /// Reading data from the cache file.
+    CacheRead,
+    /// Fetching data from the storage backend.
+    Backend,
+    /// Decompressing and validating chunk data.
+    Decompress,
+    /// Writing data into the cache file.
+    CacheWrite,
+    Max,
+}
+
+impl IoStage {
+    fn as_str(&self) -> &'static str {
+        match self {
+            IoStage::FuseQueue => "fuse_queue",
+            IoStage::MapChunks => "map_chunks",
+            IoStage::ChunkMap => "chunk_map",
+            IoStage::CacheRead => "cache_read",
+            IoStage::Backend => "backend",
+            IoStage::Decompress => "decompress",
+            IoStage::CacheWrite => "cache_write",
+            IoStage::Max => "max",
+        }
+    }
+}
+
+#[derive(Default)]
+struct TraceState {
+    stages: [Duration; IoStage::Max as usize],
+    // Stages being timed, to avoid accounting time twice for nested timers of the same stage.
+    timing: [bool; IoStage::Max as usize],
+    chunks: Vec<SlowIoChunks>,
+    nr_chunks: usize,
+}
+
+thread_local! {
+    static TRACE_STATE: RefCell<Option<TraceState>> = RefCell::new(None);
+    // Time when the request being served by the current thread was read from the fuse device.
+    static REQUEST_RECEIVED: Cell<Option<Instant>> = Cell::new(None);
+}
+
+/// Mark that the current thread has read a request from the fuse device.
+pub fn mark_request_received() {
+    REQUEST_RECEIVED.with(|r| r.set(Some(Instant::now())));
+}
+
+#[cfg(test)]
+fn is_tracing() -> bool {
+    TRACE_STATE.with(|s| s.borrow().is_some())
+}
+
+/// Account chunk `index` of blob `blob_id` to the read request traced by the current thread.
+pub fn trace_chunk(blob_id: &str, index: u32) {
+    TRACE_STATE.with(|s| {
+        if let Some(state) = s.borrow_mut().as_mut() {
+            if state.nr_chunks >= SLOW_IO_MAX_CHUNKS {
+                return;
+            }
+            state.nr_chunks += 1;
+            match state.chunks.last_mut() {
+                Some(c) if c.blob_id == blob_id => c.chunks.push(index),
+                _ => state.chunks.push(SlowIoChunks {
+                    blob_id: blob_id.to_string(),
+                    chunks: vec![index],
+                }),
+            }
+        }
+    })
+}
+
+/// Guard object to account time spent in a stage to the read request traced by current thread.
+pub struct StageTimer {
+    stage: IoStage,
+    begin: Option<Instant>,
+}
+
+impl StageTimer {
+    /// Start timing stage `stage`, it's a no-op if the current thread isn't tracing or the stage
+    /// is being timed.
+    pub fn new(stage: IoStage) -> Self {
+        let begin = TRACE_STATE.with(|s| match s.borrow_mut().as_mut() {
+            Some(state) if !state.timing[stage as usize] => {
+                state.timing[stage as usize] = true;
+                Some(Instant::now())
+            }
+            _ => None,
+        });
+
+        StageTimer { stage, begin }
+    }
+}
+
+impl Drop for StageTimer {
+    fn drop(&mut self) {
+        if let Some(begin) = self.begin {
+            let elapsed = begin.elapsed();
+            TRACE_STATE.with(|s| {
+                if let Some(state) = s.borrow_mut().as_mut() {
+                    state.stages[self.stage as usize] += elapsed;
+                    state.timing[self.stage as usize] = false;
+                }
+            })
+        }
+    }
+}
+
+/// Chunks of a blob accessed by a read request.
+#[derive(Clone, Debug, Serialize)]
+pub struct SlowIoChunks {
+    blob_id: String,
+    chunks: Vec<u32>,
+}
+
+/// A slow read request with per-stage latency breakdown.
+#[derive(Clone, Debug, Serialize)]
+pub struct SlowIoRecord {
+    ino: u64,
+    path: String,
+    offset: u64,
+    size: u32,
+    /// Wall time when the request was received, in unit of seconds.
+    timestamp_secs: u64,
+    /// Latency of the whole request, in unit of microseconds.
+    latency_micros: u64,
+    /// Latency of stages, in unit of microseconds.
+    ///
+    /// Time not accounted to any stage, such as copying data into fuse buffers, is in `other`.
+    stages: BTreeMap<&'static str, u64>,
+    chunks: Vec<SlowIoChunks>,
+}
+
+/// Trace of a read request on the current thread, created by [SlowIoRecorder::begin()].
+///
+/// Tracing is stopped and the result is discarded if it's dropped without calling
+/// [SlowIoRecorder::end()].
+pub struct SlowIoTrace {
+    begin: Instant,
+    timestamp: SystemTime,
+}
+
+impl Drop for SlowIoTrace {
+    fn drop(&mut self) {
+        TRACE_STATE.with(|s| s.borrow_mut().take());
+    }
+}
+
+/// Recorder to keep the slowest read requests of a filesystem.
+#[derive(Debug, Default)]
+pub struct SlowIoRecorder {
+    enabled: AtomicBool,
+    // Only requests slower than the threshold are recorded, in unit of microseconds.
+    threshold: AtomicU64,
+    capacity: AtomicUsize,
+    records: Mutex<Vec<SlowIoRecord>>,
+}
+
+impl SlowIoRecorder {
+    /// Enable recording the `capacity` slowest requests taking longer than `threshold`.
+    pub fn enable(&self, threshold: Duration, capacity: usize) {
+        self.threshold
+            .store(threshold.as_micros() as u64, Ordering::Relaxed);
+        self.capacity.store(capacity, Ordering::Relaxed);
+        self.enabled.store(capacity > 0, Ordering::Relaxed);
+    }
+
+    /// Start tracing a read request on the current thread if the recorder is enabled.
+    pub fn begin(&self) -> Option<SlowIoTrace> {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let now = Instant::now();
+        let mut state = TraceState::default();
+        // The mark is consumed to avoid accounting it to other requests.
+        let begin = match REQUEST_RECEIVED.with(|r| r.take()) {
+            Some(received) if received <= now => {
+                state.stages[IoStage::FuseQueue as usize] = now - received;
+                received
+            }
+            _ => now,
+        };
+        TRACE_STATE.with(|s| *s.borrow_mut() = Some(state));
+        let timestamp = SystemTime::now();
+        Some(SlowIoTrace {
+            begin,
+            timestamp: timestamp.checked_sub(now - begin).unwrap_or(timestamp),
+        })
+    }
+
+    /// Stop tracing the read request and record it if it's slow enough.
+    ///
+    /// The `path` callback is only called for recorded requests, to avoid the cost of resolving
+    /// file paths for fast requests.
+    pub fn end<F>(&self, trace: SlowIoTrace, ino: u64, offset: u64, size: u32, path: F)
+    where
+        F: FnOnce() -> String,
+    {
+        let latency = trace.begin.elapsed();
+        let state = match TRACE_STATE.with(|s| s.borrow_mut().take()) {
+            Some(v) => v,
+            None => return,
+        };
+        let latency_micros = latency.as_micros() as u64;
+        if latency_micros < self.threshold.load(Ordering::Relaxed)
+            || !self.is_slower(latency_micros)
+        {
+            return;
+        }
+
+        let mut stages = BTreeMap::new();
+        let mut accounted = Duration::default();
+        for (idx, d) in state.stages.iter().enumerate() {
+            if !d.is_zero() {
+                accounted += *d;
+                stages.insert(STAGES[idx].as_str(), d.as_micros() as u64);
+            }
+        }
+        stages.insert(
+            "other",
+            latency.saturating_sub(accounted).as_micros() as u64,
+        );
+        let timestamp_secs = trace
+            .timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let record = SlowIoRecord {
+            ino,
+            path: path(),
+            offset,
+            size,
+            timestamp_secs,
+            latency_micros,
+            stages,
+            chunks: state.chunks,
+        };
+
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        let mut records = self.records.lock().unwrap();
+        if records.len() < capacity {
+            records.push(record);
+        } else {
+            let fastest = records
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, r)| r.latency_micros)
+                .map(|(idx, r)| (idx, r.latency_micros));
+            match fastest {
+                Some((idx, v)) if v < latency_micros => records[idx] = record,
+                _ => {}
+            }
+        }
+        records.truncate(capacity);
+    }
+
+    // Check whether a request with `latency` would be recorded, without resolving its path.
+    fn is_slower(&self, latency: u64) -> bool {
+        let records = self.records.lock().unwrap();
+        records.len() < self.capacity.load(Ordering::Relaxed)
+            || records.iter().any(|r| r.latency_micros < latency)
+    }
+
+    /// Get recorded requests, from the slowest to the fastest.
+    pub fn records(&self) -> Vec<SlowIoRecord> {
+        let mut records = self.records.lock().unwrap().clone();
+        records.sort_by_key(|r| cmp::Reverse(r.latency_micros));
+        records
+    }
+}
+
+const STAGES: [IoStage; IoStage::Max as usize] = [
+    IoStage::FuseQueue,
+    IoStage::MapChunks,
+    IoStage::ChunkMap,
+    IoStage::CacheRead,
+    IoStage::Backend,
+    IoStage::Decompress,
+    IoStage::CacheWrite,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn read(recorder: &SlowIoRecorder, ino: u64, delay: u64) {
+        let trace = recorder.begin();
+        {
+            let _timer = StageTimer::new(IoStage::Backend);
+            // Nested timers of the same stage are ignored.
+            let _nested = StageTimer::new(IoStage::Backend);
+            trace_chunk("blob1", 1);
+            trace_chunk("blob1", 2);
+            trace_chunk("blob2", 0);
+            thread::sleep(Duration::from_millis(delay));
+        }
+        if let Some(trace) = trace {
+            recorder.end(trace, ino, 0x1000, 0x2000, || format!("/file{}", ino));
+        }
+    }
+
+    #[test]
+    fn test_slow_io_recorder() {
+        let recorder = SlowIoRecorder::default();
+        read(&recorder, 1, 1);
+        assert!(recorder.records().is_empty());
+        // No tracing out of requests.
+        trace_chunk("blob1", 1);
+        assert!(!is_tracing());
+
+        recorder.enable(Duration::from_millis(10), 2);
+        read(&recorder, 2, 1);
+        assert!(recorder.records().is_empty());
+        read(&recorder, 3, 30);
+        read(&recorder, 4, 50);
+        read(&recorder, 5, 20);
+        assert!(!is_tracing());
+
+        // Only the two slowest requests are kept.
+        let records = recorder.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].ino, 4);
+        assert_eq!(records[0].path, "/file4");
+        assert_eq!(records[1].ino, 3);
+        assert!(records[0].latency_micros >= 50_000);
+        assert_eq!(records[0].stages.len(), 2);
+        assert!(records[0].stages["backend"] >= 50_000);
+        assert!(records[0].stages["backend"] <= records[0].latency_micros);
+        assert!(records[0].stages.contains_key("other"));
+        assert_eq!(records[0].chunks.len(), 2);
+        assert_eq!(records[0].chunks[0].blob_id, "blob1");
+        assert_eq!(records[0].chunks[0].chunks, vec![1, 2]);
+        assert_eq!(records[0].chunks[1].chunks, vec![0]);
+
+        // Time between receiving the fuse request and serving it.
+        mark_request_received();
+        thread::sleep(Duration::from_millis(60));
+        read(&recorder, 6, 1);
+        let records = recorder.records();
+        assert_eq!(records[0].ino, 6);
+        assert!(records[0].stages["fuse_queue"] >= 60_000);
+        assert!(records[0].latency_micros >= 60_000);
+        // The mark is only accounted to one request.
+        read(&recorder, 7, 70);
+        let records = recorder.records();
+        assert_eq!(records[0].ino, 7);
+        assert!(!records[0].stages.contains_key("fuse_queue"));
+
+        // Tracing is stopped if the request fails.
+        let trace = recorder.begin();
+        assert!(is_tracing());
+        drop(trace);
+        assert!(!is_tracing());
+    }
+}